@@ -5,11 +5,19 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
+use serde::{Deserialize, Serialize};
+
 use super::error::{Result, SpsError};
 use crate::Config;
 
-/// Define how long cache entries are considered valid
-const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+/// HTTP validators of a cached file, used to make the next refresh a conditional request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheValidators {
+    /// The URL the file was fetched from. Validators of another URL are not reused.
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
 
 /// Cache struct to manage cache operations
 pub struct Cache {
@@ -38,9 +46,25 @@ impl Cache {
 
     /// Stores raw string data in the cache
     pub fn store_raw(&self, filename: &str, data: &str) -> Result<()> {
+        tracing::debug!(
+            "Saving raw data to cache file: {:?}",
+            self.cache_dir.join(filename)
+        );
+        self.write_atomic(filename, data.as_bytes())
+    }
+
+    /// Writes a cache file through a temp file and a rename, so readers never see it partially
+    /// written.
+    fn write_atomic(&self, filename: &str, data: &[u8]) -> Result<()> {
         let path = self.cache_dir.join(filename);
-        tracing::debug!("Saving raw data to cache file: {:?}", path);
-        fs::write(&path, data)?;
+        let tmp_path = self
+            .cache_dir
+            .join(format!(".{filename}.{}.tmp", std::process::id()));
+        fs::write(&tmp_path, data)?;
+        if let Err(e) = fs::rename(&tmp_path, &path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
         Ok(())
     }
 
@@ -71,7 +95,48 @@ impl Cache {
             .duration_since(modified_time)
             .map_err(|e| SpsError::Cache(format!("System time error: {e}")))?;
 
-        Ok(age <= CACHE_TTL)
+        // How long cache entries are considered valid (`cache_ttl_secs`, 24 hours by default)
+        Ok(age <= Duration::from_secs(self._config.cache_ttl_secs))
+    }
+
+    /// Loads the validators stored next to a cache file, if the file itself still exists.
+    pub fn load_validators(&self, filename: &str) -> Option<CacheValidators> {
+        if !self.cache_dir.join(filename).is_file() {
+            return None;
+        }
+        let data = fs::read(self.cache_dir.join(validators_file_name(filename))).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// Stores validators next to a cache file. `None` removes them.
+    pub fn store_validators(
+        &self,
+        filename: &str,
+        validators: Option<&CacheValidators>,
+    ) -> Result<()> {
+        let validators_file = validators_file_name(filename);
+        match validators {
+            Some(validators) => {
+                let data = serde_json::to_vec_pretty(validators)?;
+                self.write_atomic(&validators_file, &data)?;
+            }
+            None => {
+                let path = self.cache_dir.join(validators_file);
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks a cache file as fresh without rewriting it, e.g. after a `304 Not Modified`.
+    pub fn touch(&self, filename: &str) -> Result<()> {
+        let file = fs::File::options()
+            .append(true)
+            .open(self.cache_dir.join(filename))?;
+        file.set_modified(SystemTime::now())?;
+        Ok(())
     }
 
     /// Clears a specific cache file
@@ -80,6 +145,7 @@ impl Cache {
         if path.exists() {
             fs::remove_file(&path)?;
         }
+        let _ = self.store_validators(filename, None);
         Ok(())
     }
 
@@ -97,3 +163,7 @@ impl Cache {
         &self._config
     }
 }
+
+fn validators_file_name(filename: &str) -> String {
+    format!("{filename}.validators.json")
+}
@@ -1,60 +1,391 @@
 // sps-common/src/config.rs
-use std::env;
+//! sps configuration.
+//!
+//! Settings are layered, later layers winning:
+//!
+//! 1. built-in defaults
+//! 2. the system file `/etc/sps/config.toml`
+//! 3. the user file `~/.config/sps/config.toml` (`$XDG_CONFIG_HOME/sps/config.toml` if set)
+//! 4. environment variables (`HOMEBREW_PREFIX`, `SPS_DOWNLOAD_RETRIES`, ...)
+//!
+//! Both files are flat TOML tables using the keys listed in [`CONFIG_KEYS`].
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::{env, fmt, fs};
 
 use directories::UserDirs; // Ensure this crate is in sps-common/Cargo.toml
-use tracing::debug;
+use tracing::{debug, warn};
 
-use super::error::Result; // Assuming SpsResult is Result from super::error
+use super::error::{Result, SpsError};
 
 // This constant will serve as a fallback if HOMEBREW_PREFIX is not set or is empty.
 const DEFAULT_FALLBACK_SPS_ROOT: &str = "/opt/homebrew";
 const SPS_ROOT_MARKER_FILENAME: &str = ".sps_root_v1";
+const DEFAULT_API_BASE_URL: &str = "https://formulae.brew.sh/api";
+const DEFAULT_AUTO_UPDATE_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 4;
+const DEFAULT_DOWNLOAD_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+const SYSTEM_CONFIG_PATH: &str = "/etc/sps/config.toml";
+
+/// A documented setting: its key in the config files and the environment variables that
+/// override it (the first one that is set wins).
+#[derive(Debug)]
+pub struct ConfigKey {
+    pub name: &'static str,
+    pub env: &'static [&'static str],
+    pub description: &'static str,
+    /// Hidden in `sps config list` output.
+    pub secret: bool,
+}
+
+pub const CONFIG_KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        name: "prefix",
+        env: &["HOMEBREW_PREFIX"],
+        description: "Installation prefix holding the Cellar, Caskroom and links",
+        secret: false,
+    },
+    ConfigKey {
+        name: "api_base_url",
+        env: &["HOMEBREW_API_DOMAIN"],
+        description: "Base URL of the formula and cask JSON API",
+        secret: false,
+    },
+    ConfigKey {
+        name: "artifact_domain",
+        env: &["HOMEBREW_ARTIFACT_DOMAIN"],
+        description: "Registry domain bottles are downloaded from instead of ghcr.io",
+        secret: false,
+    },
+    ConfigKey {
+        name: "docker_registry_token",
+        env: &["HOMEBREW_DOCKER_REGISTRY_TOKEN"],
+        description: "Bearer token for the bottle registry",
+        secret: true,
+    },
+    ConfigKey {
+        name: "docker_registry_basic_auth",
+        env: &["HOMEBREW_DOCKER_REGISTRY_BASIC_AUTH_TOKEN"],
+        description: "Base64 basic auth credentials for the bottle registry",
+        secret: true,
+    },
+    ConfigKey {
+        name: "github_api_token",
+        env: &["HOMEBREW_GITHUB_API_TOKEN"],
+        description: "GitHub API token",
+        secret: true,
+    },
+    ConfigKey {
+        name: "workers",
+        env: &["SPS_WORKERS"],
+        description: "Number of parallel install workers (0 picks one from the CPU count)",
+        secret: false,
+    },
+    ConfigKey {
+        name: "auto_update_secs",
+        env: &["SPS_AUTO_UPDATE_SECS"],
+        description: "Seconds between automatic metadata updates",
+        secret: false,
+    },
+    ConfigKey {
+        name: "cache_ttl_secs",
+        env: &["SPS_CACHE_TTL_SECS"],
+        description: "Seconds cached API data is considered fresh",
+        secret: false,
+    },
+    ConfigKey {
+        name: "download_retries",
+        env: &["SPS_DOWNLOAD_RETRIES"],
+        description: "Retries per URL for failed downloads",
+        secret: false,
+    },
+    ConfigKey {
+        name: "download_timeout_secs",
+        env: &["SPS_DOWNLOAD_TIMEOUT_SECS"],
+        description: "Abort a download after this many seconds without data",
+        secret: false,
+    },
+    ConfigKey {
+        name: "connect_timeout_secs",
+        env: &["SPS_CONNECT_TIMEOUT_SECS"],
+        description: "Connection timeout in seconds",
+        secret: false,
+    },
+    ConfigKey {
+        name: "offline",
+        env: &["SPS_OFFLINE"],
+        description: "Never use the network; metadata comes from the cache, downloads from the mirror",
+        secret: false,
+    },
+    ConfigKey {
+        name: "mirror",
+        env: &["SPS_MIRROR"],
+        description: "Local directory or file:// URL that bottles, sources and casks are copied from when offline",
+        secret: false,
+    },
+    ConfigKey {
+        name: "url_rewrites",
+        env: &["SPS_URL_REWRITES"],
+        description: "Comma-separated prefix=replacement rules applied to download URLs",
+        secret: false,
+    },
+    ConfigKey {
+        name: "allow_insecure_localhost",
+        env: &["SPS_ALLOW_INSECURE_LOCALHOST"],
+        description: "Accept plain http:// URLs that point at localhost (test servers)",
+        secret: false,
+    },
+    ConfigKey {
+        name: "api_public_key",
+        env: &["SPS_API_PUBLIC_KEY"],
+        description: "PEM public key the signed (*.jws.json) API payloads must verify against",
+        secret: false,
+    },
+];
+
+/// Replaces the leading `prefix` of a download URL, e.g. to point it at an internal mirror.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlRewrite {
+    pub prefix: String,
+    pub replacement: String,
+}
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    Flag(&'static str),
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigOrigin::Default => f.write_str("default"),
+            ConfigOrigin::File(path) => write!(f, "{}", path.display()),
+            ConfigOrigin::Env(var) => write!(f, "env {var}"),
+            ConfigOrigin::Flag(flag) => write!(f, "flag {flag}"),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub sps_root: PathBuf, // Public for direct construction in main for init if needed
+    pub sps_root: PathBuf,
     pub api_base_url: String,
     pub artifact_domain: Option<String>,
     pub docker_registry_token: Option<String>,
     pub docker_registry_basic_auth: Option<String>,
     pub github_api_token: Option<String>,
+    /// Parallel install workers; `None` picks a number from the CPU count.
+    pub workers: Option<usize>,
+    pub auto_update_secs: u64,
+    pub cache_ttl_secs: u64,
+    /// How often a failed download is retried (per URL) before giving up.
+    pub download_retries: u32,
+    /// A download is aborted if no data arrives for this long. There is no limit on the total
+    /// duration, so large bottles and casks can finish on slow connections.
+    pub download_timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    /// Set by `--offline` or `SPS_OFFLINE=1`: no network access at all.
+    pub offline: bool,
+    /// Where downloads are looked up in offline mode: a directory or a `file://` URL.
+    pub mirror: Option<String>,
+    pub url_rewrites: Vec<UrlRewrite>,
+    pub allow_insecure_localhost: bool,
+    /// Pinned RSA key for API payload signatures. When set, only signed payloads are accepted.
+    pub api_public_key: Option<PathBuf>,
+    origins: HashMap<&'static str, ConfigOrigin>,
 }
 
 impl Config {
+    /// Loads the configuration from defaults, config files and the environment.
+    /// Fails if a config file can't be parsed or contains an invalid value.
     pub fn load() -> Result<Self> {
+        Self::load_from(&system_config_path())
+    }
+
+    /// Like [`Config::load`], with the system file read from `system_path`.
+    pub fn load_from(system_path: &Path) -> Result<Self> {
         debug!("Loading sps configuration");
+        let mut config = Self::defaults();
+        for path in [Some(system_path.to_path_buf()), user_config_path()]
+            .into_iter()
+            .flatten()
+        {
+            config.apply_file(&path)?;
+        }
+        config.apply_env();
+        debug!("Effective SPS_ROOT set to: {}", config.sps_root.display());
+        debug!("Configuration loaded successfully.");
+        Ok(config)
+    }
+
+    /// The built-in defaults, without reading any file or environment variable.
+    pub fn defaults() -> Self {
+        Self {
+            sps_root: PathBuf::from(DEFAULT_FALLBACK_SPS_ROOT),
+            api_base_url: DEFAULT_API_BASE_URL.to_string(),
+            artifact_domain: None,
+            docker_registry_token: None,
+            docker_registry_basic_auth: None,
+            github_api_token: None,
+            workers: None,
+            auto_update_secs: DEFAULT_AUTO_UPDATE_SECS,
+            cache_ttl_secs: DEFAULT_CACHE_TTL_SECS,
+            download_retries: DEFAULT_DOWNLOAD_RETRIES,
+            download_timeout_secs: DEFAULT_DOWNLOAD_TIMEOUT_SECS,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            offline: false,
+            mirror: None,
+            url_rewrites: Vec::new(),
+            allow_insecure_localhost: false,
+            api_public_key: None,
+            origins: HashMap::new(),
+        }
+    }
 
-        // Try to get SPS_ROOT from HOMEBREW_PREFIX environment variable.
-        // Fallback to DEFAULT_FALLBACK_SPS_ROOT if not set or empty.
-        let sps_root_str = env::var("HOMEBREW_PREFIX").ok().filter(|s| !s.is_empty())
-            .unwrap_or_else(|| {
-                debug!(
-                    "HOMEBREW_PREFIX environment variable not set or empty, falling back to default: {}",
-                    DEFAULT_FALLBACK_SPS_ROOT
-                );
-                DEFAULT_FALLBACK_SPS_ROOT.to_string()
-            });
+    /// Sets `key` from its string form. Empty values clear optional settings.
+    pub fn set(&mut self, key: &str, value: &str, origin: ConfigOrigin) -> Result<()> {
+        let def = config_key(key)?;
+        let value = value.trim();
+        let optional = || (!value.is_empty()).then(|| value.to_string());
+        match def.name {
+            "prefix" => {
+                if value.is_empty() {
+                    return Err(SpsError::Config("prefix can't be empty".to_string()));
+                }
+                self.sps_root = expand_home(value);
+            }
+            "api_base_url" => {
+                if value.is_empty() {
+                    return Err(SpsError::Config("api_base_url can't be empty".to_string()));
+                }
+                self.api_base_url = value.trim_end_matches('/').to_string();
+            }
+            "artifact_domain" => self.artifact_domain = optional(),
+            "docker_registry_token" => self.docker_registry_token = optional(),
+            "docker_registry_basic_auth" => self.docker_registry_basic_auth = optional(),
+            "github_api_token" => self.github_api_token = optional(),
+            "workers" => {
+                let workers: usize = parse_number(def.name, value)?;
+                self.workers = (workers > 0).then_some(workers);
+            }
+            "auto_update_secs" => self.auto_update_secs = parse_number(def.name, value)?,
+            "cache_ttl_secs" => self.cache_ttl_secs = parse_number(def.name, value)?,
+            "download_retries" => self.download_retries = parse_number(def.name, value)?,
+            "download_timeout_secs" => self.download_timeout_secs = parse_number(def.name, value)?,
+            "connect_timeout_secs" => self.connect_timeout_secs = parse_number(def.name, value)?,
+            "offline" => self.offline = parse_bool(def.name, value)?,
+            "mirror" => self.mirror = optional(),
+            "url_rewrites" => self.url_rewrites = parse_url_rewrites(value)?,
+            "allow_insecure_localhost" => {
+                self.allow_insecure_localhost = parse_bool(def.name, value)?
+            }
+            "api_public_key" => self.api_public_key = optional().map(|v| expand_home(&v)),
+            _ => unreachable!("every key in CONFIG_KEYS is handled"),
+        }
+        self.origins.insert(def.name, origin);
+        Ok(())
+    }
 
-        let sps_root_path = PathBuf::from(&sps_root_str);
-        debug!("Effective SPS_ROOT set to: {}", sps_root_path.display());
+    /// The effective value of `key` in its string form, `None` if an optional setting is unset.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let def = config_key(key)?;
+        Ok(match def.name {
+            "prefix" => Some(self.sps_root.display().to_string()),
+            "api_base_url" => Some(self.api_base_url.clone()),
+            "artifact_domain" => self.artifact_domain.clone(),
+            "docker_registry_token" => self.docker_registry_token.clone(),
+            "docker_registry_basic_auth" => self.docker_registry_basic_auth.clone(),
+            "github_api_token" => self.github_api_token.clone(),
+            "workers" => Some(self.workers.unwrap_or(0).to_string()),
+            "auto_update_secs" => Some(self.auto_update_secs.to_string()),
+            "cache_ttl_secs" => Some(self.cache_ttl_secs.to_string()),
+            "download_retries" => Some(self.download_retries.to_string()),
+            "download_timeout_secs" => Some(self.download_timeout_secs.to_string()),
+            "connect_timeout_secs" => Some(self.connect_timeout_secs.to_string()),
+            "offline" => Some(self.offline.to_string()),
+            "mirror" => self.mirror.clone(),
+            "url_rewrites" => (!self.url_rewrites.is_empty()).then(|| {
+                self.url_rewrites
+                    .iter()
+                    .map(|rule| format!("{}={}", rule.prefix, rule.replacement))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            "allow_insecure_localhost" => Some(self.allow_insecure_localhost.to_string()),
+            "api_public_key" => self
+                .api_public_key
+                .as_ref()
+                .map(|p| p.display().to_string()),
+            _ => unreachable!("every key in CONFIG_KEYS is handled"),
+        })
+    }
 
-        let api_base_url = "https://formulae.brew.sh/api".to_string();
+    /// Where the effective value of `key` came from.
+    pub fn origin(&self, key: &str) -> ConfigOrigin {
+        self.origins
+            .get(key)
+            .cloned()
+            .unwrap_or(ConfigOrigin::Default)
+    }
 
-        let artifact_domain = env::var("HOMEBREW_ARTIFACT_DOMAIN").ok();
-        let docker_registry_token = env::var("HOMEBREW_DOCKER_REGISTRY_TOKEN").ok();
-        let docker_registry_basic_auth = env::var("HOMEBREW_DOCKER_REGISTRY_BASIC_AUTH_TOKEN").ok();
-        let github_api_token = env::var("HOMEBREW_GITHUB_API_TOKEN").ok();
+    fn apply_file(&mut self, path: &Path) -> Result<()> {
+        let Some(table) = read_config_file(path)? else {
+            return Ok(());
+        };
+        debug!("Reading config file {}", path.display());
+        for (key, value) in table {
+            if CONFIG_KEYS.iter().all(|def| def.name != key) {
+                warn!("Ignoring unknown setting '{}' in {}", key, path.display());
+                continue;
+            }
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(n) => n.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                // Lists, e.g. url_rewrites = ["https://a/=https://b/"], are joined with commas.
+                toml::Value::Array(items) if items.iter().all(|v| v.is_str()) => items
+                    .iter()
+                    .filter_map(toml::Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(","),
+                other => {
+                    return Err(SpsError::Config(format!(
+                        "{}: '{key}' must be a string, integer, boolean or list of strings, got {}",
+                        path.display(),
+                        other.type_str()
+                    )))
+                }
+            };
+            self.set(&key, &value, ConfigOrigin::File(path.to_path_buf()))
+                .map_err(|e| match e {
+                    SpsError::Config(msg) => SpsError::Config(format!("{}: {msg}", path.display())),
+                    other => other,
+                })?;
+        }
+        Ok(())
+    }
 
-        debug!("Configuration loaded successfully.");
-        Ok(Self {
-            sps_root: sps_root_path,
-            api_base_url,
-            artifact_domain,
-            docker_registry_token,
-            docker_registry_basic_auth,
-            github_api_token,
-        })
+    fn apply_env(&mut self) {
+        for def in CONFIG_KEYS {
+            for var in def.env {
+                let Ok(value) = env::var(var) else {
+                    continue;
+                };
+                if value.is_empty() {
+                    continue;
+                }
+                match self.set(def.name, &value, ConfigOrigin::Env(var)) {
+                    Ok(()) => break,
+                    Err(e) => warn!("Ignoring {}: {}", var, e),
+                }
+            }
+        }
     }
 
     pub fn sps_root(&self) -> &Path {
@@ -199,3 +530,138 @@ impl Default for Config {
 pub fn load_config() -> Result<Config> {
     Config::load()
 }
+
+/// The user config file: `$XDG_CONFIG_HOME/sps/config.toml`, else `~/.config/sps/config.toml`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| UserDirs::new().map(|ud| ud.home_dir().join(".config")))?;
+    Some(base.join("sps").join("config.toml"))
+}
+
+pub fn system_config_path() -> PathBuf {
+    PathBuf::from(SYSTEM_CONFIG_PATH)
+}
+
+/// Writes `key = value` into the config file at `path`, creating it if needed. `None` removes
+/// the key. Other keys are kept; comments are not.
+pub fn write_config_value(path: &Path, key: &str, value: Option<&str>) -> Result<()> {
+    let def = config_key(key)?;
+    let mut table = read_config_file(path)?.unwrap_or_default();
+    match value {
+        Some(value) => {
+            // Validate before writing anything.
+            Config::defaults().set(def.name, value, ConfigOrigin::Default)?;
+            let value = match value.trim().parse::<i64>() {
+                Ok(n) if is_numeric_key(def.name) => toml::Value::Integer(n),
+                _ if is_bool_key(def.name) => toml::Value::Boolean(parse_bool(def.name, value)?),
+                _ => toml::Value::String(value.trim().to_string()),
+            };
+            table.insert(def.name.to_string(), value);
+        }
+        None => {
+            table.remove(def.name);
+        }
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let data = toml::to_string_pretty(&table)
+        .map_err(|e| SpsError::Config(format!("Failed to serialize config: {e}")))?;
+    fs::write(path, data)?;
+    Ok(())
+}
+
+fn read_config_file(path: &Path) -> Result<Option<toml::Table>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(SpsError::Config(format!(
+                "Failed to read {}: {e}",
+                path.display()
+            )))
+        }
+    };
+    data.parse::<toml::Table>()
+        .map(Some)
+        .map_err(|e| SpsError::Config(format!("Failed to parse {}: {e}", path.display())))
+}
+
+fn config_key(key: &str) -> Result<&'static ConfigKey> {
+    CONFIG_KEYS
+        .iter()
+        .find(|def| def.name == key)
+        .ok_or_else(|| {
+            SpsError::Config(format!(
+                "Unknown setting '{key}'. Known settings: {}",
+                CONFIG_KEYS
+                    .iter()
+                    .map(|def| def.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
+
+fn is_numeric_key(key: &str) -> bool {
+    matches!(
+        key,
+        "workers"
+            | "auto_update_secs"
+            | "cache_ttl_secs"
+            | "download_retries"
+            | "download_timeout_secs"
+            | "connect_timeout_secs"
+    )
+}
+
+fn is_bool_key(key: &str) -> bool {
+    matches!(key, "offline" | "allow_insecure_localhost")
+}
+
+/// Parses `prefix=replacement[,prefix=replacement...]`.
+fn parse_url_rewrites(value: &str) -> Result<Vec<UrlRewrite>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| match rule.split_once('=') {
+            Some((prefix, replacement)) if !prefix.trim().is_empty() => Ok(UrlRewrite {
+                prefix: prefix.trim().to_string(),
+                replacement: replacement.trim().to_string(),
+            }),
+            _ => Err(SpsError::Config(format!(
+                "Invalid URL rewrite '{rule}': expected prefix=replacement"
+            ))),
+        })
+        .collect()
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" | "" => Ok(false),
+        _ => Err(SpsError::Config(format!(
+            "Invalid value '{value}' for {key}: expected true or false"
+        ))),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| {
+        SpsError::Config(format!(
+            "Invalid value '{value}' for {key}: expected a non-negative number"
+        ))
+    })
+}
+
+fn expand_home(value: &str) -> PathBuf {
+    match value.strip_prefix("~/") {
+        Some(rest) => UserDirs::new()
+            .map(|ud| ud.home_dir().join(rest))
+            .unwrap_or_else(|| PathBuf::from(value)),
+        None => PathBuf::from(value),
+    }
+}
@@ -660,18 +660,8 @@ impl Formula {
         Self {
             name: name.to_string(),
             stable_version_str: "0.0.0".to_string(),
-            version_semver: semver::Version::new(0, 0, 0), // Direct use
-            revision: 0,
             desc: Some("Placeholder for unresolved formula".to_string()),
-            homepage: None,
-            url: String::new(),
-            sha256: String::new(),
-            mirrors: Vec::new(),
-            bottle: Default::default(),
-            dependencies: Vec::new(),
-            requirements: Vec::new(),
-            resources: Vec::new(),
-            install_keg_path: None,
+            ..Default::default()
         }
     }
 }
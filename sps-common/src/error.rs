@@ -79,11 +79,35 @@ pub enum SpsError {
     #[error("Mach-O Modification Error: {0}")]
     MachOModificationError(String),
 
+    #[error("ELF Error: {0}")]
+    ElfError(String),
+
+    #[error("Link conflict: {0}")]
+    LinkConflict(String),
+
+    #[error("Lock Error: {0}")]
+    LockError(String),
+
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
+
+    #[error("Not available offline: {0}")]
+    Offline(String),
+
+    #[error("Signature verification failed: {0}")]
+    SignatureError(String),
+
+    #[error("Pinned: {0}")]
+    Pinned(String),
+
     #[error("Mach-O Relocation Error: Path too long - {0}")]
     PathTooLongError(String),
 
     #[error("Codesign Error: {0}")]
     CodesignError(String),
+
+    #[error("Unsafe archive: {0}")]
+    UnsafeArchive(String),
 }
 
 impl From<std::io::Error> for SpsError {
@@ -1,17 +1,20 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use tracing::debug;
 
 use super::cache::Cache;
 use super::config::Config;
 use super::error::{Result, SpsError};
+use super::index::{IndexKind, MetadataIndex};
 use super::model::formula::Formula;
 
 #[derive()]
 pub struct Formulary {
     cache: Cache,
     parsed_cache: std::sync::Mutex<HashMap<String, std::sync::Arc<Formula>>>,
+    /// The on-disk index of `formula.json`, opened on first use.
+    index: OnceLock<Result<Option<MetadataIndex>>>,
 }
 
 impl Formulary {
@@ -22,35 +25,34 @@ impl Formulary {
         Self {
             cache,
             parsed_cache: std::sync::Mutex::new(HashMap::new()),
+            index: OnceLock::new(),
         }
     }
 
     pub fn load_formula(&self, name: &str) -> Result<Formula> {
-        let mut parsed_cache_guard = self.parsed_cache.lock().unwrap();
-        if let Some(formula_arc) = parsed_cache_guard.get(name) {
+        if let Some(formula_arc) = self.parsed_cache.lock().unwrap().get(name) {
             debug!("Loaded formula '{}' from parsed cache.", name);
             return Ok(Arc::clone(formula_arc).as_ref().clone());
         }
-        drop(parsed_cache_guard);
 
-        let raw_data = self.cache.load_raw("formula.json")?;
-        let all_formulas: Vec<Formula> = serde_json::from_str(&raw_data)
-            .map_err(|e| SpsError::Cache(format!("Failed to parse cached formula data: {e}")))?;
-        debug!("Parsed {} formulas.", all_formulas.len());
-
-        let mut found_formula: Option<Formula> = None;
-        parsed_cache_guard = self.parsed_cache.lock().unwrap();
-        for formula in all_formulas {
-            let formula_name = formula.name.clone();
-            let formula_arc = std::sync::Arc::new(formula);
-
-            if formula_name == name {
-                found_formula = Some(Arc::clone(&formula_arc).as_ref().clone());
+        let index = self
+            .index
+            .get_or_init(|| MetadataIndex::open(&self.cache, IndexKind::Formula));
+        let index = match index {
+            Ok(Some(index)) => index,
+            Ok(None) => {
+                return Err(SpsError::Cache(
+                    "Cache file formula.json does not exist".to_string(),
+                ))
             }
-
-            parsed_cache_guard
-                .entry(formula_name)
-                .or_insert(formula_arc);
+            Err(e) => return Err(e.clone()),
+        };
+        let found_formula: Option<Formula> = index.get(name)?;
+        if let Some(formula) = &found_formula {
+            self.parsed_cache
+                .lock()
+                .unwrap()
+                .insert(name.to_string(), Arc::new(formula.clone()));
         }
 
         match found_formula {
@@ -0,0 +1,585 @@
+// sps-common/src/index.rs
+//! Compact on-disk index of the cached API data.
+//!
+//! `formula.json` and `cask.json` are several megabytes each. `sps update` compiles each of them
+//! into `<kind>.idx` next to it, so single lookups read a few hundred kilobytes of key tables and
+//! parse one package instead of the whole list. Layout (little endian):
+//!
+//! ```text
+//! header   magic, source length + mtime + hash, counts and region offsets
+//! keys     sorted (key, kind) records pointing at a package: names, aliases and old names
+//! packages per package: offset/length of its JSON and of its search text
+//! strings  key strings
+//! search   lowercase name/description text per package, scanned by `sps search`
+//! blob     the packages' JSON, verbatim
+//! ```
+//!
+//! The index records the length, mtime and a content hash of the JSON it was built from; when the
+//! JSON changes it is stale and rebuilt on the next open. An index that fails its bounds checks
+//! is treated the same way.
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use tracing::debug;
+
+use super::cache::Cache;
+use super::error::{Result, SpsError};
+
+const MAGIC: &[u8; 8] = b"SPSIDX02";
+const HEADER_LEN: usize = 8 + 8 + 8 + 4 + 4 + 4 + 5 * 8 + 8;
+const KEY_RECORD_LEN: usize = 16;
+const PACKAGE_RECORD_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    Formula,
+    Cask,
+}
+
+impl IndexKind {
+    /// The cached API file the index is built from.
+    pub fn source_file(self) -> &'static str {
+        match self {
+            IndexKind::Formula => "formula.json",
+            IndexKind::Cask => "cask.json",
+        }
+    }
+
+    fn index_file(self) -> &'static str {
+        match self {
+            IndexKind::Formula => "formula.idx",
+            IndexKind::Cask => "cask.idx",
+        }
+    }
+}
+
+/// How a key refers to its package. Lookups prefer names over aliases over old names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+enum KeyKind {
+    Name = 0,
+    Alias = 1,
+    OldName = 2,
+}
+
+#[derive(Deserialize)]
+struct FormulaKeys {
+    name: String,
+    #[serde(default)]
+    full_name: Option<String>,
+    #[serde(default)]
+    desc: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    oldname: Option<String>,
+    #[serde(default)]
+    oldnames: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CaskKeys {
+    token: String,
+    #[serde(default)]
+    full_token: Option<String>,
+    #[serde(default)]
+    name: Vec<String>,
+    #[serde(default)]
+    desc: Option<String>,
+    #[serde(default)]
+    old_tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SourceStamp {
+    len: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+}
+
+impl SourceStamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Self {
+            len: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+        })
+    }
+}
+
+struct Header {
+    stamp: SourceStamp,
+    source_hash: u64,
+    key_count: u32,
+    package_count: u32,
+    keys_off: u64,
+    packages_off: u64,
+    strings_off: u64,
+    search_off: u64,
+    search_len: u64,
+}
+
+struct KeyRecord {
+    str_off: u32,
+    str_len: u32,
+    package: u32,
+    kind: u8,
+}
+
+struct PackageRecord {
+    json_off: u64,
+    json_len: u32,
+    search_off: u32,
+    search_len: u32,
+}
+
+/// A read-only view of a built index. Only the key tables are held in memory; package JSON and
+/// search text are read from the file when needed.
+pub struct MetadataIndex {
+    kind: IndexKind,
+    file: File,
+    header: Header,
+    keys: Vec<KeyRecord>,
+    packages: Vec<PackageRecord>,
+    strings: Vec<u8>,
+    blob_off: u64,
+}
+
+impl MetadataIndex {
+    /// Opens the index for `kind`, building it first if it is missing or older than the cached
+    /// JSON. Returns `None` when there is no cached JSON to build from.
+    pub fn open(cache: &Cache, kind: IndexKind) -> Result<Option<Self>> {
+        let source = cache.get_dir().join(kind.source_file());
+        if !source.is_file() {
+            return Ok(None);
+        }
+        let index_path = cache.get_dir().join(kind.index_file());
+        match Self::open_path(&index_path, kind, &source) {
+            Ok(Some(index)) => return Ok(Some(index)),
+            Ok(None) => debug!("{} is stale, rebuilding", index_path.display()),
+            Err(e) => debug!(
+                "Could not read {}: {}. Rebuilding.",
+                index_path.display(),
+                e
+            ),
+        }
+        build(cache, kind)?;
+        Self::open_path(&index_path, kind, &source)
+    }
+
+    fn open_path(path: &Path, kind: IndexKind, source: &Path) -> Result<Option<Self>> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut raw_header = [0u8; HEADER_LEN];
+        file.read_exact_at(&mut raw_header, 0)?;
+        let header = parse_header(&raw_header)?;
+        if header.stamp != SourceStamp::of(source)? {
+            return Ok(None);
+        }
+
+        // The regions follow each other in the order `build` writes them; checking that before
+        // allocating anything keeps a corrupt header from asking for absurd buffers.
+        let keys_len = u64::from(header.key_count) * KEY_RECORD_LEN as u64;
+        let packages_len = u64::from(header.package_count) * PACKAGE_RECORD_LEN as u64;
+        let strings_len = header
+            .search_off
+            .checked_sub(header.strings_off)
+            .ok_or_else(|| corrupt("search region starts before the strings"))?;
+        let blob_off = header
+            .search_off
+            .checked_add(header.search_len)
+            .filter(|&end| end <= file_len)
+            .ok_or_else(|| corrupt("search region runs past the end of the file"))?;
+        if header.keys_off != HEADER_LEN as u64
+            || header.keys_off.checked_add(keys_len) != Some(header.packages_off)
+            || header.packages_off.checked_add(packages_len) != Some(header.strings_off)
+        {
+            return Err(corrupt("region offsets don't match the record counts"));
+        }
+        let blob_len = file_len - blob_off;
+
+        let mut keys_raw = vec![0u8; keys_len as usize];
+        file.read_exact_at(&mut keys_raw, header.keys_off)?;
+        let mut packages_raw = vec![0u8; packages_len as usize];
+        file.read_exact_at(&mut packages_raw, header.packages_off)?;
+        let mut strings = vec![0u8; strings_len as usize];
+        file.read_exact_at(&mut strings, header.strings_off)?;
+
+        let keys = keys_raw
+            .chunks_exact(KEY_RECORD_LEN)
+            .map(|r| KeyRecord {
+                str_off: u32_at(r, 0),
+                str_len: u32_at(r, 4),
+                package: u32_at(r, 8),
+                kind: r[12],
+            })
+            .collect::<Vec<_>>();
+        let packages = packages_raw
+            .chunks_exact(PACKAGE_RECORD_LEN)
+            .map(|r| PackageRecord {
+                json_off: u64_at(r, 0),
+                json_len: u32_at(r, 8),
+                search_off: u32_at(r, 12),
+                search_len: u32_at(r, 16),
+            })
+            .collect::<Vec<_>>();
+        for key in &keys {
+            if !within(key.str_off.into(), key.str_len.into(), strings_len)
+                || key.package >= header.package_count
+            {
+                return Err(corrupt("key record out of bounds"));
+            }
+        }
+        for package in &packages {
+            if !within(package.json_off, package.json_len.into(), blob_len)
+                || !within(
+                    package.search_off.into(),
+                    package.search_len.into(),
+                    header.search_len,
+                )
+            {
+                return Err(corrupt("package record out of bounds"));
+            }
+        }
+        Ok(Some(Self {
+            kind,
+            file,
+            header,
+            keys,
+            packages,
+            strings,
+            blob_off,
+        }))
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        self.kind
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// The raw JSON of the package called `name` (or with that alias or old name).
+    pub fn get_raw(&self, name: &str) -> Result<Option<String>> {
+        match self.find(name) {
+            Some(package) => self.package_json(package).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Deserializes the package called `name` (or with that alias or old name).
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        let Some(raw) = self.get_raw(name)? else {
+            return Ok(None);
+        };
+        serde_json::from_str(&raw).map(Some).map_err(|e| {
+            SpsError::Cache(format!(
+                "Failed to parse cached {} '{name}': {e}",
+                self.kind.source_file()
+            ))
+        })
+    }
+
+    /// The primary names of all packages.
+    pub fn names(&self) -> Vec<&str> {
+        self.keys
+            .iter()
+            .filter(|k| k.kind == KeyKind::Name as u8)
+            .map(|k| self.key_str(k))
+            .collect()
+    }
+
+    /// Raw JSON of every package whose name, aliases or description contain `query_lower`
+    /// (already lowercased).
+    pub fn search(&self, query_lower: &str) -> Result<Vec<String>> {
+        let mut search = vec![0u8; self.header.search_len as usize];
+        self.file
+            .read_exact_at(&mut search, self.header.search_off)?;
+        let mut matches = Vec::new();
+        for (i, package) in self.packages.iter().enumerate() {
+            let start = package.search_off as usize;
+            let text = search
+                .get(start..start + package.search_len as usize)
+                .ok_or_else(|| corrupt("package record out of bounds"))?;
+            if String::from_utf8_lossy(text)
+                .split('\n')
+                .any(|field| field.contains(query_lower))
+            {
+                matches.push(self.package_json(i as u32)?);
+            }
+        }
+        Ok(matches)
+    }
+
+    fn find(&self, name: &str) -> Option<u32> {
+        let start = self
+            .keys
+            .partition_point(|k| self.key_str(k).cmp(name) == Ordering::Less);
+        // Records are sorted by (key, kind), so the first match is the preferred one.
+        self.keys
+            .get(start)
+            .filter(|k| self.key_str(k) == name)
+            .map(|k| k.package)
+    }
+
+    fn key_str(&self, key: &KeyRecord) -> &str {
+        let start = key.str_off as usize;
+        self.strings
+            .get(start..start + key.str_len as usize)
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+            .unwrap_or("")
+    }
+
+    fn package_json(&self, package: u32) -> Result<String> {
+        let record = self
+            .packages
+            .get(package as usize)
+            .ok_or_else(|| corrupt("key points at a missing package"))?;
+        let offset = self
+            .blob_off
+            .checked_add(record.json_off)
+            .ok_or_else(|| corrupt("package record out of bounds"))?;
+        let mut buf = vec![0u8; record.json_len as usize];
+        self.file.read_exact_at(&mut buf, offset)?;
+        String::from_utf8(buf).map_err(corrupt)
+    }
+}
+
+/// Compiles the cached `<kind>.json` into `<kind>.idx`. Returns the number of packages.
+pub fn build(cache: &Cache, kind: IndexKind) -> Result<usize> {
+    let source = cache.get_dir().join(kind.source_file());
+    let stamp = SourceStamp::of(&source)?;
+    let data = fs::read_to_string(&source)?;
+    let source_hash = content_hash(data.as_bytes());
+    let packages: Vec<&RawValue> = serde_json::from_str(&data).map_err(|e| {
+        SpsError::Cache(format!(
+            "Failed to parse cached {}: {e}",
+            kind.source_file()
+        ))
+    })?;
+
+    let mut keys: Vec<(String, KeyKind, u32)> = Vec::new();
+    let mut searches: Vec<String> = Vec::with_capacity(packages.len());
+    for (i, raw) in packages.iter().enumerate() {
+        let i = i as u32;
+        let (names, search) = package_keys(kind, raw.get())?;
+        for (key, key_kind) in names {
+            keys.push((key, key_kind, i));
+        }
+        searches.push(search);
+    }
+    keys.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    keys.dedup_by(|a, b| a.0 == b.0);
+
+    let mut strings = Vec::new();
+    let mut key_records = Vec::with_capacity(keys.len() * KEY_RECORD_LEN);
+    for (key, key_kind, package) in &keys {
+        key_records.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        key_records.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        key_records.extend_from_slice(&package.to_le_bytes());
+        key_records.extend_from_slice(&[*key_kind as u8, 0, 0, 0]);
+        strings.extend_from_slice(key.as_bytes());
+    }
+
+    let mut search_region = Vec::new();
+    let mut package_records = Vec::with_capacity(packages.len() * PACKAGE_RECORD_LEN);
+    let mut json_off = 0u64;
+    for (raw, search) in packages.iter().zip(&searches) {
+        let json_len = raw.get().len() as u32;
+        package_records.extend_from_slice(&json_off.to_le_bytes());
+        package_records.extend_from_slice(&json_len.to_le_bytes());
+        package_records.extend_from_slice(&(search_region.len() as u32).to_le_bytes());
+        package_records.extend_from_slice(&(search.len() as u32).to_le_bytes());
+        package_records.extend_from_slice(&[0u8; 4]);
+        search_region.extend_from_slice(search.as_bytes());
+        json_off += u64::from(json_len);
+    }
+
+    let keys_off = HEADER_LEN as u64;
+    let packages_off = keys_off + key_records.len() as u64;
+    let strings_off = packages_off + package_records.len() as u64;
+    let search_off = strings_off + strings.len() as u64;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&stamp.len.to_le_bytes());
+    header.extend_from_slice(&stamp.mtime_secs.to_le_bytes());
+    header.extend_from_slice(&stamp.mtime_nanos.to_le_bytes());
+    header.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+    header.extend_from_slice(&(packages.len() as u32).to_le_bytes());
+    for off in [keys_off, packages_off, strings_off, search_off] {
+        header.extend_from_slice(&off.to_le_bytes());
+    }
+    header.extend_from_slice(&(search_region.len() as u64).to_le_bytes());
+    header.extend_from_slice(&source_hash.to_le_bytes());
+    debug_assert_eq!(header.len(), HEADER_LEN);
+
+    let index_path = cache.get_dir().join(kind.index_file());
+    let tmp_path = tmp_path(&index_path);
+    let write = || -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(&header)?;
+        out.write_all(&key_records)?;
+        out.write_all(&package_records)?;
+        out.write_all(&strings)?;
+        out.write_all(&search_region)?;
+        for raw in &packages {
+            out.write_all(raw.get().as_bytes())?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, &index_path)
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    debug!(
+        "Built {} with {} packages and {} keys",
+        index_path.display(),
+        packages.len(),
+        keys.len()
+    );
+    Ok(packages.len())
+}
+
+/// Re-stamps an index after its JSON was touched but not changed (a `304 Not Modified`
+/// refresh), so it is not rebuilt needlessly. Does nothing unless the JSON still has the length
+/// and content hash the index was built from.
+pub fn restamp(cache: &Cache, kind: IndexKind) -> Result<()> {
+    let source = cache.get_dir().join(kind.source_file());
+    let index_path = cache.get_dir().join(kind.index_file());
+    let Ok(file) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&index_path)
+    else {
+        return Ok(());
+    };
+    let mut raw_header = [0u8; HEADER_LEN];
+    file.read_exact_at(&mut raw_header, 0)?;
+    let header = parse_header(&raw_header)?;
+    let stamp = SourceStamp::of(&source)?;
+    if header.stamp.len != stamp.len || header.source_hash != content_hash(&fs::read(&source)?) {
+        return Ok(());
+    }
+    let mut raw_stamp = Vec::with_capacity(12);
+    raw_stamp.extend_from_slice(&stamp.mtime_secs.to_le_bytes());
+    raw_stamp.extend_from_slice(&stamp.mtime_nanos.to_le_bytes());
+    file.write_all_at(&raw_stamp, 16)?;
+    Ok(())
+}
+
+/// The keys of one package and its search text (searchable fields, lowercased, one per line).
+fn package_keys(kind: IndexKind, raw: &str) -> Result<(Vec<(String, KeyKind)>, String)> {
+    let parse_error = |e: serde_json::Error| {
+        SpsError::Cache(format!(
+            "Unexpected entry in cached {}: {e}",
+            kind.source_file()
+        ))
+    };
+    let mut keys = Vec::new();
+    let mut fields = Vec::new();
+    match kind {
+        IndexKind::Formula => {
+            let f: FormulaKeys = serde_json::from_str(raw).map_err(parse_error)?;
+            fields.push(f.name.clone());
+            keys.push((f.name, KeyKind::Name));
+            if let Some(full_name) = f.full_name {
+                fields.push(full_name.clone());
+                keys.push((full_name, KeyKind::Name));
+            }
+            fields.extend(f.desc);
+            fields.extend(f.aliases.iter().cloned());
+            keys.extend(f.aliases.into_iter().map(|a| (a, KeyKind::Alias)));
+            keys.extend(
+                f.oldnames
+                    .into_iter()
+                    .chain(f.oldname)
+                    .map(|o| (o, KeyKind::OldName)),
+            );
+        }
+        IndexKind::Cask => {
+            let c: CaskKeys = serde_json::from_str(raw).map_err(parse_error)?;
+            fields.push(c.token.clone());
+            keys.push((c.token, KeyKind::Name));
+            if let Some(full_token) = c.full_token {
+                keys.push((full_token, KeyKind::Name));
+            }
+            fields.extend(c.name);
+            fields.extend(c.desc);
+            keys.extend(c.old_tokens.into_iter().map(|o| (o, KeyKind::OldName)));
+        }
+    }
+    let search = fields
+        .iter()
+        .map(|f| f.to_lowercase().replace('\n', " "))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok((keys, search))
+}
+
+fn parse_header(raw: &[u8; HEADER_LEN]) -> Result<Header> {
+    if &raw[..8] != MAGIC {
+        return Err(SpsError::Cache("Not a metadata index".to_string()));
+    }
+    Ok(Header {
+        stamp: SourceStamp {
+            len: u64_at(raw, 8),
+            mtime_secs: u64_at(raw, 16),
+            mtime_nanos: u32_at(raw, 24),
+        },
+        key_count: u32_at(raw, 28),
+        package_count: u32_at(raw, 32),
+        keys_off: u64_at(raw, 36),
+        packages_off: u64_at(raw, 44),
+        strings_off: u64_at(raw, 52),
+        search_off: u64_at(raw, 60),
+        search_len: u64_at(raw, 68),
+        source_hash: u64_at(raw, 76),
+    })
+}
+
+fn corrupt(detail: impl std::fmt::Display) -> SpsError {
+    SpsError::Cache(format!("Corrupt metadata index: {detail}"))
+}
+
+/// Whether `len` bytes at `off` fit in a region of `region_len` bytes.
+fn within(off: u64, len: u64, region_len: u64) -> bool {
+    off.checked_add(len).is_some_and(|end| end <= region_len)
+}
+
+/// 64-bit FNV-1a, to tell whether a touched JSON file still holds what the index was built from.
+fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+fn u32_at(bytes: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(bytes[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(bytes[off..off + 8].try_into().unwrap())
+}
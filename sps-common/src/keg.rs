@@ -1,12 +1,13 @@
 // sps-common/src/keg.rs
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 // Corrected tracing imports: added error, removed unused debug
 use tracing::{debug, error, warn};
 
 use super::config::Config;
 use super::error::{Result, SpsError};
+use super::model::version::PkgVersion;
 
 /// Represents information about an installed package (Keg).
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,6 +17,19 @@ pub struct InstalledKeg {
     pub path: PathBuf,
 }
 
+impl InstalledKeg {
+    /// The keg's version for ordering. Reads `version_scheme` from the install receipt
+    /// when present; unparseable directory names sort before every real version.
+    pub fn pkg_version(&self) -> Option<PkgVersion> {
+        let version_scheme = fs::read_to_string(self.path.join("INSTALL_RECEIPT.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+            .and_then(|receipt| receipt.get("version_scheme").and_then(|v| v.as_u64()))
+            .unwrap_or(0) as u32;
+        PkgVersion::parse_with_scheme(&self.version_str, version_scheme).ok()
+    }
+}
+
 /// Manages querying installed packages in the Cellar.
 #[derive(Debug)]
 pub struct KegRegistry {
@@ -68,7 +82,7 @@ impl KegRegistry {
                                 path.display()
                             );
 
-                            if path.is_dir() {
+                            if path.is_dir() && !is_hidden(&path) {
                                 if let Some(version_str_full) =
                                     path.file_name().and_then(|n| n.to_str())
                                 {
@@ -82,11 +96,10 @@ impl KegRegistry {
 
                                     match latest_keg {
                                         Some(ref current_latest) => {
-                                            // Compare &str with &str
-                                            if version_str_full
-                                                > current_latest.version_str.as_str()
+                                            if current_keg_candidate.pkg_version()
+                                                > current_latest.pkg_version()
                                             {
-                                                debug!("[KEG_REGISTRY:{}] get_installed_keg: Updating latest keg (lexicographical) to: {}", name, path.display());
+                                                debug!("[KEG_REGISTRY:{}] get_installed_keg: Updating latest keg to: {}", name, path.display());
                                                 latest_keg = Some(current_keg_candidate);
                                             }
                                         }
@@ -125,6 +138,55 @@ impl KegRegistry {
         Ok(latest_keg)
     }
 
+    /// The keg `opt/<name>` points into, if the link resolves to a version directory of the
+    /// formula in the Cellar.
+    pub fn get_linked_keg(&self, name: &str) -> Option<InstalledKeg> {
+        let formula_dir = self.formula_cellar_path(name);
+        let target = fs::canonicalize(self.get_opt_path(name)).ok()?;
+        let canonical_formula_dir = fs::canonicalize(&formula_dir).ok()?;
+        let Some(Component::Normal(version_str)) = target
+            .strip_prefix(&canonical_formula_dir)
+            .ok()?
+            .components()
+            .next()
+        else {
+            return None;
+        };
+        let path = formula_dir.join(version_str);
+        if !path.is_dir() || is_hidden(&path) {
+            return None;
+        }
+        Some(InstalledKeg {
+            name: name.to_string(),
+            version_str: version_str.to_string_lossy().to_string(),
+            path,
+        })
+    }
+
+    /// Every installed version of a formula, oldest first.
+    pub fn list_kegs(&self, name: &str) -> Result<Vec<InstalledKeg>> {
+        let formula_dir = self.formula_cellar_path(name);
+        if !formula_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut kegs = Vec::new();
+        for entry in fs::read_dir(&formula_dir)? {
+            let path = entry?.path();
+            if !path.is_dir() || is_hidden(&path) {
+                continue;
+            }
+            if let Some(version_str) = path.file_name().and_then(|n| n.to_str()) {
+                kegs.push(InstalledKeg {
+                    name: name.to_string(),
+                    version_str: version_str.to_string(),
+                    path: path.clone(),
+                });
+            }
+        }
+        kegs.sort_by_cached_key(|keg| keg.pkg_version());
+        Ok(kegs)
+    }
+
     pub fn list_installed_kegs(&self) -> Result<Vec<InstalledKeg>> {
         let mut installed_kegs = Vec::new();
         let cellar_dir = self.cellar_path();
@@ -152,7 +214,7 @@ impl KegRegistry {
                 formula_path.display()
             );
 
-            if formula_path.is_dir() {
+            if formula_path.is_dir() && !is_hidden(&formula_path) {
                 if let Some(formula_name) = formula_path.file_name().and_then(|n| n.to_str()) {
                     debug!(
                         "[KEG_REGISTRY] list_installed_kegs: Found formula directory: {}",
@@ -171,7 +233,7 @@ impl KegRegistry {
                                 let version_path = version_entry.path();
                                 debug!("[KEG_REGISTRY:{}] list_installed_kegs: Examining version path: {}", formula_name, version_path.display());
 
-                                if version_path.is_dir() {
+                                if version_path.is_dir() && !is_hidden(&version_path) {
                                     if let Some(version_str_full) =
                                         version_path.file_name().and_then(|n| n.to_str())
                                     {
@@ -218,3 +280,11 @@ impl KegRegistry {
         self.formula_cellar_path(name).join(version_str_raw)
     }
 }
+
+/// Dot-directories in the Cellar (e.g. the staging and backup kegs of an unfinished install)
+/// are not installed kegs.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
@@ -4,8 +4,11 @@ pub mod config;
 pub mod dependency;
 pub mod error;
 pub mod formulary;
+pub mod index;
 pub mod keg;
+pub mod lock;
 pub mod model;
+pub mod pin;
 pub mod pipeline;
 // Optional: pub mod dependency_def;
 
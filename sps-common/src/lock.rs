@@ -0,0 +1,228 @@
+// sps-common/src/lock.rs
+//! Advisory lock on the sps prefix.
+//!
+//! Read-only commands take a shared lock, commands that change the Cellar, Caskroom, links or
+//! cached API data take an exclusive one. The lock lives in `Config::state_dir()` and is
+//! released when the [`PrefixLock`] is dropped, or by the OS if the process dies.
+//! Each holder also leaves a small record (PID, mode, command line) next to the lock so a
+//! blocked process can say who it is waiting for.
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::error::{Result, SpsError};
+
+const LOCK_FILE_NAME: &str = "prefix.lock";
+const HOLDERS_DIR_NAME: &str = "prefix.lock.d";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            LockMode::Shared => "shared",
+            LockMode::Exclusive => "exclusive",
+        }
+    }
+}
+
+/// A process currently holding (or recorded as holding) the prefix lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub mode: String,
+    pub command: String,
+}
+
+/// Holds the prefix lock until dropped.
+#[derive(Debug)]
+pub struct PrefixLock {
+    _file: Option<File>,
+    record_path: Option<PathBuf>,
+    mode: LockMode,
+}
+
+impl PrefixLock {
+    /// Acquires the prefix lock. If another process holds it, prints who holds it and either
+    /// blocks until it is released (`wait == true`) or fails with [`SpsError::LockError`].
+    ///
+    /// A shared lock that can't be set up (e.g. a read-only prefix) only logs a warning, so
+    /// read-only commands keep working.
+    pub fn acquire(config: &Config, mode: LockMode, wait: bool) -> Result<Self> {
+        let state_dir = config.state_dir();
+        let lock_path = state_dir.join(LOCK_FILE_NAME);
+        let file = match fs::create_dir_all(&state_dir).and_then(|_| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&lock_path)
+        }) {
+            Ok(f) => f,
+            Err(e) if mode == LockMode::Shared => {
+                warn!(
+                    "Could not open prefix lock {}: {}. Continuing without a lock.",
+                    lock_path.display(),
+                    e
+                );
+                return Ok(Self {
+                    _file: None,
+                    record_path: None,
+                    mode,
+                });
+            }
+            Err(e) => {
+                return Err(SpsError::LockError(format!(
+                    "could not open prefix lock {}: {e}",
+                    lock_path.display()
+                )));
+            }
+        };
+
+        let holders_dir = state_dir.join(HOLDERS_DIR_NAME);
+        match flock(&file, mode, false) {
+            Ok(true) => {}
+            Ok(false) => {
+                let holders = describe_holders(&read_holders(&holders_dir));
+                if !wait {
+                    return Err(SpsError::LockError(format!(
+                        "another sps process is using {} ({holders}). Retry later, or run without --no-wait to wait for it.",
+                        config.sps_root().display()
+                    )));
+                }
+                eprintln!("Waiting for another sps process to finish ({holders})...");
+                flock(&file, mode, true).map_err(|e| {
+                    SpsError::LockError(format!("failed to lock {}: {e}", lock_path.display()))
+                })?;
+            }
+            Err(e) => {
+                return Err(SpsError::LockError(format!(
+                    "failed to lock {}: {e}",
+                    lock_path.display()
+                )));
+            }
+        }
+        debug!(
+            "Acquired {} prefix lock {}",
+            mode.as_str(),
+            lock_path.display()
+        );
+
+        let record_path = write_holder_record(&holders_dir, mode);
+        Ok(Self {
+            _file: Some(file),
+            record_path,
+            mode,
+        })
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl Drop for PrefixLock {
+    fn drop(&mut self) {
+        if let Some(record) = &self.record_path {
+            let _ = fs::remove_file(record);
+        }
+        // The lock itself is released when the file is closed.
+    }
+}
+
+/// Takes a `flock(2)` lock on `file`. Without `blocking`, returns `Ok(false)` instead of
+/// waiting when another process holds a conflicting lock.
+fn flock(file: &File, mode: LockMode, blocking: bool) -> io::Result<bool> {
+    let mut operation = match mode {
+        LockMode::Shared => libc::LOCK_SH,
+        LockMode::Exclusive => libc::LOCK_EX,
+    };
+    if !blocking {
+        operation |= libc::LOCK_NB;
+    }
+    loop {
+        // SAFETY: `file` is open for the duration of the call, so the descriptor is valid.
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+        let err = io::Error::last_os_error();
+        match err.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock if !blocking => return Ok(false),
+            _ => return Err(err),
+        }
+    }
+}
+
+/// Records this process as a holder. Records that can't belong to a live holder any more
+/// are removed first: with an exclusive lock nobody else holds it, and with a shared lock no
+/// exclusive holder can exist.
+fn write_holder_record(holders_dir: &Path, mode: LockMode) -> Option<PathBuf> {
+    if let Err(e) = fs::create_dir_all(holders_dir) {
+        debug!(
+            "Could not create lock holder directory {}: {}",
+            holders_dir.display(),
+            e
+        );
+        return None;
+    }
+    for holder in read_holders(holders_dir) {
+        if mode == LockMode::Exclusive || holder.mode == LockMode::Exclusive.as_str() {
+            let _ = fs::remove_file(holders_dir.join(holder.pid.to_string()));
+        }
+    }
+    let pid = std::process::id();
+    let command = std::env::args().collect::<Vec<_>>().join(" ");
+    let record_path = holders_dir.join(pid.to_string());
+    match fs::write(&record_path, format!("{}\n{}\n", mode.as_str(), command)) {
+        Ok(()) => Some(record_path),
+        Err(e) => {
+            debug!(
+                "Could not write lock holder record {}: {}",
+                record_path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Lists the processes recorded as holding the prefix lock.
+pub fn read_holders(holders_dir: &Path) -> Vec<LockHolder> {
+    let Ok(entries) = fs::read_dir(holders_dir) else {
+        return Vec::new();
+    };
+    let mut holders: Vec<LockHolder> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let content = fs::read_to_string(entry.path()).ok()?;
+            let mut lines = content.lines();
+            let mode = lines.next()?.to_string();
+            let command = lines.next().unwrap_or_default().to_string();
+            Some(LockHolder { pid, mode, command })
+        })
+        .collect();
+    holders.sort_by_key(|h| h.pid);
+    holders
+}
+
+fn describe_holders(holders: &[LockHolder]) -> String {
+    if holders.is_empty() {
+        return "holder unknown".to_string();
+    }
+    holders
+        .iter()
+        .map(|h| format!("PID {} `{}`, {} lock", h.pid, h.command, h.mode))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
@@ -1,20 +1,17 @@
-// sps-core/src/model/formula.rs
-// *** Corrected: Removed derive Deserialize from ResourceSpec, removed unused SpsError import,
-// added ResourceSpec struct and parsing ***
+// sps-common/src/model/formula.rs
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 
-use semver::Version;
-use serde::{de, Deserialize, Deserializer, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use tracing::{debug, error};
 
 use crate::dependency::{Dependency, DependencyTag, Requirement};
 use crate::error::Result; // <-- Import only Result // Use log crate imports
+use crate::model::version::{PkgVersion, Version};
 
 // --- Resource Spec Struct ---
-// *** Added struct definition, REMOVED #[derive(Deserialize)] ***
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 pub struct ResourceSpec {
     pub name: String,
@@ -51,80 +48,443 @@ pub struct FormulaVersions {
     pub bottle: bool,
 }
 
+// --- Source URL Structs ---
+/// One entry of the `urls` object (`stable` or `head`).
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct UrlSpec {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub using: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct FormulaUrls {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stable: Option<UrlSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<UrlSpec>,
+}
+
+// --- Formula Metadata Structs ---
+/// Why a formula is keg-only, e.g. `{ "reason": ":provided_by_macos", "explanation": "" }`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct KegOnlyReason {
+    pub reason: String,
+    #[serde(default)]
+    pub explanation: String,
+}
+
+impl KegOnlyReason {
+    /// Human-readable reason, phrased to follow "because".
+    pub fn describe(&self) -> String {
+        let explanation = self.explanation.trim();
+        if !explanation.is_empty() {
+            return explanation.to_string();
+        }
+        match self.reason.trim_start_matches(':') {
+            "provided_by_macos" => "macOS already provides this software and installing another version in parallel can cause all kinds of trouble".to_string(),
+            "shadowed_by_macos" => "macOS provides similar software and installing this software in parallel can cause all kinds of trouble".to_string(),
+            "versioned_formula" => "this is an alternate version of another formula".to_string(),
+            other => other.replace('_', " "),
+        }
+    }
+}
+
+/// A build option such as `{ "option": "--with-foo", "description": "..." }`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct FormulaOption {
+    pub option: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A single tag or a list of tags, as used in `uses_from_macos` contexts.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TagList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl TagList {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            TagList::One(s) => vec![s.clone()],
+            TagList::Many(v) => v.clone(),
+        }
+    }
+}
+
+/// An entry of `uses_from_macos`: either a plain name or `{ "name": "build" }`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum UsesFromMacos {
+    Name(String),
+    WithTags(BTreeMap<String, TagList>),
+}
+
+impl UsesFromMacos {
+    /// Name of the dependency this entry refers to.
+    pub fn name(&self) -> &str {
+        match self {
+            UsesFromMacos::Name(name) => name,
+            UsesFromMacos::WithTags(map) => map.keys().next().map_or("", String::as_str),
+        }
+    }
+
+    /// Dependency tags (`build`, `test`, ...). Empty means a runtime dependency.
+    pub fn tags(&self) -> Vec<String> {
+        match self {
+            UsesFromMacos::Name(_) => Vec::new(),
+            UsesFromMacos::WithTags(map) => map.values().flat_map(TagList::to_vec).collect(),
+        }
+    }
+}
+
+/// The macOS version bound paired by index with each `uses_from_macos` entry.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct MacOSBound {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<String>,
+}
+
+/// `run` of a service block: a command string, an argv list, or per-OS variants.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ServiceRun {
+    Command(String),
+    Args(Vec<String>),
+    PerOs(BTreeMap<String, TagList>),
+}
+
+/// `keep_alive` of a service block.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct ServiceKeepAlive {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub always: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub successful_exit: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crashed: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// The `service` block of a formula (what `brew services` would run).
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct ServiceSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<ServiceRun>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<ServiceKeepAlive>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch_only_once: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_root: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment_variables: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_log_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_delay: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<BTreeMap<String, String>>,
+    /// Less common keys (`sockets`, `nice`, `macos_legacy_timers`, ...) kept verbatim.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Per-platform overrides from `variations`, keyed by bottle tag (e.g. `x86_64_linux`).
+/// Only keys present for that platform are set.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct FormulaVariation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dependencies: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_dependencies: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_dependencies: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recommended_dependencies: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optional_dependencies: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uses_from_macos: Option<Vec<UsesFromMacos>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uses_from_macos_bounds: Option<Vec<MacOSBound>>,
+    /// Any other overridden keys, kept verbatim.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
 // --- Main Formula Struct ---
-// *** Added 'resources' field ***
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+// Serialization goes through `RawFormulaData`, so a `Formula` serializes back into the
+// Homebrew API shape and deserializes into an identical value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Formula {
     pub name: String,
     pub stable_version_str: String,
-    #[serde(rename = "versions")]
-    pub version_semver: Version,
-    #[serde(default)]
+    pub version: Version,
+    pub head_version_str: Option<String>,
     pub revision: u32,
-    #[serde(default)]
+    pub version_scheme: u32,
     pub desc: Option<String>,
-    #[serde(default)]
     pub homepage: Option<String>,
-    #[serde(default)]
+    pub license: Option<String>,
     pub url: String,
-    #[serde(default)]
     pub sha256: String,
-    #[serde(default)]
+    pub urls: FormulaUrls,
     pub mirrors: Vec<String>,
-    #[serde(default)]
     pub bottle: BottleSpec,
-    #[serde(skip_deserializing)]
     pub dependencies: Vec<Dependency>,
-    #[serde(default, deserialize_with = "deserialize_requirements")]
     pub requirements: Vec<Requirement>,
-    #[serde(skip_deserializing)] // Skip direct deserialization for this field
     pub resources: Vec<ResourceSpec>, // Stores parsed resources
-    #[serde(skip)]
+    pub aliases: Vec<String>,
+    pub oldnames: Vec<String>,
+    pub keg_only: bool,
+    pub keg_only_reason: Option<KegOnlyReason>,
+    pub caveats: Option<String>,
+    pub options: Vec<FormulaOption>,
+    pub conflicts_with: Vec<String>,
+    pub conflicts_with_reasons: Vec<Option<String>>,
+    pub uses_from_macos: Vec<UsesFromMacos>,
+    pub uses_from_macos_bounds: Vec<MacOSBound>,
+    pub link_overwrite: Vec<String>,
+    pub deprecated: bool,
+    pub deprecation_date: Option<String>,
+    pub deprecation_reason: Option<String>,
+    pub deprecation_replacement_formula: Option<String>,
+    pub deprecation_replacement_cask: Option<String>,
+    pub disabled: bool,
+    pub disable_date: Option<String>,
+    pub disable_reason: Option<String>,
+    pub disable_replacement_formula: Option<String>,
+    pub disable_replacement_cask: Option<String>,
+    pub post_install_defined: bool,
+    pub service: Option<ServiceSpec>,
+    pub variations: BTreeMap<String, FormulaVariation>,
     pub install_keg_path: Option<PathBuf>,
 }
 
+/// The formula JSON as the API serves it, before it is turned into a [`Formula`].
+#[derive(Serialize, Deserialize, Debug)]
+struct RawFormulaData {
+    name: String,
+    #[serde(default)]
+    revision: u32,
+    #[serde(default)]
+    version_scheme: u32,
+    desc: Option<String>,
+    homepage: Option<String>,
+    #[serde(default)]
+    license: Option<String>,
+    versions: FormulaVersions,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    sha256: String,
+    #[serde(default)]
+    mirrors: Vec<String>,
+    #[serde(default)]
+    bottle: BottleSpec,
+    #[serde(default)]
+    dependencies: Vec<String>,
+    #[serde(default)]
+    build_dependencies: Vec<String>,
+    #[serde(default)]
+    test_dependencies: Vec<String>,
+    #[serde(default)]
+    recommended_dependencies: Vec<String>,
+    #[serde(default)]
+    optional_dependencies: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_requirements")]
+    requirements: Vec<Requirement>,
+    #[serde(default)]
+    resources: Vec<Value>, // Capture resources as generic Value first
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    urls: FormulaUrls,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    aliases: Vec<String>,
+    #[serde(default)]
+    oldname: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    oldnames: Vec<String>,
+    #[serde(default)]
+    keg_only: bool,
+    #[serde(default)]
+    keg_only_reason: Option<KegOnlyReason>,
+    #[serde(default)]
+    caveats: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    options: Vec<FormulaOption>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    conflicts_with: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    conflicts_with_reasons: Vec<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    uses_from_macos: Vec<UsesFromMacos>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    uses_from_macos_bounds: Vec<MacOSBound>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    link_overwrite: Vec<String>,
+    #[serde(default)]
+    deprecated: bool,
+    #[serde(default)]
+    deprecation_date: Option<String>,
+    #[serde(default)]
+    deprecation_reason: Option<String>,
+    #[serde(default)]
+    deprecation_replacement_formula: Option<String>,
+    #[serde(default)]
+    deprecation_replacement_cask: Option<String>,
+    #[serde(default)]
+    disabled: bool,
+    #[serde(default)]
+    disable_date: Option<String>,
+    #[serde(default)]
+    disable_reason: Option<String>,
+    #[serde(default)]
+    disable_replacement_formula: Option<String>,
+    #[serde(default)]
+    disable_replacement_cask: Option<String>,
+    #[serde(default)]
+    post_install_defined: bool,
+    #[serde(default)]
+    service: Option<ServiceSpec>,
+    #[serde(default, deserialize_with = "deserialize_null_default")]
+    variations: BTreeMap<String, FormulaVariation>,
+}
+
+impl From<&Formula> for RawFormulaData {
+    fn from(formula: &Formula) -> Self {
+        // Split the combined tag set back into the per-kind lists the API uses.
+        let mut dependencies = Vec::new();
+        let mut build_dependencies = Vec::new();
+        let mut test_dependencies = Vec::new();
+        let mut recommended_dependencies = Vec::new();
+        let mut optional_dependencies = Vec::new();
+        for dep in &formula.dependencies {
+            let tags = dep.tags;
+            if tags.contains(DependencyTag::RECOMMENDED) {
+                recommended_dependencies.push(dep.name.clone());
+            } else if tags.contains(DependencyTag::OPTIONAL) {
+                optional_dependencies.push(dep.name.clone());
+            } else if tags.contains(DependencyTag::RUNTIME) {
+                dependencies.push(dep.name.clone());
+            }
+            if tags.contains(DependencyTag::BUILD) {
+                build_dependencies.push(dep.name.clone());
+            }
+            if tags.contains(DependencyTag::TEST) {
+                test_dependencies.push(dep.name.clone());
+            }
+        }
+
+        let resources = formula
+            .resources
+            .iter()
+            .map(|res| {
+                let mut entry = serde_json::Map::new();
+                entry.insert(
+                    res.name.clone(),
+                    serde_json::to_value(res).unwrap_or(Value::Null),
+                );
+                Value::Object(entry)
+            })
+            .collect();
+
+        Self {
+            name: formula.name.clone(),
+            revision: formula.revision,
+            version_scheme: formula.version_scheme,
+            desc: formula.desc.clone(),
+            homepage: formula.homepage.clone(),
+            license: formula.license.clone(),
+            versions: FormulaVersions {
+                stable: Some(formula.stable_version_str.clone()),
+                head: formula.head_version_str.clone(),
+                bottle: formula.bottle.stable.is_some(),
+            },
+            url: formula.url.clone(),
+            sha256: formula.sha256.clone(),
+            mirrors: formula.mirrors.clone(),
+            bottle: formula.bottle.clone(),
+            dependencies,
+            build_dependencies,
+            test_dependencies,
+            recommended_dependencies,
+            optional_dependencies,
+            requirements: formula.requirements.clone(),
+            resources,
+            urls: formula.urls.clone(),
+            aliases: formula.aliases.clone(),
+            oldname: formula.oldnames.first().cloned(),
+            oldnames: formula.oldnames.clone(),
+            keg_only: formula.keg_only,
+            keg_only_reason: formula.keg_only_reason.clone(),
+            caveats: formula.caveats.clone(),
+            options: formula.options.clone(),
+            conflicts_with: formula.conflicts_with.clone(),
+            conflicts_with_reasons: formula.conflicts_with_reasons.clone(),
+            uses_from_macos: formula.uses_from_macos.clone(),
+            uses_from_macos_bounds: formula.uses_from_macos_bounds.clone(),
+            link_overwrite: formula.link_overwrite.clone(),
+            deprecated: formula.deprecated,
+            deprecation_date: formula.deprecation_date.clone(),
+            deprecation_reason: formula.deprecation_reason.clone(),
+            deprecation_replacement_formula: formula.deprecation_replacement_formula.clone(),
+            deprecation_replacement_cask: formula.deprecation_replacement_cask.clone(),
+            disabled: formula.disabled,
+            disable_date: formula.disable_date.clone(),
+            disable_reason: formula.disable_reason.clone(),
+            disable_replacement_formula: formula.disable_replacement_formula.clone(),
+            disable_replacement_cask: formula.disable_replacement_cask.clone(),
+            post_install_defined: formula.post_install_defined,
+            service: formula.service.clone(),
+            variations: formula.variations.clone(),
+        }
+    }
+}
+
+impl Serialize for Formula {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        RawFormulaData::from(self).serialize(serializer)
+    }
+}
+
 // Custom deserialization logic for Formula
 impl<'de> Deserialize<'de> for Formula {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        // Temporary struct reflecting the JSON structure more closely
-        // *** Added 'resources' field to capture raw JSON Value ***
-        #[derive(Deserialize, Debug)]
-        struct RawFormulaData {
-            name: String,
-            #[serde(default)]
-            revision: u32,
-            desc: Option<String>,
-            homepage: Option<String>,
-            versions: FormulaVersions,
-            #[serde(default)]
-            url: String,
-            #[serde(default)]
-            sha256: String,
-            #[serde(default)]
-            mirrors: Vec<String>,
-            #[serde(default)]
-            bottle: BottleSpec,
-            #[serde(default)]
-            dependencies: Vec<String>,
-            #[serde(default)]
-            build_dependencies: Vec<String>,
-            #[serde(default)]
-            test_dependencies: Vec<String>,
-            #[serde(default)]
-            recommended_dependencies: Vec<String>,
-            #[serde(default)]
-            optional_dependencies: Vec<String>,
-            #[serde(default, deserialize_with = "deserialize_requirements")]
-            requirements: Vec<Requirement>,
-            #[serde(default)]
-            resources: Vec<Value>, // Capture resources as generic Value first
-            #[serde(default)]
-            urls: Option<Value>,
-        }
-
         let raw: RawFormulaData = RawFormulaData::deserialize(deserializer)?;
 
         // --- Version Parsing (Original logic) ---
@@ -133,79 +493,22 @@ impl<'de> Deserialize<'de> for Formula {
             .stable
             .clone()
             .ok_or_else(|| de::Error::missing_field("versions.stable"))?;
-        let version_semver = match crate::model::version::Version::parse(&stable_version_str) {
-            Ok(v) => v.into(),
-            Err(_) => {
-                let mut majors = 0u32;
-                let mut minors = 0u32;
-                let mut patches = 0u32;
-                let mut part_count = 0;
-                for (i, part) in stable_version_str.split('.').enumerate() {
-                    let numeric_part = part
-                        .chars()
-                        .take_while(|c| c.is_ascii_digit())
-                        .collect::<String>();
-                    if numeric_part.is_empty() && i > 0 {
-                        break;
-                    }
-                    if numeric_part.len() < part.len() && i > 0 {
-                        if let Ok(num) = numeric_part.parse::<u32>() {
-                            match i {
-                                0 => majors = num,
-                                1 => minors = num,
-                                2 => patches = num,
-                                _ => {}
-                            }
-                            part_count += 1;
-                        }
-                        break;
-                    }
-                    if let Ok(num) = numeric_part.parse::<u32>() {
-                        match i {
-                            0 => majors = num,
-                            1 => minors = num,
-                            2 => patches = num,
-                            _ => {}
-                        }
-                        part_count += 1;
-                    }
-                    if i >= 2 {
-                        break;
-                    }
-                }
-                let version_str_padded = match part_count {
-                    1 => format!("{majors}.0.0"),
-                    2 => format!("{majors}.{minors}.0"),
-                    _ => format!("{majors}.{minors}.{patches}"),
-                };
-                match Version::parse(&version_str_padded) {
-                    Ok(v) => v,
-                    Err(_) => {
-                        error!(
-                            "Warning: Could not parse version '{}' (sanitized to '{}') for formula '{}'. Using 0.0.0.",
-                            stable_version_str, version_str_padded, raw.name
-                        );
-                        Version::new(0, 0, 0)
-                    }
-                }
-            }
-        };
+        let version = Version::parse(&stable_version_str).unwrap_or_else(|_| {
+            error!(
+                "Warning: Could not parse version '{}' for formula '{}'. Using 0.",
+                stable_version_str, raw.name
+            );
+            Version::default()
+        });
 
         // --- URL/SHA256 Logic (Original logic) ---
         let mut final_url = raw.url;
         let mut final_sha256 = raw.sha256;
         if final_url.is_empty() {
-            if let Some(Value::Object(urls_map)) = raw.urls {
-                if let Some(Value::Object(stable_url_info)) = urls_map.get("stable") {
-                    if let Some(Value::String(u)) = stable_url_info.get("url") {
-                        final_url = u.clone();
-                    }
-                    if let Some(Value::String(s)) = stable_url_info
-                        .get("checksum")
-                        .or_else(|| stable_url_info.get("sha256"))
-                    {
-                        final_sha256 = s.clone();
-                    }
+            if let Some(stable_url_info) = &raw.urls.stable {
+                final_url = stable_url_info.url.clone();
+                if let Some(checksum) = &stable_url_info.checksum {
+                    final_sha256 = checksum.clone();
                 }
             }
         }
@@ -214,13 +517,18 @@ impl<'de> Deserialize<'de> for Formula {
         }
 
         // --- Dependency Processing (Original logic) ---
+        // Keep first-seen order so the result is deterministic.
         let mut combined_dependencies: Vec<Dependency> = Vec::new();
-        let mut seen_deps: HashMap<String, DependencyTag> = HashMap::new();
+        let mut seen_deps: HashMap<String, usize> = HashMap::new();
         let mut process_list = |deps: &[String], tag: DependencyTag| {
             for name in deps {
-                *seen_deps
-                    .entry(name.clone())
-                    .or_insert(DependencyTag::empty()) |= tag;
+                match seen_deps.get(name) {
+                    Some(&idx) => combined_dependencies[idx].tags |= tag,
+                    None => {
+                        seen_deps.insert(name.clone(), combined_dependencies.len());
+                        combined_dependencies.push(Dependency::new_with_tags(name.clone(), tag));
+                    }
+                }
             }
         };
         process_list(&raw.dependencies, DependencyTag::RUNTIME);
@@ -234,12 +542,8 @@ impl<'de> Deserialize<'de> for Formula {
             &raw.optional_dependencies,
             DependencyTag::OPTIONAL | DependencyTag::RUNTIME,
         );
-        for (name, tags) in seen_deps {
-            combined_dependencies.push(Dependency::new_with_tags(name, tags));
-        }
 
         // --- Resource Processing ---
-        // *** Added parsing logic for the 'resources' field ***
         let mut combined_resources: Vec<ResourceSpec> = Vec::new();
         for res_val in raw.resources {
             // Homebrew API JSON format puts resource spec inside a keyed object
@@ -294,20 +598,56 @@ impl<'de> Deserialize<'de> for Formula {
             }
         }
 
+        // --- Legacy single `oldname` folds into `oldnames` ---
+        let mut oldnames = raw.oldnames;
+        if let Some(oldname) = raw.oldname {
+            if !oldnames.contains(&oldname) {
+                oldnames.insert(0, oldname);
+            }
+        }
+
         Ok(Self {
             name: raw.name,
             stable_version_str,
-            version_semver,
+            version,
+            head_version_str: raw.versions.head,
             revision: raw.revision,
+            version_scheme: raw.version_scheme,
             desc: raw.desc,
             homepage: raw.homepage,
+            license: raw.license,
             url: final_url,
             sha256: final_sha256,
+            urls: raw.urls,
             mirrors: raw.mirrors,
             bottle: raw.bottle,
             dependencies: combined_dependencies,
             requirements: raw.requirements,
             resources: combined_resources, // Assign parsed resources
+            aliases: raw.aliases,
+            oldnames,
+            keg_only: raw.keg_only,
+            keg_only_reason: raw.keg_only_reason,
+            caveats: raw.caveats,
+            options: raw.options,
+            conflicts_with: raw.conflicts_with,
+            conflicts_with_reasons: raw.conflicts_with_reasons,
+            uses_from_macos: raw.uses_from_macos,
+            uses_from_macos_bounds: raw.uses_from_macos_bounds,
+            link_overwrite: raw.link_overwrite,
+            deprecated: raw.deprecated,
+            deprecation_date: raw.deprecation_date,
+            deprecation_reason: raw.deprecation_reason,
+            deprecation_replacement_formula: raw.deprecation_replacement_formula,
+            deprecation_replacement_cask: raw.deprecation_replacement_cask,
+            disabled: raw.disabled,
+            disable_date: raw.disable_date,
+            disable_reason: raw.disable_reason,
+            disable_replacement_formula: raw.disable_replacement_formula,
+            disable_replacement_cask: raw.disable_replacement_cask,
+            post_install_defined: raw.post_install_defined,
+            service: raw.service,
+            variations: raw.variations,
             install_keg_path: None,
         })
     }
@@ -323,7 +663,7 @@ impl Formula {
         Ok(self.requirements.clone())
     }
 
-    // *** Added: Returns a clone of the defined resources. ***
+    /// The formula's additional resources.
     pub fn resources(&self) -> Result<Vec<ResourceSpec>> {
         Ok(self.resources.clone())
     }
@@ -339,11 +679,15 @@ impl Formula {
             self.stable_version_str.clone()
         }
     }
+    /// The available version as a comparable [`PkgVersion`] (version, revision and scheme).
+    pub fn pkg_version(&self) -> PkgVersion {
+        PkgVersion::new(self.version.clone(), self.revision, self.version_scheme)
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
     pub fn version(&self) -> &Version {
-        &self.version_semver
+        &self.version
     }
     pub fn source_url(&self) -> &str {
         &self.url
@@ -354,6 +698,31 @@ impl Formula {
     pub fn get_bottle_spec(&self, bottle_tag: &str) -> Option<&BottleFileSpec> {
         self.bottle.stable.as_ref()?.files.get(bottle_tag)
     }
+    pub fn is_keg_only(&self) -> bool {
+        self.keg_only
+    }
+    pub fn caveats(&self) -> Option<&str> {
+        self.caveats.as_deref().filter(|c| !c.trim().is_empty())
+    }
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+    pub fn head_url(&self) -> Option<&UrlSpec> {
+        self.urls.head.as_ref()
+    }
+    /// True if `name` is this formula's name, one of its aliases or a former name.
+    pub fn answers_to(&self, name: &str) -> bool {
+        self.name == name
+            || self.aliases.iter().any(|a| a == name)
+            || self.oldnames.iter().any(|o| o == name)
+    }
+    /// Platform-specific overrides for the given bottle tag, if any.
+    pub fn variation_for(&self, platform_tag: &str) -> Option<&FormulaVariation> {
+        self.variations.get(platform_tag)
+    }
 }
 
 // --- BuildEnvironment Dependency Interface (Unchanged) ---
@@ -405,7 +774,10 @@ where
     let raw_reqs: Vec<Value> = Deserialize::deserialize(deserializer)?;
     let mut requirements = Vec::new();
     for req_val in raw_reqs {
-        if let Ok(req_obj) = serde_json::from_value::<ReqWrapper>(req_val.clone()) {
+        // Already in sps' own form, e.g. `{"MacOS": "13"}` from a serialized Formula.
+        if let Ok(req) = serde_json::from_value::<Requirement>(req_val.clone()) {
+            requirements.push(req);
+        } else if let Ok(req_obj) = serde_json::from_value::<ReqWrapper>(req_val.clone()) {
             match req_obj.name.as_str() {
                 "macos" => {
                     requirements.push(Requirement::MacOS(
@@ -451,6 +823,15 @@ where
     Ok(requirements)
 }
 
+// The API emits `null` for several list/object fields; treat that like a missing key.
+fn deserialize_null_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
 // Manual impl Deserialize for ResourceSpec (unchanged, this is needed)
 impl<'de> Deserialize<'de> for ResourceSpec {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
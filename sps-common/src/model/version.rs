@@ -1,4 +1,11 @@
-// **File:** sps-core/src/model/version.rs (New file)
+// sps-common/src/model/version.rs
+//! Homebrew-compatible version ordering.
+//!
+//! Versions are split into tokens (numbers, words and pre/post-release markers such as
+//! `alpha1`, `rc2` or `p3`) and compared token by token, the same way Homebrew's
+//! `Version` does. This handles `1.2.3.4`, `2024a`, `9e` or `r123`, none of which fit semver.
+//! [`PkgVersion`] adds the formula revision (`1.0.0_1`) and `version_scheme` on top.
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 
@@ -6,33 +13,228 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::error::{Result, SpsError};
 
-/// Wrapper around semver::Version for formula versions.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Version(semver::Version);
+/// One comparable piece of a version string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionToken {
+    /// A run of digits. Leading zeros are stripped so values of any length compare correctly.
+    Numeric(String),
+    /// A word that is not a recognised release marker (e.g. the `a` in `2024a`).
+    Text(String),
+    Alpha(u64),
+    Beta(u64),
+    Pre(u64),
+    Rc(u64),
+    Patch(u64),
+    Post(u64),
+}
+
+impl VersionToken {
+    // Relative rank of release markers. Everything below zero sorts before a missing
+    // token (pre-releases), everything above after it.
+    fn marker_rank(&self) -> Option<i8> {
+        match self {
+            VersionToken::Alpha(_) => Some(-4),
+            VersionToken::Beta(_) => Some(-3),
+            VersionToken::Pre(_) => Some(-2),
+            VersionToken::Rc(_) => Some(-1),
+            VersionToken::Patch(_) => Some(1),
+            VersionToken::Post(_) => Some(2),
+            VersionToken::Numeric(_) | VersionToken::Text(_) => None,
+        }
+    }
+
+    fn marker_value(&self) -> u64 {
+        match self {
+            VersionToken::Alpha(n)
+            | VersionToken::Beta(n)
+            | VersionToken::Pre(n)
+            | VersionToken::Rc(n)
+            | VersionToken::Patch(n)
+            | VersionToken::Post(n) => *n,
+            VersionToken::Numeric(_) | VersionToken::Text(_) => 0,
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            VersionToken::Numeric(s) | VersionToken::Text(s) => s.clone(),
+            VersionToken::Alpha(n) => format!("alpha{n}"),
+            VersionToken::Beta(n) => format!("beta{n}"),
+            VersionToken::Pre(n) => format!("pre{n}"),
+            VersionToken::Rc(n) => format!("rc{n}"),
+            VersionToken::Patch(n) => format!("p{n}"),
+            VersionToken::Post(n) => format!("post{n}"),
+        }
+    }
+
+    /// Compares two tokens, `None` standing in for a token missing from the shorter version.
+    fn compare(a: Option<&Self>, b: Option<&Self>) -> Ordering {
+        match (a, b) {
+            (None, None) => Ordering::Equal,
+            (None, Some(other)) => Self::compare(Some(other), None).reverse(),
+            (Some(VersionToken::Numeric(n)), None) => {
+                // `1.0` == `1.0.0`, but `1.0` < `1.0.1`.
+                if n == "0" {
+                    Ordering::Equal
+                } else {
+                    Ordering::Greater
+                }
+            }
+            (Some(VersionToken::Text(_)), None) => Ordering::Greater,
+            (Some(marker), None) => {
+                if marker.marker_rank().unwrap_or(0) < 0 {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            (Some(VersionToken::Numeric(x)), Some(VersionToken::Numeric(y))) => {
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            (Some(VersionToken::Numeric(_)), Some(_)) => Ordering::Greater,
+            (Some(_), Some(VersionToken::Numeric(_))) => Ordering::Less,
+            (Some(x), Some(y)) => match (x.marker_rank(), y.marker_rank()) {
+                (Some(rx), Some(ry)) if rx == ry => x.marker_value().cmp(&y.marker_value()),
+                (Some(rx), Some(ry)) => rx.cmp(&ry),
+                _ => x.text().cmp(&y.text()),
+            },
+        }
+    }
+}
+
+/// Splits a version string into tokens. Any non-alphanumeric character is a separator.
+fn tokenize(s: &str) -> Vec<VersionToken> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            let trimmed = digits.trim_start_matches('0');
+            let value = if trimmed.is_empty() { "0" } else { trimmed };
+            tokens.push(VersionToken::Numeric(value.to_string()));
+        } else if c.is_alphabetic() {
+            let start = i;
+            while i < chars.len() && chars[i].is_alphabetic() {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+            let digit_start = i;
+            let mut digit_end = i;
+            while digit_end < chars.len() && chars[digit_end].is_ascii_digit() {
+                digit_end += 1;
+            }
+            let has_digits = digit_end > digit_start;
+            let n = if has_digits {
+                chars[digit_start..digit_end]
+                    .iter()
+                    .collect::<String>()
+                    .parse()
+                    .unwrap_or(u64::MAX)
+            } else {
+                0
+            };
+            // Single-letter markers (`a1`, `b2`) only count when followed by a number,
+            // otherwise `2024a` would become a pre-release.
+            let marker = match word.as_str() {
+                "alpha" => Some(VersionToken::Alpha(n)),
+                "a" if has_digits => Some(VersionToken::Alpha(n)),
+                "beta" => Some(VersionToken::Beta(n)),
+                "b" if has_digits => Some(VersionToken::Beta(n)),
+                "pre" => Some(VersionToken::Pre(n)),
+                "rc" => Some(VersionToken::Rc(n)),
+                "p" | "patch" | "pl" => Some(VersionToken::Patch(n)),
+                "post" => Some(VersionToken::Post(n)),
+                _ => None,
+            };
+            match marker {
+                Some(token) => {
+                    tokens.push(token);
+                    i = digit_end;
+                }
+                None => tokens.push(VersionToken::Text(word)),
+            }
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// A formula or cask version, compared the way Homebrew compares versions.
+/// The original string is kept for display.
+#[derive(Debug, Clone)]
+pub struct Version {
+    raw: String,
+    tokens: Vec<VersionToken>,
+}
 
 impl Version {
     pub fn parse(s: &str) -> Result<Self> {
-        // Attempt standard semver parse first
-        semver::Version::parse(s).map(Version).or_else(|_| {
-            // Homebrew often uses versions like "1.2.3_1" (revision) or just "123"
-            // Try to handle these by stripping suffixes or padding
-            // This is a simplified handling, Homebrew's PkgVersion is complex
-            let cleaned = s.split('_').next().unwrap_or(s); // Take part before _
-            let parts: Vec<&str> = cleaned.split('.').collect();
-            let padded = match parts.len() {
-                1 => format!("{}.0.0", parts[0]),
-                2 => format!("{}.{}.0", parts[0], parts[1]),
-                _ => cleaned.to_string(), // Use original if 3+ parts
-            };
-            semver::Version::parse(&padded).map(Version).map_err(|e| {
-                SpsError::VersionError(format!(
-                    "Failed to parse version '{s}' (tried '{padded}'): {e}"
-                ))
-            })
+        let raw = s.trim();
+        let tokens = tokenize(raw);
+        if tokens.is_empty() {
+            return Err(SpsError::VersionError(format!(
+                "Failed to parse version '{s}': no version components"
+            )));
+        }
+        Ok(Self {
+            raw: raw.to_string(),
+            tokens,
         })
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn tokens(&self) -> &[VersionToken] {
+        &self.tokens
+    }
+}
+
+impl Default for Version {
+    /// Version `0`, the lowest version there is.
+    fn default() -> Self {
+        Self {
+            raw: "0".to_string(),
+            tokens: vec![VersionToken::Numeric("0".to_string())],
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.tokens.len().max(other.tokens.len());
+        for i in 0..len {
+            let ord = VersionToken::compare(self.tokens.get(i), other.tokens.get(i));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
 impl FromStr for Version {
     type Err = SpsError;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
@@ -42,9 +244,7 @@ impl FromStr for Version {
 
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: Preserve original format if possible? PkgVersion complexity.
-        // For now, display the parsed semver representation.
-        write!(f, "{}", self.0)
+        f.write_str(&self.raw)
     }
 }
 
@@ -54,7 +254,17 @@ impl Serialize for Version {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&self.to_string())
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
 
@@ -64,15 +274,91 @@ impl AsRef<Self> for Version {
     }
 }
 
-// Removed redundant ToString implementation as it conflicts with the blanket implementation in std.
+/// A version plus formula revision and `version_scheme`, e.g. the keg name `1.0.0_1`.
+///
+/// Ordering follows Homebrew: a higher `version_scheme` always wins, then the version,
+/// then the revision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkgVersion {
+    pub version: Version,
+    pub revision: u32,
+    pub version_scheme: u32,
+}
+
+impl PkgVersion {
+    pub fn new(version: Version, revision: u32, version_scheme: u32) -> Self {
+        Self {
+            version,
+            revision,
+            version_scheme,
+        }
+    }
+
+    /// Parses a keg-style version string. A trailing `_<digits>` is taken as the revision.
+    pub fn parse(s: &str) -> Result<Self> {
+        Self::parse_with_scheme(s, 0)
+    }
+
+    pub fn parse_with_scheme(s: &str, version_scheme: u32) -> Result<Self> {
+        let s = s.trim();
+        let (version_part, revision) = match s.rsplit_once('_') {
+            Some((v, rev))
+                if !v.is_empty() && !rev.is_empty() && rev.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                (v, rev.parse::<u32>().unwrap_or(0))
+            }
+            _ => (s, 0),
+        };
+        Ok(Self::new(
+            Version::parse(version_part)?,
+            revision,
+            version_scheme,
+        ))
+    }
+}
+
+impl Ord for PkgVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.version_scheme
+            .cmp(&other.version_scheme)
+            .then_with(|| self.version.cmp(&other.version))
+            .then_with(|| self.revision.cmp(&other.revision))
+    }
+}
 
-impl From<Version> for semver::Version {
-    fn from(version: Version) -> Self {
-        version.0
+impl PartialOrd for PkgVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl<'de> Deserialize<'de> for Version {
+impl FromStr for PkgVersion {
+    type Err = SpsError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for PkgVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.revision > 0 {
+            write!(f, "{}_{}", self.version, self.revision)
+        } else {
+            write!(f, "{}", self.version)
+        }
+    }
+}
+
+impl Serialize for PkgVersion {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PkgVersion {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
@@ -81,11 +367,3 @@ impl<'de> Deserialize<'de> for Version {
         Self::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
-
-// Add to sps-core/src/utils/error.rs:
-// #[error("Version error: {0}")]
-// VersionError(String),
-
-// Add to sps-core/Cargo.toml:
-// [dependencies]
-// semver = "1.0"
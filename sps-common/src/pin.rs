@@ -0,0 +1,68 @@
+// sps-common/src/pin.rs
+//! Packages held at their installed version.
+//!
+//! Pins are stored as a sorted JSON array of names in `Config::state_dir()/pinned.json`. A
+//! pinned formula or cask is left alone by `upgrade --all` and by upgrades of its dependents,
+//! and an explicit upgrade of it fails until it is unpinned.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use tracing::debug;
+
+use crate::config::Config;
+use crate::error::{Result, SpsError};
+
+const PINS_FILE_NAME: &str = "pinned.json";
+
+#[derive(Debug, Clone, Default)]
+pub struct PinnedPackages {
+    names: BTreeSet<String>,
+}
+
+impl PinnedPackages {
+    /// Reads the pins; no file means nothing is pinned.
+    pub fn load(config: &Config) -> Result<Self> {
+        let path = pins_path(config);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let names = serde_json::from_str(&content)
+            .map_err(|e| SpsError::Config(format!("Invalid pin list {}: {e}", path.display())))?;
+        Ok(Self { names })
+    }
+
+    pub fn save(&self, config: &Config) -> Result<()> {
+        let path = pins_path(config);
+        fs::create_dir_all(config.state_dir())?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.names)?)?;
+        fs::rename(&tmp_path, &path)?;
+        debug!("Saved {} pins to {}", self.names.len(), path.display());
+        Ok(())
+    }
+
+    pub fn is_pinned(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    /// Returns false if `name` was already pinned.
+    pub fn pin(&mut self, name: &str) -> bool {
+        self.names.insert(name.to_string())
+    }
+
+    /// Returns false if `name` wasn't pinned.
+    pub fn unpin(&mut self, name: &str) -> bool {
+        self.names.remove(name)
+    }
+}
+
+fn pins_path(config: &Config) -> PathBuf {
+    config.state_dir().join(PINS_FILE_NAME)
+}
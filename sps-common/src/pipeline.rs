@@ -36,6 +36,8 @@ pub struct PlannedJob {
     pub action: JobAction,
     pub is_source_build: bool,
     pub use_private_store_source: Option<PathBuf>,
+    /// False for packages that are only installed because something else depends on them.
+    pub installed_on_request: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -192,5 +194,8 @@ pub struct PlannedOperations {
     pub jobs: Vec<PlannedJob>,           // Topologically sorted for formulae
     pub errors: Vec<(String, SpsError)>, // Errors from planning phase
     pub already_installed_or_up_to_date: std::collections::HashSet<String>,
+    /// Kegs of already installed formulae the user asked for, to be marked as installed on
+    /// request once the pipeline has run.
+    pub requested_kegs: Vec<PathBuf>,
     pub resolved_graph: Option<Arc<ResolvedGraph>>, // Graph for dependency checking in runner
 }
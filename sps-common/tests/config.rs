@@ -0,0 +1,270 @@
+//! Loading layered configuration from config files in a temporary directory and from the
+//! environment. The environment is shared by the whole test binary, so every test holds
+//! [`ENV_LOCK`] while it changes it.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::{env, fs};
+
+use sps_common::config::{
+    user_config_path, write_config_value, Config, ConfigOrigin, UrlRewrite, CONFIG_KEYS,
+};
+use sps_common::error::SpsError;
+use tempfile::TempDir;
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// A system file, a user file under `$XDG_CONFIG_HOME` and a clean environment, restored when
+/// dropped.
+struct Layers {
+    dir: TempDir,
+    saved: Vec<(&'static str, Option<OsString>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Layers {
+    fn new() -> Self {
+        let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = TempDir::new().unwrap();
+        let vars = CONFIG_KEYS
+            .iter()
+            .flat_map(|def| def.env.iter().copied())
+            .chain(["XDG_CONFIG_HOME"]);
+        let saved = vars.map(|var| (var, env::var_os(var))).collect();
+        let layers = Self {
+            dir,
+            saved,
+            _lock: lock,
+        };
+        for (var, _) in &layers.saved {
+            env::remove_var(var);
+        }
+        env::set_var("XDG_CONFIG_HOME", layers.dir.path().join("xdg"));
+        layers
+    }
+
+    fn system_path(&self) -> PathBuf {
+        self.dir.path().join("etc/sps/config.toml")
+    }
+
+    fn user_path(&self) -> PathBuf {
+        self.dir.path().join("xdg/sps/config.toml")
+    }
+
+    fn write(path: PathBuf, toml: &str) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, toml).unwrap();
+        path
+    }
+
+    fn system(&self, toml: &str) -> PathBuf {
+        Self::write(self.system_path(), toml)
+    }
+
+    fn user(&self, toml: &str) -> PathBuf {
+        Self::write(self.user_path(), toml)
+    }
+
+    fn env(&self, var: &str, value: &str) {
+        env::set_var(var, value);
+    }
+
+    fn load(&self) -> Result<Config, SpsError> {
+        Config::load_from(&self.system_path())
+    }
+}
+
+impl Drop for Layers {
+    fn drop(&mut self) {
+        for (var, value) in &self.saved {
+            match value {
+                Some(value) => env::set_var(var, value),
+                None => env::remove_var(var),
+            }
+        }
+    }
+}
+
+fn assert_config_error(result: Result<Config, SpsError>, needle: &str) {
+    match result {
+        Err(SpsError::Config(msg)) => assert!(msg.contains(needle), "{msg}"),
+        other => panic!("expected a config error mentioning {needle}, got {other:?}"),
+    }
+}
+
+#[test]
+fn user_file_lives_under_xdg_config_home() {
+    let layers = Layers::new();
+    assert_eq!(user_config_path(), Some(layers.user_path()));
+
+    // A relative XDG_CONFIG_HOME is ignored, as the spec asks.
+    layers.env("XDG_CONFIG_HOME", "relative/dir");
+    assert_ne!(
+        user_config_path(),
+        Some(PathBuf::from("relative/dir/sps/config.toml"))
+    );
+}
+
+#[test]
+fn later_layers_win_and_record_where_values_came_from() {
+    let layers = Layers::new();
+    let system = layers
+        .system("download_retries = 1\ndownload_timeout_secs = 10\nconnect_timeout_secs = 5\n");
+    let user = layers.user("download_retries = 2\ndownload_timeout_secs = 20\n");
+    layers.env("SPS_DOWNLOAD_RETRIES", "3");
+
+    let config = layers.load().unwrap();
+    assert_eq!(config.download_retries, 3);
+    assert_eq!(config.download_timeout_secs, 20);
+    assert_eq!(config.connect_timeout_secs, 5);
+    assert_eq!(config.cache_ttl_secs, Config::defaults().cache_ttl_secs);
+
+    assert_eq!(
+        config.origin("download_retries"),
+        ConfigOrigin::Env("SPS_DOWNLOAD_RETRIES")
+    );
+    assert_eq!(
+        config.origin("download_timeout_secs"),
+        ConfigOrigin::File(user)
+    );
+    assert_eq!(
+        config.origin("connect_timeout_secs"),
+        ConfigOrigin::File(system)
+    );
+    assert_eq!(config.origin("cache_ttl_secs"), ConfigOrigin::Default);
+}
+
+#[test]
+fn missing_files_leave_the_defaults() {
+    let layers = Layers::new();
+    let config = layers.load().unwrap();
+    let defaults = Config::defaults();
+
+    assert_eq!(config.sps_root, defaults.sps_root);
+    assert_eq!(config.download_retries, defaults.download_retries);
+    assert!(CONFIG_KEYS
+        .iter()
+        .all(|def| config.origin(def.name) == ConfigOrigin::Default));
+}
+
+#[test]
+fn invalid_or_empty_environment_values_are_ignored() {
+    let layers = Layers::new();
+    let user = layers.user("download_retries = 2\noffline = true\n");
+    layers.env("SPS_DOWNLOAD_RETRIES", "many");
+    layers.env("SPS_OFFLINE", "");
+
+    let config = layers.load().unwrap();
+    assert_eq!(config.download_retries, 2);
+    assert_eq!(
+        config.origin("download_retries"),
+        ConfigOrigin::File(user.clone())
+    );
+    assert!(config.offline);
+    assert_eq!(config.origin("offline"), ConfigOrigin::File(user));
+}
+
+#[test]
+fn invalid_file_values_fail_and_name_the_file() {
+    let layers = Layers::new();
+    let user = layers.user("download_retries = \"many\"\n");
+    assert_config_error(layers.load(), &user.display().to_string());
+
+    layers.user("[download_retries]\nvalue = 2\n");
+    assert_config_error(layers.load(), "must be a string, integer, boolean or list");
+
+    layers.user("download_retries = ");
+    assert_config_error(layers.load(), "Failed to parse");
+
+    // Unknown keys are only warned about.
+    layers.user("no_such_setting = 1\ndownload_retries = 2\n");
+    assert_eq!(layers.load().unwrap().download_retries, 2);
+}
+
+#[test]
+fn toml_lists_are_joined_with_commas() {
+    let layers = Layers::new();
+    let user = layers.user(
+        r#"url_rewrites = ["https://ghcr.io/=https://mirror.example/ghcr/", "https://github.com/=file:///srv/github/"]"#,
+    );
+    layers.system("url_rewrites = \"https://unused/=https://unused/\"\n");
+
+    let config = layers.load().unwrap();
+    let rule = |prefix: &str, replacement: &str| UrlRewrite {
+        prefix: prefix.to_string(),
+        replacement: replacement.to_string(),
+    };
+    assert_eq!(
+        config.url_rewrites,
+        [
+            rule("https://ghcr.io/", "https://mirror.example/ghcr/"),
+            rule("https://github.com/", "file:///srv/github/"),
+        ]
+    );
+    assert_eq!(
+        config.get("url_rewrites").unwrap().as_deref(),
+        Some(
+            "https://ghcr.io/=https://mirror.example/ghcr/,https://github.com/=file:///srv/github/"
+        )
+    );
+    assert_eq!(config.origin("url_rewrites"), ConfigOrigin::File(user));
+
+    layers.user("url_rewrites = [1, 2]\n");
+    assert_config_error(layers.load(), "list of strings");
+}
+
+#[test]
+fn written_values_are_typed_and_load_back() {
+    let layers = Layers::new();
+    let user = layers.user_path();
+    write_config_value(&user, "download_retries", Some("7")).unwrap();
+    write_config_value(&user, "offline", Some("yes")).unwrap();
+    write_config_value(&user, "mirror", Some(" /srv/mirror ")).unwrap();
+
+    let table: toml::Table = fs::read_to_string(&user).unwrap().parse().unwrap();
+    assert_eq!(table["download_retries"], toml::Value::Integer(7));
+    assert_eq!(table["offline"], toml::Value::Boolean(true));
+    assert_eq!(
+        table["mirror"],
+        toml::Value::String("/srv/mirror".to_string())
+    );
+
+    let config = layers.load().unwrap();
+    assert_eq!(config.download_retries, 7);
+    assert!(config.offline);
+    assert_eq!(config.mirror.as_deref(), Some("/srv/mirror"));
+    assert_eq!(config.origin("mirror"), ConfigOrigin::File(user.clone()));
+
+    // Removing a key keeps the others.
+    write_config_value(&user, "offline", None).unwrap();
+    let config = layers.load().unwrap();
+    assert!(!config.offline);
+    assert_eq!(config.origin("offline"), ConfigOrigin::Default);
+    assert_eq!(config.download_retries, 7);
+}
+
+#[test]
+fn invalid_values_are_not_written() {
+    let layers = Layers::new();
+    let user = layers.user("download_retries = 2\n");
+    let before = fs::read_to_string(&user).unwrap();
+
+    let rejected = [
+        ("download_retries", "-1"),
+        ("offline", "maybe"),
+        ("prefix", " "),
+        ("url_rewrites", "no-equals-sign"),
+    ];
+    for (key, value) in rejected {
+        match write_config_value(&user, key, Some(value)) {
+            Err(SpsError::Config(_)) => {}
+            other => panic!("{key} = {value:?}: expected a config error, got {other:?}"),
+        }
+    }
+    match write_config_value(&user, "no_such_setting", Some("1")) {
+        Err(SpsError::Config(msg)) => assert!(msg.contains("Known settings"), "{msg}"),
+        other => panic!("expected a config error, got {other:?}"),
+    }
+    assert_eq!(fs::read_to_string(&user).unwrap(), before);
+}
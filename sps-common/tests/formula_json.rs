@@ -0,0 +1,170 @@
+//! Parsing formulae from the Homebrew API's `formula.json`, using two entries modelled on real ones
+//! (an option was added to `curl` so every field is covered).
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use sps_common::dependency::DependencyTag;
+use sps_common::model::formula::{
+    FormulaOption, KegOnlyReason, MacOSBound, ServiceKeepAlive, ServiceRun, TagList, UsesFromMacos,
+};
+use sps_common::Formula;
+
+const FORMULA_JSON: &str = include_str!("fixtures/formula.json");
+
+fn formula(name: &str) -> Formula {
+    let all: Vec<Formula> = serde_json::from_str(FORMULA_JSON).unwrap();
+    all.into_iter().find(|f| f.name == name).unwrap()
+}
+
+#[test]
+fn urls_and_versions() {
+    let curl = formula("curl");
+    assert_eq!(curl.stable_version_str, "8.11.1");
+    assert_eq!(curl.revision, 1);
+    assert_eq!(curl.version_str_full(), "8.11.1_1");
+    assert_eq!(curl.head_version_str.as_deref(), Some("HEAD"));
+
+    let stable = curl.urls.stable.as_ref().unwrap();
+    assert_eq!(stable.url, "https://curl.se/download/curl-8.11.1.tar.bz2");
+    assert_eq!(stable.tag, None);
+    // The legacy top-level url/sha256 are filled in from `urls.stable`.
+    assert_eq!(curl.source_url(), stable.url);
+    assert_eq!(Some(curl.source_sha256()), stable.checksum.as_deref());
+
+    let head = curl.head_url().unwrap();
+    assert_eq!(head.url, "https://github.com/curl/curl.git");
+    assert_eq!(head.branch.as_deref(), Some("master"));
+
+    assert!(curl.get_bottle_spec("x86_64_linux").is_some());
+    assert!(curl.get_bottle_spec("ventura").is_none());
+}
+
+#[test]
+fn keg_only_reason_and_options() {
+    let curl = formula("curl");
+    assert!(curl.is_keg_only());
+    let reason = curl.keg_only_reason.as_ref().unwrap();
+    assert_eq!(
+        reason,
+        &KegOnlyReason {
+            reason: ":provided_by_macos".to_string(),
+            explanation: String::new(),
+        }
+    );
+    assert!(reason
+        .describe()
+        .starts_with("macOS already provides this software"));
+    assert_eq!(
+        curl.options,
+        [FormulaOption {
+            option: "--with-gssapi".to_string(),
+            description: "Build with GSSAPI/Kerberos authentication support.".to_string(),
+        }]
+    );
+
+    let unbound = formula("unbound");
+    assert!(!unbound.is_keg_only());
+    assert_eq!(unbound.keg_only_reason, None);
+    assert!(unbound.options.is_empty());
+}
+
+#[test]
+fn dependencies_keep_their_tags() {
+    let curl = formula("curl");
+    let deps = curl.dependencies().unwrap();
+    let pkgconf = deps.iter().find(|d| d.name == "pkgconf").unwrap();
+    assert_eq!(pkgconf.tags, DependencyTag::BUILD);
+    let openssl = deps.iter().find(|d| d.name == "openssl@3").unwrap();
+    assert_eq!(openssl.tags, DependencyTag::RUNTIME);
+    // Declaration order is kept.
+    assert_eq!(deps[0].name, "brotli");
+}
+
+#[test]
+fn uses_from_macos_with_tags_and_bounds() {
+    let curl = formula("curl");
+    assert_eq!(
+        curl.uses_from_macos,
+        [
+            UsesFromMacos::WithTags(BTreeMap::from([(
+                "python".to_string(),
+                TagList::One("build".to_string()),
+            )])),
+            UsesFromMacos::Name("krb5".to_string()),
+            UsesFromMacos::Name("zlib".to_string()),
+        ]
+    );
+    assert_eq!(curl.uses_from_macos[0].name(), "python");
+    assert_eq!(curl.uses_from_macos[0].tags(), ["build"]);
+    assert!(curl.uses_from_macos[1].tags().is_empty());
+    assert_eq!(
+        curl.uses_from_macos_bounds,
+        [
+            MacOSBound::default(),
+            MacOSBound::default(),
+            MacOSBound {
+                since: Some("sonoma".to_string()),
+            },
+        ]
+    );
+}
+
+#[test]
+fn service_block() {
+    assert_eq!(formula("curl").service, None);
+
+    let unbound = formula("unbound");
+    assert!(unbound.post_install_defined);
+    let service = unbound.service.as_ref().unwrap();
+    assert_eq!(
+        service.run,
+        Some(ServiceRun::Args(vec![
+            "/opt/homebrew/opt/unbound/sbin/unbound".to_string(),
+            "-d".to_string(),
+            "-c".to_string(),
+            "/opt/homebrew/etc/unbound/unbound.conf".to_string(),
+        ]))
+    );
+    assert_eq!(service.run_type.as_deref(), Some("immediate"));
+    assert_eq!(
+        service.keep_alive,
+        Some(ServiceKeepAlive {
+            always: Some(true),
+            ..Default::default()
+        })
+    );
+    assert_eq!(service.require_root, Some(true));
+    // Keys without a dedicated field are kept as they were.
+    assert_eq!(
+        service.extra.get("sockets"),
+        Some(&Value::String("tcp://127.0.0.1:53".to_string()))
+    );
+}
+
+#[test]
+fn variations_by_bottle_tag() {
+    let unbound = formula("unbound");
+    let linux = unbound.variation_for("x86_64_linux").unwrap();
+    assert_eq!(
+        linux.dependencies.as_deref(),
+        Some(&["libevent", "libnghttp2", "openssl@3", "expat"].map(String::from)[..])
+    );
+    assert_eq!(linux.build_dependencies, None);
+    assert!(unbound.variation_for("arm64_sequoia").is_none());
+}
+
+#[test]
+fn serialize_round_trip() {
+    let all: Vec<Formula> = serde_json::from_str(FORMULA_JSON).unwrap();
+    for formula in all {
+        let json = serde_json::to_value(&formula).unwrap();
+        let reparsed: Formula = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(
+            reparsed, formula,
+            "{} did not survive a round trip",
+            formula.name
+        );
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+    }
+}
@@ -0,0 +1,183 @@
+//! Building and reading the on-disk metadata index, using the `formula.json` fixture with an
+//! alias and an old name added to `unbound`.
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::PathBuf;
+
+use serde_json::Value;
+use sps_common::cache::Cache;
+use sps_common::index::{self, IndexKind, MetadataIndex};
+use sps_common::{Config, Formula};
+use tempfile::TempDir;
+
+/// Size of the index header; the first key record follows it.
+const HEADER_LEN: usize = 84;
+
+/// A named way of damaging an index file.
+type Corruption = (&'static str, fn(&mut Vec<u8>));
+
+struct Fixture {
+    _dir: TempDir,
+    cache: Cache,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config::defaults();
+        config.sps_root = dir.path().to_path_buf();
+        let cache = Cache::new(&config).unwrap();
+        let mut formulae: Vec<Value> =
+            serde_json::from_str(include_str!("fixtures/formula.json")).unwrap();
+        formulae[1]["aliases"] = serde_json::json!(["unbound-dns"]);
+        formulae[1]["oldnames"] = serde_json::json!(["libunbound"]);
+        cache
+            .store_raw("formula.json", &serde_json::to_string(&formulae).unwrap())
+            .unwrap();
+        Self { _dir: dir, cache }
+    }
+
+    fn open(&self) -> MetadataIndex {
+        MetadataIndex::open(&self.cache, IndexKind::Formula)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.cache.get_dir().join("formula.idx")
+    }
+
+    fn source_path(&self) -> PathBuf {
+        self.cache.get_dir().join("formula.json")
+    }
+
+    /// The index file's inode; a rebuild replaces the file, so it changes.
+    fn index_inode(&self) -> u64 {
+        fs::metadata(self.index_path()).unwrap().ino()
+    }
+}
+
+#[test]
+fn missing_source_has_no_index() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::defaults();
+    config.sps_root = dir.path().to_path_buf();
+    let cache = Cache::new(&config).unwrap();
+    assert!(MetadataIndex::open(&cache, IndexKind::Formula)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn build_open_get_and_search() {
+    let fixture = Fixture::new();
+    let index = fixture.open();
+    assert_eq!(index.kind(), IndexKind::Formula);
+    assert_eq!(index.len(), 2);
+    let mut names = index.names();
+    names.sort_unstable();
+    assert_eq!(names, ["curl", "unbound"]);
+
+    let curl: Formula = index.get("curl").unwrap().unwrap();
+    assert_eq!(curl.name, "curl");
+    assert_eq!(curl.stable_version_str, "8.11.1");
+    // Aliases and old names find their package.
+    for key in ["unbound", "unbound-dns", "libunbound"] {
+        let raw = index.get_raw(key).unwrap().unwrap();
+        let value: Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(value["name"], "unbound", "looking up {key}");
+    }
+    assert!(index.get_raw("wget").unwrap().is_none());
+
+    // Search covers names, aliases and descriptions.
+    let search = |query: &str| -> Vec<String> {
+        index
+            .search(query)
+            .unwrap()
+            .iter()
+            .map(|raw| serde_json::from_str::<Value>(raw).unwrap()["name"].to_string())
+            .collect()
+    };
+    assert_eq!(search("dns"), [r#""unbound""#]);
+    assert_eq!(search("https"), [r#""curl""#]);
+    assert_eq!(search("unbound-dns"), [r#""unbound""#]);
+    assert_eq!(search("r").len(), 2);
+    assert!(search("nothing matches this").is_empty());
+}
+
+#[test]
+fn changed_source_rebuilds_the_index() {
+    let fixture = Fixture::new();
+    fixture.open();
+    fixture.cache.store_raw("formula.json", "[]").unwrap();
+    assert!(fixture.open().is_empty());
+}
+
+#[test]
+fn corrupt_index_is_rebuilt() {
+    let fixture = Fixture::new();
+    fixture.open();
+    let good = fs::read(fixture.index_path()).unwrap();
+    let corruptions: [Corruption; 5] = [
+        ("truncated", |data| data.truncate(data.len() / 2)),
+        ("bad magic", |data| data[0] = b'X'),
+        ("huge package count", |data| {
+            data[32..36].copy_from_slice(&u32::MAX.to_le_bytes())
+        }),
+        ("strings after search", |data| {
+            data[52..60].copy_from_slice(&u64::MAX.to_le_bytes())
+        }),
+        ("key pointing at a missing package", |data| {
+            data[HEADER_LEN + 8..HEADER_LEN + 12].copy_from_slice(&7u32.to_le_bytes())
+        }),
+    ];
+    for (what, corrupt) in corruptions {
+        let mut data = good.clone();
+        corrupt(&mut data);
+        // Keep the source stamp so only the bounds checks can notice.
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(fixture.index_path())
+            .unwrap();
+        file.write_all_at(&data, 0).unwrap();
+        drop(file);
+
+        let index = fixture.open();
+        assert_eq!(index.len(), 2, "{what}");
+        assert!(index.get_raw("libunbound").unwrap().is_some(), "{what}");
+        assert_eq!(fs::read(fixture.index_path()).unwrap(), good, "{what}");
+    }
+}
+
+#[test]
+fn restamp_keeps_an_index_whose_source_was_only_touched() {
+    let fixture = Fixture::new();
+    fixture.open();
+    let inode = fixture.index_inode();
+
+    fixture.cache.touch("formula.json").unwrap();
+    index::restamp(&fixture.cache, IndexKind::Formula).unwrap();
+    fixture.open();
+    assert_eq!(fixture.index_inode(), inode);
+}
+
+#[test]
+fn restamp_ignores_a_source_changed_in_place() {
+    let fixture = Fixture::new();
+    fixture.open();
+    let inode = fixture.index_inode();
+
+    // Same length, different content.
+    let source = fs::read_to_string(fixture.source_path()).unwrap();
+    let changed = source.replace("8.11.1", "8.11.2");
+    assert_eq!(changed.len(), source.len());
+    fs::write(fixture.source_path(), changed).unwrap();
+    index::restamp(&fixture.cache, IndexKind::Formula).unwrap();
+
+    let curl: Formula = fixture.open().get("curl").unwrap().unwrap();
+    assert_eq!(curl.stable_version_str, "8.11.2");
+    assert_ne!(fixture.index_inode(), inode);
+}
@@ -0,0 +1,73 @@
+//! The prefix lock between "processes". `flock` locks belong to an open file, so two
+//! acquisitions in one process conflict the same way two processes do.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use sps_common::error::SpsError;
+use sps_common::lock::{read_holders, LockMode, PrefixLock};
+use sps_common::Config;
+use tempfile::TempDir;
+
+fn config(dir: &TempDir) -> Config {
+    let mut config = Config::defaults();
+    config.sps_root = dir.path().to_path_buf();
+    config
+}
+
+fn assert_busy(result: Result<PrefixLock, SpsError>) {
+    match result {
+        Err(SpsError::LockError(msg)) => assert!(msg.contains("another sps process"), "{msg}"),
+        other => panic!("expected LockError, got {other:?}"),
+    }
+}
+
+#[test]
+fn shared_locks_coexist() {
+    let dir = TempDir::new().unwrap();
+    let config = config(&dir);
+    let _first = PrefixLock::acquire(&config, LockMode::Shared, false).unwrap();
+    let _second = PrefixLock::acquire(&config, LockMode::Shared, false).unwrap();
+    assert_busy(PrefixLock::acquire(&config, LockMode::Exclusive, false));
+}
+
+#[test]
+fn exclusive_lock_excludes_everyone() {
+    let dir = TempDir::new().unwrap();
+    let config = config(&dir);
+    let held = PrefixLock::acquire(&config, LockMode::Exclusive, false).unwrap();
+    assert_eq!(held.mode(), LockMode::Exclusive);
+    assert_busy(PrefixLock::acquire(&config, LockMode::Shared, false));
+    assert_busy(PrefixLock::acquire(&config, LockMode::Exclusive, false));
+
+    let holders = read_holders(&config.state_dir().join("prefix.lock.d"));
+    assert_eq!(holders.len(), 1);
+    assert_eq!(holders[0].pid, std::process::id());
+    assert_eq!(holders[0].mode, "exclusive");
+
+    drop(held);
+    assert!(read_holders(&config.state_dir().join("prefix.lock.d")).is_empty());
+    PrefixLock::acquire(&config, LockMode::Exclusive, false).unwrap();
+}
+
+#[test]
+fn waiting_blocks_until_the_lock_is_released() {
+    let dir = TempDir::new().unwrap();
+    let config = config(&dir);
+    let held = PrefixLock::acquire(&config, LockMode::Exclusive, false).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let waiter = {
+        let config = config.clone();
+        thread::spawn(move || {
+            let lock = PrefixLock::acquire(&config, LockMode::Exclusive, true);
+            tx.send(()).unwrap();
+            lock.map(|_| ())
+        })
+    };
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    drop(held);
+    rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    waiter.join().unwrap().unwrap();
+}
@@ -0,0 +1,100 @@
+//! Homebrew version ordering for versions semver can't express.
+
+use sps_common::model::version::{PkgVersion, Version};
+use sps_common::Formula;
+
+fn v(s: &str) -> Version {
+    Version::parse(s).unwrap()
+}
+
+fn pkg(s: &str) -> PkgVersion {
+    PkgVersion::parse(s).unwrap()
+}
+
+/// Asserts that `versions` is in strictly ascending order.
+fn assert_ascending(versions: &[&str]) {
+    for pair in versions.windows(2) {
+        assert!(
+            v(pair[0]) < v(pair[1]),
+            "expected {} < {}",
+            pair[0],
+            pair[1]
+        );
+        assert!(
+            v(pair[1]) > v(pair[0]),
+            "expected {} > {}",
+            pair[1],
+            pair[0]
+        );
+    }
+}
+
+#[test]
+fn four_component_versions() {
+    assert_ascending(&["1.2.3", "1.2.3.4", "1.2.3.9", "1.2.3.10", "1.2.4"]);
+    assert_eq!(v("1.2.3.4"), v("1.2.3.4"));
+}
+
+#[test]
+fn trailing_letters() {
+    assert_ascending(&["2024", "2024a", "2024b", "2025"]);
+    assert_ascending(&["9", "9d", "9e", "9f", "10"]);
+}
+
+#[test]
+fn svn_style_revisions() {
+    assert_ascending(&["r99", "r123", "r124", "r1000"]);
+}
+
+#[test]
+fn pre_and_post_releases() {
+    assert_ascending(&[
+        "1.0alpha1",
+        "1.0alpha2",
+        "1.0beta1",
+        "1.0rc1",
+        "1.0rc2",
+        "1.0",
+        "1.0p1",
+        "1.0.1",
+    ]);
+}
+
+#[test]
+fn trailing_zeros_and_leading_zeros() {
+    assert_eq!(v("1.0"), v("1.0.0"));
+    assert_eq!(v("1.02"), v("1.2"));
+    assert!(v("1.0") < v("1.0.1"));
+    assert!(v("1.9") < v("1.10"));
+}
+
+#[test]
+fn revisions() {
+    assert!(pkg("1.0.0_1") < pkg("1.0.0_2"));
+    assert!(pkg("1.0.0") < pkg("1.0.0_1"));
+    assert!(pkg("1.0.0_9") < pkg("1.0.1"));
+    assert_eq!(pkg("1.0.0_2").revision, 2);
+    assert_eq!(pkg("1.0.0_2").to_string(), "1.0.0_2");
+}
+
+#[test]
+fn version_scheme_wins() {
+    let newer_scheme = PkgVersion::parse_with_scheme("1.0", 1).unwrap();
+    assert!(pkg("2.0") < newer_scheme);
+}
+
+#[test]
+fn formula_version_uses_homebrew_ordering() {
+    let formula = |version: &str| -> Formula {
+        serde_json::from_value(serde_json::json!({
+            "name": "jpeg",
+            "versions": { "stable": version, "bottle": false },
+            "urls": { "stable": { "url": "https://example.com/jpeg.tar.gz" } },
+        }))
+        .unwrap()
+    };
+    let (older, newer) = (formula("9d"), formula("9e"));
+    assert!(older.version() < newer.version());
+    assert_eq!(newer.version().as_str(), "9e");
+    assert!(formula("1.2.3.4").version() > formula("1.2.3").version());
+}
@@ -33,9 +33,11 @@ pub use meson::meson_build;
 pub use perl::perl_build;
 pub use python::python_build;
 
-const SUPPORTED_ARCHIVE_EXTENSIONS: [&str; 5] = ["gz", "bz2", "xz", "tar", "zip"];
-pub(crate) const RECOGNISED_SINGLE_FILE_EXTENSIONS: [&str; 9] =
-    ["tar", "gz", "tgz", "bz2", "tbz", "tbz2", "xz", "txz", "zip"];
+const SUPPORTED_ARCHIVE_EXTENSIONS: [&str; 8] =
+    ["gz", "bz2", "xz", "zst", "lz", "7z", "tar", "zip"];
+pub(crate) const RECOGNISED_SINGLE_FILE_EXTENSIONS: [&str; 14] = [
+    "tar", "gz", "tgz", "bz2", "tbz", "tbz2", "xz", "txz", "zst", "tzst", "lz", "tlz", "7z", "zip",
+];
 
 pub async fn download_source(formula: &Formula, config: &Config) -> Result<PathBuf> {
     download_source_with_progress(formula, config, None).await
@@ -295,6 +297,7 @@ pub async fn build_from_source(
     formula: &Formula,
     config: &Config,
     all_installed_paths: &[PathBuf],
+    installed_on_request: bool,
 ) -> Result<PathBuf> {
     let install_dir = formula.install_prefix(config.cellar_dir().as_path())?;
     let formula_name = formula.name();
@@ -308,7 +311,13 @@ pub async fn build_from_source(
         debug!("Installing single file formula: {}", formula_name);
         create_dir_all_with_context(&install_dir, "install directory")?;
         install_single_file(source_path, formula, &install_dir)?;
-        bottle::write_receipt(formula, &install_dir, "source")?;
+        bottle::write_receipt(
+            formula,
+            &install_dir,
+            "source",
+            installed_on_request,
+            config,
+        )?;
         return Ok(install_dir);
     }
 
@@ -360,6 +369,25 @@ pub async fn build_from_source(
     )?;
     debug!("Extracted main source to {}", build_dir.display());
 
+    if inferred_root_dir.is_none() {
+        if let Some(decompressed) = single_extracted_file(build_dir)? {
+            debug!(
+                "Installing single file formula {} from compressed source",
+                formula_name
+            );
+            create_dir_all_with_context(&install_dir, "install directory")?;
+            install_single_file(&decompressed, formula, &install_dir)?;
+            bottle::write_receipt(
+                formula,
+                &install_dir,
+                "source",
+                installed_on_request,
+                config,
+            )?;
+            return Ok(install_dir);
+        }
+    }
+
     let resources = formula.resources()?;
     let mut resource_stage_paths = HashMap::new();
 
@@ -461,7 +489,13 @@ pub async fn build_from_source(
             install_dir.display()
         );
     }
-    crate::install::bottle::write_receipt(formula, &install_dir, "source")?;
+    crate::install::bottle::write_receipt(
+        formula,
+        &install_dir,
+        "source",
+        installed_on_request,
+        config,
+    )?;
     debug!(
         "Build completed, temporary directory {} will be cleaned up.",
         build_dir.display()
@@ -605,6 +639,21 @@ fn install_python_resource(
     Ok(())
 }
 
+/// The file a compressed single-file source (`tool.gz`) decompressed to, if that is all the
+/// extraction produced.
+fn single_extracted_file(build_dir: &Path) -> Result<Option<PathBuf>> {
+    let mut entries = fs::read_dir(build_dir)
+        .map_err(|e| SpsError::IoError(format!("Failed to read {}: {}", build_dir.display(), e)))?
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| SpsError::IoError(format!("Failed to read {}: {}", build_dir.display(), e)))?;
+    if entries.len() != 1 {
+        return Ok(None);
+    }
+    let entry = entries.remove(0);
+    let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+    Ok(is_file.then(|| entry.path()))
+}
+
 fn install_single_file(source_path: &Path, formula: &Formula, install_dir: &Path) -> Result<()> {
     let target_dir = install_dir.join("share").join(&formula.name);
     create_dir_all_with_context(&target_dir, "single file target directory")?;
@@ -0,0 +1,304 @@
+// sps-core/src/check/dependents.rs
+//! Dependency edges between installed formulae, and from installed casks to formulae.
+//!
+//! A keg's runtime dependencies come from the `runtime_dependencies` of its install receipt
+//! when it records them, otherwise from its definition in the cached formula index. Formulae
+//! that installed casks depend on are taken from the cached cask index.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use sps_common::cache::Cache;
+use sps_common::dependency::DependencyExt;
+use sps_common::error::Result;
+use sps_common::index::{IndexKind, MetadataIndex};
+use sps_common::keg::KegRegistry;
+use sps_common::model::cask::Cask;
+use sps_common::model::formula::Formula;
+use tracing::{debug, warn};
+
+use crate::install::bottle::installed_on_request;
+
+/// Runtime dependencies of every installed formula, and the formulae installed casks need.
+#[derive(Debug, Clone, Default)]
+pub struct InstalledDependencies {
+    dependencies: HashMap<String, BTreeSet<String>>,
+    on_request: HashSet<String>,
+    /// Formulae named in the `depends_on` of each installed cask, by cask token.
+    cask_dependencies: HashMap<String, BTreeSet<String>>,
+}
+
+impl InstalledDependencies {
+    pub fn load(cache: &Cache) -> Result<Self> {
+        let keg_registry = KegRegistry::new(cache.config().clone());
+        let names: BTreeSet<String> = keg_registry
+            .list_installed_kegs()?
+            .into_iter()
+            .map(|keg| keg.name)
+            .collect();
+
+        let index = match MetadataIndex::open(cache, IndexKind::Formula) {
+            Ok(index) => index,
+            Err(e) => {
+                warn!("Failed to open the formula index: {}", e);
+                None
+            }
+        };
+
+        let mut dependencies = HashMap::new();
+        let mut on_request = HashSet::new();
+        for name in names {
+            let Some(keg) = keg_registry.get_installed_keg(&name)? else {
+                continue;
+            };
+            if installed_on_request(&keg.path) {
+                on_request.insert(name.clone());
+            }
+            let deps = match receipt_runtime_dependencies(&keg.path) {
+                Some(deps) => deps,
+                None => index
+                    .as_ref()
+                    .and_then(|index| index.get::<Formula>(&name).ok().flatten())
+                    .map(|formula| {
+                        formula
+                            .dependencies()
+                            .unwrap_or_default()
+                            .runtime()
+                            .into_iter()
+                            .map(|dep| dep.name.clone())
+                            .collect()
+                    })
+                    .unwrap_or_else(|| {
+                        debug!("No dependency information for installed formula '{}'", name);
+                        BTreeSet::new()
+                    }),
+            };
+            dependencies.insert(name, deps);
+        }
+        Ok(Self {
+            dependencies,
+            on_request,
+            cask_dependencies: installed_cask_dependencies(cache),
+        })
+    }
+
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.dependencies.contains_key(name)
+    }
+
+    pub fn installed(&self) -> impl Iterator<Item = &str> {
+        self.dependencies.keys().map(String::as_str)
+    }
+
+    pub fn installed_on_request(&self, name: &str) -> bool {
+        self.on_request.contains(name)
+    }
+
+    /// Installed runtime dependencies of `name`.
+    pub fn dependencies_of(&self, name: &str) -> Vec<&str> {
+        self.dependencies
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|dep| self.is_installed(dep))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Installed formulae that depend on `name` directly.
+    pub fn dependents_of(&self, name: &str) -> Vec<&str> {
+        let mut dependents: Vec<&str> = self
+            .dependencies
+            .iter()
+            .filter(|(_, deps)| deps.contains(name))
+            .map(|(dependent, _)| dependent.as_str())
+            .collect();
+        dependents.sort_unstable();
+        dependents
+    }
+
+    /// Installed casks that depend on the formula `name`, by token.
+    pub fn cask_dependents_of(&self, name: &str) -> Vec<&str> {
+        let mut dependents: Vec<&str> = self
+            .cask_dependencies
+            .iter()
+            .filter(|(_, deps)| deps.contains(name))
+            .map(|(token, _)| token.as_str())
+            .collect();
+        dependents.sort_unstable();
+        dependents
+    }
+
+    /// Formulae installed on request that no other installed formula depends on.
+    pub fn leaves(&self) -> Vec<&str> {
+        let mut leaves: Vec<&str> = self
+            .on_request
+            .iter()
+            .map(String::as_str)
+            .filter(|name| self.dependents_of(name).is_empty())
+            .collect();
+        leaves.sort_unstable();
+        leaves
+    }
+
+    /// Formulae installed only as dependencies that neither a formula installed on request nor
+    /// an installed cask needs, directly or through other formulae.
+    pub fn unneeded(&self) -> BTreeSet<String> {
+        let mut needed: HashSet<&str> = HashSet::new();
+        let mut pending: Vec<&str> = self
+            .on_request
+            .iter()
+            .chain(self.cask_dependencies.values().flatten())
+            .map(String::as_str)
+            .filter(|name| self.is_installed(name))
+            .collect();
+        while let Some(name) = pending.pop() {
+            if needed.insert(name) {
+                pending.extend(self.dependencies_of(name));
+            }
+        }
+        self.installed()
+            .filter(|name| !needed.contains(name))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// `names` plus everything installed that depends on them, directly or not.
+    pub fn with_dependents(&self, names: &[String]) -> BTreeSet<String> {
+        let mut closure: BTreeSet<String> = names.iter().cloned().collect();
+        let mut pending: Vec<String> = names.to_vec();
+        while let Some(name) = pending.pop() {
+            for dependent in self.dependents_of(&name) {
+                if closure.insert(dependent.to_string()) {
+                    pending.push(dependent.to_string());
+                }
+            }
+        }
+        closure
+    }
+
+    /// Orders `names` so that every formula comes before its dependencies, i.e. the order to
+    /// remove them in. Formulae on a cycle are appended in name order.
+    pub fn removal_order(&self, names: &BTreeSet<String>) -> Vec<String> {
+        let mut remaining: BTreeSet<&str> = names.iter().map(String::as_str).collect();
+        let mut order = Vec::with_capacity(names.len());
+        loop {
+            let ready: Vec<&str> = remaining
+                .iter()
+                .copied()
+                .filter(|name| {
+                    !self
+                        .dependents_of(name)
+                        .iter()
+                        .any(|dependent| remaining.contains(dependent))
+                })
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            for name in ready {
+                remaining.remove(name);
+                order.push(name.to_string());
+            }
+        }
+        order.extend(remaining.into_iter().map(str::to_string));
+        order
+    }
+
+    /// The subset of `names` that can be removed without breaking an installed formula or cask
+    /// outside of it, and for each of the rest the dependents that keep it. Casks are named
+    /// `<token> (cask)`; those in `removed_casks` are being uninstalled too and keep nothing.
+    pub fn removable(
+        &self,
+        names: &[String],
+        removed_casks: &[String],
+    ) -> (Vec<String>, Vec<(String, Vec<String>)>) {
+        let needed_by_cask = |name: &str| {
+            self.cask_dependents_of(name)
+                .into_iter()
+                .filter(|token| !removed_casks.iter().any(|removed| removed == token))
+                .collect::<Vec<_>>()
+        };
+        let mut removable: HashSet<&str> = names.iter().map(String::as_str).collect();
+        loop {
+            let blocked: Vec<&str> = removable
+                .iter()
+                .copied()
+                .filter(|name| {
+                    !needed_by_cask(name).is_empty()
+                        || self
+                            .dependents_of(name)
+                            .iter()
+                            .any(|dependent| !removable.contains(dependent))
+                })
+                .collect();
+            if blocked.is_empty() {
+                break;
+            }
+            for name in blocked {
+                removable.remove(name);
+            }
+        }
+        let mut kept = Vec::new();
+        for name in names {
+            if !removable.contains(name.as_str()) {
+                let dependents = self
+                    .dependents_of(name)
+                    .into_iter()
+                    .filter(|dependent| !removable.contains(dependent))
+                    .map(str::to_string)
+                    .chain(
+                        needed_by_cask(name)
+                            .into_iter()
+                            .map(|token| format!("{token} (cask)")),
+                    )
+                    .collect();
+                kept.push((name.clone(), dependents));
+            }
+        }
+        let removable = names
+            .iter()
+            .filter(|name| removable.contains(name.as_str()))
+            .cloned()
+            .collect();
+        (removable, kept)
+    }
+}
+
+/// Formulae named in the `depends_on` of each installed cask, by cask token.
+fn installed_cask_dependencies(cache: &Cache) -> HashMap<String, BTreeSet<String>> {
+    let Ok(tokens) = fs::read_dir(cache.config().cask_room_dir()) else {
+        return HashMap::new();
+    };
+    let index = match MetadataIndex::open(cache, IndexKind::Cask) {
+        Ok(Some(index)) => index,
+        Ok(None) => return HashMap::new(),
+        Err(e) => {
+            warn!("Failed to open the cask index: {}", e);
+            return HashMap::new();
+        }
+    };
+    tokens
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter_map(|token| index.get::<Cask>(&token).ok().flatten())
+        .filter_map(|cask| {
+            let formulae: BTreeSet<String> = cask.depends_on?.formula.into_iter().collect();
+            (!formulae.is_empty()).then_some((cask.token, formulae))
+        })
+        .collect()
+}
+
+/// Names in the receipt's `runtime_dependencies`, if it has them. Tap-qualified names
+/// (`user/tap/foo`) are reduced to the formula name.
+pub fn receipt_runtime_dependencies(keg_path: &Path) -> Option<BTreeSet<String>> {
+    let content = fs::read_to_string(keg_path.join("INSTALL_RECEIPT.json")).ok()?;
+    let receipt: serde_json::Value = serde_json::from_str(&content).ok()?;
+    let deps = receipt.get("runtime_dependencies")?.as_array()?;
+    Some(
+        deps.iter()
+            .filter_map(|dep| dep.get("full_name").and_then(|n| n.as_str()))
+            .map(|name| name.rsplit('/').next().unwrap_or(name).to_string())
+            .collect(),
+    )
+}
@@ -1,4 +1,5 @@
 // sps-core/src/check/installed.rs
+use std::collections::BTreeSet;
 use std::fs::{self}; // Removed DirEntry as it's not directly used here
 use std::io;
 use std::path::PathBuf;
@@ -42,13 +43,19 @@ pub async fn get_installed_packages(config: &Config) -> Result<Vec<InstalledPack
 
     match keg_registry.list_installed_kegs() {
         Ok(kegs) => {
-            for keg in kegs {
-                installed.push(InstalledPackageInfo {
-                    name: keg.name,
-                    version: keg.version_str, // Use keg.version_str
-                    pkg_type: PackageType::Formula,
-                    path: keg.path,
-                });
+            // One entry per formula: its current keg, not every version kept in the Cellar.
+            let names: BTreeSet<String> = kegs.into_iter().map(|keg| keg.name).collect();
+            for name in names {
+                match keg_registry.get_installed_keg(&name) {
+                    Ok(Some(keg)) => installed.push(InstalledPackageInfo {
+                        name: keg.name,
+                        version: keg.version_str, // Use keg.version_str
+                        pkg_type: PackageType::Formula,
+                        path: keg.path,
+                    }),
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to read installed versions of {}: {}", name, e),
+                }
             }
         }
         Err(e) => warn!("Failed to list installed formulae: {}", e),
@@ -1,5 +1,7 @@
+pub mod dependents;
 pub mod installed;
 pub mod update;
 
+pub use dependents::InstalledDependencies;
 pub use installed::{InstalledPackageInfo, PackageType};
 pub use update::UpdateInfo;
@@ -1,6 +1,5 @@
 // sps-core/src/check/update.rs
 
-use std::collections::HashMap;
 use std::sync::Arc;
 
 // Imports from sps-common
@@ -8,7 +7,8 @@ use sps_common::cache::Cache;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use sps_common::formulary::Formulary; // Using the shared Formulary
-use sps_common::model::version::Version as PkgVersion;
+use sps_common::index::{IndexKind, MetadataIndex};
+use sps_common::keg::InstalledKeg;
 use sps_common::model::Cask; // Using the Cask and Formula from sps-common
 // Use the Cask and Formula structs from sps_common::model
 // Ensure InstallTargetIdentifier is correctly pathed if it's also in sps_common::model
@@ -31,20 +31,19 @@ pub struct UpdateInfo {
 /// Ensures that the raw JSON data for formulas and casks exists in the main disk cache,
 /// fetching from the API if necessary.
 async fn ensure_api_data_cached(cache: &Cache) -> Result<()> {
-    let formula_check = cache.load_raw("formula.json");
-    let cask_check = cache.load_raw("cask.json");
+    // Only whether the files exist matters here; they are several megabytes each, and the
+    // index reads them when it needs to.
+    let is_cached = |kind: IndexKind| cache.get_dir().join(kind.source_file()).is_file();
 
     // Determine if fetches are needed
-    let mut fetch_formulas = false;
-    if formula_check.is_err() {
-        tracing::debug!("Local formula.json cache missing or unreadable. Scheduling fetch.");
-        fetch_formulas = true;
+    let fetch_formulas = !is_cached(IndexKind::Formula);
+    if fetch_formulas {
+        tracing::debug!("Local formula.json cache missing. Scheduling fetch.");
     }
 
-    let mut fetch_casks = false;
-    if cask_check.is_err() {
-        tracing::debug!("Local cask.json cache missing or unreadable. Scheduling fetch.");
-        fetch_casks = true;
+    let fetch_casks = !is_cached(IndexKind::Cask);
+    if fetch_casks {
+        tracing::debug!("Local cask.json cache missing. Scheduling fetch.");
     }
 
     // Perform fetches concurrently if needed
@@ -53,12 +52,12 @@ async fn ensure_api_data_cached(cache: &Cache) -> Result<()> {
             tracing::debug!("Populating missing formula.json and cask.json from API...");
             let (formula_res, cask_res) = tokio::join!(
                 async {
-                    let data = api::fetch_all_formulas().await?;
+                    let data = api::fetch_all_formulas(cache.config()).await?;
                     cache.store_raw("formula.json", &data)?;
                     Ok::<(), SpsError>(())
                 },
                 async {
-                    let data = api::fetch_all_casks().await?;
+                    let data = api::fetch_all_casks(cache.config()).await?;
                     cache.store_raw("cask.json", &data)?;
                     Ok::<(), SpsError>(())
                 }
@@ -69,13 +68,13 @@ async fn ensure_api_data_cached(cache: &Cache) -> Result<()> {
         }
         (true, false) => {
             tracing::debug!("Populating missing formula.json from API...");
-            let data = api::fetch_all_formulas().await?;
+            let data = api::fetch_all_formulas(cache.config()).await?;
             cache.store_raw("formula.json", &data)?;
             tracing::debug!("Formula.json populated from API.");
         }
         (false, true) => {
             tracing::debug!("Populating missing cask.json from API...");
-            let data = api::fetch_all_casks().await?;
+            let data = api::fetch_all_casks(cache.config()).await?;
             cache.store_raw("cask.json", &data)?;
             tracing::debug!("Cask.json populated from API.");
         }
@@ -112,30 +111,14 @@ pub async fn check_for_updates(
     // 2. Instantiate Formulary. It uses the `cache` (from sps-common) to load `formula.json`.
     let formulary = Formulary::new(config.clone());
 
-    // 3. For Casks: Load the entire `cask.json` from cache and parse it robustly into Vec<Cask>.
-    //    This uses the Cask definition from sps_common::model::cask.
-    let casks_map: HashMap<String, Arc<Cask>> = match cache.load_raw("cask.json") {
-        Ok(json_str) => {
-            // Parse directly into Vec<Cask> using the definition from sps-common::model::cask
-            match serde_json::from_str::<Vec<Cask>>(&json_str) {
-                Ok(casks) => casks
-                    .into_iter()
-                    .map(|c| (c.token.clone(), Arc::new(c)))
-                    .collect(),
-                Err(e) => {
-                    tracing::warn!(
-                        "Failed to parse full cask.json string into Vec<Cask> (from sps-common): {}. Cask update checks may be incomplete.",
-                        e
-                    );
-                    HashMap::new()
-                }
-            }
-        }
+    // 3. For Casks: Look definitions up in the compiled `cask.json` index.
+    let casks_index = match MetadataIndex::open(cache, IndexKind::Cask) {
+        Ok(index) => index,
         Err(e) => {
             tracing::warn!(
-                "Failed to load cask.json string from cache: {}. Cask update checks will be based on no remote data.", e
+                "Failed to open the cask index: {}. Cask update checks will be based on no remote data.", e
             );
-            HashMap::new()
+            None
         }
     };
 
@@ -167,25 +150,27 @@ pub async fn check_for_updates(
                             latest_version_str
                         );
 
-                        let installed_v_res = PkgVersion::parse(&installed.version);
-                        let latest_v_res = PkgVersion::parse(&latest_version_str);
-                        let installed_revision = installed
-                            .version
-                            .split('_')
-                            .nth(1)
-                            .and_then(|s| s.parse::<u32>().ok())
-                            .unwrap_or(0);
+                        let installed_keg = InstalledKeg {
+                            name: installed.name.clone(),
+                            version_str: installed.version.clone(),
+                            path: installed.path.clone(),
+                        };
+                        let installed_v_res = installed_keg.pkg_version();
+                        let lv = latest_formula_arc.pkg_version();
 
-                        let needs_update = match (installed_v_res, latest_v_res) {
-                            (Ok(iv), Ok(lv)) => {
-                                let version_newer = lv > iv;
-                                let revision_newer =
-                                    lv == iv && latest_formula_arc.revision > installed_revision;
-                                tracing::debug!("[UpdateCheck] Formula '{}': version_newer={}, revision_newer={} (installed_rev={}, latest_rev={})", 
-                                    installed.name, version_newer, revision_newer, installed_revision, latest_formula_arc.revision);
-                                version_newer || revision_newer
+                        let needs_update = match installed_v_res {
+                            Some(iv) => {
+                                tracing::debug!(
+                                    "[UpdateCheck] Formula '{}': installed={} (scheme {}), latest={} (scheme {})",
+                                    installed.name,
+                                    iv,
+                                    iv.version_scheme,
+                                    lv,
+                                    lv.version_scheme
+                                );
+                                lv > iv
                             }
-                            _ => {
+                            None => {
                                 let different = installed.version != latest_version_str;
                                 tracing::debug!("[UpdateCheck] Formula '{}': fallback string comparison, different={}", 
                                     installed.name, different);
@@ -220,7 +205,21 @@ pub async fn check_for_updates(
                 }
             }
             PackageType::Cask => {
-                if let Some(latest_cask_arc) = casks_map.get(&installed.name) {
+                let latest_cask = casks_index.as_ref().and_then(|index| {
+                    match index.get::<Cask>(&installed.name) {
+                        Ok(cask) => cask,
+                        Err(e) => {
+                            tracing::warn!(
+                                "Failed to read cask '{}' from the index: {}",
+                                installed.name,
+                                e
+                            );
+                            None
+                        }
+                    }
+                });
+                if let Some(latest_cask) = latest_cask {
+                    let latest_cask_arc = Arc::new(latest_cask);
                     // latest_cask_arc is Arc<sps_common::model::cask::Cask>
                     if let Some(available_version) = latest_cask_arc.version.as_ref() {
                         if &installed.version != available_version {
@@ -231,19 +230,19 @@ pub async fn check_for_updates(
                                 pkg_type: PackageType::Cask,
                                 target_definition: InstallTargetIdentifier::Cask(
                                     // From sps-common
-                                    latest_cask_arc.clone(),
+                                    latest_cask_arc,
                                 ),
                             });
                         }
                     } else {
                         tracing::warn!(
-                            "Latest cask definition for '{}' from casks_map has no version string.",
+                            "Latest cask definition for '{}' from the cask index has no version string.",
                             installed.name
                         );
                     }
                 } else {
                     tracing::warn!(
-                        "Installed cask '{}' not found in the fully parsed casks_map.",
+                        "Installed cask '{}' not found in the cask index.",
                         installed.name
                     );
                 }
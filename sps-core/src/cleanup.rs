@@ -0,0 +1,336 @@
+// sps-core/src/cleanup.rs
+//! Finds what `sps cleanup` can remove:
+//!
+//! - formula kegs other than the current one (the keg `opt/<name>` points at, or the latest if the
+//!   formula isn't linked)
+//! - Caskroom versions other than the installed one, and private-store app copies of versions or
+//!   casks that are no longer installed
+//! - cached bottles and cask downloads of versions that are no longer current, or older than the
+//!   prune age; with `scrub` all of them
+//! - partial `.download` files left by interrupted downloads
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::index::{IndexKind, MetadataIndex};
+use sps_common::keg::KegRegistry;
+use sps_common::model::cask::{Cask, UrlField};
+use sps_common::model::formula::Formula;
+use tracing::{debug, warn};
+use url::Url;
+use walkdir::WalkDir;
+
+use crate::uninstall::common::remove_filesystem_artifact;
+
+#[derive(Debug, Clone, Default)]
+pub struct CleanupOptions {
+    /// Only clean up after these formulae and casks; all if empty.
+    pub names: Vec<String>,
+    /// Also remove downloads older than this many days, even of current versions.
+    pub prune_days: Option<u64>,
+    /// Remove every cached download, current or not.
+    pub scrub: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanupKind {
+    Keg,
+    CaskVersion,
+    PrivateStoreCopy,
+    Download,
+    PartialDownload,
+}
+
+#[derive(Debug, Clone)]
+pub struct CleanupItem {
+    pub path: PathBuf,
+    pub kind: CleanupKind,
+    /// Bytes on disk; symlinks are not followed.
+    pub size: u64,
+}
+
+/// Lists everything that can be removed. Nothing is touched.
+pub fn find_cleanup_items(cache: &Cache, options: &CleanupOptions) -> Result<Vec<CleanupItem>> {
+    let config = cache.config();
+    let wanted = |name: &str| options.names.is_empty() || options.names.iter().any(|n| n == name);
+    let mut items = Vec::new();
+
+    stale_kegs(config, &wanted, &mut items)?;
+    stale_cask_versions(config, &wanted, &mut items)?;
+
+    let formulae = open_index(cache, IndexKind::Formula);
+    let casks = open_index(cache, IndexKind::Cask);
+    let cutoff = options.prune_days.and_then(prune_cutoff);
+    stale_downloads(
+        &config.cache_dir().join("bottles"),
+        options,
+        cutoff,
+        &mut items,
+        |file_name| bottle_owner(file_name, formulae.as_ref()?),
+        &wanted,
+    )?;
+    stale_downloads(
+        &config.cache_dir().join("cask_downloads"),
+        options,
+        cutoff,
+        &mut items,
+        |file_name| cask_download_owner(file_name, casks.as_ref()?),
+        &wanted,
+    )?;
+    Ok(items)
+}
+
+/// Removes one item found by [`find_cleanup_items`].
+pub fn remove_cleanup_item(item: &CleanupItem) -> Result<()> {
+    if remove_filesystem_artifact(&item.path, false) {
+        Ok(())
+    } else {
+        Err(SpsError::Generic(format!(
+            "Failed to remove {}",
+            item.path.display()
+        )))
+    }
+}
+
+fn open_index(cache: &Cache, kind: IndexKind) -> Option<MetadataIndex> {
+    match MetadataIndex::open(cache, kind) {
+        Ok(index) => index,
+        Err(e) => {
+            warn!(
+                "Failed to open the {} index; keeping its downloads: {}",
+                kind.source_file(),
+                e
+            );
+            None
+        }
+    }
+}
+
+fn stale_kegs(
+    config: &Config,
+    wanted: &dyn Fn(&str) -> bool,
+    items: &mut Vec<CleanupItem>,
+) -> Result<()> {
+    let keg_registry = KegRegistry::new(config.clone());
+    let mut names: Vec<String> = keg_registry
+        .list_installed_kegs()?
+        .into_iter()
+        .map(|keg| keg.name)
+        .filter(|name| wanted(name))
+        .collect();
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        // After `sps switch` the linked keg can be older than the latest one.
+        let current = match keg_registry.get_linked_keg(&name) {
+            Some(keg) => Some(keg),
+            None => keg_registry.get_installed_keg(&name)?,
+        };
+        let Some(current) = current else {
+            continue;
+        };
+        for keg in keg_registry.list_kegs(&name)? {
+            if keg.path != current.path {
+                push_item(items, keg.path, CleanupKind::Keg);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn stale_cask_versions(
+    config: &Config,
+    wanted: &dyn Fn(&str) -> bool,
+    items: &mut Vec<CleanupItem>,
+) -> Result<()> {
+    let mut installed: HashSet<(String, String)> = HashSet::new();
+    for token_entry in read_dir_entries(&config.cask_room_dir()) {
+        let token = token_entry.file_name().to_string_lossy().to_string();
+        if !token_entry.path().is_dir() || token.starts_with('.') {
+            continue;
+        }
+        let versions: Vec<PathBuf> = read_dir_entries(&token_entry.path())
+            .into_iter()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && !is_hidden(path))
+            .collect();
+        let current = versions
+            .iter()
+            .filter(|path| is_installed_cask_version(path))
+            .max_by_key(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+        if let Some(current) = current {
+            let version = current.file_name().unwrap_or_default().to_string_lossy();
+            installed.insert((token.clone(), version.to_string()));
+        }
+        if !wanted(&token) {
+            continue;
+        }
+        for path in &versions {
+            if Some(path) != current {
+                push_item(items, path.clone(), CleanupKind::CaskVersion);
+            }
+        }
+    }
+
+    for token_entry in read_dir_entries(&config.cask_store_dir()) {
+        let token = token_entry.file_name().to_string_lossy().to_string();
+        if !token_entry.path().is_dir() || token.starts_with('.') || !wanted(&token) {
+            continue;
+        }
+        let versions: Vec<PathBuf> = read_dir_entries(&token_entry.path())
+            .into_iter()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        let orphaned: Vec<&PathBuf> = versions
+            .iter()
+            .filter(|path| {
+                let version = path.file_name().unwrap_or_default().to_string_lossy();
+                !installed.contains(&(token.clone(), version.to_string()))
+            })
+            .collect();
+        if !versions.is_empty() && orphaned.len() == versions.len() {
+            push_item(items, token_entry.path(), CleanupKind::PrivateStoreCopy);
+        } else {
+            for path in orphaned {
+                push_item(items, path.clone(), CleanupKind::PrivateStoreCopy);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A Caskroom version directory is installed if it has a manifest that doesn't say otherwise.
+fn is_installed_cask_version(path: &Path) -> bool {
+    let manifest = path.join("CASK_INSTALL_MANIFEST.json");
+    fs::read_to_string(manifest)
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .map(|json| json.get("is_installed").and_then(|v| v.as_bool()) != Some(false))
+        .unwrap_or(false)
+}
+
+/// Downloads last modified before this are old enough to prune. `None` when `days` reaches
+/// further back than the clock can, i.e. nothing is that old.
+fn prune_cutoff(days: u64) -> Option<SystemTime> {
+    let age = days.checked_mul(24 * 60 * 60).map(Duration::from_secs)?;
+    SystemTime::now().checked_sub(age)
+}
+
+/// Adds the downloads in `dir` that can go. `owner` maps a file name to the package it belongs
+/// to and whether it is the package's current version; `None` means unknown.
+fn stale_downloads(
+    dir: &Path,
+    options: &CleanupOptions,
+    cutoff: Option<SystemTime>,
+    items: &mut Vec<CleanupItem>,
+    owner: impl Fn(&str) -> Option<(String, bool)>,
+    wanted: &dyn Fn(&str) -> bool,
+) -> Result<()> {
+    for entry in read_dir_entries(dir) {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.starts_with('.') && file_name.ends_with(".download") {
+            if !options.names.is_empty() {
+                continue;
+            }
+            // Downloads take the prefix lock exclusively, as does cleanup: nothing is writing
+            // these.
+            push_item(items, path, CleanupKind::PartialDownload);
+            continue;
+        }
+        let owner = owner(&file_name);
+        if !options.names.is_empty() && !owner.as_ref().is_some_and(|(name, _)| wanted(name)) {
+            continue;
+        }
+        let outdated = matches!(owner, Some((_, false)));
+        let expired = cutoff.is_some_and(|cutoff| {
+            entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified <= cutoff)
+        });
+        if options.scrub || outdated || expired {
+            debug!(
+                "Cached download {} can go (outdated: {}, expired: {})",
+                file_name, outdated, expired
+            );
+            push_item(items, path, CleanupKind::Download);
+        }
+    }
+    Ok(())
+}
+
+/// `<name>-<version>.<tag>.bottle.tar.gz`: the formula and whether `version` is its current one.
+/// Names can contain dashes, so every split is tried against the index.
+fn bottle_owner(file_name: &str, index: &MetadataIndex) -> Option<(String, bool)> {
+    let stem = file_name.strip_suffix(".bottle.tar.gz")?;
+    let (name_version, _tag) = stem.rsplit_once('.')?;
+    for (i, _) in name_version.match_indices('-').rev() {
+        let (name, version) = (&name_version[..i], &name_version[i + 1..]);
+        if let Ok(Some(formula)) = index.get::<Formula>(name) {
+            return Some((
+                formula.name().to_string(),
+                formula.version_str_full() == version,
+            ));
+        }
+    }
+    None
+}
+
+/// `cask-<token>-<file name>`: the cask and whether the file is its current download.
+fn cask_download_owner(file_name: &str, index: &MetadataIndex) -> Option<(String, bool)> {
+    let rest = file_name.strip_prefix("cask-")?;
+    for (i, _) in rest.match_indices('-') {
+        let token = &rest[..i];
+        if let Ok(Some(cask)) = index.get::<Cask>(token) {
+            let current_file = cask
+                .url
+                .as_ref()
+                .map(|url| match url {
+                    UrlField::Simple(u) => u.as_str(),
+                    UrlField::WithSpec { url, .. } => url.as_str(),
+                })
+                .and_then(|url| Url::parse(url).ok())
+                .and_then(|url| Some(url.path_segments()?.next_back()?.to_string()))
+                .unwrap_or_default();
+            return Some((cask.token.clone(), rest[i + 1..] == current_file));
+        }
+    }
+    None
+}
+
+fn push_item(items: &mut Vec<CleanupItem>, path: PathBuf, kind: CleanupKind) {
+    let size = disk_usage(&path);
+    items.push(CleanupItem { path, kind, size });
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn read_dir_entries(dir: &Path) -> Vec<fs::DirEntry> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
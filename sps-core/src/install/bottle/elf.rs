@@ -0,0 +1,875 @@
+// sps-core/src/install/bottle/elf.rs
+// Contains ELF specific patching logic for Linux bottle relocation.
+// Rewrites PT_INTERP, DT_RPATH/DT_RUNPATH (plus DT_NEEDED/DT_SONAME) and embedded placeholder
+// strings. Strings that no longer fit are written to a fresh copy of .dynstr (and a fresh
+// interpreter string) in a segment appended to the end of the file, so relocation never depends
+// on the new prefix being shorter than the placeholder. When the program header table has no
+// entry to spare for that segment, the table itself moves into it with one more entry.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use sps_common::error::{Result, SpsError};
+use tempfile::NamedTempFile;
+use tracing::{debug, error};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+
+const PT_NULL: u32 = 0;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_NOTE: u32 = 4;
+const PT_PHDR: u32 = 6;
+const PF_R: u32 = 4;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_STRSZ: u64 = 10;
+const DT_SONAME: u64 = 14;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// Smallest page alignment used for the appended segment.
+const MIN_PAGE_ALIGN: u64 = 0x1000;
+/// Most zero padding written before the appended segment, which has to clear the memory image
+/// (including .bss) when the program header table moves into it.
+const MAX_SEGMENT_PADDING: u64 = 256 << 20;
+
+/// Byte layout of the file being patched (class and endianness).
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl Layout {
+    fn word_size(&self) -> usize {
+        if self.is_64 {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn read_u16(&self, buf: &[u8], off: usize) -> Result<u16> {
+        let bytes: [u8; 2] = read_array(buf, off)?;
+        Ok(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_u32(&self, buf: &[u8], off: usize) -> Result<u32> {
+        let bytes: [u8; 4] = read_array(buf, off)?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_u64(&self, buf: &[u8], off: usize) -> Result<u64> {
+        let bytes: [u8; 8] = read_array(buf, off)?;
+        Ok(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    /// Reads an address/offset/size sized word (`Elf32_Word` or `Elf64_Xword`).
+    fn read_word(&self, buf: &[u8], off: usize) -> Result<u64> {
+        if self.is_64 {
+            self.read_u64(buf, off)
+        } else {
+            self.read_u32(buf, off).map(u64::from)
+        }
+    }
+
+    fn write_u16(&self, buf: &mut [u8], off: usize, val: u16) -> Result<()> {
+        let bytes = if self.little_endian {
+            val.to_le_bytes()
+        } else {
+            val.to_be_bytes()
+        };
+        write_bytes(buf, off, &bytes)
+    }
+
+    fn write_u32(&self, buf: &mut [u8], off: usize, val: u32) -> Result<()> {
+        let bytes = if self.little_endian {
+            val.to_le_bytes()
+        } else {
+            val.to_be_bytes()
+        };
+        write_bytes(buf, off, &bytes)
+    }
+
+    fn write_word(&self, buf: &mut [u8], off: usize, val: u64) -> Result<()> {
+        if self.is_64 {
+            let bytes = if self.little_endian {
+                val.to_le_bytes()
+            } else {
+                val.to_be_bytes()
+            };
+            write_bytes(buf, off, &bytes)
+        } else {
+            let val = u32::try_from(val).map_err(|_| {
+                SpsError::ElfError(format!("Value {val:#x} does not fit a 32-bit ELF field"))
+            })?;
+            self.write_u32(buf, off, val)
+        }
+    }
+}
+
+fn read_array<const N: usize>(buf: &[u8], off: usize) -> Result<[u8; N]> {
+    off.checked_add(N)
+        .and_then(|end| buf.get(off..end))
+        .and_then(|s| s.try_into().ok())
+        .ok_or_else(|| SpsError::ElfError(format!("Read of {N} bytes at {off:#x} out of bounds")))
+}
+
+fn write_bytes(buf: &mut [u8], off: usize, bytes: &[u8]) -> Result<()> {
+    let len = buf.len();
+    off.checked_add(bytes.len())
+        .and_then(|end| buf.get_mut(off..end))
+        .ok_or_else(|| {
+            SpsError::ElfError(format!(
+                "Write of {} bytes at {off:#x} out of bounds (file is {len} bytes)",
+                bytes.len()
+            ))
+        })?
+        .copy_from_slice(bytes);
+    Ok(())
+}
+
+fn align_up(value: u64, align: u64) -> Result<u64> {
+    if align <= 1 {
+        return Ok(value);
+    }
+    value
+        .div_ceil(align)
+        .checked_mul(align)
+        .ok_or_else(|| overflow("Aligned segment address"))
+}
+
+/// Header fields are untrusted; arithmetic on them that overflows means the file is corrupt.
+fn overflow(what: &str) -> SpsError {
+    SpsError::ElfError(format!("{what} overflows"))
+}
+
+/// One entry of the program header table.
+#[derive(Debug, Clone, Copy)]
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+/// One entry of the section header table (only the fields relocation touches).
+#[derive(Debug, Clone, Copy)]
+struct SectionHeader {
+    index: usize,
+    sh_addr: u64,
+    sh_offset: u64,
+}
+
+/// A dynamic entry whose value is an offset into the dynamic string table.
+#[derive(Debug, Clone, Copy)]
+struct DynStrRef {
+    tag: u64,
+    /// File offset of the `d_val` field.
+    val_offset: usize,
+    str_offset: u64,
+}
+
+/// Parsed view over an ELF image held in memory.
+struct ElfImage {
+    layout: Layout,
+    buf: Vec<u8>,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+    shoff: usize,
+    shentsize: usize,
+    shnum: usize,
+}
+
+impl ElfImage {
+    fn parse(buf: Vec<u8>) -> Result<Self> {
+        if buf.len() < 0x34 || &buf[..4] != ELF_MAGIC {
+            return Err(SpsError::ElfError("Not an ELF file".to_string()));
+        }
+        let is_64 = match buf[4] {
+            ELFCLASS32 => false,
+            ELFCLASS64 => true,
+            other => {
+                return Err(SpsError::ElfError(format!("Unknown ELF class {other}")));
+            }
+        };
+        let little_endian = match buf[5] {
+            ELFDATA2LSB => true,
+            ELFDATA2MSB => false,
+            other => {
+                return Err(SpsError::ElfError(format!(
+                    "Unknown ELF data encoding {other}"
+                )));
+            }
+        };
+        let layout = Layout {
+            is_64,
+            little_endian,
+        };
+        let (phoff_at, shoff_at, phentsize_at) = if is_64 { (32, 40, 54) } else { (28, 32, 42) };
+        let phoff = layout.read_word(&buf, phoff_at)? as usize;
+        let shoff = layout.read_word(&buf, shoff_at)? as usize;
+        let phentsize = layout.read_u16(&buf, phentsize_at)? as usize;
+        let phnum = layout.read_u16(&buf, phentsize_at + 2)? as usize;
+        let shentsize = layout.read_u16(&buf, phentsize_at + 4)? as usize;
+        let shnum = layout.read_u16(&buf, phentsize_at + 6)? as usize;
+
+        let expected_phentsize = if is_64 { 56 } else { 32 };
+        if phnum > 0 && phentsize != expected_phentsize {
+            return Err(SpsError::ElfError(format!(
+                "Unexpected program header entry size {phentsize}"
+            )));
+        }
+        let table_end = phnum
+            .checked_mul(phentsize)
+            .and_then(|size| phoff.checked_add(size))
+            .ok_or_else(|| overflow("Program header table end"))?;
+        if table_end > buf.len() {
+            return Err(SpsError::ElfError(
+                "Program header table extends past end of file".to_string(),
+            ));
+        }
+        Ok(Self {
+            layout,
+            buf,
+            phoff,
+            phentsize,
+            phnum,
+            shoff,
+            shentsize,
+            shnum,
+        })
+    }
+
+    fn phdr_offset(&self, index: usize) -> usize {
+        self.phoff + index * self.phentsize
+    }
+
+    fn read_phdr(&self, index: usize) -> Result<ProgramHeader> {
+        let l = self.layout;
+        let b = &self.buf;
+        let o = self.phdr_offset(index);
+        if l.is_64 {
+            Ok(ProgramHeader {
+                p_type: l.read_u32(b, o)?,
+                p_flags: l.read_u32(b, o + 4)?,
+                p_offset: l.read_u64(b, o + 8)?,
+                p_vaddr: l.read_u64(b, o + 16)?,
+                p_paddr: l.read_u64(b, o + 24)?,
+                p_filesz: l.read_u64(b, o + 32)?,
+                p_memsz: l.read_u64(b, o + 40)?,
+                p_align: l.read_u64(b, o + 48)?,
+            })
+        } else {
+            Ok(ProgramHeader {
+                p_type: l.read_u32(b, o)?,
+                p_offset: l.read_word(b, o + 4)?,
+                p_vaddr: l.read_word(b, o + 8)?,
+                p_paddr: l.read_word(b, o + 12)?,
+                p_filesz: l.read_word(b, o + 16)?,
+                p_memsz: l.read_word(b, o + 20)?,
+                p_flags: l.read_u32(b, o + 24)?,
+                p_align: l.read_word(b, o + 28)?,
+            })
+        }
+    }
+
+    fn write_phdr(&mut self, index: usize, ph: &ProgramHeader) -> Result<()> {
+        let l = self.layout;
+        let o = self.phdr_offset(index);
+        let b = &mut self.buf;
+        if l.is_64 {
+            l.write_u32(b, o, ph.p_type)?;
+            l.write_u32(b, o + 4, ph.p_flags)?;
+            l.write_word(b, o + 8, ph.p_offset)?;
+            l.write_word(b, o + 16, ph.p_vaddr)?;
+            l.write_word(b, o + 24, ph.p_paddr)?;
+            l.write_word(b, o + 32, ph.p_filesz)?;
+            l.write_word(b, o + 40, ph.p_memsz)?;
+            l.write_word(b, o + 48, ph.p_align)?;
+        } else {
+            l.write_u32(b, o, ph.p_type)?;
+            l.write_word(b, o + 4, ph.p_offset)?;
+            l.write_word(b, o + 8, ph.p_vaddr)?;
+            l.write_word(b, o + 12, ph.p_paddr)?;
+            l.write_word(b, o + 16, ph.p_filesz)?;
+            l.write_word(b, o + 20, ph.p_memsz)?;
+            l.write_u32(b, o + 24, ph.p_flags)?;
+            l.write_word(b, o + 28, ph.p_align)?;
+        }
+        Ok(())
+    }
+
+    /// Points `e_phoff`/`e_phnum` at a program header table of `phnum` entries at `phoff`.
+    fn set_program_header_table(&mut self, phoff: usize, phnum: usize) -> Result<()> {
+        let (phoff_at, phnum_at) = if self.layout.is_64 {
+            (32, 56)
+        } else {
+            (28, 44)
+        };
+        let count = u16::try_from(phnum)
+            .ok()
+            .filter(|&n| n < u16::MAX)
+            .ok_or_else(|| SpsError::ElfError(format!("Too many program headers ({phnum})")))?;
+        let l = self.layout;
+        l.write_word(&mut self.buf, phoff_at, phoff as u64)?;
+        l.write_u16(&mut self.buf, phnum_at, count)?;
+        self.phoff = phoff;
+        self.phnum = phnum;
+        Ok(())
+    }
+
+    fn program_headers(&self) -> Result<Vec<ProgramHeader>> {
+        (0..self.phnum).map(|i| self.read_phdr(i)).collect()
+    }
+
+    fn section_headers(&self) -> Result<Vec<SectionHeader>> {
+        let expected_shentsize = if self.layout.is_64 { 64 } else { 40 };
+        let table_end = self
+            .shnum
+            .checked_mul(self.shentsize)
+            .and_then(|size| self.shoff.checked_add(size));
+        if self.shoff == 0
+            || self.shentsize != expected_shentsize
+            || table_end.is_none_or(|end| end > self.buf.len())
+        {
+            // Stripped or unusual section headers: nothing to keep in sync.
+            return Ok(Vec::new());
+        }
+        let (addr_at, offset_at) = if self.layout.is_64 {
+            (16, 24)
+        } else {
+            (12, 16)
+        };
+        (0..self.shnum)
+            .map(|index| {
+                let o = self.shoff + index * self.shentsize;
+                Ok(SectionHeader {
+                    index,
+                    sh_addr: self.layout.read_word(&self.buf, o + addr_at)?,
+                    sh_offset: self.layout.read_word(&self.buf, o + offset_at)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Points section `index` at a new location so tools like `readelf` and `strip` keep
+    /// working after a table was moved.
+    fn update_section(&mut self, index: usize, addr: u64, offset: u64, size: u64) -> Result<()> {
+        let (addr_at, offset_at, size_at) = if self.layout.is_64 {
+            (16, 24, 32)
+        } else {
+            (12, 16, 20)
+        };
+        let o = self.shoff + index * self.shentsize;
+        let l = self.layout;
+        l.write_word(&mut self.buf, o + addr_at, addr)?;
+        l.write_word(&mut self.buf, o + offset_at, offset)?;
+        l.write_word(&mut self.buf, o + size_at, size)
+    }
+
+    /// Translates a virtual address into a file offset using the PT_LOAD segments.
+    fn vaddr_to_offset(&self, phdrs: &[ProgramHeader], vaddr: u64) -> Result<Option<u64>> {
+        phdrs
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .find(|ph| vaddr >= ph.p_vaddr && vaddr - ph.p_vaddr < ph.p_filesz)
+            .map(|ph| {
+                (vaddr - ph.p_vaddr)
+                    .checked_add(ph.p_offset)
+                    .ok_or_else(|| overflow("Segment file offset"))
+            })
+            .transpose()
+    }
+
+    /// Reads the NUL-terminated string at `off`, returning it and its length without the NUL.
+    fn c_str_at(&self, off: usize) -> Option<(&str, usize)> {
+        let tail = self.buf.get(off..)?;
+        let len = tail.iter().position(|&b| b == 0)?;
+        std::str::from_utf8(&tail[..len]).ok().map(|s| (s, len))
+    }
+}
+
+/// Location of the dynamic section's string table and every entry that refers into it.
+struct DynamicInfo {
+    strtab_vaddr: u64,
+    strtab_offset: u64,
+    strsz: u64,
+    /// File offsets of the `d_val` fields of DT_STRTAB and DT_STRSZ.
+    strtab_val_offset: usize,
+    strsz_val_offset: usize,
+    refs: Vec<DynStrRef>,
+}
+
+fn read_dynamic(image: &ElfImage, phdrs: &[ProgramHeader]) -> Result<Option<DynamicInfo>> {
+    let Some(dynamic) = phdrs.iter().find(|ph| ph.p_type == PT_DYNAMIC) else {
+        return Ok(None);
+    };
+    let l = image.layout;
+    let entsize = 2 * l.word_size();
+    let start = dynamic.p_offset as usize;
+    let count = dynamic.p_filesz as usize / entsize;
+
+    let mut strtab = None;
+    let mut strsz = None;
+    let mut refs = Vec::new();
+    for i in 0..count {
+        let o = i
+            .checked_mul(entsize)
+            .and_then(|rel| start.checked_add(rel))
+            .ok_or_else(|| overflow("Dynamic entry offset"))?;
+        let tag = l.read_word(&image.buf, o)?;
+        let val_offset = o
+            .checked_add(l.word_size())
+            .ok_or_else(|| overflow("Dynamic entry offset"))?;
+        let val = l.read_word(&image.buf, val_offset)?;
+        match tag {
+            DT_NULL => break,
+            DT_STRTAB => strtab = Some((val, val_offset)),
+            DT_STRSZ => strsz = Some((val, val_offset)),
+            DT_NEEDED | DT_SONAME | DT_RPATH | DT_RUNPATH => refs.push(DynStrRef {
+                tag,
+                val_offset,
+                str_offset: val,
+            }),
+            _ => {}
+        }
+    }
+    let (Some((strtab_vaddr, strtab_val_offset)), Some((strsz, strsz_val_offset))) =
+        (strtab, strsz)
+    else {
+        return Ok(None);
+    };
+    let strtab_offset = image.vaddr_to_offset(phdrs, strtab_vaddr)?.ok_or_else(|| {
+        SpsError::ElfError(format!(
+            "DT_STRTAB address {strtab_vaddr:#x} is not covered by any PT_LOAD segment"
+        ))
+    })?;
+    Ok(Some(DynamicInfo {
+        strtab_vaddr,
+        strtab_offset,
+        strsz,
+        strtab_val_offset,
+        strsz_val_offset,
+        refs,
+    }))
+}
+
+/// Helper to replace placeholders in a string based on the replacements map.
+/// Returns `Some(String)` with replacements if any were made, `None` otherwise.
+fn replace_placeholders(current: &str, replacements: &HashMap<String, String>) -> Option<String> {
+    let mut new_value = current.to_string();
+    let mut modified = false;
+    for (placeholder, replacement) in replacements {
+        if new_value.contains(placeholder.as_str()) {
+            new_value = new_value.replace(placeholder.as_str(), replacement);
+            modified = true;
+        }
+    }
+    modified.then_some(new_value)
+}
+
+/// Overwrites a NUL-terminated string in place, padding the rest of its slot with NULs.
+/// `slot_len` is the length of the old string (without its terminator).
+fn overwrite_c_str(buf: &mut [u8], off: usize, slot_len: usize, new_value: &str) -> Result<()> {
+    debug_assert!(new_value.len() <= slot_len);
+    write_bytes(buf, off, new_value.as_bytes())?;
+    buf[off + new_value.len()..=off + slot_len].fill(0);
+    Ok(())
+}
+
+/// Quick magic-number check so callers can skip non-ELF files without reading them fully.
+pub fn is_elf_file(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && &magic == ELF_MAGIC
+}
+
+/// Main entry point for ELF path patching.
+/// Returns `Ok(true)` if the file was rewritten, `Ok(false)` if it is not an ELF file or
+/// contained nothing to relocate.
+pub fn patch_elf_file(path: &Path, replacements: &HashMap<String, String>) -> Result<bool> {
+    if !is_elf_file(path) {
+        return Ok(false);
+    }
+    debug!("Processing ELF file: {}", path.display());
+    let buffer = fs::read(path)?;
+    let mut image = ElfImage::parse(buffer)?;
+    let original_len = image.buf.len() as u64;
+    let phdrs = image.program_headers()?;
+    let mut modified = false;
+
+    // Strings that do not fit their current slot, appended to the new segment.
+    let mut new_interp: Option<String> = None;
+    let mut grown_dyn_refs: Vec<(DynStrRef, String)> = Vec::new();
+
+    // --- 1) Interpreter (PT_INTERP) ---
+    let interp_index = phdrs.iter().position(|ph| ph.p_type == PT_INTERP);
+    if let Some(idx) = interp_index {
+        let interp = phdrs[idx];
+        let off = interp.p_offset as usize;
+        if let Some((current, len)) = image.c_str_at(off) {
+            if let Some(replaced) = replace_placeholders(current, replacements) {
+                debug!("    ELF interpreter '{}' -> '{}'", current, replaced);
+                if replaced.len() <= len {
+                    overwrite_c_str(&mut image.buf, off, len, &replaced)?;
+                    modified = true;
+                } else {
+                    new_interp = Some(replaced);
+                }
+            }
+        }
+    }
+
+    // --- 2) Dynamic string references (RPATH, RUNPATH, NEEDED, SONAME) ---
+    let dynamic = read_dynamic(&image, &phdrs)?;
+    if let Some(info) = &dynamic {
+        for r in &info.refs {
+            let off = info
+                .strtab_offset
+                .checked_add(r.str_offset)
+                .ok_or_else(|| overflow("Dynamic string offset"))? as usize;
+            let Some((current, len)) = image.c_str_at(off) else {
+                debug!(
+                    "    Unreadable dynamic string for tag {} at {:#x}",
+                    r.tag, off
+                );
+                continue;
+            };
+            let Some(replaced) = replace_placeholders(current, replacements) else {
+                continue;
+            };
+            debug!(
+                "    ELF dynamic tag {}: '{}' -> '{}'",
+                r.tag, current, replaced
+            );
+            if replaced.len() <= len {
+                overwrite_c_str(&mut image.buf, off, len, &replaced)?;
+                modified = true;
+            } else {
+                grown_dyn_refs.push((*r, replaced));
+            }
+        }
+    }
+
+    // --- 3) Grow: append a segment holding a new .dynstr and/or interpreter ---
+    if new_interp.is_some() || !grown_dyn_refs.is_empty() {
+        append_relocation_segment(
+            &mut image,
+            &phdrs,
+            spare_phdr_index(&phdrs),
+            dynamic.as_ref(),
+            interp_index,
+            new_interp.as_deref(),
+            &grown_dyn_refs,
+        )
+        .map_err(|e| {
+            SpsError::ElfError(format!(
+                "Could not relocate strings in {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        modified = true;
+    }
+
+    // --- 4) Embedded placeholder strings anywhere in the original image ---
+    if patch_embedded_strings(&mut image.buf[..original_len as usize], replacements) {
+        modified = true;
+    }
+
+    if modified {
+        write_patched_elf(path, &image.buf)?;
+        debug!("    Relocated ELF file {}", path.display());
+    }
+    Ok(modified)
+}
+
+/// The program header entry that can be repurposed to map a new segment.
+///
+/// Taking over an unused PT_NULL or PT_NOTE entry keeps the table where it is. Notes are
+/// informational only (build-id, ABI tag) and are still reachable through the section headers.
+fn spare_phdr_index(phdrs: &[ProgramHeader]) -> Option<usize> {
+    phdrs
+        .iter()
+        .position(|ph| ph.p_type == PT_NULL)
+        .or_else(|| phdrs.iter().rposition(|ph| ph.p_type == PT_NOTE))
+}
+
+/// Appends a read-only PT_LOAD segment with the strings that outgrew their slots, mapped by the
+/// program header at `slot_index`. Without a spare header the program header table is copied
+/// into the segment with one more entry, and the segment is placed so that file offsets and
+/// addresses differ by the same amount as in the first PT_LOAD; loaders that derive the table's
+/// address from `e_phoff` then still find it.
+fn append_relocation_segment(
+    image: &mut ElfImage,
+    phdrs: &[ProgramHeader],
+    slot_index: Option<usize>,
+    dynamic: Option<&DynamicInfo>,
+    interp_index: Option<usize>,
+    new_interp: Option<&str>,
+    grown_dyn_refs: &[(DynStrRef, String)],
+) -> Result<()> {
+    let loads = || phdrs.iter().filter(|ph| ph.p_type == PT_LOAD);
+    let align = loads()
+        .map(|ph| ph.p_align)
+        .max()
+        .unwrap_or(MIN_PAGE_ALIGN)
+        .max(MIN_PAGE_ALIGN);
+    let mut vaddr_end = 0;
+    for ph in loads() {
+        let end = ph
+            .p_vaddr
+            .checked_add(ph.p_memsz)
+            .ok_or_else(|| overflow("Segment end address"))?;
+        vaddr_end = vaddr_end.max(end);
+    }
+    let (seg_offset, seg_vaddr) = match slot_index {
+        Some(_) => (
+            align_up(image.buf.len() as u64, align)?,
+            align_up(vaddr_end, align)?,
+        ),
+        None => {
+            let first = loads().min_by_key(|ph| ph.p_vaddr).ok_or_else(|| {
+                SpsError::ElfError("No PT_LOAD segment to place the program headers by".into())
+            })?;
+            let delta = first.p_vaddr.checked_sub(first.p_offset).ok_or_else(|| {
+                SpsError::ElfError("First PT_LOAD segment maps below its file offset".into())
+            })?;
+            let min_offset = (image.buf.len() as u64).max(vaddr_end.saturating_sub(delta));
+            let seg_offset = align_up(min_offset, align)?;
+            if seg_offset - image.buf.len() as u64 > MAX_SEGMENT_PADDING {
+                return Err(SpsError::ElfError(format!(
+                    "Moving the program headers needs {} bytes of padding",
+                    seg_offset - image.buf.len() as u64
+                )));
+            }
+            let seg_vaddr = seg_offset
+                .checked_add(delta)
+                .ok_or_else(|| overflow("Segment address"))?;
+            (seg_offset, seg_vaddr)
+        }
+    };
+
+    let mut segment: Vec<u8> = Vec::new();
+    let sections = image.section_headers()?;
+
+    // New string table: old contents first so every existing offset stays valid.
+    if let (Some(info), false) = (dynamic, grown_dyn_refs.is_empty()) {
+        let old_start = info.strtab_offset as usize;
+        let old_table = old_start
+            .checked_add(info.strsz as usize)
+            .and_then(|old_end| image.buf.get(old_start..old_end))
+            .ok_or_else(|| {
+                SpsError::ElfError("Dynamic string table extends past end of file".to_string())
+            })?;
+        segment.extend_from_slice(old_table);
+        for (r, value) in grown_dyn_refs {
+            let new_str_offset = segment.len() as u64;
+            segment.extend_from_slice(value.as_bytes());
+            segment.push(0);
+            image
+                .layout
+                .write_word(&mut image.buf, r.val_offset, new_str_offset)?;
+        }
+        let l = image.layout;
+        l.write_word(&mut image.buf, info.strtab_val_offset, seg_vaddr)?;
+        l.write_word(&mut image.buf, info.strsz_val_offset, segment.len() as u64)?;
+        if let Some(sh) = sections.iter().find(|sh| {
+            sh.index != 0 && sh.sh_addr == info.strtab_vaddr && sh.sh_offset == info.strtab_offset
+        }) {
+            image.update_section(sh.index, seg_vaddr, seg_offset, segment.len() as u64)?;
+        }
+        debug!(
+            "    Moved .dynstr to offset {:#x} (vaddr {:#x}, {} bytes)",
+            seg_offset,
+            seg_vaddr,
+            segment.len()
+        );
+    }
+
+    let mut table: Vec<ProgramHeader> = image.program_headers()?;
+    if let (Some(idx), Some(interp)) = (interp_index, new_interp) {
+        let rel = segment.len() as u64;
+        segment.extend_from_slice(interp.as_bytes());
+        segment.push(0);
+        let old = phdrs[idx];
+        let len = interp.len() as u64 + 1;
+        table[idx] = ProgramHeader {
+            p_offset: seg_offset + rel,
+            p_vaddr: seg_vaddr + rel,
+            p_paddr: seg_vaddr + rel,
+            p_filesz: len,
+            p_memsz: len,
+            ..old
+        };
+        if let Some(sh) = sections
+            .iter()
+            .find(|sh| sh.index != 0 && sh.sh_offset == old.p_offset && sh.sh_addr == old.p_vaddr)
+        {
+            image.update_section(sh.index, seg_vaddr + rel, seg_offset + rel, len)?;
+        }
+        debug!("    Moved interpreter to offset {:#x}", seg_offset + rel);
+    }
+
+    // PT_LOAD entries must stay sorted by address; the new one has the highest address, so it
+    // goes right after the last existing PT_LOAD.
+    let last_load = phdrs.iter().rposition(|ph| ph.p_type == PT_LOAD);
+    let insert_at = match (last_load, slot_index) {
+        (Some(i), Some(slot)) if i > slot => i,
+        (Some(i), _) => i + 1,
+        (None, _) => 0,
+    };
+    if let Some(slot) = slot_index {
+        table.remove(slot);
+    }
+    let new_table_at = match slot_index {
+        Some(_) => None,
+        None => {
+            let rel = align_up(segment.len() as u64, image.layout.word_size() as u64)?;
+            let size = ((table.len() + 1) * image.phentsize) as u64;
+            segment.resize(rel as usize + size as usize, 0);
+            if let Some(phdr) = table.iter_mut().find(|ph| ph.p_type == PT_PHDR) {
+                phdr.p_offset = seg_offset + rel;
+                phdr.p_vaddr = seg_vaddr + rel;
+                phdr.p_paddr = seg_vaddr + rel;
+                phdr.p_filesz = size;
+                phdr.p_memsz = size;
+            }
+            debug!(
+                "    Moved the program header table to offset {:#x} to add an entry",
+                seg_offset + rel
+            );
+            Some(seg_offset + rel)
+        }
+    };
+    table.insert(
+        insert_at,
+        ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags: PF_R,
+            p_offset: seg_offset,
+            p_vaddr: seg_vaddr,
+            p_paddr: seg_vaddr,
+            p_filesz: segment.len() as u64,
+            p_memsz: segment.len() as u64,
+            p_align: align,
+        },
+    );
+
+    image.buf.resize(seg_offset as usize, 0);
+    image.buf.extend_from_slice(&segment);
+    if let Some(phoff) = new_table_at {
+        image.set_program_header_table(phoff as usize, table.len())?;
+    }
+    for (i, ph) in table.iter().enumerate() {
+        image.write_phdr(i, ph)?;
+    }
+    Ok(())
+}
+
+/// Replaces placeholders inside NUL-terminated strings that are not reachable through the
+/// dynamic section (e.g. paths compiled into `.rodata`). Such strings cannot be moved, so they
+/// are only rewritten when the result fits; otherwise they are left untouched.
+fn patch_embedded_strings(buf: &mut [u8], replacements: &HashMap<String, String>) -> bool {
+    let mut modified = false;
+    let placeholders: Vec<(&String, &String)> = replacements
+        .iter()
+        .filter(|(k, _)| k.starts_with("@@") && k.ends_with("@@"))
+        .collect();
+    for (placeholder, _) in &placeholders {
+        let needle = placeholder.as_bytes();
+        let mut pos = 0;
+        while let Some(found) = find_subslice(&buf[pos..], needle) {
+            let hit = pos + found;
+            // Expand to the surrounding C string.
+            let start = buf[..hit]
+                .iter()
+                .rposition(|&b| b == 0)
+                .map_or(0, |p| p + 1);
+            let Some(len) = buf[start..].iter().position(|&b| b == 0) else {
+                break;
+            };
+            pos = start + len + 1;
+            let Ok(current) = std::str::from_utf8(&buf[start..start + len]) else {
+                continue;
+            };
+            let Some(replaced) = replace_placeholders(current, replacements) else {
+                continue;
+            };
+            if replaced.len() <= len {
+                debug!("    Embedded string '{}' -> '{}'", current, replaced);
+                if overwrite_c_str(buf, start, len, &replaced).is_ok() {
+                    modified = true;
+                }
+            } else {
+                debug!(
+                    "    Embedded string '{}' cannot grow in place to '{}'; leaving as is",
+                    current, replaced
+                );
+            }
+        }
+    }
+    modified
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Writes the patched image to the original path atomically, keeping its permissions.
+fn write_patched_elf(original_path: &Path, buffer: &[u8]) -> Result<()> {
+    let dir = original_path.parent().ok_or_else(|| {
+        SpsError::Generic(format!(
+            "Cannot get parent directory for {}",
+            original_path.display()
+        ))
+    })?;
+    let permissions = fs::metadata(original_path)?.permissions();
+    let mut temp_file = NamedTempFile::new_in(dir)?;
+    temp_file.write_all(buffer)?;
+    temp_file.flush()?;
+    temp_file.as_file().sync_all()?;
+    fs::set_permissions(temp_file.path(), permissions)?;
+    temp_file.persist(original_path).map_err(|e| {
+        error!(
+            "    Failed to persist/rename temporary file over {}: {}",
+            original_path.display(),
+            e.error
+        );
+        SpsError::Io(std::sync::Arc::new(e.error))
+    })?;
+    Ok(())
+}
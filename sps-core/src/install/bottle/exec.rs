@@ -7,10 +7,10 @@ use std::process::{Command as StdCommand, Stdio};
 use std::sync::Arc;
 
 use reqwest::Client;
-use semver;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use sps_common::model::formula::{BottleFileSpec, Formula, FormulaDependencies};
+use sps_common::model::version::Version;
 use sps_net::http::ProgressCallback;
 use sps_net::oci;
 use sps_net::validation::verify_checksum;
@@ -18,9 +18,10 @@ use tempfile::NamedTempFile;
 use tracing::{debug, error, warn};
 use walkdir::WalkDir;
 
-use super::macho;
+use super::{elf, macho};
 use crate::install::bottle::get_current_platform;
 use crate::install::extract::extract_archive;
+use crate::pipeline::transaction::InstallTransaction;
 
 pub async fn download_bottle(
     formula: &Formula,
@@ -328,20 +329,23 @@ pub fn get_bottle_for_platform(formula: &Formula) -> Result<(String, &BottleFile
     ))
 }
 
-pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) -> Result<PathBuf> {
+/// Installs a bottle. The archive is extracted and relocated in a staging directory and
+/// only moved to its keg path once it is complete; a keg already at that path is set aside
+/// through `transaction` so it can be restored if the job fails.
+pub fn install_bottle(
+    bottle_path: &Path,
+    formula: &Formula,
+    config: &Config,
+    transaction: &mut InstallTransaction,
+    installed_on_request: bool,
+) -> Result<PathBuf> {
     let install_dir = formula.install_prefix(config.cellar_dir().as_path())?;
     if install_dir.exists() {
         debug!(
-            "Removing existing keg directory before installing: {}",
+            "Setting existing keg directory aside before installing: {}",
             install_dir.display()
         );
-        fs::remove_dir_all(&install_dir).map_err(|e| {
-            SpsError::InstallError(format!(
-                "Failed to remove existing keg {}: {}",
-                install_dir.display(),
-                e
-            ))
-        })?;
+        transaction.set_aside(formula.name(), &install_dir)?;
     }
     if let Some(parent_dir) = install_dir.parent() {
         fs::create_dir_all(parent_dir).map_err(|e| {
@@ -360,29 +364,32 @@ pub fn install_bottle(bottle_path: &Path, formula: &Formula, config: &Config) ->
             install_dir.display()
         )));
     }
-    fs::create_dir_all(&install_dir).map_err(|e| {
-        SpsError::Io(std::sync::Arc::new(std::io::Error::new(
-            e.kind(),
-            format!("Failed to create keg dir {}: {}", install_dir.display(), e),
-        )))
-    })?;
+    let staging_dir = transaction.stage(&install_dir)?;
     let strip_components = 2;
     debug!(
         "Extracting bottle archive {} to {} with strip_components={}",
         bottle_path.display(),
-        install_dir.display(),
+        staging_dir.display(),
         strip_components
     );
-    extract_archive(bottle_path, &install_dir, strip_components, "gz")?;
+    extract_archive(bottle_path, &staging_dir, strip_components, "gz")?;
+    transaction.checkpoint()?;
     debug!(
         "Ensuring write permissions for extracted files in {}",
-        install_dir.display()
+        staging_dir.display()
     );
-    ensure_write_permissions(&install_dir)?;
-    debug!("Performing bottle relocation in {}", install_dir.display());
-    perform_bottle_relocation(formula, &install_dir, config)?;
-    ensure_llvm_symlinks(&install_dir, formula, config)?;
-    crate::install::bottle::write_receipt(formula, &install_dir, "bottle")?;
+    ensure_write_permissions(&staging_dir)?;
+    debug!("Performing bottle relocation in {}", staging_dir.display());
+    perform_bottle_relocation(formula, &staging_dir, &install_dir, config)?;
+    ensure_llvm_symlinks(&staging_dir, formula, config)?;
+    crate::install::bottle::write_receipt(
+        formula,
+        &staging_dir,
+        "bottle",
+        installed_on_request,
+        config,
+    )?;
+    transaction.commit_staged(&staging_dir, &install_dir)?;
     debug!(
         "Bottle installation complete for {} at {}",
         formula.name(),
@@ -431,7 +438,14 @@ fn ensure_write_permissions(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Config) -> Result<()> {
+/// Patches the files under `work_dir` (usually a staging directory) so they work once moved
+/// to `install_dir`. Paths written into the files always refer to `install_dir`.
+fn perform_bottle_relocation(
+    formula: &Formula,
+    work_dir: &Path,
+    install_dir: &Path,
+    config: &Config,
+) -> Result<()> {
     let mut repl: HashMap<String, String> = HashMap::new();
     repl.insert(
         "@@HOMEBREW_CELLAR@@".into(),
@@ -472,7 +486,7 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
         let mut parts = version_full.split('.');
         if let (Some(major), Some(minor)) = (parts.next(), parts.next()) {
             let framework_version = format!("{major}.{minor}");
-            let framework_dir = install_dir
+            let framework_dir = work_dir
                 .join("Frameworks")
                 .join("Python.framework")
                 .join("Versions")
@@ -712,7 +726,7 @@ fn perform_bottle_relocation(formula: &Formula, install_dir: &Path, config: &Con
     for (k, v) in &repl {
         tracing::debug!("{}  →  {}", k, v);
     }
-    original_relocation_scan_and_patch(formula, install_dir, config, repl)
+    original_relocation_scan_and_patch(formula, work_dir, config, repl)
 }
 
 fn original_relocation_scan_and_patch(
@@ -725,6 +739,8 @@ fn original_relocation_scan_and_patch(
     let mut macho_patched_count = 0;
     let mut permission_errors = 0;
     let mut macho_errors = 0;
+    let mut elf_patched_count = 0;
+    let mut elf_errors = 0;
     let mut io_errors = 0;
     let mut files_to_chmod: Vec<PathBuf> = Vec::new();
     for entry in WalkDir::new(install_dir).into_iter().filter_map(|e| e.ok()) {
@@ -895,6 +911,19 @@ fn original_relocation_scan_and_patch(
                     }
                 }
             }
+        } else if cfg!(target_os = "linux") && elf::is_elf_file(path) {
+            match elf::patch_elf_file(path, &replacements) {
+                Ok(true) => {
+                    elf_patched_count += 1;
+                    was_modified = true;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("ELF relocation failed for {}: {}", path.display(), e);
+                    elf_errors += 1;
+                    continue;
+                }
+            }
         }
         // Fallback to text replacement if not modified by Mach-O patching
         if !was_modified {
@@ -1009,14 +1038,15 @@ fn original_relocation_scan_and_patch(
     }
 
     debug!(
-        "Relocation scan complete. Text files replaced: {}, Mach-O files patched: {}",
-        text_replaced_count, macho_patched_count
+        "Relocation scan complete. Text files replaced: {}, Mach-O files patched: {}, ELF files patched: {}",
+        text_replaced_count, macho_patched_count, elf_patched_count
     );
-    if permission_errors > 0 || macho_errors > 0 || io_errors > 0 {
+    if permission_errors > 0 || macho_errors > 0 || elf_errors > 0 || io_errors > 0 {
         debug!(
-            "Bottle relocation finished with issues: {} chmod errors, {} Mach-O errors, {} IO errors in {}.",
+            "Bottle relocation finished with issues: {} chmod errors, {} Mach-O errors, {} ELF errors, {} IO errors in {}.",
             permission_errors,
             macho_errors,
+            elf_errors,
             io_errors,
             install_dir.display()
         );
@@ -1027,6 +1057,13 @@ fn original_relocation_scan_and_patch(
                 install_dir.display()
             )));
         }
+        if elf_errors > 0 {
+            return Err(SpsError::InstallError(format!(
+                "Bottle relocation failed due to {} ELF errors in {}",
+                elf_errors,
+                install_dir.display()
+            )));
+        }
     }
     Ok(())
 }
@@ -1104,7 +1141,7 @@ fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
     if !opt_dir.is_dir() {
         return None;
     }
-    let mut best: Option<(semver::Version, PathBuf)> = None;
+    let mut best: Option<(Version, PathBuf)> = None;
     match fs::read_dir(opt_dir) {
         Ok(entries) => {
             for entry_res in entries.flatten() {
@@ -1115,20 +1152,7 @@ fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
                     continue;
                 }
                 if let Some(version_part) = s.strip_prefix("perl@") {
-                    let version_str_padded = if version_part.contains('.') {
-                        let parts: Vec<&str> = version_part.split('.').collect();
-                        match parts.len() {
-                            1 => format!("{}.0.0", parts[0]), // e.g., perl@5 -> 5.0.0
-                            2 => format!("{}.{}.0", parts[0], parts[1]), /* e.g., perl@5.30 -> */
-                            // 5.30.0
-                            _ => version_part.to_string(), // Already 3+ parts
-                        }
-                    } else {
-                        format!("{version_part}.0.0") // e.g., perl@5 -> 5.0.0 (handles case with no
-                                                      // dot)
-                    };
-
-                    if let Ok(v) = semver::Version::parse(&version_str_padded) {
+                    if let Ok(v) = Version::parse(version_part) {
                         let candidate_bin = entry_path.join("bin/perl");
                         if candidate_bin.is_file()
                             && (best.is_none() || v > best.as_ref().unwrap().0)
@@ -1139,7 +1163,7 @@ fn find_brewed_perl(prefix: &Path) -> Option<PathBuf> {
                 } else if s == "perl" {
                     let candidate_bin = entry_path.join("bin/perl");
                     if candidate_bin.is_file() && best.is_none() {
-                        if let Ok(v_base) = semver::Version::parse("5.0.0") {
+                        if let Ok(v_base) = Version::parse("5") {
                             best = Some((v_base, candidate_bin));
                         }
                     }
@@ -1,11 +1,12 @@
 // ===== sps-core/src/build/formula/link.rs =====
-use std::fs;
 use std::io::Write;
 use std::os::unix::fs as unix_fs;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::{fmt, fs};
 
+use serde::{Deserialize, Serialize};
 use serde_json;
 use sps_common::config::Config; // Import Config
 use sps_common::error::{Result, SpsError};
@@ -13,224 +14,876 @@ use sps_common::model::formula::Formula;
 use tracing::{debug, error};
 
 const STANDARD_KEG_DIRS: [&str; 6] = ["bin", "lib", "share", "include", "etc", "Frameworks"];
+const WRAPPER_MARKER: &str = "# Wrapper script generated by sp";
+
+/// A per-formula override of the default linking behaviour, set by `sps link --force` or
+/// `sps unlink`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkOverride {
+    /// Link into the prefix even though the formula is keg-only.
+    Forced,
+    /// Only keep the `opt/` link, even for a regular formula.
+    Unlinked,
+}
+
+/// Contents of `INSTALL_MANIFEST.json` inside a formula keg.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormulaInstallManifest {
+    /// Symlinks and wrapper scripts created outside the keg.
+    pub links: Vec<String>,
+    #[serde(default)]
+    pub keg_only: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_override: Option<LinkOverride>,
+    /// The formula's `link_overwrite` globs, kept so `sps link` can honour them later.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub link_overwrite: Vec<String>,
+}
+
+/// Options for linking a keg into the prefix.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkOptions {
+    /// Replace files owned by other kegs or not managed by sps instead of aborting.
+    pub overwrite: bool,
+    /// Only work out what would be linked; change nothing.
+    pub dry_run: bool,
+}
+
+/// A prefix path that is already occupied by another keg or by something sps doesn't manage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkConflict {
+    pub target: PathBuf,
+    /// Formula owning the target, `None` if it isn't managed by sps.
+    pub owner: Option<String>,
+    /// Existing real directories are never replaced, even with `--overwrite`.
+    pub is_directory: bool,
+}
+
+impl fmt::Display for LinkConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.owner, self.is_directory) {
+            (_, true) => write!(f, "{} (existing directory)", self.target.display()),
+            (Some(owner), false) => write!(f, "{} (owned by {owner})", self.target.display()),
+            (None, false) => write!(f, "{} (not managed by sps)", self.target.display()),
+        }
+    }
+}
+
+/// What linking did, or would do when `dry_run` is set.
+#[derive(Debug, Clone, Default)]
+pub struct LinkOutcome {
+    /// Links and wrappers created (or to be created) outside the keg.
+    pub links: Vec<String>,
+    /// Targets replaced because of `--overwrite` or a `link_overwrite` glob.
+    pub overwritten: Vec<LinkConflict>,
+    /// Targets that block linking. Only non-empty for dry runs; otherwise linking fails.
+    pub conflicts: Vec<LinkConflict>,
+}
 
 /// Link all artifacts from a formula's installation directory.
-// Added Config parameter
+///
+/// Keg-only formulae only get their `opt/` link unless `link_override` forces a full link.
+/// Fails with [`SpsError::LinkConflict`] if a target belongs to another keg or isn't managed
+/// by sps; the `opt/` link is still created in that case.
 pub fn link_formula_artifacts(
     formula: &Formula,
     installed_keg_path: &Path,
-    config: &Config, // Added config
-) -> Result<()> {
+    config: &Config,
+    link_override: Option<LinkOverride>,
+    options: &LinkOptions,
+) -> Result<LinkOutcome> {
+    let keg = KegLinkInfo {
+        formula_name: formula.name(),
+        keg_only: formula.is_keg_only(),
+        link_overwrite: &formula.link_overwrite,
+    };
+    link_keg(&keg, installed_keg_path, config, link_override, options)
+}
+
+/// Re-links an installed keg with a new override, replacing the links it currently owns.
+/// Used by `sps link` and `sps unlink`, which don't need the formula definition. If the new
+/// links would conflict, the current ones are left alone and [`SpsError::LinkConflict`] is
+/// returned.
+pub fn relink_keg(
+    formula_name: &str,
+    installed_keg_path: &Path,
+    config: &Config,
+    link_override: Option<LinkOverride>,
+    options: &LinkOptions,
+) -> Result<LinkOutcome> {
+    let manifest = read_install_manifest(installed_keg_path)?.unwrap_or_default();
+    let keg = KegLinkInfo {
+        formula_name,
+        keg_only: manifest.keg_only,
+        link_overwrite: &manifest.link_overwrite,
+    };
+
+    let preview = link_keg(
+        &keg,
+        installed_keg_path,
+        config,
+        link_override,
+        &LinkOptions {
+            dry_run: true,
+            ..*options
+        },
+    )?;
+    if options.dry_run {
+        return Ok(preview);
+    }
+    if !preview.conflicts.is_empty() {
+        return Err(SpsError::LinkConflict(conflict_report(
+            formula_name,
+            &preview.conflicts,
+        )));
+    }
+
+    remove_manifest_links(&manifest.links, config);
+    link_keg(&keg, installed_keg_path, config, link_override, options)
+}
+
+/// Moves a formula's links from the keg they point into (`from`) to another installed keg of
+/// the same formula. `to` keeps `from`'s link override and uses its own manifest for keg-only
+/// and `link_overwrite`, falling back to `from`'s. If `to` would conflict with other kegs or
+/// unmanaged files, nothing is unlinked and [`SpsError::LinkConflict`] is returned.
+pub fn switch_keg(
+    formula_name: &str,
+    from: Option<&Path>,
+    to: &Path,
+    config: &Config,
+    options: &LinkOptions,
+) -> Result<LinkOutcome> {
+    let from_manifest = match from {
+        Some(from) => read_install_manifest(from)?,
+        None => None,
+    };
+    let to_manifest = read_install_manifest(to)?;
+    let link_override = match &from_manifest {
+        Some(manifest) => manifest.link_override,
+        None => to_manifest.as_ref().and_then(|m| m.link_override),
+    };
+    let recorded = to_manifest
+        .as_ref()
+        .or(from_manifest.as_ref())
+        .cloned()
+        .unwrap_or_default();
+    let keg = KegLinkInfo {
+        formula_name,
+        keg_only: recorded.keg_only,
+        link_overwrite: &recorded.link_overwrite,
+    };
+
+    let preview = link_keg(
+        &keg,
+        to,
+        config,
+        link_override,
+        &LinkOptions {
+            dry_run: true,
+            ..*options
+        },
+    )?;
+    if options.dry_run {
+        return Ok(preview);
+    }
+    if !preview.conflicts.is_empty() {
+        let mut report = format!(
+            "could not switch {formula_name} to {}; {} target(s) already exist:",
+            to.display(),
+            preview.conflicts.len()
+        );
+        for conflict in &preview.conflicts {
+            report.push_str(&format!("\n  {conflict}"));
+        }
+        report.push_str("\nUse --overwrite to replace them.");
+        return Err(SpsError::LinkConflict(report));
+    }
+
+    for manifest in [&from_manifest, &to_manifest].into_iter().flatten() {
+        remove_manifest_links(&manifest.links, config);
+    }
+    link_keg(&keg, to, config, link_override, options)
+}
+
+/// Caveats shown after installing a formula: its own caveats plus, for a keg-only formula
+/// that was not force-linked, how to use it from the `opt/` path.
+pub fn formula_caveats(
+    formula: &Formula,
+    config: &Config,
+    link_override: Option<LinkOverride>,
+) -> Option<String> {
+    let mut sections = Vec::new();
+    if let Some(caveats) = formula.caveats() {
+        sections.push(caveats.trim_end().to_string());
+    }
+    if formula.is_keg_only() && link_override != Some(LinkOverride::Forced) {
+        let name = formula.name();
+        let opt = config.formula_opt_path(name);
+        let reason = formula
+            .keg_only_reason
+            .as_ref()
+            .map(|r| r.describe())
+            .unwrap_or_else(|| "it is keg-only".to_string());
+        let mut text = format!(
+            "{name} is keg-only, which means it was not symlinked into {},\nbecause {reason}.",
+            config.sps_root().display()
+        );
+        if opt.join("bin").is_dir() || opt.join("sbin").is_dir() {
+            text.push_str(&format!(
+                "\n\nIf you need to have {name} first in your PATH, run:\n  export PATH=\"{}:$PATH\"",
+                opt.join("bin").display()
+            ));
+        }
+        if opt.join("lib").is_dir() || opt.join("include").is_dir() {
+            text.push_str(&format!(
+                "\n\nFor compilers to find {name} you may need to set:\n  export LDFLAGS=\"-L{}\"\n  export CPPFLAGS=\"-I{}\"",
+                opt.join("lib").display(),
+                opt.join("include").display()
+            ));
+        }
+        if opt.join("lib/pkgconfig").is_dir() {
+            text.push_str(&format!(
+                "\n\nFor pkg-config to find {name} you may need to set:\n  export PKG_CONFIG_PATH=\"{}\"",
+                opt.join("lib/pkgconfig").display()
+            ));
+        }
+        text.push_str(&format!(
+            "\n\nTo link it into {} anyway, run:\n  sps link --force {name}",
+            config.sps_root().display()
+        ));
+        sections.push(text);
+    }
+    if sections.is_empty() {
+        None
+    } else {
+        Some(sections.join("\n\n"))
+    }
+}
+
+/// What the linker needs to know about the formula being linked.
+struct KegLinkInfo<'a> {
+    formula_name: &'a str,
+    keg_only: bool,
+    link_overwrite: &'a [String],
+}
+
+fn link_keg(
+    keg: &KegLinkInfo<'_>,
+    installed_keg_path: &Path,
+    config: &Config,
+    link_override: Option<LinkOverride>,
+    options: &LinkOptions,
+) -> Result<LinkOutcome> {
+    let formula_name = keg.formula_name;
     debug!(
-        "Linking artifacts for {} from {}",
-        formula.name(),
-        installed_keg_path.display()
+        "Linking artifacts for {} from {} (keg_only={}, override={:?}, {:?})",
+        formula_name,
+        installed_keg_path.display(),
+        keg.keg_only,
+        link_override,
+        options
     );
 
     let formula_content_root = determine_content_root(installed_keg_path)?;
-    let mut symlinks_created = Vec::<String>::new();
+    let mut outcome = LinkOutcome::default();
 
     // Use config methods for paths
-    let opt_link_path = config.formula_opt_path(formula.name());
+    let opt_link_path = config.formula_opt_path(formula_name);
     let target_keg_dir = &formula_content_root;
+    let alias_path = formula_name
+        .split_once('@')
+        .map(|(base, _version)| config.opt_dir().join(base))
+        .filter(|alias| !alias.exists());
+
+    let link_into_prefix = match link_override {
+        Some(LinkOverride::Forced) => true,
+        Some(LinkOverride::Unlinked) => false,
+        None => !keg.keg_only,
+    };
+    let plan = if link_into_prefix {
+        Some(plan_prefix_links(
+            keg,
+            &formula_content_root,
+            config,
+            options.overwrite,
+        )?)
+    } else {
+        debug!(
+            "  {} is {}; only the opt link will be created",
+            formula_name,
+            if keg.keg_only { "keg-only" } else { "unlinked" }
+        );
+        None
+    };
+
+    if options.dry_run {
+        outcome
+            .links
+            .push(opt_link_path.to_string_lossy().to_string());
+        if let Some(alias) = &alias_path {
+            outcome.links.push(alias.to_string_lossy().to_string());
+        }
+        if let Some(plan) = plan {
+            outcome
+                .links
+                .extend(plan.targets().map(|t| t.to_string_lossy().to_string()));
+            outcome.overwritten = plan.overwritten;
+            outcome.conflicts = plan.conflicts;
+        }
+        return Ok(outcome);
+    }
 
     remove_existing_link_target(&opt_link_path)?;
     unix_fs::symlink(target_keg_dir, &opt_link_path).map_err(|e| {
         SpsError::Io(std::sync::Arc::new(std::io::Error::new(
             e.kind(),
-            format!("Failed to create opt symlink for {}: {}", formula.name(), e),
+            format!("Failed to create opt symlink for {}: {}", formula_name, e),
         )))
     })?;
-    symlinks_created.push(opt_link_path.to_string_lossy().to_string());
+    outcome
+        .links
+        .push(opt_link_path.to_string_lossy().to_string());
     debug!(
         "  Linked opt path: {} -> {}",
         opt_link_path.display(),
         target_keg_dir.display()
     );
 
-    if let Some((base, _version)) = formula.name().split_once('@') {
-        let alias_path = config.opt_dir().join(base); // Use config.opt_dir()
-        if !alias_path.exists() {
-            match unix_fs::symlink(target_keg_dir, &alias_path) {
-                Ok(_) => {
-                    debug!(
-                        "  Added un‑versioned opt alias: {} -> {}",
-                        alias_path.display(),
-                        target_keg_dir.display()
-                    );
-                    symlinks_created.push(alias_path.to_string_lossy().to_string());
-                }
-                Err(e) => {
-                    debug!(
-                        "  Could not create opt alias {}: {}",
-                        alias_path.display(),
-                        e
-                    );
-                }
+    if let Some(alias_path) = alias_path {
+        match unix_fs::symlink(target_keg_dir, &alias_path) {
+            Ok(_) => {
+                debug!(
+                    "  Added un‑versioned opt alias: {} -> {}",
+                    alias_path.display(),
+                    target_keg_dir.display()
+                );
+                outcome.links.push(alias_path.to_string_lossy().to_string());
+            }
+            Err(e) => {
+                debug!(
+                    "  Could not create opt alias {}: {}",
+                    alias_path.display(),
+                    e
+                );
             }
         }
     }
 
+    let mut manifest = FormulaInstallManifest {
+        links: Vec::new(),
+        keg_only: keg.keg_only,
+        link_override,
+        link_overwrite: keg.link_overwrite.to_vec(),
+    };
+
+    if let Some(plan) = plan {
+        if !plan.conflicts.is_empty() {
+            // Record what was linked so far so a later unlink/relink stays accurate.
+            manifest.links = outcome.links;
+            write_install_manifest(installed_keg_path, &manifest)?;
+            return Err(SpsError::LinkConflict(conflict_report(
+                formula_name,
+                &plan.conflicts,
+            )));
+        }
+        outcome.overwritten = plan.overwritten.clone();
+        execute_link_plan(plan, &formula_content_root, &mut outcome.links)?;
+    }
+
+    manifest.links = outcome.links.clone();
+    write_install_manifest(installed_keg_path, &manifest)?;
+
+    debug!(
+        "Successfully completed linking artifacts for {}",
+        formula_name
+    );
+    Ok(outcome)
+}
+
+fn conflict_report(formula_name: &str, conflicts: &[LinkConflict]) -> String {
+    let mut report = format!(
+        "could not link {formula_name} into the prefix; {} target(s) already exist:",
+        conflicts.len()
+    );
+    for conflict in conflicts {
+        report.push_str(&format!("\n  {conflict}"));
+    }
+    report.push_str(&format!(
+        "\nTo replace them, run:\n  sps link --overwrite {formula_name}\nTo list what would be replaced, run:\n  sps link --overwrite --dry-run {formula_name}"
+    ));
+    if conflicts.iter().any(|c| c.is_directory) {
+        report.push_str("\nExisting directories are never replaced and must be removed by hand.");
+    }
+    report
+}
+
+// --- Link planning ---
+// Targets are inspected before anything is touched, so a conflict aborts the link without
+// leaving a half-linked keg behind.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkKind {
+    Symlink,
+    Wrapper,
+}
+
+#[derive(Debug, Clone)]
+struct PlannedLink {
+    source: PathBuf,
+    target: PathBuf,
+    kind: LinkKind,
+    /// Keg whose manifest lists the target being replaced.
+    displaced_keg: Option<PathBuf>,
+}
+
+/// Another keg's directory symlink that has to become a real directory so both kegs can
+/// link into it. The other keg's children are re-linked individually.
+#[derive(Debug, Clone)]
+struct PlannedExplode {
+    target: PathBuf,
+    owner_keg: PathBuf,
+    owner_dir: PathBuf,
+}
+
+#[derive(Debug, Default)]
+struct LinkPlan {
+    prefix_dirs: Vec<PathBuf>,
+    explodes: Vec<PlannedExplode>,
+    links: Vec<PlannedLink>,
+    overwritten: Vec<LinkConflict>,
+    conflicts: Vec<LinkConflict>,
+}
+
+impl LinkPlan {
+    fn targets(&self) -> impl Iterator<Item = &PathBuf> {
+        self.links.iter().map(|l| &l.target)
+    }
+}
+
+/// What currently sits at a link target.
+#[derive(Debug)]
+enum Occupant {
+    Vacant,
+    /// A link or wrapper of this formula, or a dangling link; safe to replace.
+    Replaceable,
+    /// A link or wrapper belonging to another keg. `dir` is set when it links a directory.
+    Foreign {
+        owner: String,
+        keg: PathBuf,
+        dir: Option<PathBuf>,
+    },
+    RealDir,
+    Unmanaged,
+}
+
+struct PlanContext<'a> {
+    keg: &'a KegLinkInfo<'a>,
+    prefix: PathBuf,
+    cellar_dir: PathBuf,
+    overwrite: bool,
+    overwrite_globs: Vec<glob::Pattern>,
+}
+
+impl PlanContext<'_> {
+    fn may_overwrite(&self, target: &Path) -> bool {
+        if self.overwrite {
+            return true;
+        }
+        let relative = target.strip_prefix(&self.prefix).unwrap_or(target);
+        self.overwrite_globs
+            .iter()
+            .any(|pattern| pattern.matches_path(relative))
+    }
+
+    /// The formula name and keg directory owning a path inside the Cellar.
+    fn keg_owner(&self, path: &Path) -> Option<(String, PathBuf)> {
+        let relative = path.strip_prefix(&self.cellar_dir).ok()?;
+        let mut components = relative.components();
+        let name = components.next()?.as_os_str().to_string_lossy().to_string();
+        let version = components.next()?.as_os_str();
+        let keg = self.cellar_dir.join(&name).join(version);
+        Some((name, keg))
+    }
+
+    fn classify_owned(&self, pointee: &Path) -> Occupant {
+        if !pointee.exists() {
+            return Occupant::Replaceable;
+        }
+        match self.keg_owner(pointee) {
+            Some((owner, _)) if owner == self.keg.formula_name => Occupant::Replaceable,
+            Some((owner, keg)) => Occupant::Foreign {
+                owner,
+                keg,
+                dir: pointee.is_dir().then(|| pointee.to_path_buf()),
+            },
+            None => Occupant::Unmanaged,
+        }
+    }
+
+    fn inspect(&self, target: &Path) -> Occupant {
+        let metadata = match target.symlink_metadata() {
+            Ok(m) => m,
+            Err(_) => return Occupant::Vacant,
+        };
+        if metadata.file_type().is_symlink() {
+            let pointee = match fs::read_link(target) {
+                Ok(p) if p.is_relative() => target.parent().unwrap_or(Path::new("/")).join(p),
+                Ok(p) => p,
+                Err(_) => return Occupant::Unmanaged,
+            };
+            return self.classify_owned(&pointee);
+        }
+        if metadata.is_dir() {
+            return Occupant::RealDir;
+        }
+        match wrapper_target(target) {
+            Some(exec_target) => self.classify_owned(&exec_target),
+            None => Occupant::Unmanaged,
+        }
+    }
+}
+
+/// Works out every link needed to put a keg's `lib`, `include`, `share` and executables into
+/// the prefix, along with any conflicts.
+fn plan_prefix_links(
+    keg: &KegLinkInfo<'_>,
+    formula_content_root: &Path,
+    config: &Config,
+    overwrite: bool,
+) -> Result<LinkPlan> {
+    let overwrite_globs = keg
+        .link_overwrite
+        .iter()
+        .filter_map(|pattern| match glob::Pattern::new(pattern) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                debug!("Ignoring invalid link_overwrite glob '{}': {}", pattern, e);
+                None
+            }
+        })
+        .collect();
+    let ctx = PlanContext {
+        keg,
+        prefix: config.sps_root().to_path_buf(),
+        cellar_dir: config.cellar_dir(),
+        overwrite,
+        overwrite_globs,
+    };
+    let mut plan = LinkPlan::default();
+
     let standard_artifact_dirs = ["lib", "include", "share"];
     for dir_name in &standard_artifact_dirs {
         let source_subdir = formula_content_root.join(dir_name);
+        if !source_subdir.is_dir() {
+            continue;
+        }
         // Use config.prefix() for target base
         let target_prefix_subdir = config.sps_root().join(dir_name);
-
-        if source_subdir.is_dir() {
-            fs::create_dir_all(&target_prefix_subdir)?;
-            for entry in fs::read_dir(&source_subdir)? {
-                let entry = entry?;
-                let source_item_path = entry.path();
-                let file_name = entry.file_name();
-                if file_name.to_string_lossy().starts_with('.') {
-                    continue;
-                }
-
-                let target_link = target_prefix_subdir.join(&file_name);
-                remove_existing_link_target(&target_link)?;
-                unix_fs::symlink(&source_item_path, &target_link).ok(); // ignore errors for individual links?
-                symlinks_created.push(target_link.to_string_lossy().to_string());
-                debug!(
-                    "  Linked {} -> {}",
-                    target_link.display(),
-                    source_item_path.display()
-                );
+        plan.prefix_dirs.push(target_prefix_subdir.clone());
+        for entry in fs::read_dir(&source_subdir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
             }
+            plan_entry(
+                &ctx,
+                &entry.path(),
+                &target_prefix_subdir.join(entry.file_name()),
+                None,
+                &mut plan,
+            )?;
         }
     }
 
     // Use config.bin_dir() for target bin
     let target_bin_dir = config.bin_dir();
-    fs::create_dir_all(&target_bin_dir).ok();
+    let mut executables = Vec::new();
+    for dir_name in ["bin", "libexec"] {
+        let source_dir = formula_content_root.join(dir_name);
+        if source_dir.is_dir() {
+            collect_executables(&source_dir, &mut executables);
+        }
+    }
+    if !executables.is_empty() {
+        plan.prefix_dirs.push(target_bin_dir.clone());
+    }
+    for source in executables {
+        let Some(file_name) = source.file_name() else {
+            continue;
+        };
+        let target = target_bin_dir.join(file_name);
+        let occupant = ctx.inspect(&target);
+        plan_leaf(&ctx, source, target, LinkKind::Wrapper, occupant, &mut plan);
+    }
 
-    let source_bin_dir = formula_content_root.join("bin");
-    if source_bin_dir.is_dir() {
-        create_wrappers_in_dir(
-            &source_bin_dir,
-            &target_bin_dir,
-            &formula_content_root,
-            &mut symlinks_created,
-        )?;
-    }
-    let source_libexec_dir = formula_content_root.join("libexec");
-    if source_libexec_dir.is_dir() {
-        create_wrappers_in_dir(
-            &source_libexec_dir,
-            &target_bin_dir,
-            &formula_content_root,
-            &mut symlinks_created,
-        )?;
+    Ok(plan)
+}
+
+/// Plans one entry of `lib`/`include`/`share`. `virtual_owner` is set while planning inside a
+/// directory that will be exploded, whose current contents are the owner's directory.
+fn plan_entry(
+    ctx: &PlanContext<'_>,
+    source: &Path,
+    target: &Path,
+    virtual_owner: Option<(&str, &Path, &Path)>,
+    plan: &mut LinkPlan,
+) -> Result<()> {
+    let occupant = match virtual_owner {
+        Some((owner, keg, owner_dir)) => {
+            let existing = owner_dir.join(target.file_name().unwrap_or_default());
+            if existing.symlink_metadata().is_ok() {
+                Occupant::Foreign {
+                    owner: owner.to_string(),
+                    keg: keg.to_path_buf(),
+                    dir: existing.is_dir().then_some(existing),
+                }
+            } else {
+                Occupant::Vacant
+            }
+        }
+        None => ctx.inspect(target),
+    };
+    let source_is_dir = source
+        .symlink_metadata()
+        .map(|m| m.is_dir())
+        .unwrap_or(false);
+
+    match occupant {
+        Occupant::RealDir if source_is_dir => {
+            for entry in fs::read_dir(source)? {
+                let entry = entry?;
+                plan_entry(
+                    ctx,
+                    &entry.path(),
+                    &target.join(entry.file_name()),
+                    None,
+                    plan,
+                )?;
+            }
+        }
+        Occupant::Foreign {
+            owner,
+            keg,
+            dir: Some(owner_dir),
+        } if source_is_dir => {
+            plan.explodes.push(PlannedExplode {
+                target: target.to_path_buf(),
+                owner_keg: keg.clone(),
+                owner_dir: owner_dir.clone(),
+            });
+            for entry in fs::read_dir(source)? {
+                let entry = entry?;
+                plan_entry(
+                    ctx,
+                    &entry.path(),
+                    &target.join(entry.file_name()),
+                    Some((&owner, &keg, &owner_dir)),
+                    plan,
+                )?;
+            }
+        }
+        occupant => plan_leaf(
+            ctx,
+            source.to_path_buf(),
+            target.to_path_buf(),
+            LinkKind::Symlink,
+            occupant,
+            plan,
+        ),
     }
+    Ok(())
+}
 
-    write_install_manifest(installed_keg_path, &symlinks_created)?;
+fn plan_leaf(
+    ctx: &PlanContext<'_>,
+    source: PathBuf,
+    target: PathBuf,
+    kind: LinkKind,
+    occupant: Occupant,
+    plan: &mut LinkPlan,
+) {
+    let (owner, displaced_keg, is_directory) = match occupant {
+        Occupant::Vacant | Occupant::Replaceable => {
+            plan.links.push(PlannedLink {
+                source,
+                target,
+                kind,
+                displaced_keg: None,
+            });
+            return;
+        }
+        Occupant::Foreign { owner, keg, .. } => (Some(owner), Some(keg), false),
+        Occupant::Unmanaged => (None, None, false),
+        Occupant::RealDir => (None, None, true),
+    };
+    let conflict = LinkConflict {
+        target: target.clone(),
+        owner,
+        is_directory,
+    };
+    if !is_directory && ctx.may_overwrite(&target) {
+        debug!("  Will overwrite {}", conflict);
+        plan.overwritten.push(conflict);
+        plan.links.push(PlannedLink {
+            source,
+            target,
+            kind,
+            displaced_keg,
+        });
+    } else {
+        plan.conflicts.push(conflict);
+    }
+}
 
+fn collect_executables(source_dir: &Path, executables: &mut Vec<PathBuf>) {
     debug!(
-        "Successfully completed linking artifacts for {}",
-        formula.name()
+        "Scanning for executables in {} to create wrappers",
+        source_dir.display()
     );
-    Ok(())
+    let entries = match fs::read_dir(source_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!(
+                "Failed to read source directory {}: {}",
+                source_dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let source_item_path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if source_item_path.is_dir() {
+            collect_executables(&source_item_path, executables);
+        } else if source_item_path.is_file() {
+            match is_executable(&source_item_path) {
+                Ok(true) => executables.push(source_item_path),
+                Ok(false) => { /* Not executable, ignore */ }
+                Err(e) => {
+                    debug!(
+                        "    Could not check executable status for {}: {}",
+                        source_item_path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
 }
 
-// remove_existing_link_target, write_install_manifest remain mostly unchanged internally) ...
-fn create_wrappers_in_dir(
-    source_dir: &Path,
-    target_bin_dir: &Path,
+fn execute_link_plan(
+    plan: LinkPlan,
     formula_content_root: &Path,
-    wrappers_created: &mut Vec<String>,
+    links_created: &mut Vec<String>,
 ) -> Result<()> {
-    debug!(
-        "Scanning for executables in {} to create wrappers in {}",
-        source_dir.display(),
-        target_bin_dir.display()
-    );
-    match fs::read_dir(source_dir) {
-        Ok(entries) => {
-            for entry_result in entries {
-                match entry_result {
-                    Ok(entry) => {
-                        let source_item_path = entry.path();
-                        let file_name = entry.file_name();
-
-                        if file_name.to_string_lossy().starts_with('.') {
-                            continue;
-                        }
+    for dir in &plan.prefix_dirs {
+        fs::create_dir_all(dir)?;
+    }
 
-                        if source_item_path.is_dir() {
-                            create_wrappers_in_dir(
-                                &source_item_path,
-                                target_bin_dir,
-                                formula_content_root,
-                                wrappers_created,
-                            )?;
-                        } else if source_item_path.is_file() {
-                            match is_executable(&source_item_path) {
-                                Ok(true) => {
-                                    let wrapper_path = target_bin_dir.join(&file_name);
-                                    debug!("Found executable: {}", source_item_path.display());
-                                    if remove_existing_link_target(&wrapper_path).is_ok() {
-                                        debug!(
-                                            "    Creating wrapper script: {} -> {}",
-                                            wrapper_path.display(),
-                                            source_item_path.display()
-                                        );
-                                        match create_wrapper_script(
-                                            &source_item_path,
-                                            &wrapper_path,
-                                            formula_content_root,
-                                        ) {
-                                            Ok(_) => {
-                                                debug!(
-                                                    "  Created wrapper {} -> {}",
-                                                    wrapper_path.display(),
-                                                    source_item_path.display()
-                                                );
-                                                wrappers_created.push(
-                                                    wrapper_path.to_string_lossy().to_string(),
-                                                );
-                                            }
-                                            Err(e) => {
-                                                error!(
-                                                    "Failed to create wrapper script {} -> {}: {}",
-                                                    wrapper_path.display(),
-                                                    source_item_path.display(),
-                                                    e
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                                Ok(false) => { /* Not executable, ignore */ }
-                                Err(e) => {
-                                    debug!(
-                                        "    Could not check executable status for {}: {}",
-                                        source_item_path.display(),
-                                        e
-                                    );
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        debug!(
-                            "  Failed to process directory entry in {}: {}",
-                            source_dir.display(),
+    for explode in &plan.explodes {
+        debug!(
+            "  Splitting {} into a directory shared with {}",
+            explode.target.display(),
+            explode.owner_keg.display()
+        );
+        fs::remove_file(&explode.target)?;
+        fs::create_dir_all(&explode.target)?;
+        let mut owner_links = Vec::new();
+        for entry in fs::read_dir(&explode.owner_dir)? {
+            let entry = entry?;
+            let link = explode.target.join(entry.file_name());
+            unix_fs::symlink(entry.path(), &link)?;
+            owner_links.push(link.to_string_lossy().to_string());
+        }
+        update_owner_manifest(&explode.owner_keg, &explode.target, &owner_links);
+    }
+
+    for planned in plan.links {
+        if let Some(keg) = &planned.displaced_keg {
+            update_owner_manifest(keg, &planned.target, &[]);
+        }
+        if let Some(parent) = planned.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        remove_existing_link_target(&planned.target)?;
+        match planned.kind {
+            LinkKind::Symlink => {
+                unix_fs::symlink(&planned.source, &planned.target).map_err(|e| {
+                    SpsError::Io(std::sync::Arc::new(std::io::Error::new(
+                        e.kind(),
+                        format!(
+                            "Failed to link {} -> {}: {}",
+                            planned.target.display(),
+                            planned.source.display(),
                             e
-                        );
-                    }
-                }
+                        ),
+                    )))
+                })?;
+            }
+            LinkKind::Wrapper => {
+                create_wrapper_script(&planned.source, &planned.target, formula_content_root)?;
             }
         }
+        debug!(
+            "  Linked {} -> {}",
+            planned.target.display(),
+            planned.source.display()
+        );
+        let target = planned.target.to_string_lossy().to_string();
+        if !links_created.contains(&target) {
+            links_created.push(target);
+        }
+    }
+    Ok(())
+}
+
+/// Keeps another keg's manifest truthful after one of its links was replaced or split up.
+fn update_owner_manifest(owner_keg: &Path, removed: &Path, added: &[String]) {
+    let mut manifest = match read_install_manifest(owner_keg) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => return,
         Err(e) => {
             debug!(
-                "Failed to read source directory {}: {}",
-                source_dir.display(),
+                "Could not read install manifest of {}: {}",
+                owner_keg.display(),
                 e
             );
+            return;
         }
+    };
+    let removed = removed.to_string_lossy();
+    manifest.links.retain(|link| *link != removed);
+    manifest.links.extend(added.iter().cloned());
+    if let Err(e) = write_install_manifest(owner_keg, &manifest) {
+        error!(
+            "Failed to update install manifest of {}: {}",
+            owner_keg.display(),
+            e
+        );
     }
-    Ok(())
 }
+
+/// For a wrapper script generated by sps, the executable it runs.
+fn wrapper_target(path: &Path) -> Option<PathBuf> {
+    let metadata = path.metadata().ok()?;
+    if metadata.len() > 64 * 1024 {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    if !content.contains(WRAPPER_MARKER) {
+        return None;
+    }
+    content.lines().find_map(|line| {
+        line.strip_prefix("exec \"")
+            .and_then(|rest| rest.strip_suffix("\" \"$@\""))
+            .map(PathBuf::from)
+    })
+}
+
 fn create_wrapper_script(
     target_executable: &Path,
     wrapper_path: &Path,
@@ -242,7 +895,8 @@ fn create_wrapper_script(
 
     let mut script_content = String::new();
     script_content.push_str("#!/bin/bash\n");
-    script_content.push_str("# Wrapper script generated by sp\n");
+    script_content.push_str(WRAPPER_MARKER);
+    script_content.push('\n');
     script_content.push_str("set -e\n\n");
 
     if perl_lib_path.exists() && perl_lib_path.is_dir() {
@@ -444,15 +1098,37 @@ fn remove_existing_link_target(path: &Path) -> Result<()> {
     }
 }
 
-fn write_install_manifest(installed_keg_path: &Path, symlinks_created: &[String]) -> Result<()> {
+/// Reads a keg's `INSTALL_MANIFEST.json`. Manifests written before keg-only support were a
+/// bare list of links and are read as such.
+pub fn read_install_manifest(installed_keg_path: &Path) -> Result<Option<FormulaInstallManifest>> {
+    let manifest_path = installed_keg_path.join("INSTALL_MANIFEST.json");
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+    let manifest_str = fs::read_to_string(&manifest_path)?;
+    if let Ok(manifest) = serde_json::from_str::<FormulaInstallManifest>(&manifest_str) {
+        return Ok(Some(manifest));
+    }
+    let links = serde_json::from_str::<Vec<String>>(&manifest_str)
+        .map_err(|e| SpsError::Json(std::sync::Arc::new(e)))?;
+    Ok(Some(FormulaInstallManifest {
+        links,
+        ..Default::default()
+    }))
+}
+
+pub fn write_install_manifest(
+    installed_keg_path: &Path,
+    manifest: &FormulaInstallManifest,
+) -> Result<()> {
     let manifest_path = installed_keg_path.join("INSTALL_MANIFEST.json");
     debug!("Writing install manifest to: {}", manifest_path.display());
-    match serde_json::to_string_pretty(&symlinks_created) {
+    match serde_json::to_string_pretty(manifest) {
         Ok(manifest_json) => match fs::write(&manifest_path, manifest_json) {
             Ok(_) => {
                 debug!(
                     "Wrote install manifest with {} links: {}",
-                    symlinks_created.len(),
+                    manifest.links.len(),
                     manifest_path.display()
                 );
             }
@@ -473,6 +1149,75 @@ fn write_install_manifest(installed_keg_path: &Path, symlinks_created: &[String]
     Ok(())
 }
 
+/// Removes the links listed in a manifest, skipping anything outside the managed prefix
+/// directories. Errors are logged, not returned.
+fn remove_manifest_links(links_to_remove: &[String], config: &Config) {
+    let mut unlinked_count = 0;
+    let mut removal_errors = 0;
+    // Use Config to get base paths for checking ownership/safety
+    let opt_base = config.opt_dir();
+    let bin_base = config.bin_dir();
+    let lib_base = config.sps_root().join("lib");
+    let include_base = config.sps_root().join("include");
+    let share_base = config.sps_root().join("share");
+
+    for link_str in links_to_remove {
+        let link_path = PathBuf::from(link_str);
+        // Check if it's under a managed directory (safety check)
+        if link_path.starts_with(&opt_base)
+            || link_path.starts_with(&bin_base)
+            || link_path.starts_with(&lib_base)
+            || link_path.starts_with(&include_base)
+            || link_path.starts_with(&share_base)
+        {
+            match remove_existing_link_target(&link_path) {
+                Ok(_) => {
+                    debug!("Removed link/wrapper: {}", link_path.display());
+                    unlinked_count += 1;
+                    // Directories created while linking leaves are removed once empty.
+                    let mut parent = link_path.parent();
+                    while let Some(dir) = parent {
+                        if [&opt_base, &bin_base, &lib_base, &include_base, &share_base]
+                            .iter()
+                            .any(|base| dir == base.as_path())
+                            || fs::remove_dir(dir).is_err()
+                        {
+                            break;
+                        }
+                        parent = dir.parent();
+                    }
+                }
+                Err(e) => {
+                    // Log error but continue trying to remove others
+                    debug!(
+                        "Failed to remove link/wrapper {}: {}",
+                        link_path.display(),
+                        e
+                    );
+                    removal_errors += 1;
+                }
+            }
+        } else {
+            // This indicates a potentially corrupted manifest or a link outside expected areas
+            error!(
+                "Manifest contains unexpected link path, skipping removal: {}",
+                link_path.display()
+            );
+            removal_errors += 1;
+        }
+    }
+    debug!(
+        "Attempted to unlink {} artifacts based on manifest.",
+        unlinked_count
+    );
+    if removal_errors > 0 {
+        error!(
+            "Encountered {} errors while removing links listed in manifest.",
+            removal_errors
+        );
+    }
+}
+
 pub fn unlink_formula_artifacts(
     formula_name: &str,
     version_str_full: &str, // e.g., "1.2.3_1"
@@ -484,110 +1229,38 @@ pub fn unlink_formula_artifacts(
     );
     // Use config method to get expected keg path based on name and version string
     let expected_keg_path = config.formula_keg_path(formula_name, version_str_full);
-    let manifest_path = expected_keg_path.join("INSTALL_MANIFEST.json"); // Manifest *inside* the keg
-
-    if manifest_path.is_file() {
-        debug!("Reading install manifest: {}", manifest_path.display());
-        match fs::read_to_string(&manifest_path) {
-            Ok(manifest_str) => {
-                match serde_json::from_str::<Vec<String>>(&manifest_str) {
-                    Ok(links_to_remove) => {
-                        let mut unlinked_count = 0;
-                        let mut removal_errors = 0;
-                        if links_to_remove.is_empty() {
-                            debug!(
-                                "Install manifest {} is empty. Cannot perform manifest-based unlink.",
-                                manifest_path.display()
-                            );
-                        } else {
-                            // Use Config to get base paths for checking ownership/safety
-                            let opt_base = config.opt_dir();
-                            let bin_base = config.bin_dir();
-                            let lib_base = config.sps_root().join("lib");
-                            let include_base = config.sps_root().join("include");
-                            let share_base = config.sps_root().join("share");
-                            // Add etc, sbin etc. if needed
-
-                            for link_str in links_to_remove {
-                                let link_path = PathBuf::from(link_str);
-                                // Check if it's under a managed directory (safety check)
-                                if link_path.starts_with(&opt_base)
-                                    || link_path.starts_with(&bin_base)
-                                    || link_path.starts_with(&lib_base)
-                                    || link_path.starts_with(&include_base)
-                                    || link_path.starts_with(&share_base)
-                                {
-                                    match remove_existing_link_target(&link_path) {
-                                        // Use helper
-                                        Ok(_) => {
-                                            debug!("Removed link/wrapper: {}", link_path.display());
-                                            unlinked_count += 1;
-                                        }
-                                        Err(e) => {
-                                            // Log error but continue trying to remove others
-                                            debug!(
-                                                "Failed to remove link/wrapper {}: {}",
-                                                link_path.display(),
-                                                e
-                                            );
-                                            removal_errors += 1;
-                                        }
-                                    }
-                                } else {
-                                    // This indicates a potentially corrupted manifest or a link
-                                    // outside expected areas
-                                    error!(
-                                        "Manifest contains unexpected link path, skipping removal: {}",
-                                        link_path.display()
-                                    );
-                                    removal_errors += 1; // Count as an error/problem
-                                }
-                            }
-                        }
-                        debug!(
-                            "Attempted to unlink {} artifacts based on manifest.",
-                            unlinked_count
-                        );
-                        if removal_errors > 0 {
-                            error!(
-                                "Encountered {} errors while removing links listed in manifest.",
-                                removal_errors
-                            );
-                            // Decide if this should be a hard error - perhaps not if keg is being
-                            // removed anyway? For now, just log
-                            // warnings.
-                        }
-                        Ok(()) // Return Ok even if some links failed, keg removal will happen next
-                    }
-                    Err(e) => {
-                        error!(
-                            "Failed to parse formula install manifest {}: {}. Proceeding without detailed unlink.",
-                            manifest_path.display(),
-                            e
-                        );
-                        // Don't error out, allow keg removal to proceed.
-                        Ok(())
-                    }
-                }
-            }
-            Err(e) => {
-                error!(
-                    "Failed to read formula install manifest {}: {}. Proceeding without detailed unlink.",
-                    manifest_path.display(),
-                    e
+    unlink_keg(&expected_keg_path, config)
+}
+
+/// Removes the links recorded in a keg's install manifest, wherever the keg currently lives.
+pub fn unlink_keg(installed_keg_path: &Path, config: &Config) -> Result<()> {
+    match read_install_manifest(installed_keg_path) {
+        Ok(Some(manifest)) => {
+            if manifest.links.is_empty() {
+                debug!(
+                    "Install manifest in {} is empty. Cannot perform manifest-based unlink.",
+                    installed_keg_path.display()
                 );
-                // Don't error out, allow keg removal to proceed.
-                Ok(())
+            } else {
+                remove_manifest_links(&manifest.links, config);
             }
         }
-    } else {
-        debug!(
-            "Warning: No install manifest found at {}. Cannot perform detailed unlink.",
-            manifest_path.display()
-        );
-        // Don't error out, allow keg removal to proceed.
-        Ok(())
+        Ok(None) => {
+            debug!(
+                "Warning: No install manifest found in {}. Cannot perform detailed unlink.",
+                installed_keg_path.display()
+            );
+        }
+        Err(e) => {
+            error!(
+                "Failed to read formula install manifest in {}: {}. Proceeding without detailed unlink.",
+                installed_keg_path.display(),
+                e
+            );
+        }
     }
+    // Don't error out, allow keg removal to proceed.
+    Ok(())
 }
 
 fn is_executable(path: &Path) -> Result<bool> {
@@ -4,9 +4,12 @@
 // Refactored to separate immutable analysis from mutable patching to fix borrow checker errors.
 
 use std::collections::HashMap;
+#[cfg(target_os = "macos")]
 use std::fs;
+#[cfg(target_os = "macos")]
 use std::io::Write; // Keep for write_patched_buffer
 use std::path::Path;
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
 use std::process::{Command as StdCommand, Stdio}; // Keep for codesign
 
 // --- Imports needed for Mach-O patching (macOS only) ---
@@ -26,8 +29,12 @@ use object::{
     FileKind,
     ReadRef,
 };
-use sps_common::error::{Result, SpsError};
+use sps_common::error::Result;
+#[cfg(target_os = "macos")]
+use sps_common::error::SpsError;
+#[cfg(target_os = "macos")]
 use tempfile::NamedTempFile;
+#[cfg(target_os = "macos")]
 use tracing::{debug, error};
 
 // --- Platform‑specific constants for Mach‑O magic detection ---
@@ -367,6 +374,7 @@ where
 
 /// Helper to replace placeholders in a string based on the replacements map.
 /// Returns `Some(String)` with replacements if any were made, `None` otherwise.
+#[cfg(target_os = "macos")]
 fn find_and_replace_placeholders(
     current_path: &str,
     replacements: &HashMap<String, String>,
@@ -515,10 +523,3 @@ fn resign_binary(_path: &Path) -> Result<()> {
     // No re-signing typically needed on Intel Macs after ad-hoc patching
     Ok(())
 }
-
-// No-op stub for resigning Innovations on non-macOS platforms
-#[cfg(not(target_os = "macos"))]
-fn resign_binary(_path: &Path) -> Result<()> {
-    // Resigning is a macOS concept
-    Ok(())
-}
@@ -1,15 +1,19 @@
 // ===== sps-core/src/build/formula/mod.rs =====
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use sps_common::config::Config;
+use sps_common::dependency::DependencyExt;
 use sps_common::error::{Result, SpsError};
+use sps_common::keg::KegRegistry;
 use sps_common::model::formula::Formula;
 use tracing::{debug, error};
 
 // Declare submodules
+pub mod elf;
 pub mod exec;
 pub mod link;
 pub mod macho;
@@ -161,11 +165,14 @@ pub fn get_formula_cellar_path(formula: &Formula, config: &Config) -> PathBuf {
     config.formula_cellar_dir(formula.name())
 }
 
-// --- write_receipt (unchanged) ---
+/// Writes `INSTALL_RECEIPT.json`. `installed_on_request` is false for kegs that were only
+/// installed as a dependency; the installed runtime dependencies are recorded with their versions.
 pub fn write_receipt(
     formula: &Formula,
     install_dir: &Path,
     installation_type: &str, // "bottle" or "source"
+    installed_on_request: bool,
+    config: &Config,
 ) -> Result<()> {
     let receipt_path = install_dir.join("INSTALL_RECEIPT.json");
     let receipt_file = File::create(&receipt_path);
@@ -197,6 +204,7 @@ pub fn write_receipt(
 
     let receipt = serde_json::json!({
         "name": formula.name, "version": formula.version_str_full(), "time": timestamp,
+        "version_scheme": formula.version_scheme,
         "source": { "type": "api", "url": formula.url, },
         "built_on": {
             "os": std::env::consts::OS, "arch": std::env::consts::ARCH,
@@ -204,6 +212,9 @@ pub fn write_receipt(
          },
         "installation_type": installation_type,
         "resources_installed": resources_installed,
+        "installed_on_request": installed_on_request,
+        "installed_as_dependency": !installed_on_request,
+        "runtime_dependencies": receipt_runtime_dependencies(formula, config),
     });
 
     let receipt_json = match serde_json::to_string_pretty(&receipt) {
@@ -225,6 +236,90 @@ pub fn write_receipt(
     Ok(())
 }
 
+/// The formula's installed runtime dependencies and, through their receipts, theirs.
+fn receipt_runtime_dependencies(formula: &Formula, config: &Config) -> Vec<serde_json::Value> {
+    let keg_registry = KegRegistry::new(config.clone());
+    let mut seen = HashSet::new();
+    let mut direct = Vec::new();
+    let mut indirect = Vec::new();
+    for dep in formula.dependencies().unwrap_or_default().runtime() {
+        let Ok(Some(keg)) = keg_registry.get_installed_keg(&dep.name) else {
+            debug!(
+                "Runtime dependency '{}' of {} is not installed; not recording it",
+                dep.name, formula.name
+            );
+            continue;
+        };
+        if !seen.insert(keg.name.clone()) {
+            continue;
+        }
+        direct.push(serde_json::json!({
+            "full_name": keg.name, "version": keg.version_str, "declared_directly": true,
+        }));
+        let inherited = read_receipt(&keg.path)
+            .and_then(|receipt| receipt.get("runtime_dependencies").cloned())
+            .and_then(|deps| deps.as_array().cloned())
+            .unwrap_or_default();
+        for inherited_dep in inherited {
+            let (Some(name), Some(version)) = (
+                inherited_dep.get("full_name").and_then(|n| n.as_str()),
+                inherited_dep.get("version").and_then(|v| v.as_str()),
+            ) else {
+                continue;
+            };
+            indirect.push((name.to_string(), version.to_string()));
+        }
+    }
+    for (name, version) in indirect {
+        if seen.insert(name.clone()) {
+            direct.push(serde_json::json!({
+                "full_name": name, "version": version, "declared_directly": false,
+            }));
+        }
+    }
+    direct
+}
+
+fn read_receipt(keg_path: &Path) -> Option<serde_json::Value> {
+    let content = std::fs::read_to_string(keg_path.join("INSTALL_RECEIPT.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Whether the keg was installed on request. Receipts written before this was recorded count
+/// as requested, so nothing is ever removed as an unneeded dependency by mistake.
+pub fn installed_on_request(keg_path: &Path) -> bool {
+    read_receipt(keg_path)
+        .and_then(|receipt| {
+            receipt
+                .get("installed_on_request")
+                .and_then(|v| v.as_bool())
+        })
+        .unwrap_or(true)
+}
+
+/// Records in an existing keg's receipt that it was asked for explicitly.
+pub fn mark_installed_on_request(keg_path: &Path) -> Result<()> {
+    let receipt_path = keg_path.join("INSTALL_RECEIPT.json");
+    let Some(mut receipt) = read_receipt(keg_path) else {
+        return Err(SpsError::NotFound(format!(
+            "No readable receipt at {}",
+            receipt_path.display()
+        )));
+    };
+    if receipt
+        .get("installed_on_request")
+        .and_then(|v| v.as_bool())
+        == Some(true)
+    {
+        return Ok(());
+    }
+    receipt["installed_on_request"] = serde_json::Value::Bool(true);
+    receipt["installed_as_dependency"] = serde_json::Value::Bool(false);
+    let json = serde_json::to_string_pretty(&receipt)
+        .map_err(|e| SpsError::Json(std::sync::Arc::new(e)))?;
+    std::fs::write(&receipt_path, json).map_err(|e| SpsError::Io(std::sync::Arc::new(e)))
+}
+
 // --- Re-exports (unchanged) ---
 pub use exec::install_bottle;
 pub use link::link_formula_artifacts;
@@ -0,0 +1,524 @@
+//! Read-only APFS reader for the first volume of a container. The volume's filesystem tree is
+//! walked once up front and indexed by object ID.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use sps_common::error::Result;
+use tracing::debug;
+
+use super::decmpfs::{self, DECMPFS_XATTR, RESOURCE_FORK_XATTR, UF_COMPRESSED};
+use super::udif::{le_u16, le_u32, le_u64, malformed, Disk};
+use super::volume::{Entry, EntryKind, Volume};
+
+const OBJECT_TYPE_NX_SUPERBLOCK: u32 = 0x0001;
+const OBJECT_TYPE_MASK: u32 = 0xffff;
+
+const BTNODE_ROOT: u16 = 0x1;
+const BTNODE_LEAF: u16 = 0x2;
+const BTNODE_FIXED_KV_SIZE: u16 = 0x4;
+const BTREE_NODE_HEADER_SIZE: usize = 56;
+const BTREE_INFO_SIZE: usize = 40;
+/// Deeper trees than this mean a corrupt (or cyclic) structure.
+const MAX_TREE_DEPTH: usize = 16;
+
+const APFS_FS_UNENCRYPTED: u64 = 0x1;
+const APFS_INCOMPAT_CASE_INSENSITIVE: u64 = 0x1;
+const APFS_INCOMPAT_NORMALIZATION_INSENSITIVE: u64 = 0x8;
+
+const OBJ_ID_MASK: u64 = 0x0fff_ffff_ffff_ffff;
+const OBJ_TYPE_SHIFT: u64 = 60;
+const APFS_TYPE_INODE: u64 = 3;
+const APFS_TYPE_XATTR: u64 = 4;
+const APFS_TYPE_FILE_EXTENT: u64 = 8;
+const APFS_TYPE_DIR_REC: u64 = 9;
+
+const ROOT_DIR_INO_NUM: u64 = 2;
+const INO_EXT_TYPE_DSTREAM: u8 = 8;
+const XATTR_DATA_STREAM: u16 = 0x1;
+const XATTR_DATA_EMBEDDED: u16 = 0x2;
+const SYMLINK_XATTR: &str = "com.apple.fs.symlink";
+const FILE_EXTENT_LEN_MASK: u64 = 0x00ff_ffff_ffff_ffff;
+
+const DT_DIR: u16 = 4;
+const DT_REG: u16 = 8;
+const DT_LNK: u16 = 10;
+const S_IFMT: u16 = 0o170000;
+const S_IFLNK: u16 = 0o120000;
+const COPY_BUFFER_SIZE: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+struct Inode {
+    private_id: u64,
+    modified: u64,
+    nlink: i32,
+    bsd_flags: u32,
+    mode: u16,
+    size: u64,
+}
+
+#[derive(Debug, Clone)]
+enum XattrData {
+    Embedded(Vec<u8>),
+    Stream { id: u64, size: u64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FileExtent {
+    logical: u64,
+    len: u64,
+    physical_block: u64,
+}
+
+pub(super) struct Apfs<'a> {
+    disk: &'a mut Disk,
+    base: u64,
+    block_size: u64,
+    inodes: HashMap<u64, Inode>,
+    children: HashMap<u64, Vec<(String, u64, u16)>>,
+    xattrs: HashMap<u64, Vec<(String, XattrData)>>,
+    extents: HashMap<u64, Vec<FileExtent>>,
+}
+
+/// A parsed B-tree node: the key/value pairs it holds and whether they are leaf records.
+struct Node {
+    is_leaf: bool,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> Apfs<'a> {
+    /// Opens the first volume of the container at `base`. Returns `None` if it is encrypted.
+    pub(super) fn open(disk: &'a mut Disk, base: u64) -> Result<Option<Self>> {
+        let head = disk.read_vec(base, 4096)?;
+        if &head[32..36] != b"NXSB" {
+            return Err(malformed("missing APFS container superblock"));
+        }
+        let block_size = u64::from(le_u32(&head, 36)?);
+        if !(4096..=65536).contains(&block_size) || !block_size.is_power_of_two() {
+            return Err(malformed("invalid APFS block size"));
+        }
+        let mut apfs = Self {
+            disk,
+            base,
+            block_size,
+            inodes: HashMap::new(),
+            children: HashMap::new(),
+            xattrs: HashMap::new(),
+            extents: HashMap::new(),
+        };
+
+        let superblock = apfs.latest_superblock()?;
+        let xid = le_u64(&superblock, 16)?;
+        let container_omap = apfs.omap_tree(le_u64(&superblock, 160)?)?;
+        let max_volumes = le_u32(&superblock, 180)? as usize;
+        let volume_oid = (0..max_volumes.min(100))
+            .map(|i| le_u64(&superblock, 184 + i * 8))
+            .find(|oid| !matches!(oid, Ok(0)))
+            .transpose()?
+            .ok_or_else(|| malformed("APFS container has no volumes"))?;
+        let volume_block = apfs.omap_lookup(container_omap, volume_oid, xid)?;
+        let volume = apfs.read_block(volume_block)?;
+        if &volume[32..36] != b"APSB" {
+            return Err(malformed("missing APFS volume superblock"));
+        }
+        if le_u64(&volume, 264)? & APFS_FS_UNENCRYPTED == 0 {
+            return Ok(None);
+        }
+        let incompat = le_u64(&volume, 56)?;
+        let hashed_names = incompat
+            & (APFS_INCOMPAT_CASE_INSENSITIVE | APFS_INCOMPAT_NORMALIZATION_INSENSITIVE)
+            != 0;
+        let volume_omap = apfs.omap_tree(le_u64(&volume, 128)?)?;
+        let root_tree = apfs.omap_lookup(volume_omap, le_u64(&volume, 136)?, xid)?;
+        apfs.load_fs_tree(root_tree, volume_omap, xid, hashed_names)?;
+        debug!(
+            "Read APFS volume: {} inodes, {} directories",
+            apfs.inodes.len(),
+            apfs.children.len()
+        );
+        Ok(Some(apfs))
+    }
+
+    fn read_block(&mut self, block: u64) -> Result<Vec<u8>> {
+        let offset = block
+            .checked_mul(self.block_size)
+            .filter(|offset| self.base + offset + self.block_size <= self.disk.size())
+            .ok_or_else(|| malformed(format!("APFS block {block} out of range")))?;
+        self.disk
+            .read_vec(self.base + offset, self.block_size as usize)
+    }
+
+    /// The newest valid container superblock in the checkpoint descriptor area, falling back
+    /// to the copy in block 0.
+    fn latest_superblock(&mut self) -> Result<Vec<u8>> {
+        let block_zero = self.read_block(0)?;
+        let desc_blocks = le_u32(&block_zero, 104)?;
+        let desc_base = le_u64(&block_zero, 112)?;
+        let mut latest = block_zero;
+        // The high bit marks a non-contiguous descriptor area, which is addressed through a
+        // B-tree; block 0 is good enough there.
+        if desc_blocks & 0x8000_0000 != 0 {
+            return Ok(latest);
+        }
+        for i in 0..u64::from(desc_blocks) {
+            let Ok(block) = self.read_block(desc_base + i) else {
+                continue;
+            };
+            let is_superblock = le_u32(&block, 24)? & OBJECT_TYPE_MASK == OBJECT_TYPE_NX_SUPERBLOCK
+                && &block[32..36] == b"NXSB"
+                && checksum_ok(&block);
+            if is_superblock && le_u64(&block, 16)? > le_u64(&latest, 16)? {
+                latest = block;
+            }
+        }
+        Ok(latest)
+    }
+
+    /// Returns the root node address of the object map stored at `omap_block`.
+    fn omap_tree(&mut self, omap_block: u64) -> Result<u64> {
+        let omap = self.read_block(omap_block)?;
+        le_u64(&omap, 48)
+    }
+
+    /// Finds the physical address of virtual object `oid` as of transaction `xid`.
+    fn omap_lookup(&mut self, tree: u64, oid: u64, xid: u64) -> Result<u64> {
+        let mut block = tree;
+        for _ in 0..MAX_TREE_DEPTH {
+            let node = self.read_node(block, 16, 16)?;
+            let found = node
+                .entries
+                .iter()
+                .take_while(|(key, _)| {
+                    let key_oid = le_u64(key, 0).unwrap_or(u64::MAX);
+                    let key_xid = le_u64(key, 8).unwrap_or(u64::MAX);
+                    (key_oid, key_xid) <= (oid, xid)
+                })
+                .last()
+                .ok_or_else(|| malformed(format!("APFS object {oid} not in object map")))?;
+            if node.is_leaf {
+                if le_u64(&found.0, 0)? != oid {
+                    return Err(malformed(format!("APFS object {oid} not in object map")));
+                }
+                return le_u64(&found.1, 8);
+            }
+            block = le_u64(&found.1, 0)?;
+        }
+        Err(malformed("APFS object map is too deep"))
+    }
+
+    /// Reads a B-tree node. `key_size`/`value_size` apply to trees with fixed-size entries,
+    /// where non-leaf values are always 8-byte child addresses.
+    fn read_node(&mut self, block: u64, key_size: usize, value_size: usize) -> Result<Node> {
+        let data = self.read_block(block)?;
+        let flags = le_u16(&data, 32)?;
+        let count = le_u32(&data, 36)? as usize;
+        let toc_offset = usize::from(le_u16(&data, 40)?);
+        let toc_len = usize::from(le_u16(&data, 42)?);
+        let is_leaf = flags & BTNODE_LEAF != 0;
+        let fixed = flags & BTNODE_FIXED_KV_SIZE != 0;
+        let toc_start = BTREE_NODE_HEADER_SIZE + toc_offset;
+        let keys_start = toc_start + toc_len;
+        let values_end = if flags & BTNODE_ROOT != 0 {
+            data.len() - BTREE_INFO_SIZE
+        } else {
+            data.len()
+        };
+
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let (key_offset, key_len, value_offset, value_len) = if fixed {
+                let at = toc_start + i * 4;
+                let value_len = if is_leaf { value_size } else { 8 };
+                (
+                    le_u16(&data, at)?,
+                    key_size,
+                    le_u16(&data, at + 2)?,
+                    value_len,
+                )
+            } else {
+                let at = toc_start + i * 8;
+                (
+                    le_u16(&data, at)?,
+                    usize::from(le_u16(&data, at + 2)?),
+                    le_u16(&data, at + 4)?,
+                    usize::from(le_u16(&data, at + 6)?),
+                )
+            };
+            // Entries without a value are placeholders.
+            if value_offset == 0xffff {
+                continue;
+            }
+            let key_start = keys_start + usize::from(key_offset);
+            let value_start = values_end
+                .checked_sub(usize::from(value_offset))
+                .ok_or_else(|| malformed("APFS B-tree value out of range"))?;
+            let key = data
+                .get(key_start..key_start + key_len)
+                .ok_or_else(|| malformed("APFS B-tree key out of range"))?;
+            let value = data
+                .get(value_start..value_start + value_len)
+                .ok_or_else(|| malformed("APFS B-tree value out of range"))?;
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(Node { is_leaf, entries })
+    }
+
+    fn load_fs_tree(&mut self, root: u64, omap: u64, xid: u64, hashed_names: bool) -> Result<()> {
+        let mut pending = vec![(root, 0)];
+        while let Some((block, depth)) = pending.pop() {
+            if depth > MAX_TREE_DEPTH {
+                return Err(malformed("APFS filesystem tree is too deep"));
+            }
+            let node = self.read_node(block, 0, 0)?;
+            for (key, value) in node.entries {
+                if node.is_leaf {
+                    self.add_record(&key, &value, hashed_names)?;
+                } else {
+                    let child = self.omap_lookup(omap, le_u64(&value, 0)?, xid)?;
+                    pending.push((child, depth + 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_record(&mut self, key: &[u8], value: &[u8], hashed_names: bool) -> Result<()> {
+        let header = le_u64(key, 0)?;
+        let id = header & OBJ_ID_MASK;
+        match header >> OBJ_TYPE_SHIFT {
+            APFS_TYPE_INODE => {
+                let inode = Inode {
+                    private_id: le_u64(value, 8)?,
+                    modified: le_u64(value, 24)?,
+                    nlink: le_u32(value, 56)? as i32,
+                    bsd_flags: le_u32(value, 68)?,
+                    mode: le_u16(value, 80)?,
+                    size: dstream_size(value)?,
+                };
+                self.inodes.insert(id, inode);
+            }
+            APFS_TYPE_DIR_REC => {
+                let (name_start, name_len) = if hashed_names {
+                    (12, (le_u32(key, 8)? & 0x3ff) as usize)
+                } else {
+                    (10, usize::from(le_u16(key, 8)?))
+                };
+                let name = c_string(key, name_start, name_len)?;
+                let file_id = le_u64(value, 0)?;
+                let kind = le_u16(value, 16)? & 0xf;
+                self.children
+                    .entry(id)
+                    .or_default()
+                    .push((name, file_id, kind));
+            }
+            APFS_TYPE_XATTR => {
+                let name = c_string(key, 10, usize::from(le_u16(key, 8)?))?;
+                if ![DECMPFS_XATTR, RESOURCE_FORK_XATTR, SYMLINK_XATTR].contains(&name.as_str()) {
+                    return Ok(());
+                }
+                let flags = le_u16(value, 0)?;
+                let len = usize::from(le_u16(value, 2)?);
+                let data = value
+                    .get(4..4 + len)
+                    .ok_or_else(|| malformed("APFS xattr value out of range"))?;
+                let data = if flags & XATTR_DATA_EMBEDDED != 0 {
+                    XattrData::Embedded(data.to_vec())
+                } else if flags & XATTR_DATA_STREAM != 0 {
+                    XattrData::Stream {
+                        id: le_u64(data, 0)?,
+                        size: le_u64(data, 8)?,
+                    }
+                } else {
+                    return Err(malformed(format!("APFS xattr '{name}' has no data")));
+                };
+                self.xattrs.entry(id).or_default().push((name, data));
+            }
+            APFS_TYPE_FILE_EXTENT => {
+                let extent = FileExtent {
+                    logical: le_u64(key, 8)?,
+                    len: le_u64(value, 0)? & FILE_EXTENT_LEN_MASK,
+                    physical_block: le_u64(value, 8)?,
+                };
+                self.extents.entry(id).or_default().push(extent);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Writes `size` bytes of data stream `stream_id`; holes read as zeros.
+    fn copy_stream(&mut self, stream_id: u64, size: u64, out: &mut dyn Write) -> Result<()> {
+        let mut extents = self.extents.get(&stream_id).cloned().unwrap_or_default();
+        extents.sort_by_key(|extent| extent.logical);
+        let mut written = 0;
+        let mut buf = Vec::new();
+        for extent in extents {
+            if extent.logical < written {
+                return Err(malformed(format!(
+                    "overlapping extents in APFS data stream {stream_id}"
+                )));
+            }
+            if extent.logical >= size {
+                break;
+            }
+            let hole = extent.logical - written;
+            write_zeros(out, hole)?;
+            written += hole;
+            let mut left = extent.len.min(size - written);
+            let mut offset = extent.physical_block * self.block_size;
+            while left > 0 {
+                let n = left.min(COPY_BUFFER_SIZE);
+                buf.resize(n as usize, 0);
+                if extent.physical_block == 0 {
+                    buf.fill(0);
+                } else {
+                    self.disk.read_at(self.base + offset, &mut buf)?;
+                }
+                out.write_all(&buf)?;
+                offset += n;
+                left -= n;
+                written += n;
+            }
+        }
+        write_zeros(out, size - written)
+    }
+
+    fn read_xattr(&mut self, id: u64, name: &str) -> Result<Option<Vec<u8>>> {
+        let found = self
+            .xattrs
+            .get(&id)
+            .and_then(|xattrs| xattrs.iter().find(|(n, _)| n == name))
+            .map(|(_, data)| data.clone());
+        match found {
+            None => Ok(None),
+            Some(XattrData::Embedded(data)) => Ok(Some(data)),
+            Some(XattrData::Stream { id, size }) => {
+                let mut data = Vec::with_capacity(size.min(self.disk.size()) as usize);
+                self.copy_stream(id, size, &mut data)?;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    fn inode(&self, entry: &Entry) -> Result<Inode> {
+        self.inodes
+            .get(&entry.id)
+            .copied()
+            .ok_or_else(|| malformed(format!("no inode for '{}'", entry.name)))
+    }
+}
+
+impl Volume for Apfs<'_> {
+    fn root(&self) -> u64 {
+        ROOT_DIR_INO_NUM
+    }
+
+    fn children(&mut self, dir: u64) -> Result<Vec<Entry>> {
+        let Some(records) = self.children.get(&dir) else {
+            return Ok(Vec::new());
+        };
+        let mut entries = Vec::with_capacity(records.len());
+        for (name, file_id, kind) in records {
+            let inode = self
+                .inodes
+                .get(file_id)
+                .ok_or_else(|| malformed(format!("no inode for '{name}'")))?;
+            let kind = match *kind {
+                DT_DIR => EntryKind::Directory,
+                DT_LNK => EntryKind::Symlink,
+                DT_REG if inode.mode & S_IFMT == S_IFLNK => EntryKind::Symlink,
+                DT_REG => EntryKind::File,
+                other => {
+                    debug!("Skipping '{}' of unsupported type {}", name, other);
+                    continue;
+                }
+            };
+            entries.push(Entry {
+                name: name.clone(),
+                id: *file_id,
+                kind,
+                mode: u32::from(inode.mode),
+                modified: Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(inode.modified)),
+                link_id: (kind == EntryKind::File && inode.nlink > 1).then_some(*file_id),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn copy_file(&mut self, entry: &Entry, out: &mut dyn Write) -> Result<()> {
+        let inode = self.inode(entry)?;
+        if inode.bsd_flags & UF_COMPRESSED != 0 {
+            let xattr = self.read_xattr(entry.id, DECMPFS_XATTR)?.ok_or_else(|| {
+                malformed(format!("'{}' lacks its compression header", entry.name))
+            })?;
+            let data = decmpfs::decompress(&xattr, || {
+                self.read_xattr(entry.id, RESOURCE_FORK_XATTR)?
+                    .ok_or_else(|| malformed(format!("'{}' lacks its resource fork", entry.name)))
+            })?;
+            out.write_all(&data)?;
+            return Ok(());
+        }
+        self.copy_stream(inode.private_id, inode.size, out)
+    }
+
+    fn read_link(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        self.read_xattr(entry.id, SYMLINK_XATTR)?
+            .ok_or_else(|| malformed(format!("symlink '{}' has no target", entry.name)))
+    }
+}
+
+fn write_zeros(out: &mut dyn Write, mut len: u64) -> Result<()> {
+    let zeros = [0u8; 4096];
+    while len > 0 {
+        let n = len.min(zeros.len() as u64) as usize;
+        out.write_all(&zeros[..n])?;
+        len -= n as u64;
+    }
+    Ok(())
+}
+
+/// Size of an inode's data stream, from its extended fields; 0 if it has none.
+fn dstream_size(inode: &[u8]) -> Result<u64> {
+    const XFIELDS_OFFSET: usize = 92;
+    if inode.len() < XFIELDS_OFFSET + 4 {
+        return Ok(0);
+    }
+    let count = usize::from(le_u16(inode, XFIELDS_OFFSET)?);
+    let mut data_offset = XFIELDS_OFFSET + 4 + count * 4;
+    for i in 0..count {
+        let at = XFIELDS_OFFSET + 4 + i * 4;
+        let kind = *inode.get(at).ok_or_else(|| malformed("short inode"))?;
+        let size = usize::from(le_u16(inode, at + 2)?);
+        if kind == INO_EXT_TYPE_DSTREAM {
+            return le_u64(inode, data_offset);
+        }
+        data_offset += size.next_multiple_of(8);
+    }
+    Ok(0)
+}
+
+/// A NUL-terminated UTF-8 string of `len` bytes (terminator included) at `start`.
+fn c_string(data: &[u8], start: usize, len: usize) -> Result<String> {
+    let bytes = data
+        .get(start..start + len)
+        .ok_or_else(|| malformed("APFS name out of range"))?;
+    let bytes = bytes.strip_suffix(b"\0").unwrap_or(bytes);
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Verifies an object's Fletcher-64 checksum, stored in its first eight bytes.
+fn checksum_ok(block: &[u8]) -> bool {
+    const MODULUS: u64 = 0xffff_ffff;
+    let mut sum1 = 0u64;
+    let mut sum2 = 0u64;
+    for word in block[8..].chunks_exact(4) {
+        sum1 =
+            (sum1 + u64::from(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))) % MODULUS;
+        sum2 = (sum2 + sum1) % MODULUS;
+    }
+    let check1 = MODULUS - ((sum1 + sum2) % MODULUS);
+    let check2 = MODULUS - ((sum1 + check1) % MODULUS);
+    let expected = (check2 << 32) | check1;
+    le_u64(block, 0).is_ok_and(|stored| stored == expected)
+}
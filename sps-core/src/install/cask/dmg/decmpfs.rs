@@ -0,0 +1,117 @@
+//! Transparent file compression shared by HFS+ and APFS: a `com.apple.decmpfs` attribute holds
+//! a header and either the compressed data itself or a pointer to the resource fork.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use sps_common::error::{Result, SpsError};
+
+use super::lzfse;
+use super::udif::{be_u32, le_u32, le_u64};
+
+pub(super) const DECMPFS_XATTR: &str = "com.apple.decmpfs";
+pub(super) const RESOURCE_FORK_XATTR: &str = "com.apple.ResourceFork";
+/// `UF_COMPRESSED` in a file's BSD flags.
+pub(super) const UF_COMPRESSED: u32 = 0x20;
+
+/// Resource fork data is split into blocks of this many uncompressed bytes.
+const BLOCK_SIZE: usize = 0x10000;
+
+#[derive(Clone, Copy)]
+enum Codec {
+    Zlib,
+    Lzvn,
+    Lzfse,
+}
+
+fn unsupported(detail: impl std::fmt::Display) -> SpsError {
+    SpsError::Generic(format!("Unsupported compressed file: {detail}"))
+}
+
+/// Returns the uncompressed contents of a file given its `com.apple.decmpfs` attribute.
+/// `resource_fork` is only called for types that keep their data there.
+pub(super) fn decompress(
+    xattr: &[u8],
+    resource_fork: impl FnOnce() -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    if xattr.get(..4) != Some(b"fpmc".as_slice()) {
+        return Err(unsupported("bad decmpfs header"));
+    }
+    let kind = le_u32(xattr, 4)?;
+    let size = usize::try_from(le_u64(xattr, 8)?)
+        .map_err(|_| unsupported("uncompressed size too large"))?;
+    let inline = &xattr[16..];
+    let data = match kind {
+        1 => inline.to_vec(),
+        3 => decode_block(Codec::Zlib, inline, size)?,
+        7 => decode_block(Codec::Lzvn, inline, size)?,
+        11 => decode_block(Codec::Lzfse, inline, size)?,
+        4 => decode_zlib_resource(&resource_fork()?, size)?,
+        8 => decode_block_table(Codec::Lzvn, &resource_fork()?, size)?,
+        12 => decode_block_table(Codec::Lzfse, &resource_fork()?, size)?,
+        other => return Err(unsupported(format!("compression type {other}"))),
+    };
+    if data.len() != size {
+        return Err(unsupported(format!(
+            "expected {} bytes, decompressed {}",
+            size,
+            data.len()
+        )));
+    }
+    Ok(data)
+}
+
+/// Decodes one block. Blocks that did not compress are stored behind a one-byte marker.
+fn decode_block(codec: Codec, data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let first = *data.first().ok_or_else(|| unsupported("empty block"))?;
+    match codec {
+        Codec::Zlib if first & 0x0f == 0x0f => Ok(data[1..].to_vec()),
+        Codec::Zlib => {
+            let mut out = Vec::with_capacity(size);
+            ZlibDecoder::new(data)
+                .read_to_end(&mut out)
+                .map_err(|e| unsupported(format!("zlib data: {e}")))?;
+            Ok(out)
+        }
+        Codec::Lzvn if first == 0x06 => Ok(data[1..].to_vec()),
+        Codec::Lzvn => lzfse::lzvn_decompress(data, size),
+        Codec::Lzfse if data.starts_with(b"bvx") => lzfse::decompress(data, size),
+        Codec::Lzfse => Ok(data[1..].to_vec()),
+    }
+}
+
+/// Type 4: a classic resource fork whose single resource is a table of zlib blocks.
+fn decode_zlib_resource(fork: &[u8], size: usize) -> Result<Vec<u8>> {
+    let data_offset = be_u32(fork, 0)? as usize;
+    // The resource data is preceded by its own 4-byte length.
+    let table_start = data_offset + 4;
+    let count = le_u32(fork, table_start)? as usize;
+    let mut out = Vec::with_capacity(size);
+    for i in 0..count {
+        let entry = table_start + 4 + i * 8;
+        let offset = table_start + le_u32(fork, entry)? as usize;
+        let len = le_u32(fork, entry + 4)? as usize;
+        let block = fork
+            .get(offset..offset + len)
+            .ok_or_else(|| unsupported("zlib block out of range"))?;
+        let expected = BLOCK_SIZE.min(size.saturating_sub(out.len()));
+        out.extend(decode_block(Codec::Zlib, block, expected)?);
+    }
+    Ok(out)
+}
+
+/// Types 8 and 12: a table of little-endian block offsets followed by the blocks.
+fn decode_block_table(codec: Codec, fork: &[u8], size: usize) -> Result<Vec<u8>> {
+    let count = size.div_ceil(BLOCK_SIZE);
+    let mut out = Vec::with_capacity(size);
+    for i in 0..count {
+        let start = le_u32(fork, i * 4)? as usize;
+        let end = le_u32(fork, (i + 1) * 4)? as usize;
+        let block = fork
+            .get(start..end)
+            .ok_or_else(|| unsupported("compressed block out of range"))?;
+        let expected = BLOCK_SIZE.min(size - out.len());
+        out.extend(decode_block(codec, block, expected)?);
+    }
+    Ok(out)
+}
@@ -0,0 +1,429 @@
+//! Read-only HFS+/HFSX reader. The catalog, extents overflow and attributes B-trees are read
+//! whole and walked leaf by leaf, so no key comparison (and no Unicode case folding) is needed.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use sps_common::error::Result;
+use tracing::debug;
+
+use super::decmpfs::{self, DECMPFS_XATTR, UF_COMPRESSED};
+use super::udif::{be_u16, be_u32, be_u64, malformed, Disk};
+use super::volume::{Entry, EntryKind, Volume};
+
+const VOLUME_HEADER_OFFSET: u64 = 1024;
+const ROOT_FOLDER_ID: u32 = 2;
+const EXTENTS_FILE_ID: u32 = 3;
+const CATALOG_FILE_ID: u32 = 4;
+const ATTRIBUTES_FILE_ID: u32 = 8;
+
+const DATA_FORK: u8 = 0x00;
+const RESOURCE_FORK: u8 = 0xff;
+
+const FOLDER_RECORD: u16 = 1;
+const FILE_RECORD: u16 = 2;
+const INLINE_ATTRIBUTE_RECORD: u32 = 0x10;
+const LEAF_NODE: i8 = -1;
+
+const HARD_LINK_TYPE: u32 = u32::from_be_bytes(*b"hlnk");
+const HARD_LINK_CREATOR: u32 = u32::from_be_bytes(*b"hfs+");
+const DIR_LINK_TYPE: u32 = u32::from_be_bytes(*b"fdrp");
+const DIR_LINK_CREATOR: u32 = u32::from_be_bytes(*b"MACS");
+const FILE_LINKS_DIR: &str = "\0\0\0\0HFS+ Private Data";
+const DIR_LINKS_DIR: &str = ".HFS+ Private Directory Data\r";
+
+const S_IFMT: u16 = 0o170000;
+const S_IFLNK: u16 = 0o120000;
+/// Seconds between the HFS+ epoch (1904-01-01) and the Unix epoch.
+const HFS_EPOCH_OFFSET: u64 = 2_082_844_800;
+const COPY_BUFFER_SIZE: u64 = 1 << 20;
+
+/// Eight (start block, block count) pairs, as stored in fork data and extent records.
+type ExtentRecord = [(u32, u32); 8];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Fork {
+    logical_size: u64,
+    extents: ExtentRecord,
+}
+
+impl Fork {
+    fn parse(data: &[u8], at: usize) -> Result<Self> {
+        Ok(Self {
+            logical_size: be_u64(data, at)?,
+            extents: parse_extents(data, at + 16)?,
+        })
+    }
+}
+
+/// An extent record: eight (start block, block count) pairs.
+fn parse_extents(data: &[u8], at: usize) -> Result<ExtentRecord> {
+    let mut extents = [(0, 0); 8];
+    for (i, extent) in extents.iter_mut().enumerate() {
+        *extent = (be_u32(data, at + i * 8)?, be_u32(data, at + 4 + i * 8)?);
+    }
+    Ok(extents)
+}
+
+#[derive(Debug, Clone)]
+struct FileRecord {
+    id: u32,
+    mode: u16,
+    modified: u32,
+    owner_flags: u8,
+    file_type: u32,
+    creator: u32,
+    /// Link target's node number for hard links.
+    special: u32,
+    data: Fork,
+    resource: Fork,
+}
+
+/// A catalog entry; file details live in `HfsPlus::files`, keyed by the file ID.
+#[derive(Debug, Clone, Copy)]
+enum Record {
+    Folder { id: u32, mode: u16 },
+    File(u32),
+}
+
+pub(super) struct HfsPlus<'a> {
+    disk: &'a mut Disk,
+    base: u64,
+    block_size: u64,
+    children: HashMap<u32, Vec<(String, Record)>>,
+    files: HashMap<u32, FileRecord>,
+    /// Extent records from the overflow file keyed by (file ID, fork type), in file order.
+    overflow: HashMap<(u32, u8), Vec<(u32, ExtentRecord)>>,
+    decmpfs: HashMap<u32, Vec<u8>>,
+}
+
+impl<'a> HfsPlus<'a> {
+    pub(super) fn open(disk: &'a mut Disk, base: u64) -> Result<Self> {
+        let header = disk.read_vec(base + VOLUME_HEADER_OFFSET, 512)?;
+        if !matches!(&header[..2], b"H+" | b"HX") {
+            return Err(malformed("missing HFS+ volume header"));
+        }
+        let block_size = u64::from(be_u32(&header, 40)?);
+        if block_size < 512 || !block_size.is_power_of_two() {
+            return Err(malformed("invalid HFS+ block size"));
+        }
+        let mut volume = Self {
+            disk,
+            base,
+            block_size,
+            children: HashMap::new(),
+            files: HashMap::new(),
+            overflow: HashMap::new(),
+            decmpfs: HashMap::new(),
+        };
+
+        let extents_file =
+            volume.read_fork(EXTENTS_FILE_ID, DATA_FORK, &Fork::parse(&header, 192)?)?;
+        let mut overflow: HashMap<_, Vec<_>> = HashMap::new();
+        for_each_leaf_record(&extents_file, |record| {
+            let key_len = usize::from(be_u16(record, 0)?);
+            let fork_type = *record.get(2).ok_or_else(|| malformed("short extent key"))?;
+            let file_id = be_u32(record, 4)?;
+            let start_block = be_u32(record, 8)?;
+            let extents = parse_extents(record, 2 + key_len)?;
+            overflow
+                .entry((file_id, fork_type))
+                .or_default()
+                .push((start_block, extents));
+            Ok(())
+        })?;
+        for runs in overflow.values_mut() {
+            runs.sort_by_key(|(start, _)| *start);
+        }
+        volume.overflow = overflow;
+
+        let catalog = volume.read_fork(CATALOG_FILE_ID, DATA_FORK, &Fork::parse(&header, 272)?)?;
+        for_each_leaf_record(&catalog, |record| volume.add_catalog_record(record))?;
+
+        let attributes_fork = Fork::parse(&header, 352)?;
+        if attributes_fork.logical_size > 0 {
+            let attributes = volume.read_fork(ATTRIBUTES_FILE_ID, DATA_FORK, &attributes_fork)?;
+            for_each_leaf_record(&attributes, |record| volume.add_attribute_record(record))?;
+        }
+        debug!(
+            "Read HFS+ catalog: {} folders, {} files",
+            volume.children.len(),
+            volume.files.len()
+        );
+        Ok(volume)
+    }
+
+    fn add_catalog_record(&mut self, record: &[u8]) -> Result<()> {
+        let key_len = usize::from(be_u16(record, 0)?);
+        let parent = be_u32(record, 2)?;
+        let name_len = usize::from(be_u16(record, 6)?);
+        let name_units: Vec<u16> = (0..name_len)
+            .map(|i| be_u16(record, 8 + i * 2))
+            .collect::<Result<_>>()?;
+        // The catalog stores a POSIX ':' as '/'.
+        let name = String::from_utf16_lossy(&name_units).replace('/', ":");
+        let data = record
+            .get(2 + key_len..)
+            .ok_or_else(|| malformed("catalog record shorter than its key"))?;
+        let entry = match be_u16(data, 0)? {
+            FOLDER_RECORD => Record::Folder {
+                id: be_u32(data, 8)?,
+                mode: be_u16(data, 42)?,
+            },
+            FILE_RECORD => {
+                let file = FileRecord {
+                    id: be_u32(data, 8)?,
+                    mode: be_u16(data, 42)?,
+                    modified: be_u32(data, 16)?,
+                    owner_flags: *data.get(41).ok_or_else(|| malformed("short file record"))?,
+                    file_type: be_u32(data, 48)?,
+                    creator: be_u32(data, 52)?,
+                    special: be_u32(data, 44)?,
+                    data: Fork::parse(data, 88)?,
+                    resource: Fork::parse(data, 168)?,
+                };
+                let id = file.id;
+                self.files.insert(id, file);
+                Record::File(id)
+            }
+            // Thread records only map IDs back to names.
+            _ => return Ok(()),
+        };
+        self.children.entry(parent).or_default().push((name, entry));
+        Ok(())
+    }
+
+    fn add_attribute_record(&mut self, record: &[u8]) -> Result<()> {
+        let key_len = usize::from(be_u16(record, 0)?);
+        let file_id = be_u32(record, 4)?;
+        let name_len = usize::from(be_u16(record, 12)?);
+        let name_units: Vec<u16> = (0..name_len)
+            .map(|i| be_u16(record, 14 + i * 2))
+            .collect::<Result<_>>()?;
+        if String::from_utf16_lossy(&name_units) != DECMPFS_XATTR {
+            return Ok(());
+        }
+        let data = record
+            .get(2 + key_len..)
+            .ok_or_else(|| malformed("attribute record shorter than its key"))?;
+        if be_u32(data, 0)? != INLINE_ATTRIBUTE_RECORD {
+            return Err(malformed("compression attribute is not stored inline"));
+        }
+        let size = be_u32(data, 12)? as usize;
+        let value = data
+            .get(16..16 + size)
+            .ok_or_else(|| malformed("truncated attribute record"))?;
+        self.decmpfs.insert(file_id, value.to_vec());
+        Ok(())
+    }
+
+    /// Extents of a fork in file order, including any from the extents overflow file.
+    fn fork_extents(&self, file_id: u32, fork_type: u8, fork: &Fork) -> Vec<(u32, u32)> {
+        let overflow = self.overflow.get(&(file_id, fork_type));
+        fork.extents
+            .iter()
+            .chain(
+                overflow
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|(_, extents)| extents),
+            )
+            .copied()
+            .take_while(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    fn copy_fork(
+        &mut self,
+        file_id: u32,
+        fork_type: u8,
+        fork: &Fork,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let mut remaining = fork.logical_size;
+        let mut buf = Vec::new();
+        for (start, count) in self.fork_extents(file_id, fork_type, fork) {
+            let mut offset = self.base + u64::from(start) * self.block_size;
+            let mut extent_left = (u64::from(count) * self.block_size).min(remaining);
+            remaining -= extent_left;
+            while extent_left > 0 {
+                let n = extent_left.min(COPY_BUFFER_SIZE);
+                buf.resize(n as usize, 0);
+                self.disk.read_at(offset, &mut buf)?;
+                out.write_all(&buf)?;
+                offset += n;
+                extent_left -= n;
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+        if remaining > 0 {
+            return Err(malformed(format!(
+                "fork of file {file_id} extends past its extents"
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_fork(&mut self, file_id: u32, fork_type: u8, fork: &Fork) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(fork.logical_size.min(self.disk.size()) as usize);
+        self.copy_fork(file_id, fork_type, fork, &mut data)?;
+        Ok(data)
+    }
+
+    /// Resolves a hard link to the file or folder record in the matching private directory.
+    fn resolve_link(&self, private_dir: &str, name: &str) -> Option<Record> {
+        let private_id = self
+            .children
+            .get(&ROOT_FOLDER_ID)?
+            .iter()
+            .find_map(|(n, record)| match record {
+                Record::Folder { id, .. } if n == private_dir => Some(*id),
+                _ => None,
+            })?;
+        self.children
+            .get(&private_id)?
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, record)| *record)
+    }
+}
+
+fn hfs_time(seconds: u32) -> Option<SystemTime> {
+    let unix = u64::from(seconds).checked_sub(HFS_EPOCH_OFFSET)?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(unix))
+}
+
+impl Volume for HfsPlus<'_> {
+    fn root(&self) -> u64 {
+        u64::from(ROOT_FOLDER_ID)
+    }
+
+    fn children(&mut self, dir: u64) -> Result<Vec<Entry>> {
+        let Some(records) = self.children.get(&(dir as u32)) else {
+            return Ok(Vec::new());
+        };
+        let mut entries = Vec::with_capacity(records.len());
+        for (name, record) in records {
+            let mut record = *record;
+            let mut link_id = None;
+            let linked = match record {
+                Record::File(id) => self.files.get(&id),
+                Record::Folder { .. } => None,
+            };
+            if let Some(file) = linked {
+                if file.file_type == HARD_LINK_TYPE && file.creator == HARD_LINK_CREATOR {
+                    let target = format!("iNode{}", file.special);
+                    record = self
+                        .resolve_link(FILE_LINKS_DIR, &target)
+                        .ok_or_else(|| malformed(format!("dangling hard link '{name}'")))?;
+                    link_id = Some(u64::from(file.special));
+                } else if file.file_type == DIR_LINK_TYPE && file.creator == DIR_LINK_CREATOR {
+                    let target = format!("dir_{}", file.special);
+                    record = self
+                        .resolve_link(DIR_LINKS_DIR, &target)
+                        .ok_or_else(|| malformed(format!("dangling directory link '{name}'")))?;
+                }
+            }
+            entries.push(match record {
+                Record::Folder { id, mode } => Entry {
+                    name: name.clone(),
+                    id: u64::from(id),
+                    kind: EntryKind::Directory,
+                    mode: u32::from(mode),
+                    modified: None,
+                    link_id: None,
+                },
+                Record::File(id) => {
+                    let file = self
+                        .files
+                        .get(&id)
+                        .ok_or_else(|| malformed(format!("no catalog record for '{name}'")))?;
+                    Entry {
+                        name: name.clone(),
+                        id: u64::from(file.id),
+                        kind: if file.mode & S_IFMT == S_IFLNK {
+                            EntryKind::Symlink
+                        } else {
+                            EntryKind::File
+                        },
+                        mode: u32::from(file.mode),
+                        modified: hfs_time(file.modified),
+                        link_id,
+                    }
+                }
+            });
+        }
+        Ok(entries)
+    }
+
+    fn copy_file(&mut self, entry: &Entry, out: &mut dyn Write) -> Result<()> {
+        let file = self
+            .files
+            .get(&(entry.id as u32))
+            .cloned()
+            .ok_or_else(|| malformed(format!("no catalog record for '{}'", entry.name)))?;
+        if file.owner_flags & UF_COMPRESSED as u8 != 0 {
+            let xattr = self.decmpfs.get(&file.id).cloned().ok_or_else(|| {
+                malformed(format!("'{}' lacks its compression header", entry.name))
+            })?;
+            let data = decmpfs::decompress(&xattr, || {
+                self.read_fork(file.id, RESOURCE_FORK, &file.resource)
+            })?;
+            out.write_all(&data)?;
+            return Ok(());
+        }
+        self.copy_fork(file.id, DATA_FORK, &file.data, out)
+    }
+
+    fn read_link(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        let file = self
+            .files
+            .get(&(entry.id as u32))
+            .cloned()
+            .ok_or_else(|| malformed(format!("no catalog record for '{}'", entry.name)))?;
+        self.read_fork(file.id, DATA_FORK, &file.data)
+    }
+}
+
+/// Calls `f` with every record in the B-tree's leaf nodes, in key order.
+fn for_each_leaf_record(tree: &[u8], mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    if tree.len() < 512 {
+        return Ok(());
+    }
+    // The header record follows the 14-byte node descriptor of node 0.
+    let first_leaf = be_u32(tree, 14 + 10)?;
+    let node_size = usize::from(be_u16(tree, 14 + 18)?);
+    let total_nodes = be_u32(tree, 14 + 22)?;
+    if node_size < 512 || !node_size.is_power_of_two() {
+        return Err(malformed("invalid B-tree node size"));
+    }
+    let mut node_id = first_leaf;
+    let mut visited = 0;
+    while node_id != 0 {
+        visited += 1;
+        if visited > total_nodes {
+            return Err(malformed("B-tree leaf chain loops"));
+        }
+        let start = node_id as usize * node_size;
+        let node = tree
+            .get(start..start + node_size)
+            .ok_or_else(|| malformed("B-tree node out of range"))?;
+        if node[8] as i8 != LEAF_NODE {
+            return Err(malformed("B-tree leaf chain reaches a non-leaf node"));
+        }
+        let count = usize::from(be_u16(node, 10)?);
+        for i in 0..count {
+            let offset = usize::from(be_u16(node, node_size - 2 * (i + 1))?);
+            let next = usize::from(be_u16(node, node_size - 2 * (i + 2))?);
+            let record = node
+                .get(offset..next)
+                .ok_or_else(|| malformed("B-tree record out of range"))?;
+            f(record)?;
+        }
+        node_id = be_u32(node, 0)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,535 @@
+//! LZFSE and LZVN decoders, following Apple's reference implementation. LZFSE is used for
+//! `ULFO` disk image chunks; both formats appear in files stored with transparent compression.
+
+use sps_common::error::{Result, SpsError};
+
+const L_STATES: usize = 64;
+const M_STATES: usize = 64;
+const D_STATES: usize = 256;
+const LITERAL_STATES: usize = 1024;
+const L_SYMBOLS: usize = 20;
+const M_SYMBOLS: usize = 20;
+const D_SYMBOLS: usize = 64;
+const LITERAL_SYMBOLS: usize = 256;
+const FREQ_COUNT: usize = L_SYMBOLS + M_SYMBOLS + D_SYMBOLS + LITERAL_SYMBOLS;
+
+/// Size of a `bvx1` block header (including the trailing alignment padding).
+const V1_HEADER_SIZE: usize = 772;
+/// Size of the fixed part of a `bvx2` block header, before the packed frequency tables.
+const V2_HEADER_FIXED_SIZE: usize = 32;
+
+const L_EXTRA_BITS: [u8; L_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 5, 8];
+const L_BASE_VALUE: [i32; L_SYMBOLS] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 20, 28, 60,
+];
+const M_EXTRA_BITS: [u8; M_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 5, 8, 11];
+const M_BASE_VALUE: [i32; M_SYMBOLS] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 24, 56, 312,
+];
+
+fn corrupt(format: &str, detail: &str) -> SpsError {
+    SpsError::Generic(format!("Corrupt {format} data: {detail}"))
+}
+
+fn read_u32(src: &[u8], at: usize) -> Result<u32> {
+    src.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| corrupt("LZFSE", "truncated block header"))
+}
+
+fn read_u64(src: &[u8], at: usize) -> Result<u64> {
+    src.get(at..at + 8)
+        .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .ok_or_else(|| corrupt("LZFSE", "truncated block header"))
+}
+
+/// Distance symbols: four symbols per extra-bit count, each group continuing where the
+/// previous one ended.
+fn d_tables() -> ([u8; D_SYMBOLS], [i32; D_SYMBOLS]) {
+    let mut extra_bits = [0u8; D_SYMBOLS];
+    let mut base_value = [0i32; D_SYMBOLS];
+    let mut next = 0i32;
+    for symbol in 0..D_SYMBOLS {
+        let bits = (symbol / 4) as u8;
+        extra_bits[symbol] = bits;
+        base_value[symbol] = next;
+        next += 1 << bits;
+    }
+    (extra_bits, base_value)
+}
+
+/// Decodes a complete LZFSE stream (a sequence of `bvx*` blocks ending in `bvx$`) that expands
+/// to at most `max_len` bytes.
+pub(super) fn decompress(src: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(max_len);
+    let mut pos = 0;
+    loop {
+        let magic = src
+            .get(pos..pos + 4)
+            .ok_or_else(|| corrupt("LZFSE", "missing end of stream marker"))?;
+        match magic {
+            b"bvx$" => return Ok(out),
+            b"bvx-" => {
+                let n_raw = read_u32(src, pos + 4)? as usize;
+                check_room(&out, n_raw, max_len)?;
+                let data = src
+                    .get(pos + 8..pos + 8 + n_raw)
+                    .ok_or_else(|| corrupt("LZFSE", "truncated uncompressed block"))?;
+                out.extend_from_slice(data);
+                pos += 8 + n_raw;
+            }
+            b"bvxn" => {
+                let n_raw = read_u32(src, pos + 4)? as usize;
+                let n_payload = read_u32(src, pos + 8)? as usize;
+                check_room(&out, n_raw, max_len)?;
+                let payload = src
+                    .get(pos + 12..pos + 12 + n_payload)
+                    .ok_or_else(|| corrupt("LZFSE", "truncated LZVN block"))?;
+                lzvn_decode_into(payload, &mut out, n_raw)?;
+                pos += 12 + n_payload;
+            }
+            b"bvx1" | b"bvx2" => {
+                let (header, header_size) = if magic == b"bvx1" {
+                    (BlockHeader::parse_v1(&src[pos..])?, V1_HEADER_SIZE)
+                } else {
+                    BlockHeader::parse_v2(&src[pos..])?
+                };
+                check_room(&out, header.n_raw_bytes as usize, max_len)?;
+                let literals_start = pos + header_size;
+                let lmd_start = literals_start + header.n_literal_payload_bytes as usize;
+                let lmd_end = lmd_start + header.n_lmd_payload_bytes as usize;
+                let literal_payload = src
+                    .get(literals_start..lmd_start)
+                    .ok_or_else(|| corrupt("LZFSE", "truncated literal payload"))?;
+                let lmd_payload = src
+                    .get(lmd_start..lmd_end)
+                    .ok_or_else(|| corrupt("LZFSE", "truncated match payload"))?;
+                header.decode(literal_payload, lmd_payload, &mut out)?;
+                pos = lmd_end;
+            }
+            _ => return Err(corrupt("LZFSE", "unknown block type")),
+        }
+    }
+}
+
+fn check_room(out: &[u8], n_raw: usize, max_len: usize) -> Result<()> {
+    if n_raw > max_len - out.len() {
+        return Err(corrupt("LZFSE", "stream expands past its expected size"));
+    }
+    Ok(())
+}
+
+/// Decodes a raw LZVN stream that expands to `expected_len` bytes.
+pub(super) fn lzvn_decompress(src: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected_len);
+    lzvn_decode_into(src, &mut out, expected_len)?;
+    Ok(out)
+}
+
+/// Header of a compressed LZFSE block, normalised to the `bvx1` layout.
+struct BlockHeader {
+    n_raw_bytes: u32,
+    n_literals: u32,
+    n_matches: u32,
+    n_literal_payload_bytes: u32,
+    n_lmd_payload_bytes: u32,
+    literal_bits: i32,
+    literal_state: [u16; 4],
+    lmd_bits: i32,
+    l_state: u16,
+    m_state: u16,
+    d_state: u16,
+    /// L, M, D and literal frequencies, in that order.
+    freq: [u16; FREQ_COUNT],
+}
+
+impl BlockHeader {
+    fn parse_v1(src: &[u8]) -> Result<Self> {
+        if src.len() < V1_HEADER_SIZE {
+            return Err(corrupt("LZFSE", "truncated block header"));
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([src[at], src[at + 1]]);
+        let mut freq = [0u16; FREQ_COUNT];
+        for (i, f) in freq.iter_mut().enumerate() {
+            *f = u16_at(50 + 2 * i);
+        }
+        Ok(Self {
+            n_raw_bytes: read_u32(src, 4)?,
+            n_literals: read_u32(src, 12)?,
+            n_matches: read_u32(src, 16)?,
+            n_literal_payload_bytes: read_u32(src, 20)?,
+            n_lmd_payload_bytes: read_u32(src, 24)?,
+            literal_bits: read_u32(src, 28)? as i32,
+            literal_state: [u16_at(32), u16_at(34), u16_at(36), u16_at(38)],
+            lmd_bits: read_u32(src, 40)? as i32,
+            l_state: u16_at(44),
+            m_state: u16_at(46),
+            d_state: u16_at(48),
+            freq,
+        })
+    }
+
+    /// Parses a `bvx2` header, returning it with the header's size.
+    fn parse_v2(src: &[u8]) -> Result<(Self, usize)> {
+        let field = |v: u64, offset: u32, bits: u32| ((v >> offset) & ((1 << bits) - 1)) as u32;
+        let v0 = read_u64(src, 8)?;
+        let v1 = read_u64(src, 16)?;
+        let v2 = read_u64(src, 24)?;
+        let header_size = field(v2, 0, 32) as usize;
+        if header_size < V2_HEADER_FIXED_SIZE || header_size > src.len() {
+            return Err(corrupt("LZFSE", "invalid block header size"));
+        }
+
+        // Frequencies are packed with a small variable-length code, least significant bit first.
+        let mut freq = [0u16; FREQ_COUNT];
+        let packed = &src[V2_HEADER_FIXED_SIZE..header_size];
+        let mut next = 0;
+        let mut accum = 0u32;
+        let mut accum_bits = 0u32;
+        for f in freq.iter_mut() {
+            while next < packed.len() && accum_bits + 8 <= 32 {
+                accum |= u32::from(packed[next]) << accum_bits;
+                accum_bits += 8;
+                next += 1;
+            }
+            let (value, bits) = decode_freq_value(accum);
+            if bits > accum_bits {
+                return Err(corrupt("LZFSE", "truncated frequency table"));
+            }
+            *f = value;
+            accum >>= bits;
+            accum_bits -= bits;
+        }
+        if accum_bits >= 8 || next != packed.len() {
+            return Err(corrupt("LZFSE", "invalid frequency table"));
+        }
+
+        let header = Self {
+            n_raw_bytes: read_u32(src, 4)?,
+            n_literals: field(v0, 0, 20),
+            n_literal_payload_bytes: field(v0, 20, 20),
+            n_matches: field(v0, 40, 20),
+            literal_bits: field(v0, 60, 3) as i32 - 7,
+            literal_state: [
+                field(v1, 0, 10) as u16,
+                field(v1, 10, 10) as u16,
+                field(v1, 20, 10) as u16,
+                field(v1, 30, 10) as u16,
+            ],
+            n_lmd_payload_bytes: field(v1, 40, 20),
+            lmd_bits: field(v1, 60, 3) as i32 - 7,
+            l_state: field(v2, 32, 10) as u16,
+            m_state: field(v2, 42, 10) as u16,
+            d_state: field(v2, 52, 10) as u16,
+            freq,
+        };
+        Ok((header, header_size))
+    }
+
+    fn decode(&self, literal_payload: &[u8], lmd_payload: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let (l_freq, rest) = self.freq.split_at(L_SYMBOLS);
+        let (m_freq, rest) = rest.split_at(M_SYMBOLS);
+        let (d_freq, literal_freq) = rest.split_at(D_SYMBOLS);
+        let (d_extra_bits, d_base_value) = d_tables();
+
+        let literal_table = symbol_table(LITERAL_STATES, literal_freq)?;
+        let l_table = value_table(L_STATES, l_freq, &L_EXTRA_BITS, &L_BASE_VALUE)?;
+        let m_table = value_table(M_STATES, m_freq, &M_EXTRA_BITS, &M_BASE_VALUE)?;
+        let d_table = value_table(D_STATES, d_freq, &d_extra_bits, &d_base_value)?;
+
+        if self.n_literals > self.n_raw_bytes {
+            return Err(corrupt("LZFSE", "more literals than output bytes"));
+        }
+        // Literals are decoded four at a time with interleaved states.
+        let n_literals = (self.n_literals as usize).next_multiple_of(4);
+        let mut literals = Vec::with_capacity(n_literals);
+        let mut bits = BitReader::new(literal_payload, self.literal_bits)?;
+        let mut states = self.literal_state.map(usize::from);
+        while literals.len() < n_literals {
+            bits.refill()?;
+            for state in states.iter_mut() {
+                let entry = literal_table
+                    .get(*state)
+                    .ok_or_else(|| corrupt("LZFSE", "invalid literal state"))?;
+                literals.push(entry.symbol);
+                *state = entry.delta as usize + bits.pull(u32::from(entry.k))? as usize;
+            }
+        }
+
+        let block_end = out.len() + self.n_raw_bytes as usize;
+        let mut bits = BitReader::new(lmd_payload, self.lmd_bits)?;
+        let mut l_state = usize::from(self.l_state);
+        let mut m_state = usize::from(self.m_state);
+        let mut d_state = usize::from(self.d_state);
+        let mut next_literal = 0;
+        let mut distance = 0;
+        for _ in 0..self.n_matches {
+            bits.refill()?;
+            let l = decode_value(&l_table, &mut l_state, &mut bits)?;
+            let m = decode_value(&m_table, &mut m_state, &mut bits)?;
+            let d = decode_value(&d_table, &mut d_state, &mut bits)?;
+            if d != 0 {
+                distance = d;
+            }
+            if out.len() + l + m > block_end {
+                return Err(corrupt("LZFSE", "block expands past its declared size"));
+            }
+            let run = literals
+                .get(next_literal..next_literal + l)
+                .ok_or_else(|| corrupt("LZFSE", "literal run out of range"))?;
+            out.extend_from_slice(run);
+            next_literal += l;
+            copy_match(out, distance, m, "LZFSE")?;
+        }
+        if out.len() != block_end {
+            return Err(corrupt("LZFSE", "block is shorter than its declared size"));
+        }
+        Ok(())
+    }
+}
+
+/// Decodes one value of a packed frequency table, returning it with the bits it used.
+fn decode_freq_value(bits: u32) -> (u16, u32) {
+    const NBITS: [u8; 32] = [
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14, 2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3,
+        2, 14,
+    ];
+    const VALUES: [u8; 32] = [
+        0, 2, 1, 4, 0, 3, 1, 0, 0, 2, 1, 5, 0, 3, 1, 0, 0, 2, 1, 6, 0, 3, 1, 0, 0, 2, 1, 7, 0, 3,
+        1, 0,
+    ];
+    let b = (bits & 31) as usize;
+    match NBITS[b] {
+        8 => (8 + ((bits >> 4) & 0xf) as u16, 8),
+        14 => (24 + ((bits >> 4) & 0x3ff) as u16, 14),
+        n => (u16::from(VALUES[b]), u32::from(n)),
+    }
+}
+
+/// Reads an FSE payload backwards from its end, most significant bit first.
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    accum: u64,
+    accum_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    /// `bits` (-7..=0) is the negated number of unused high bits in the final byte.
+    fn new(buf: &'a [u8], bits: i32) -> Result<Self> {
+        if !(-7..=0).contains(&bits) {
+            return Err(corrupt("LZFSE", "invalid bit stream padding"));
+        }
+        let (take, accum_bits) = if bits == 0 {
+            (7, 56)
+        } else {
+            (8, (64 + bits) as u32)
+        };
+        let pos = buf
+            .len()
+            .checked_sub(take)
+            .ok_or_else(|| corrupt("LZFSE", "bit stream too short"))?;
+        let mut bytes = [0u8; 8];
+        bytes[..take].copy_from_slice(&buf[pos..]);
+        let accum = u64::from_le_bytes(bytes);
+        if accum >> accum_bits != 0 {
+            return Err(corrupt("LZFSE", "invalid bit stream padding"));
+        }
+        Ok(Self {
+            buf,
+            pos,
+            accum,
+            accum_bits,
+        })
+    }
+
+    /// Tops the accumulator up to at least 56 bits.
+    fn refill(&mut self) -> Result<()> {
+        let nbits = (63 - self.accum_bits) & !7;
+        let nbytes = (nbits / 8) as usize;
+        if nbytes == 0 {
+            return Ok(());
+        }
+        self.pos = self
+            .pos
+            .checked_sub(nbytes)
+            .ok_or_else(|| corrupt("LZFSE", "bit stream exhausted"))?;
+        let mut bytes = [0u8; 8];
+        bytes[..nbytes].copy_from_slice(&self.buf[self.pos..self.pos + nbytes]);
+        self.accum = (self.accum << nbits) | u64::from_le_bytes(bytes);
+        self.accum_bits += nbits;
+        Ok(())
+    }
+
+    fn pull(&mut self, n: u32) -> Result<u64> {
+        if n > self.accum_bits {
+            return Err(corrupt("LZFSE", "bit stream exhausted"));
+        }
+        self.accum_bits -= n;
+        let value = self.accum >> self.accum_bits;
+        self.accum &= (1u64 << self.accum_bits) - 1;
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct SymbolEntry {
+    k: u8,
+    symbol: u8,
+    delta: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ValueEntry {
+    total_bits: u8,
+    value_bits: u8,
+    delta: u32,
+    base: i32,
+}
+
+/// For each symbol, the states it owns and how each state maps to the next one:
+/// `(k, delta)` such that the next state is `delta + <k bits of input>`.
+fn state_transitions(nstates: usize, freq: &[u16]) -> Result<Vec<(usize, u8, u32)>> {
+    let total: usize = freq.iter().map(|&f| usize::from(f)).sum();
+    if total > nstates {
+        return Err(corrupt("LZFSE", "frequency table exceeds state count"));
+    }
+    let n_clz = (nstates as u32).leading_zeros();
+    let mut transitions = Vec::with_capacity(nstates);
+    for (symbol, &f) in freq.iter().enumerate() {
+        if f == 0 {
+            continue;
+        }
+        let f = u32::from(f);
+        let k = f.leading_zeros() - n_clz;
+        let j0 = ((2 * nstates as u32) >> k) - f;
+        for j in 0..f {
+            if j < j0 {
+                transitions.push((symbol, k as u8, ((f + j) << k) - nstates as u32));
+            } else {
+                transitions.push((symbol, (k - 1) as u8, (j - j0) << (k - 1)));
+            }
+        }
+    }
+    transitions.resize(nstates, (0, 0, 0));
+    Ok(transitions)
+}
+
+fn symbol_table(nstates: usize, freq: &[u16]) -> Result<Vec<SymbolEntry>> {
+    Ok(state_transitions(nstates, freq)?
+        .into_iter()
+        .map(|(symbol, k, delta)| SymbolEntry {
+            k,
+            symbol: symbol as u8,
+            delta,
+        })
+        .collect())
+}
+
+fn value_table(
+    nstates: usize,
+    freq: &[u16],
+    extra_bits: &[u8],
+    base_value: &[i32],
+) -> Result<Vec<ValueEntry>> {
+    Ok(state_transitions(nstates, freq)?
+        .into_iter()
+        .map(|(symbol, k, delta)| ValueEntry {
+            total_bits: k + extra_bits[symbol],
+            value_bits: extra_bits[symbol],
+            delta,
+            base: base_value[symbol],
+        })
+        .collect())
+}
+
+fn decode_value(table: &[ValueEntry], state: &mut usize, bits: &mut BitReader) -> Result<usize> {
+    let entry = table
+        .get(*state)
+        .ok_or_else(|| corrupt("LZFSE", "invalid match state"))?;
+    let raw = bits.pull(u32::from(entry.total_bits))?;
+    *state = entry.delta as usize + (raw >> entry.value_bits) as usize;
+    let extra = raw & ((1u64 << entry.value_bits) - 1);
+    Ok((i64::from(entry.base) + extra as i64) as usize)
+}
+
+/// Appends `len` bytes copied from `distance` bytes back; the ranges may overlap.
+fn copy_match(out: &mut Vec<u8>, distance: usize, len: usize, format: &str) -> Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    if distance == 0 || distance > out.len() {
+        return Err(corrupt(format, "match distance out of range"));
+    }
+    let start = out.len() - distance;
+    if distance >= len {
+        out.extend_from_within(start..start + len);
+    } else {
+        for i in 0..len {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+    Ok(())
+}
+
+/// Decodes LZVN opcodes from `src`, appending exactly `n_raw` bytes to `out`.
+fn lzvn_decode_into(src: &[u8], out: &mut Vec<u8>, n_raw: usize) -> Result<()> {
+    let end = out.len() + n_raw;
+    let byte = |at: usize| {
+        src.get(at)
+            .copied()
+            .map(usize::from)
+            .ok_or_else(|| corrupt("LZVN", "truncated opcode"))
+    };
+    let mut pos = 0;
+    let mut distance = 0;
+    while out.len() < end {
+        let opc = byte(pos)?;
+        // (opcode length, literal count, match length, match distance)
+        let (len, l, m, d) = match opc {
+            0x06 => break,
+            0x0e | 0x16 => {
+                pos += 1;
+                continue;
+            }
+            0x70..=0x7f | 0xd0..=0xdf => return Err(corrupt("LZVN", "undefined opcode")),
+            0xa0..=0xbf => {
+                let w = byte(pos + 1)? | (byte(pos + 2)? << 8);
+                (3, (opc >> 3) & 3, (((opc & 7) << 2) | (w & 3)) + 3, w >> 2)
+            }
+            0xe0 => (2, byte(pos + 1)? + 16, 0, distance),
+            0xe1..=0xef => (1, opc & 0xf, 0, distance),
+            0xf0 => (2, 0, byte(pos + 1)? + 16, distance),
+            0xf1..=0xff => (1, 0, opc & 0xf, distance),
+            _ => match opc & 7 {
+                7 => {
+                    let d = byte(pos + 1)? | (byte(pos + 2)? << 8);
+                    (3, opc >> 6, ((opc >> 3) & 7) + 3, d)
+                }
+                6 if opc < 0x40 => return Err(corrupt("LZVN", "undefined opcode")),
+                6 => (1, opc >> 6, ((opc >> 3) & 7) + 3, distance),
+                _ => (
+                    2,
+                    opc >> 6,
+                    ((opc >> 3) & 7) + 3,
+                    ((opc & 7) << 8) | byte(pos + 1)?,
+                ),
+            },
+        };
+        if out.len() + l + m > end {
+            return Err(corrupt("LZVN", "stream expands past its declared size"));
+        }
+        let literals = src
+            .get(pos + len..pos + len + l)
+            .ok_or_else(|| corrupt("LZVN", "truncated literals"))?;
+        out.extend_from_slice(literals);
+        pos += len + l;
+        copy_match(out, d, m, "LZVN")?;
+        distance = d;
+    }
+    if out.len() != end {
+        return Err(corrupt("LZVN", "stream is shorter than its declared size"));
+    }
+    Ok(())
+}
@@ -1,17 +1,25 @@
-// In sps-core/src/build/cask/dmg.rs
+mod apfs;
+mod decmpfs;
+mod hfsplus;
+mod lzfse;
+mod udif;
+mod volume;
 
 use std::fs;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use sps_common::error::{Result, SpsError};
 use tracing::{debug, error}; // Added log imports
 
+use self::udif::{FilesystemKind, Image};
+use crate::install::extract::{ExtractGuard, ExtractPolicy};
+
 // --- Keep Existing Helpers ---
 pub fn mount_dmg(dmg_path: &Path) -> Result<PathBuf> {
     debug!("Mounting DMG: {}", dmg_path.display());
-    let output = Command::new("hdiutil")
+    let mut child = Command::new("hdiutil")
         .arg("attach")
         .arg("-plist")
         .arg("-nobrowse")
@@ -19,7 +27,15 @@ pub fn mount_dmg(dmg_path: &Path) -> Result<PathBuf> {
         .arg("-mountrandom")
         .arg("/tmp") // Consider making mount location configurable or more robust
         .arg(dmg_path)
-        .output()?;
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Images with a license agreement page it and wait for an answer: quit the pager, agree.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(b"qy\n");
+    }
+    let output = child.wait_with_output()?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -148,16 +164,90 @@ fn parse_mount_point(output: &[u8]) -> Result<PathBuf> {
     }
 }
 
-// --- NEW Function ---
-/// Extracts the contents of a mounted DMG to a staging directory using `ditto`.
+/// Extracts the contents of a DMG into a staging directory. The image is read in-process;
+/// only encrypted or license-protected images are attached with `hdiutil` and copied out.
 pub fn extract_dmg_to_stage(dmg_path: &Path, stage_dir: &Path) -> Result<()> {
-    let mount_point = mount_dmg(dmg_path)?;
-
     // Ensure the stage directory exists (though TempDir should handle it)
     if !stage_dir.exists() {
         fs::create_dir_all(stage_dir).map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
     }
 
+    if let Some(reason) = extract_dmg_in_process(dmg_path, stage_dir)? {
+        if !cfg!(target_os = "macos") {
+            return Err(SpsError::Generic(format!(
+                "Cannot extract DMG '{}': {}, and mounting it requires hdiutil (macOS only)",
+                dmg_path.display(),
+                reason
+            )));
+        }
+        debug!(
+            "Falling back to hdiutil for {}: {}",
+            dmg_path.display(),
+            reason
+        );
+        copy_mounted_dmg(dmg_path, stage_dir)?;
+    }
+
+    // After extraction, quarantine any .app bundles in the stage (macOS only)
+    #[cfg(target_os = "macos")]
+    {
+        if let Err(e) = crate::install::extract::quarantine_extracted_apps_in_stage(
+            stage_dir,
+            "sps-dmg-extractor",
+        ) {
+            tracing::warn!(
+                "Error during post-DMG extraction quarantine scan for {}: {}",
+                dmg_path.display(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reads the image's filesystem directly into `stage_dir`. Returns the reason when the image
+/// can only be opened by mounting it.
+fn extract_dmg_in_process(dmg_path: &Path, stage_dir: &Path) -> Result<Option<&'static str>> {
+    let mut disk = match udif::open(dmg_path)? {
+        Image::Readable(disk) => disk,
+        Image::NeedsMount(reason) => return Ok(Some(reason)),
+    };
+    let mut guard = ExtractGuard::new(&ExtractPolicy::default(), stage_dir, dmg_path)?;
+    match disk.find_filesystem()? {
+        Some((FilesystemKind::HfsPlus, offset)) => {
+            debug!(
+                "Reading HFS+ volume at byte {} of {}",
+                offset,
+                dmg_path.display()
+            );
+            let mut hfs = hfsplus::HfsPlus::open(&mut disk, offset)?;
+            volume::extract_volume(&mut hfs, stage_dir, &mut guard)?;
+        }
+        Some((FilesystemKind::Apfs, offset)) => {
+            debug!(
+                "Reading APFS container at byte {} of {}",
+                offset,
+                dmg_path.display()
+            );
+            match apfs::Apfs::open(&mut disk, offset)? {
+                Some(mut apfs) => volume::extract_volume(&mut apfs, stage_dir, &mut guard)?,
+                None => return Ok(Some("its APFS volume is encrypted")),
+            }
+        }
+        None => {
+            return Err(SpsError::Generic(format!(
+                "No HFS+ or APFS volume found in DMG '{}'",
+                dmg_path.display()
+            )))
+        }
+    }
+    Ok(None)
+}
+
+/// Mounts the DMG and copies its contents to the staging directory using `ditto`.
+fn copy_mounted_dmg(dmg_path: &Path, stage_dir: &Path) -> Result<()> {
+    let mount_point = mount_dmg(dmg_path)?;
+
     debug!(
         "Copying contents from DMG mount {} to stage {} using ditto",
         mount_point.display(),
@@ -191,20 +281,5 @@ pub fn extract_dmg_to_stage(dmg_path: &Path, stage_dir: &Path) -> Result<()> {
         )));
     }
 
-    // After ditto, quarantine any .app bundles in the stage (macOS only)
-    #[cfg(target_os = "macos")]
-    {
-        if let Err(e) = crate::install::extract::quarantine_extracted_apps_in_stage(
-            stage_dir,
-            "sps-dmg-extractor",
-        ) {
-            tracing::warn!(
-                "Error during post-DMG extraction quarantine scan for {}: {}",
-                dmg_path.display(),
-                e
-            );
-        }
-    }
-
     unmount_result // Return the result of unmounting
 }
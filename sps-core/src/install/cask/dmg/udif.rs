@@ -0,0 +1,588 @@
+//! Random access to the disk inside a `.dmg`: UDIF images (`koly` trailer plus `blkx` chunk
+//! tables) are decompressed chunk by chunk on demand; anything else is read as a raw disk.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use bzip2::read::BzDecoder;
+use flate2::read::ZlibDecoder;
+use lzma_rust2::XzReader;
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+
+use super::lzfse;
+
+const SECTOR_SIZE: u64 = 512;
+const KOLY_SIZE: u64 = 512;
+/// Decompressed chunks kept around; filesystem metadata reads tend to revisit the same ones.
+const CHUNK_CACHE_SIZE: usize = 8;
+/// Largest compressed chunk accepted. `hdiutil` writes chunks of 1 MiB or less, so anything
+/// near this comes from a damaged or hostile chunk table.
+const MAX_CHUNK_LEN: u64 = 64 << 20;
+
+const CHUNK_ZERO_FILL: u32 = 0x0000_0000;
+const CHUNK_RAW: u32 = 0x0000_0001;
+const CHUNK_IGNORED: u32 = 0x0000_0002;
+const CHUNK_ADC: u32 = 0x8000_0004;
+const CHUNK_ZLIB: u32 = 0x8000_0005;
+const CHUNK_BZIP2: u32 = 0x8000_0006;
+const CHUNK_LZFSE: u32 = 0x8000_0007;
+const CHUNK_LZMA: u32 = 0x8000_0008;
+const CHUNK_COMMENT: u32 = 0x7fff_fffe;
+const CHUNK_TERMINATOR: u32 = 0xffff_ffff;
+
+const HFS_PLUS_PARTITION_GUID: [u8; 16] =
+    guid_bytes(0x4846_5300, 0x0000, 0x11aa, 0xaa11_0030_6543_ecac);
+const APFS_PARTITION_GUID: [u8; 16] =
+    guid_bytes(0x7c34_57ef, 0x0000, 0x11aa, 0xaa11_0030_6543_ecac);
+
+/// GPT stores the first three GUID fields little-endian.
+const fn guid_bytes(a: u32, b: u16, c: u16, d: u64) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    let d = d.to_be_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6],
+        d[7],
+    ]
+}
+
+pub(super) fn malformed(detail: impl std::fmt::Display) -> SpsError {
+    SpsError::Generic(format!("Malformed disk image: {detail}"))
+}
+
+pub(super) fn be_u16(data: &[u8], at: usize) -> Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+pub(super) fn be_u32(data: &[u8], at: usize) -> Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+pub(super) fn be_u64(data: &[u8], at: usize) -> Result<u64> {
+    data.get(at..at + 8)
+        .map(|b| u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+pub(super) fn le_u16(data: &[u8], at: usize) -> Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+pub(super) fn le_u32(data: &[u8], at: usize) -> Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+pub(super) fn le_u64(data: &[u8], at: usize) -> Result<u64> {
+    data.get(at..at + 8)
+        .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+/// Result of opening an image file.
+pub(super) enum Image {
+    Readable(Disk),
+    /// The image can only be attached by `hdiutil`; carries the reason for logging.
+    NeedsMount(&'static str),
+}
+
+/// Filesystems sps can read from a disk image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FilesystemKind {
+    HfsPlus,
+    Apfs,
+}
+
+/// One run of sectors in a UDIF image and where its (possibly compressed) data lives.
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    kind: u32,
+    /// Byte range of the disk this chunk covers.
+    start: u64,
+    len: u64,
+    /// Byte range of the image file holding the chunk's data.
+    data_offset: u64,
+    data_len: u64,
+}
+
+impl Chunk {
+    fn end(&self) -> u64 {
+        self.start + self.len
+    }
+}
+
+enum Located {
+    Chunk(usize, Chunk),
+    /// Not covered by any chunk, up to the next chunk's start.
+    Gap {
+        next_start: u64,
+    },
+}
+
+enum Layout {
+    Raw,
+    Udif(Vec<Chunk>),
+}
+
+/// The disk stored in an image file, readable at arbitrary byte offsets.
+pub(super) struct Disk {
+    file: File,
+    layout: Layout,
+    size: u64,
+    /// Byte offsets where the image's partitions start, as listed in its chunk tables.
+    partition_starts: Vec<u64>,
+    cache: VecDeque<(usize, Vec<u8>)>,
+}
+
+pub(super) fn open(path: &Path) -> Result<Image> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut head = [0u8; 8];
+    if file_len >= 8 {
+        file.read_exact(&mut head)?;
+    }
+    let mut tail = [0u8; 8];
+    if file_len >= 8 {
+        file.seek(SeekFrom::Start(file_len - 8))?;
+        file.read_exact(&mut tail)?;
+    }
+    if &head == b"encrcdsa" || &tail == b"cdsaencr" {
+        return Ok(Image::NeedsMount("the image is encrypted"));
+    }
+
+    if file_len >= KOLY_SIZE {
+        let mut koly = [0u8; KOLY_SIZE as usize];
+        file.seek(SeekFrom::Start(file_len - KOLY_SIZE))?;
+        file.read_exact(&mut koly)?;
+        if &koly[..4] == b"koly" {
+            return open_udif(file, &koly, file_len);
+        }
+    }
+
+    debug!(
+        "{} has no UDIF trailer, reading it as a raw disk",
+        path.display()
+    );
+    Ok(Image::Readable(Disk {
+        file,
+        layout: Layout::Raw,
+        size: file_len,
+        partition_starts: Vec::new(),
+        cache: VecDeque::new(),
+    }))
+}
+
+fn open_udif(mut file: File, koly: &[u8], file_len: u64) -> Result<Image> {
+    let data_fork_offset = be_u64(koly, 24)?;
+    let xml_offset = be_u64(koly, 216)?;
+    let xml_length = be_u64(koly, 224)?;
+    let sector_count = be_u64(koly, 492)?;
+    if xml_length == 0 {
+        return Err(malformed(
+            "UDIF image without an XML property list is not supported",
+        ));
+    }
+    if xml_offset
+        .checked_add(xml_length)
+        .is_none_or(|end| end > file_len)
+    {
+        return Err(malformed("XML property list lies outside the image"));
+    }
+
+    let mut xml = vec![0u8; xml_length as usize];
+    file.seek(SeekFrom::Start(xml_offset))?;
+    file.read_exact(&mut xml)?;
+    let plist = plist::Value::from_reader_xml(Cursor::new(xml))
+        .map_err(|e| malformed(format!("unreadable property list: {e}")))?;
+    let resources = plist
+        .as_dictionary()
+        .and_then(|root| root.get("resource-fork"))
+        .and_then(|fork| fork.as_dictionary())
+        .ok_or_else(|| malformed("property list has no resource-fork"))?;
+    if resources.contains_key("LPic") {
+        return Ok(Image::NeedsMount("the image has a license agreement"));
+    }
+    let tables = resources
+        .get("blkx")
+        .and_then(|blkx| blkx.as_array())
+        .ok_or_else(|| malformed("property list has no blkx table"))?;
+
+    let mut chunks = Vec::new();
+    let mut partition_starts = Vec::new();
+    for table in tables {
+        let table = table
+            .as_dictionary()
+            .ok_or_else(|| malformed("blkx entry is not a dictionary"))?;
+        let data = table
+            .get("Data")
+            .and_then(|data| data.as_data())
+            .ok_or_else(|| malformed("blkx entry has no data"))?;
+        let name = table
+            .get("Name")
+            .or_else(|| table.get("CFName"))
+            .and_then(|name| name.as_string())
+            .unwrap_or("");
+        let start = parse_mish(data, data_fork_offset, file_len, &mut chunks)?;
+        debug!("UDIF partition '{}' starts at byte {}", name, start);
+        partition_starts.push(start);
+    }
+    chunks.sort_by_key(|chunk| chunk.start);
+
+    let size = sector_count
+        .checked_mul(SECTOR_SIZE)
+        .ok_or_else(|| malformed("sector count out of range"))?
+        .max(chunks.last().map_or(0, Chunk::end));
+    Ok(Image::Readable(Disk {
+        file,
+        layout: Layout::Udif(chunks),
+        size,
+        partition_starts,
+        cache: VecDeque::new(),
+    }))
+}
+
+/// Parses one `mish` block table into `chunks`, returning the byte offset its partition starts at.
+/// Every chunk's data must lie within the first `file_len` bytes of the image file.
+fn parse_mish(
+    data: &[u8],
+    data_fork_offset: u64,
+    file_len: u64,
+    chunks: &mut Vec<Chunk>,
+) -> Result<u64> {
+    if data.get(..4) != Some(b"mish".as_slice()) {
+        return Err(malformed("blkx entry is not a mish table"));
+    }
+    let first_sector = be_u64(data, 8)?;
+    let table_data_offset = be_u64(data, 24)?;
+    let count = be_u32(data, 200)? as usize;
+    for i in 0..count {
+        let entry = 204 + i * 40;
+        let kind = be_u32(data, entry)?;
+        match kind {
+            CHUNK_COMMENT => continue,
+            CHUNK_TERMINATOR => break,
+            CHUNK_ZERO_FILL | CHUNK_RAW | CHUNK_IGNORED | CHUNK_ADC | CHUNK_ZLIB | CHUNK_BZIP2
+            | CHUNK_LZFSE | CHUNK_LZMA => {}
+            other => {
+                return Err(malformed(format!(
+                    "unsupported chunk compression {other:#010x}"
+                )))
+            }
+        }
+        let sector = be_u64(data, entry + 8)?;
+        let sector_count = be_u64(data, entry + 16)?;
+        let compressed_offset = be_u64(data, entry + 24)?;
+        let data_len = be_u64(data, entry + 32)?;
+        let out_of_range = || malformed("chunk table entry out of range");
+        let start = first_sector
+            .checked_add(sector)
+            .and_then(|sector| sector.checked_mul(SECTOR_SIZE))
+            .ok_or_else(out_of_range)?;
+        let len = sector_count
+            .checked_mul(SECTOR_SIZE)
+            .filter(|len| start.checked_add(*len).is_some())
+            .ok_or_else(out_of_range)?;
+        let data_offset = data_fork_offset
+            .checked_add(table_data_offset)
+            .and_then(|offset| offset.checked_add(compressed_offset))
+            .ok_or_else(out_of_range)?;
+        let has_data = !matches!(kind, CHUNK_ZERO_FILL | CHUNK_IGNORED);
+        if has_data
+            && data_offset
+                .checked_add(data_len)
+                .is_none_or(|end| end > file_len)
+        {
+            return Err(malformed("chunk data lies outside the image"));
+        }
+        if has_data && kind != CHUNK_RAW && (len > MAX_CHUNK_LEN || data_len > MAX_CHUNK_LEN) {
+            return Err(malformed(format!(
+                "compressed chunk of {len} bytes exceeds the {MAX_CHUNK_LEN} byte limit"
+            )));
+        }
+        chunks.push(Chunk {
+            kind,
+            start,
+            len,
+            data_offset,
+            data_len,
+        });
+    }
+    first_sector
+        .checked_mul(SECTOR_SIZE)
+        .ok_or_else(|| malformed("partition start out of range"))
+}
+
+impl Disk {
+    pub(super) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Fills `buf` from the disk starting at byte `offset`. Sectors no chunk covers read as zeros.
+    pub(super) fn read_at(&mut self, mut offset: u64, buf: &mut [u8]) -> Result<()> {
+        if let Layout::Raw = self.layout {
+            self.file.seek(SeekFrom::Start(offset))?;
+            return self.file.read_exact(buf).map_err(|e| {
+                malformed(format!(
+                    "read of {} bytes at {} failed: {}",
+                    buf.len(),
+                    offset,
+                    e
+                ))
+            });
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let want = (buf.len() - done) as u64;
+            let (index, chunk) = match self.locate(offset) {
+                Located::Chunk(index, chunk) => (index, chunk),
+                Located::Gap { next_start } => {
+                    let n = want.min(next_start - offset) as usize;
+                    buf[done..done + n].fill(0);
+                    done += n;
+                    offset += n as u64;
+                    continue;
+                }
+            };
+            let within = offset - chunk.start;
+            let n = want.min(chunk.len - within) as usize;
+            let dest = &mut buf[done..done + n];
+            match chunk.kind {
+                CHUNK_ZERO_FILL | CHUNK_IGNORED => dest.fill(0),
+                CHUNK_RAW => {
+                    if within + n as u64 > chunk.data_len {
+                        return Err(malformed("raw chunk is shorter than the sectors it covers"));
+                    }
+                    self.file
+                        .seek(SeekFrom::Start(chunk.data_offset + within))?;
+                    self.file.read_exact(dest)?;
+                }
+                _ => {
+                    let data = self.decoded_chunk(index, chunk)?;
+                    let src = data
+                        .get(within as usize..within as usize + n)
+                        .ok_or_else(|| {
+                            malformed("chunk decompressed to fewer bytes than expected")
+                        })?;
+                    dest.copy_from_slice(src);
+                }
+            }
+            done += n;
+            offset += n as u64;
+        }
+        Ok(())
+    }
+
+    fn locate(&self, offset: u64) -> Located {
+        let Layout::Udif(chunks) = &self.layout else {
+            return Located::Gap {
+                next_start: u64::MAX,
+            };
+        };
+        let index = chunks.partition_point(|chunk| chunk.start <= offset);
+        match index.checked_sub(1) {
+            Some(i) if chunks[i].end() > offset => Located::Chunk(i, chunks[i]),
+            _ => Located::Gap {
+                next_start: chunks.get(index).map_or(u64::MAX, |chunk| chunk.start),
+            },
+        }
+    }
+
+    pub(super) fn read_vec(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if offset
+            .checked_add(len as u64)
+            .is_none_or(|end| end > self.size)
+        {
+            return Err(malformed(format!(
+                "read of {len} bytes at {offset} is past the end of the disk"
+            )));
+        }
+        let mut buf = vec![0u8; len];
+        self.read_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn decoded_chunk(&mut self, index: usize, chunk: Chunk) -> Result<&[u8]> {
+        if let Some(pos) = self.cache.iter().position(|(i, _)| *i == index) {
+            let entry = self.cache.remove(pos).expect("position is in range");
+            self.cache.push_front(entry);
+        } else {
+            let mut compressed = vec![0u8; chunk.data_len as usize];
+            self.file.seek(SeekFrom::Start(chunk.data_offset))?;
+            self.file.read_exact(&mut compressed)?;
+            let data = decompress_chunk(chunk.kind, &compressed, chunk.len as usize)?;
+            self.cache.push_front((index, data));
+            self.cache.truncate(CHUNK_CACHE_SIZE);
+        }
+        Ok(&self.cache[0].1)
+    }
+
+    /// Locates the first HFS+ or APFS filesystem on the disk, returning its kind and byte offset.
+    pub(super) fn find_filesystem(&mut self) -> Result<Option<(FilesystemKind, u64)>> {
+        let mut candidates = vec![0];
+        candidates.extend(self.partition_starts.iter().copied());
+        candidates.extend(self.gpt_partition_starts()?);
+        candidates.extend(self.apm_partition_starts()?);
+        let mut seen = Vec::new();
+        for offset in candidates {
+            if seen.contains(&offset) || offset.checked_add(2048).is_none_or(|end| end > self.size)
+            {
+                continue;
+            }
+            seen.push(offset);
+            let head = self.read_vec(offset, 2048)?;
+            if &head[32..36] == b"NXSB" {
+                return Ok(Some((FilesystemKind::Apfs, offset)));
+            }
+            if matches!(&head[1024..1026], b"H+" | b"HX") {
+                return Ok(Some((FilesystemKind::HfsPlus, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn gpt_partition_starts(&mut self) -> Result<Vec<u64>> {
+        if self.size < 2 * SECTOR_SIZE {
+            return Ok(Vec::new());
+        }
+        let header = self.read_vec(SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        if &header[..8] != b"EFI PART" {
+            return Ok(Vec::new());
+        }
+        let entries_lba = le_u64(&header, 72)?;
+        let count = le_u32(&header, 80)?.min(128) as usize;
+        let entry_size = le_u32(&header, 84)? as usize;
+        if entry_size < 128 {
+            return Err(malformed("GPT entry size too small"));
+        }
+        let entries_offset = entries_lba
+            .checked_mul(SECTOR_SIZE)
+            .ok_or_else(|| malformed("GPT entry table out of range"))?;
+        let entries_len = count
+            .checked_mul(entry_size)
+            .ok_or_else(|| malformed("GPT entry table out of range"))?;
+        let entries = self.read_vec(entries_offset, entries_len)?;
+        let mut starts = Vec::new();
+        for entry in entries.chunks_exact(entry_size) {
+            if entry[..16] == HFS_PLUS_PARTITION_GUID || entry[..16] == APFS_PARTITION_GUID {
+                let start = le_u64(entry, 32)?
+                    .checked_mul(SECTOR_SIZE)
+                    .ok_or_else(|| malformed("GPT partition start out of range"))?;
+                starts.push(start);
+            }
+        }
+        Ok(starts)
+    }
+
+    fn apm_partition_starts(&mut self) -> Result<Vec<u64>> {
+        if self.size < 2 * SECTOR_SIZE {
+            return Ok(Vec::new());
+        }
+        let first = self.read_vec(SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        if &first[..2] != b"PM" {
+            return Ok(Vec::new());
+        }
+        let count = be_u32(&first, 4)?.min(64) as u64;
+        let mut starts = Vec::new();
+        for i in 1..=count {
+            if (i + 1) * SECTOR_SIZE > self.size {
+                break;
+            }
+            let entry = self.read_vec(i * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+            if &entry[..2] != b"PM" {
+                break;
+            }
+            let kind = &entry[48..80];
+            if kind.starts_with(b"Apple_HFS") || kind.starts_with(b"Apple_APFS") {
+                starts.push(u64::from(be_u32(&entry, 8)?) * SECTOR_SIZE);
+            }
+        }
+        Ok(starts)
+    }
+}
+
+/// Decompresses a chunk that covers `len` bytes of the disk, which [`parse_mish`] has already
+/// capped at [`MAX_CHUNK_LEN`]. Anything the data decodes to beyond `len` is dropped.
+fn decompress_chunk(kind: u32, data: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let limit = len as u64;
+    match kind {
+        CHUNK_ZLIB => {
+            ZlibDecoder::new(data)
+                .take(limit)
+                .read_to_end(&mut out)
+                .map_err(|e| malformed(format!("zlib chunk: {e}")))?;
+        }
+        CHUNK_BZIP2 => {
+            BzDecoder::new(data)
+                .take(limit)
+                .read_to_end(&mut out)
+                .map_err(|e| malformed(format!("bzip2 chunk: {e}")))?;
+        }
+        CHUNK_LZMA => {
+            if !data.starts_with(b"\xfd7zXZ\x00") {
+                return Err(malformed("LZMA chunk is not an xz stream"));
+            }
+            XzReader::new(data, true)
+                .take(limit)
+                .read_to_end(&mut out)
+                .map_err(|e| malformed(format!("LZMA chunk: {e}")))?;
+        }
+        CHUNK_LZFSE => out = lzfse::decompress(data, len)?,
+        CHUNK_ADC => out = adc_decompress(data, len)?,
+        other => return Err(malformed(format!("unexpected chunk type {other:#010x}"))),
+    }
+    if out.len() < len {
+        return Err(malformed("chunk decompressed to fewer bytes than expected"));
+    }
+    Ok(out)
+}
+
+/// Apple Data Compression, used by old `UDCO` images.
+fn adc_decompress(src: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < src.len() && out.len() < len {
+        let op = src[pos];
+        let (run, distance, advance) = if op & 0x80 != 0 {
+            let run = usize::from(op & 0x7f) + 1;
+            let literals = src
+                .get(pos + 1..pos + 1 + run)
+                .ok_or_else(|| malformed("truncated ADC literal run"))?;
+            out.extend_from_slice(literals);
+            pos += 1 + run;
+            continue;
+        } else if op & 0x40 != 0 {
+            let distance = usize::from(be_u16(src, pos + 1)?) + 1;
+            (usize::from(op & 0x3f) + 4, distance, 3)
+        } else {
+            let low = *src
+                .get(pos + 1)
+                .ok_or_else(|| malformed("truncated ADC match"))?;
+            let distance = ((usize::from(op & 0x03) << 8) | usize::from(low)) + 1;
+            (usize::from((op & 0x3c) >> 2) + 3, distance, 2)
+        };
+        if distance > out.len() {
+            return Err(malformed("ADC match distance out of range"));
+        }
+        let start = out.len() - distance;
+        for i in 0..run {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+        pos += advance;
+    }
+    Ok(out)
+}
@@ -0,0 +1,184 @@
+//! Copies a filesystem read out of a disk image into the staging directory, the way `ditto`
+//! copies a mounted volume.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, warn};
+
+use crate::install::extract::ExtractGuard;
+
+/// Volume bookkeeping that a mounted image hides or that is meaningless once copied out.
+const SKIPPED_ROOT_ENTRIES: &[&str] = &[
+    "\0\0\0\0HFS+ Private Data",
+    ".HFS+ Private Directory Data\r",
+    ".journal",
+    ".journal_info_block",
+    ".Trashes",
+    ".fseventsd",
+    ".Spotlight-V100",
+    ".DocumentRevisions-V100",
+    ".TemporaryItems",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum EntryKind {
+    Directory,
+    File,
+    Symlink,
+}
+
+/// A directory entry on a disk image volume.
+#[derive(Debug, Clone)]
+pub(super) struct Entry {
+    pub name: String,
+    /// Filesystem-specific identifier passed back to [`Volume`] methods.
+    pub id: u64,
+    pub kind: EntryKind,
+    /// Unix mode; the permission bits are 0 when the volume does not record any.
+    pub mode: u32,
+    pub modified: Option<SystemTime>,
+    /// Shared by entries that are hard links to the same file.
+    pub link_id: Option<u64>,
+}
+
+/// A filesystem read in-process from a disk image.
+pub(super) trait Volume {
+    fn root(&self) -> u64;
+    fn children(&mut self, dir: u64) -> Result<Vec<Entry>>;
+    fn copy_file(&mut self, entry: &Entry, out: &mut dyn Write) -> Result<()>;
+    fn read_link(&mut self, entry: &Entry) -> Result<Vec<u8>>;
+}
+
+/// Copies the whole volume into `stage_dir`, checking every entry against `guard`.
+pub(super) fn extract_volume(
+    volume: &mut dyn Volume,
+    stage_dir: &Path,
+    guard: &mut ExtractGuard,
+) -> Result<()> {
+    let mut state = ExtractState::default();
+    let root = volume.root();
+    state.visited_dirs.insert(root);
+    extract_dir(volume, root, stage_dir, true, &mut state, guard)?;
+    debug!(
+        "Extracted {} files from disk image to {}",
+        state.files,
+        stage_dir.display()
+    );
+    Ok(())
+}
+
+#[derive(Default)]
+struct ExtractState {
+    visited_dirs: HashSet<u64>,
+    hard_links: HashMap<u64, PathBuf>,
+    files: usize,
+}
+
+fn extract_dir(
+    volume: &mut dyn Volume,
+    dir: u64,
+    dest: &Path,
+    is_root: bool,
+    state: &mut ExtractState,
+    guard: &mut ExtractGuard,
+) -> Result<()> {
+    for entry in volume.children(dir)? {
+        if is_root && SKIPPED_ROOT_ENTRIES.contains(&entry.name.as_str()) {
+            continue;
+        }
+        if entry.name.is_empty()
+            || entry.name == "."
+            || entry.name == ".."
+            || entry.name.contains(['/', '\0'])
+        {
+            warn!(
+                "Skipping disk image entry with unsafe name {:?}",
+                entry.name
+            );
+            continue;
+        }
+        guard.count_entry()?;
+        let target = dest.join(&entry.name);
+        match entry.kind {
+            EntryKind::Directory => {
+                if !state.visited_dirs.insert(entry.id) {
+                    warn!("Skipping directory cycle at {}", target.display());
+                    continue;
+                }
+                fs::create_dir_all(&target)?;
+                extract_dir(volume, entry.id, &target, false, state, guard)?;
+                fs::set_permissions(&target, permissions(entry.mode, 0o755))?;
+            }
+            EntryKind::Symlink => {
+                let link = volume.read_link(&entry)?;
+                let link = String::from_utf8_lossy(&link);
+                let link = Path::new(link.trim_end_matches('\0'));
+                guard.check_symlink(&target, link)?;
+                std::os::unix::fs::symlink(link, &target)?;
+            }
+            EntryKind::File => {
+                if let Some(existing) = entry.link_id.and_then(|id| state.hard_links.get(&id)) {
+                    fs::hard_link(existing, &target)?;
+                    continue;
+                }
+                let mut out = Budgeted {
+                    out: BufWriter::new(File::create(&target)?),
+                    guard: &mut *guard,
+                    violation: None,
+                };
+                let copied = volume.copy_file(&entry, &mut out);
+                if let Some(violation) = out.violation {
+                    return Err(violation);
+                }
+                copied?;
+                let file = out.out.into_inner().map_err(|e| e.into_error())?;
+                file.set_permissions(permissions(entry.mode, 0o644))?;
+                if let Some(modified) = entry.modified {
+                    file.set_modified(modified)?;
+                }
+                if let Some(id) = entry.link_id {
+                    state.hard_links.insert(id, target);
+                }
+                state.files += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Counts file content against the guard as it is written. The volume readers only see an
+/// `io::Error`, so a policy violation is kept aside to be returned as itself.
+struct Budgeted<'g, 'a, W> {
+    out: W,
+    guard: &'g mut ExtractGuard<'a>,
+    violation: Option<SpsError>,
+}
+
+impl<W: Write> Write for Budgeted<'_, '_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Err(e) = self.guard.reserve(buf.len() as u64) {
+            let message = e.to_string();
+            self.violation = Some(e);
+            return Err(io::Error::other(message));
+        }
+        self.out.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn permissions(mode: u32, default: u32) -> fs::Permissions {
+    match mode & 0o777 {
+        0 => fs::Permissions::from_mode(default),
+        bits => fs::Permissions::from_mode(bits),
+    }
+}
@@ -1,9 +1,11 @@
 pub mod artifacts;
 pub mod dmg;
 pub mod helpers;
+pub mod xar;
 
+use std::collections::HashSet;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
 use infer;
@@ -98,7 +100,10 @@ pub async fn download_cask_with_progress(
     debug!("Downloading cask from {}", url_str);
     let parsed = Url::parse(url_str)
         .map_err(|e| SpsError::Generic(format!("Invalid URL '{url_str}': {e}")))?;
-    sps_net::validation::validate_url(parsed.as_str())?;
+    sps_net::validation::validate_url_with_policy(
+        parsed.as_str(),
+        &sps_net::validation::UrlPolicy::from_config(cache.config()),
+    )?;
     let file_name = parsed
         .path_segments()
         .and_then(|mut segments| segments.next_back())
@@ -116,55 +121,20 @@ pub async fn download_cask_with_progress(
         return Ok(cache_path);
     }
 
-    use futures::StreamExt;
-    use tokio::fs::File as TokioFile;
-    use tokio::io::AsyncWriteExt;
-
-    let client = reqwest::Client::new();
-    let response = client
-        .get(parsed.clone())
-        .send()
-        .await
-        .map_err(|e| SpsError::Http(std::sync::Arc::new(e)))?;
-    if !response.status().is_success() {
-        return Err(SpsError::DownloadError(
-            cask.token.clone(),
-            url_str.to_string(),
-            format!("HTTP status {}", response.status()),
-        ));
-    }
-
-    // Get total size from Content-Length header if available
-    let total_size = response.content_length();
-
-    if let Some(parent) = cache_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    let mut file = TokioFile::create(&cache_path)
-        .await
-        .map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
-
-    // Use bytes_stream() for chunked download with progress reporting
-    let mut stream = response.bytes_stream();
-    let mut bytes_downloaded = 0u64;
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| SpsError::Http(std::sync::Arc::new(e)))?;
-
-        file.write_all(&chunk)
-            .await
-            .map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
-
-        bytes_downloaded += chunk.len() as u64;
-
-        // Call progress callback if provided
-        if let Some(ref callback) = progress_callback {
-            callback(bytes_downloaded, total_size);
+    sps_net::http::download_file_with_progress(
+        parsed.as_str(),
+        &cache_path,
+        cache.config(),
+        progress_callback,
+    )
+    .await
+    .map_err(|e| match e {
+        SpsError::DownloadError(..) => e,
+        other => {
+            SpsError::DownloadError(cask.token.clone(), url_str.to_string(), other.to_string())
         }
-    }
+    })?;
 
-    drop(file);
     match cask.sha256.as_ref() {
         Some(Sha256Field::Hex(s)) => {
             if s.eq_ignore_ascii_case("no_check") {
@@ -229,36 +199,178 @@ pub async fn download_cask_with_progress(
 
 use sps_common::pipeline::JobAction;
 
-pub fn install_cask(
-    cask: &Cask,
+/// Artifact stanzas that `install_cask` knows how to dispatch.
+pub const SUPPORTED_ARTIFACT_STANZAS: &[&str] = &[
+    "app",
+    "pkg",
+    "binary",
+    "font",
+    "manpage",
+    "colorpicker",
+    "dictionary",
+    "input_method",
+    "internet_plugin",
+    "keyboard_layout",
+    "mdimporter",
+    "prefpane",
+    "qlplugin",
+    "screen_saver",
+    "service",
+    "suite",
+    "audio_unit_plugin",
+    "vst_plugin",
+    "vst3_plugin",
+    "installer",
+    "preflight",
+    "uninstall",
+    "zap",
+];
+
+/// Stanzas the API emits that are understood but have no install-time effect
+/// (Ruby blocks serialized as `null`, or markers already covered by other fields).
+const IGNORED_ARTIFACT_STANZAS: &[&str] = &[
+    "postflight",
+    "uninstall_preflight",
+    "uninstall_postflight",
+    "stage_only",
+];
+
+/// Name of the directory inside the Caskroom version path that keeps staged files referenced by
+/// symlinked artifacts.
+const PERSISTENT_STAGE_DIR_NAME: &str = ".staged";
+
+/// Stage-relative paths named by the cask's `stanza` entries: plain strings, or the `source`
+/// of a `{ "source": ..., "target": ... }` entry. Paths that leave the stage are dropped.
+fn staged_sources(cask: &Cask, stanza: &str) -> Vec<PathBuf> {
+    cask.artifacts
+        .iter()
+        .flatten()
+        .filter_map(|artifact| artifact.get(stanza))
+        .flat_map(|entries| match entries.as_array() {
+            Some(entries) => entries.iter().collect::<Vec<_>>(),
+            None => vec![entries],
+        })
+        .filter_map(|entry| entry.as_str().or_else(|| entry.get("source")?.as_str()))
+        .map(PathBuf::from)
+        .filter(|path| {
+            path.components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        })
+        .collect()
+}
+
+/// Copies the staged files a symlinked stanza (`binary`, `manpage`) refers to into `dest`, at
+/// the same relative paths. Nothing else is touched, so stanzas that move items out of the
+/// stage themselves (`app`, `font`, ...) still find them whichever order the stanzas come in.
+fn persist_staged_sources(cask: &Cask, stanza: &str, stage_path: &Path, dest: &Path) -> Result<()> {
+    let stage_root = fs::canonicalize(stage_path)?;
+    for source in staged_sources(cask, stanza) {
+        let target = dest.join(&source);
+        if target.symlink_metadata().is_ok() {
+            continue;
+        }
+        // A source that is itself a symlink is persisted as what it points to.
+        let Ok(src) = fs::canonicalize(stage_path.join(&source)) else {
+            debug!("Staged {} source '{}' not found", stanza, source.display());
+            continue;
+        };
+        if !src.starts_with(&stage_root) {
+            debug!(
+                "Staged {} source '{}' resolves outside the stage, skipping",
+                stanza,
+                source.display()
+            );
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        debug!(
+            "Persisting staged item '{}' → '{}'",
+            src.display(),
+            target.display()
+        );
+        copy_staged_item(&src, &target)?;
+    }
+    Ok(())
+}
+
+/// Copies a file or directory tree, keeping symlinks and permissions. The stage usually lives
+/// on a different volume than the prefix, so this can't be a rename.
+fn copy_staged_item(src: &Path, dest: &Path) -> Result<()> {
+    let metadata = src.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dest)?;
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_staged_item(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dest, metadata.permissions())?;
+    } else {
+        fs::copy(src, dest)?;
+    }
+    Ok(())
+}
+
+/// A compressed single file (`tool.gz`) is decompressed under its download cache name,
+/// `cask-<token>-tool`. Renames it back to `tool` so artifact stanzas can refer to it.
+fn strip_cache_prefix_from_staged_file(
+    stage_path: &Path,
     download_path: &Path,
-    config: &Config,
-    job_action: &JobAction,
+    token: &str,
 ) -> Result<()> {
-    debug!("Installing cask: {}", cask.token);
-    // This is the path in the *actual* Caskroom (e.g., /opt/homebrew/Caskroom/token/version)
-    // where metadata and symlinks to /Applications will go.
-    let actual_cask_room_version_path = config.cask_room_version_path(
-        &cask.token,
-        &cask.version.clone().unwrap_or_else(|| "latest".to_string()),
+    let Some(staged_name) = download_path.file_stem().and_then(|s| s.to_str()) else {
+        return Ok(());
+    };
+    let Some(original_name) = staged_name.strip_prefix(&format!("cask-{token}-")) else {
+        return Ok(());
+    };
+    let staged = stage_path.join(staged_name);
+    if original_name.is_empty() || !staged.is_file() {
+        return Ok(());
+    }
+    debug!(
+        "Renaming decompressed file '{}' to '{}'",
+        staged_name, original_name
     );
+    fs::rename(&staged, stage_path.join(original_name))?;
+    Ok(())
+}
 
-    if !actual_cask_room_version_path.exists() {
-        fs::create_dir_all(&actual_cask_room_version_path).map_err(|e| {
-            SpsError::Io(std::sync::Arc::new(std::io::Error::new(
-                e.kind(),
-                format!(
-                    "Failed create cask_room dir {}: {}",
-                    actual_cask_room_version_path.display(),
-                    e
-                ),
-            )))
-        })?;
-        debug!(
-            "Created actual cask_room version directory: {}",
-            actual_cask_room_version_path.display()
-        );
+/// Fails if the cask declares an artifact stanza that sps cannot install, so nothing is
+/// touched for a cask that could only be partially installed.
+fn ensure_artifact_stanzas_supported(cask: &Cask) -> Result<()> {
+    let unsupported: Vec<&str> = cask
+        .artifacts
+        .iter()
+        .flatten()
+        .filter_map(|artifact| artifact.as_object())
+        .flat_map(|obj| obj.keys())
+        .map(String::as_str)
+        .filter(|key| {
+            !SUPPORTED_ARTIFACT_STANZAS.contains(key) && !IGNORED_ARTIFACT_STANZAS.contains(key)
+        })
+        .collect();
+    if unsupported.is_empty() {
+        return Ok(());
     }
+    error!(
+        "Cask '{}' declares unsupported artifact stanzas: {}",
+        cask.token,
+        unsupported.join(", ")
+    );
+    Err(SpsError::InstallError(format!(
+        "Cask '{}' uses artifact stanza(s) sps does not support: {}",
+        cask.token,
+        unsupported.join(", ")
+    )))
+}
+
+/// The container type of a cask download: its extension, or the sniffed content type when
+/// the URL does not end in a meaningful one.
+fn detect_download_extension(download_path: &Path) -> Result<String> {
     let mut detected_extension = download_path
         .extension()
         .and_then(|e| e.to_str())
@@ -301,6 +413,171 @@ pub fn install_cask(
             detected_extension
         );
     }
+    Ok(detected_extension)
+}
+
+/// Unpacks a downloaded container (dmg, zip, tarball, ...) into `stage_path`.
+fn stage_download(
+    cask: &Cask,
+    download_path: &Path,
+    detected_extension: &str,
+    stage_path: &Path,
+) -> Result<()> {
+    match detected_extension {
+        "dmg" => {
+            debug!(
+                "Extracting DMG {} to stage {}...",
+                download_path.display(),
+                stage_path.display()
+            );
+            dmg::extract_dmg_to_stage(download_path, stage_path)?;
+            debug!("Successfully extracted DMG to staging area.");
+        }
+        "zip" => {
+            debug!(
+                "Extracting ZIP {} to stage {}...",
+                download_path.display(),
+                stage_path.display()
+            );
+            extract::extract_archive(download_path, stage_path, 0, "zip")?;
+            debug!("Successfully extracted ZIP to staging area.");
+        }
+        "gz" | "tgz" | "bz2" | "tbz" | "tbz2" | "xz" | "txz" | "zst" | "tzst" | "lz" | "tlz"
+        | "7z" | "tar" => {
+            let archive_type_for_extraction = detected_extension;
+            debug!(
+                "Extracting archive ({}) {} to stage {}...",
+                archive_type_for_extraction,
+                download_path.display(),
+                stage_path.display()
+            );
+            extract::extract_archive(download_path, stage_path, 0, archive_type_for_extraction)?;
+            strip_cache_prefix_from_staged_file(stage_path, download_path, &cask.token)?;
+            debug!("Successfully extracted archive to staging area.");
+        }
+        _ => {
+            error!(
+                "Unsupported container/installer type '{}' for staged installation derived from {}",
+                detected_extension,
+                download_path.display()
+            );
+            return Err(SpsError::Generic(format!(
+                "Unsupported file type for staged installation: {detected_extension}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the pkg installers a cask would run without running them: the download itself when
+/// it is a `.pkg`, otherwise each `pkg` artifact found after staging the download. Returns the
+/// pkg's name alongside its contents. Works on any platform.
+pub fn inspect_cask_pkgs(
+    cask: &Cask,
+    download_path: &Path,
+) -> Result<Vec<(String, xar::PkgContents)>> {
+    let detected_extension = detect_download_extension(download_path)?;
+    if detected_extension == "pkg" || detected_extension == "mpkg" {
+        let name = download_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        return Ok(vec![(name, xar::inspect_pkg(download_path)?)]);
+    }
+
+    let pkg_names: Vec<&str> = cask
+        .artifacts
+        .iter()
+        .flatten()
+        .filter_map(|artifact| artifact.get("pkg")?.as_array())
+        .flatten()
+        .filter_map(|pkg| pkg.as_str())
+        .collect();
+    if pkg_names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let stage_dir = TempDir::new().map_err(|e| {
+        SpsError::Io(std::sync::Arc::new(std::io::Error::new(
+            e.kind(),
+            format!("Failed to create staging directory: {e}"),
+        )))
+    })?;
+    stage_download(cask, download_path, &detected_extension, stage_dir.path())?;
+    pkg_names
+        .into_iter()
+        .map(|name| {
+            let pkg_path = staged_pkg_path(cask, stage_dir.path(), name)?;
+            Ok((name.to_string(), xar::inspect_pkg(&pkg_path)?))
+        })
+        .collect()
+}
+
+/// Resolves a `pkg` artifact name against the staging directory. The name comes from the cask
+/// definition, so it must be a plain relative path that stays inside the stage once resolved.
+fn staged_pkg_path(cask: &Cask, stage_dir: &Path, name: &str) -> Result<PathBuf> {
+    let unsafe_name = || {
+        SpsError::Generic(format!(
+            "Cask '{}' names pkg '{}' outside its staged download",
+            cask.token, name
+        ))
+    };
+    let relative = Path::new(name);
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(unsafe_name());
+    }
+    let pkg_path = stage_dir.join(relative);
+    let resolved = fs::canonicalize(&pkg_path).map_err(|_| {
+        SpsError::NotFound(format!(
+            "Cask '{}' pkg '{}' not found in its staged download",
+            cask.token, name
+        ))
+    })?;
+    if !resolved.starts_with(fs::canonicalize(stage_dir)?) {
+        return Err(unsafe_name());
+    }
+    Ok(resolved)
+}
+
+pub fn install_cask(
+    cask: &Cask,
+    download_path: &Path,
+    config: &Config,
+    job_action: &JobAction,
+) -> Result<()> {
+    debug!("Installing cask: {}", cask.token);
+    // This is the path in the *actual* Caskroom (e.g., /opt/homebrew/Caskroom/token/version)
+    // where metadata and symlinks to /Applications will go.
+    let actual_cask_room_version_path = config.cask_room_version_path(
+        &cask.token,
+        &cask.version.clone().unwrap_or_else(|| "latest".to_string()),
+    );
+
+    if !actual_cask_room_version_path.exists() {
+        fs::create_dir_all(&actual_cask_room_version_path).map_err(|e| {
+            SpsError::Io(std::sync::Arc::new(std::io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed create cask_room dir {}: {}",
+                    actual_cask_room_version_path.display(),
+                    e
+                ),
+            )))
+        })?;
+        debug!(
+            "Created actual cask_room version directory: {}",
+            actual_cask_room_version_path.display()
+        );
+    }
+    let detected_extension = detect_download_extension(download_path)?;
+    if let Err(e) = ensure_artifact_stanzas_supported(cask) {
+        let _ = fs::remove_dir_all(&actual_cask_room_version_path);
+        return Err(e);
+    }
     if detected_extension == "pkg" || detected_extension == "mpkg" {
         debug!("Detected PKG installer, running directly");
         match artifacts::pkg::install_pkg_from_path(
@@ -363,49 +640,13 @@ pub fn install_cask(
             expected_ext
         );
     }
-    match detected_extension.as_str() {
-        "dmg" => {
-            debug!(
-                "Extracting DMG {} to stage {}...",
-                download_path.display(),
-                stage_path.display()
-            );
-            dmg::extract_dmg_to_stage(download_path, stage_path)?;
-            debug!("Successfully extracted DMG to staging area.");
-        }
-        "zip" => {
-            debug!(
-                "Extracting ZIP {} to stage {}...",
-                download_path.display(),
-                stage_path.display()
-            );
-            extract::extract_archive(download_path, stage_path, 0, "zip")?;
-            debug!("Successfully extracted ZIP to staging area.");
-        }
-        "gz" | "bz2" | "xz" | "tar" => {
-            let archive_type_for_extraction = detected_extension.as_str();
-            debug!(
-                "Extracting TAR archive ({}) {} to stage {}...",
-                archive_type_for_extraction,
-                download_path.display(),
-                stage_path.display()
-            );
-            extract::extract_archive(download_path, stage_path, 0, archive_type_for_extraction)?;
-            debug!("Successfully extracted TAR archive to staging area.");
-        }
-        _ => {
-            error!(
-                "Unsupported container/installer type '{}' for staged installation derived from {}",
-                detected_extension,
-                download_path.display()
-            );
-            return Err(SpsError::Generic(format!(
-                "Unsupported file type for staged installation: {detected_extension}"
-            )));
-        }
-    }
+    stage_download(cask, download_path, &detected_extension, stage_path)?;
     let mut all_installed_artifacts: Vec<InstalledArtifact> = Vec::new();
     let mut artifact_install_errors = Vec::new();
+    let mut handled_stanzas: HashSet<&str> = HashSet::new();
+    // Symlinked artifacts (binary, manpage) must point at something that outlives the
+    // temporary stage, so their sources are kept inside the Caskroom version directory.
+    let persistent_stage_path = actual_cask_room_version_path.join(PERSISTENT_STAGE_DIR_NAME);
     if let Some(artifacts_def) = &cask.artifacts {
         debug!(
             "Processing {} declared artifacts from staging area...",
@@ -488,10 +729,165 @@ pub fn install_cask(
                             }
                             Ok(installed_pkgs)
                         }
-                        _ => {
-                            debug!("Artifact type '{}' not supported yet — skipping.", key);
+                        "binary" | "font" | "manpage" | "colorpicker" | "dictionary"
+                        | "input_method" | "internet_plugin" | "keyboard_layout" | "mdimporter"
+                        | "prefpane" | "qlplugin" | "screen_saver" | "service" | "suite"
+                        | "audio_unit_plugin" | "vst_plugin" | "vst3_plugin" | "installer"
+                        | "preflight" | "uninstall"
+                            if !handled_stanzas.insert(key.as_str()) =>
+                        {
+                            // These handlers walk every matching stanza of the cask on their
+                            // own, so a repeated key must not run them a second time.
+                            debug!("Artifact type '{}' already processed — skipping.", key);
                             Ok(vec![])
                         }
+                        "binary" => persist_staged_sources(
+                            cask,
+                            "binary",
+                            stage_path,
+                            &persistent_stage_path,
+                        )
+                        .and_then(|_| {
+                            artifacts::binary::install_binary(
+                                cask,
+                                &persistent_stage_path,
+                                &actual_cask_room_version_path,
+                                config,
+                            )
+                        }),
+                        "font" => artifacts::font::install_font(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "manpage" => persist_staged_sources(
+                            cask,
+                            "manpage",
+                            stage_path,
+                            &persistent_stage_path,
+                        )
+                        .and_then(|_| {
+                            artifacts::manpage::install_manpage(
+                                cask,
+                                &persistent_stage_path,
+                                &actual_cask_room_version_path,
+                                config,
+                            )
+                        }),
+                        "colorpicker" => artifacts::colorpicker::install_colorpicker(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "dictionary" => artifacts::dictionary::install_dictionary(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "input_method" => artifacts::input_method::install_input_method(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "internet_plugin" => artifacts::internet_plugin::install_internet_plugin(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "keyboard_layout" => artifacts::keyboard_layout::install_keyboard_layout(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "mdimporter" => artifacts::mdimporter::install_mdimporter(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "prefpane" => artifacts::prefpane::install_prefpane(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "qlplugin" => artifacts::qlplugin::install_qlplugin(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "screen_saver" => artifacts::screen_saver::install_screen_saver(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "service" => artifacts::service::install_service(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "suite" => artifacts::suite::install_suite(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "audio_unit_plugin" => {
+                            artifacts::audio_unit_plugin::install_audio_unit_plugin(
+                                cask,
+                                stage_path,
+                                &actual_cask_room_version_path,
+                                config,
+                            )
+                        }
+                        "vst_plugin" => artifacts::vst_plugin::install_vst_plugin(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "vst3_plugin" => artifacts::vst3_plugin::install_vst3_plugin(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "installer" => artifacts::installer::run_installer(
+                            cask,
+                            stage_path,
+                            &actual_cask_room_version_path,
+                            config,
+                        ),
+                        "preflight" => {
+                            artifacts::preflight::run_preflight(cask, stage_path, config)
+                        }
+                        "uninstall" => artifacts::uninstall::record_uninstall(cask),
+                        "zap" => {
+                            // Zap only runs on `uninstall --zap`, which re-reads the stanza from
+                            // the cask definition. Nothing to do at install time.
+                            debug!("Deferring 'zap' stanza to uninstall time.");
+                            Ok(vec![])
+                        }
+                        key if IGNORED_ARTIFACT_STANZAS.contains(&key) => {
+                            debug!(
+                                "Artifact type '{}' has no install-time effect — skipping.",
+                                key
+                            );
+                            Ok(vec![])
+                        }
+                        _ => Err(SpsError::InstallError(format!(
+                            "Cask '{}' uses artifact stanza '{}', which sps does not support.",
+                            cask.token, key
+                        ))),
                     };
                     match result {
                         Ok(installed) => {
@@ -536,6 +932,11 @@ pub fn install_cask(
             cask.token
         )));
     }
+    if persistent_stage_path.exists() {
+        all_installed_artifacts.push(InstalledArtifact::CaskroomReference {
+            path: persistent_stage_path.clone(),
+        });
+    }
     if !artifact_install_errors.is_empty() {
         error!(
             "Encountered {} errors installing artifacts for cask '{}'. Installation incomplete.",
@@ -0,0 +1,564 @@
+//! The xar container: a fixed header, a zlib-compressed XML table of contents and a heap
+//! holding each file's (possibly compressed) data.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use bzip2::read::BzDecoder;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use lzma_rust2::{LzmaReader, XzReader};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+
+const XAR_MAGIC: &[u8; 4] = b"xar!";
+const HEADER_MIN_SIZE: u16 = 28;
+const CKSUM_NONE: u32 = 0;
+const CKSUM_SHA1: u32 = 1;
+const CKSUM_MD5: u32 = 2;
+/// The algorithm is named by the table of contents' `<checksum style=...>`.
+const CKSUM_OTHER: u32 = 3;
+/// Upper bound for the table of contents, compressed or not; real ones are a few hundred KiB.
+const MAX_TOC_SIZE: u64 = 64 << 20;
+/// Longest digest any supported checksum produces (SHA-512).
+const MAX_CHECKSUM_SIZE: u64 = 64;
+/// Memory `.lzma` streams may ask for, in KiB; xar's encoder uses dictionaries of at most 64 MiB.
+const LZMA_MEM_LIMIT_KB: u32 = 256 << 10;
+
+pub(super) fn malformed(detail: impl std::fmt::Display) -> SpsError {
+    SpsError::ParseError("xar archive", detail.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Raw,
+    Gzip,
+    Bzip2,
+    Xz,
+    /// Older xar writes `application/x-lzma` as an `.lzma` (LZMA-alone) stream, newer ones as xz.
+    Lzma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChecksumKind {
+    Sha1,
+    Sha256,
+    Sha512,
+    /// MD5 and anything unknown: recorded by the archive but not verified.
+    Unverified,
+}
+
+impl ChecksumKind {
+    fn from_style(style: &str) -> Self {
+        match style.to_ascii_lowercase().as_str() {
+            "sha1" => Self::Sha1,
+            "sha256" => Self::Sha256,
+            "sha512" => Self::Sha512,
+            _ => Self::Unverified,
+        }
+    }
+}
+
+/// Where a file's data lives in the heap and how it is encoded.
+#[derive(Debug, Clone)]
+struct EntryData {
+    offset: u64,
+    length: u64,
+    size: u64,
+    encoding: Encoding,
+    archived_checksum: Option<(ChecksumKind, String)>,
+}
+
+/// A file listed in the table of contents.
+#[derive(Debug, Clone)]
+pub(super) struct Entry {
+    /// Slash-separated path inside the archive.
+    pub path: String,
+    pub kind: EntryKind,
+    pub link_target: Option<String>,
+    data: Option<EntryData>,
+}
+
+pub(super) struct XarArchive {
+    file: File,
+    heap_offset: u64,
+    entries: Vec<Entry>,
+}
+
+impl XarArchive {
+    pub(super) fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut header = [0u8; HEADER_MIN_SIZE as usize];
+        file.read_exact(&mut header)
+            .map_err(|_| malformed(format!("{} is too short", path.display())))?;
+        if &header[..4] != XAR_MAGIC {
+            return Err(malformed(format!(
+                "{} is not a flat package (no xar header)",
+                path.display()
+            )));
+        }
+        let header_size = u16::from_be_bytes([header[4], header[5]]);
+        let toc_compressed = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        let toc_uncompressed = u64::from_be_bytes(header[16..24].try_into().unwrap());
+        let checksum_alg = u32::from_be_bytes(header[24..28].try_into().unwrap());
+        if header_size < HEADER_MIN_SIZE {
+            return Err(malformed("header size too small"));
+        }
+        if toc_uncompressed > MAX_TOC_SIZE || toc_compressed > MAX_TOC_SIZE {
+            return Err(malformed("table of contents is unreasonably large"));
+        }
+        if u64::from(header_size) + toc_compressed > file_len {
+            return Err(malformed("truncated table of contents"));
+        }
+
+        let mut compressed = vec![0u8; toc_compressed as usize];
+        file.seek(SeekFrom::Start(u64::from(header_size)))?;
+        file.read_exact(&mut compressed)
+            .map_err(|_| malformed("truncated table of contents"))?;
+        let mut xml = Vec::with_capacity(toc_uncompressed as usize);
+        ZlibDecoder::new(compressed.as_slice())
+            .take(MAX_TOC_SIZE)
+            .read_to_end(&mut xml)
+            .map_err(|e| malformed(format!("table of contents: {e}")))?;
+        if xml.len() as u64 != toc_uncompressed {
+            return Err(malformed("table of contents has the wrong size"));
+        }
+
+        let toc = parse_toc(&xml)?;
+        let mut archive = Self {
+            file,
+            heap_offset: u64::from(header_size) + toc_compressed,
+            entries: toc.entries,
+        };
+        archive.verify_toc_checksum(checksum_alg, toc.checksum, &compressed)?;
+        debug!(
+            "Read xar table of contents of {}: {} entries",
+            path.display(),
+            archive.entries.len()
+        );
+        Ok(archive)
+    }
+
+    pub(super) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub(super) fn entry(&self, path: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// The table of contents is covered by a checksum stored at the start of the heap.
+    fn verify_toc_checksum(
+        &mut self,
+        algorithm: u32,
+        location: Option<TocChecksum>,
+        compressed_toc: &[u8],
+    ) -> Result<()> {
+        let kind = match (algorithm, &location) {
+            (CKSUM_NONE, _) => return Ok(()),
+            (CKSUM_SHA1, _) => ChecksumKind::Sha1,
+            (CKSUM_OTHER, Some(location)) => ChecksumKind::from_style(&location.style),
+            (CKSUM_MD5, _) | (CKSUM_OTHER, None) => ChecksumKind::Unverified,
+            (other, _) => return Err(malformed(format!("unknown checksum algorithm {other}"))),
+        };
+        let Some(location) = location else {
+            return Err(malformed("table of contents has no checksum location"));
+        };
+        if kind == ChecksumKind::Unverified {
+            debug!(
+                "Not verifying xar table of contents checksum ({})",
+                location.style
+            );
+            return Ok(());
+        }
+        if location.size > MAX_CHECKSUM_SIZE {
+            return Err(malformed("table of contents checksum is too long"));
+        }
+        let mut stored = vec![0u8; location.size as usize];
+        self.file
+            .seek(SeekFrom::Start(self.heap_offset + location.offset))?;
+        self.file
+            .read_exact(&mut stored)
+            .map_err(|_| malformed("truncated table of contents checksum"))?;
+        let mut hasher = Hasher::new(kind);
+        hasher.update(compressed_toc);
+        if hasher.finish() != stored {
+            return Err(SpsError::ChecksumMismatch(
+                "xar table of contents does not match its checksum".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns a reader over the decoded contents of `entry`. The archived checksum is verified
+    /// as the data is read; a mismatch surfaces as an `InvalidData` error at the end.
+    pub(super) fn open_entry(&mut self, entry: &Entry) -> Result<Box<dyn Read + '_>> {
+        let Some(data) = entry.data.clone() else {
+            return Ok(Box::new(io::empty()));
+        };
+        self.file
+            .seek(SeekFrom::Start(self.heap_offset + data.offset))?;
+        let raw = (&mut self.file).take(data.length);
+        let checked = CheckedReader {
+            inner: raw,
+            remaining: data.length,
+            hasher: data
+                .archived_checksum
+                .as_ref()
+                .map(|(kind, _)| Hasher::new(*kind)),
+            expected: data.archived_checksum.map(|(_, hex)| hex),
+            path: entry.path.clone(),
+        };
+        let decoded: Box<dyn Read + '_> = match data.encoding {
+            Encoding::Raw => Box::new(checked),
+            Encoding::Gzip => {
+                // xar's "gzip" encoding is a bare zlib stream; accept real gzip as well.
+                let mut buffered = BufReader::new(checked);
+                let is_gzip = io::BufRead::fill_buf(&mut buffered)?.starts_with(&[0x1f, 0x8b]);
+                if is_gzip {
+                    Box::new(GzDecoder::new(buffered))
+                } else {
+                    Box::new(ZlibDecoder::new(buffered))
+                }
+            }
+            Encoding::Bzip2 => Box::new(BzDecoder::new(checked)),
+            Encoding::Xz => Box::new(XzReader::new(checked, true)),
+            Encoding::Lzma => {
+                let mut buffered = BufReader::new(checked);
+                let is_xz = io::BufRead::fill_buf(&mut buffered)?.starts_with(b"\xfd7zXZ\x00");
+                if is_xz {
+                    Box::new(XzReader::new(buffered, true))
+                } else {
+                    let reader = LzmaReader::new_mem_limit(buffered, LZMA_MEM_LIMIT_KB, None)
+                        .map_err(|e| malformed(format!("'{}': {}", entry.path, e)))?;
+                    Box::new(reader)
+                }
+            }
+        };
+        Ok(Box::new(decoded.take(data.size)))
+    }
+
+    pub(super) fn read_entry(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open_entry(entry)?
+            .read_to_end(&mut data)
+            .map_err(|e| malformed(format!("'{}': {}", entry.path, e)))?;
+        Ok(data)
+    }
+}
+
+enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    None,
+}
+
+impl Hasher {
+    fn new(kind: ChecksumKind) -> Self {
+        match kind {
+            ChecksumKind::Sha1 => Self::Sha1(Sha1::new()),
+            ChecksumKind::Sha256 => Self::Sha256(Sha256::new()),
+            ChecksumKind::Sha512 => Self::Sha512(Sha512::new()),
+            ChecksumKind::Unverified => Self::None,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::None => {}
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Self::Sha1(h) => h.finalize().to_vec(),
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+            Self::None => Vec::new(),
+        }
+    }
+}
+
+/// Hashes the archived (still encoded) bytes of an entry and checks them as soon as the last
+/// one has been read, since decoders need not read on to end of file.
+struct CheckedReader<R> {
+    inner: R,
+    remaining: u64,
+    hasher: Option<Hasher>,
+    expected: Option<String>,
+    path: String,
+}
+
+impl<R: Read> Read for CheckedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.remaining = self.remaining.saturating_sub(n as u64);
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        if n == 0 || self.remaining == 0 {
+            self.verify()?;
+        }
+        Ok(n)
+    }
+}
+
+impl<R> CheckedReader<R> {
+    fn verify(&mut self) -> io::Result<()> {
+        let (Some(hasher), Some(expected)) = (self.hasher.take(), self.expected.as_deref()) else {
+            return Ok(());
+        };
+        if matches!(hasher, Hasher::None) {
+            return Ok(());
+        }
+        let actual = hex::encode(hasher.finish());
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("checksum mismatch for '{}'", self.path),
+            ));
+        }
+        Ok(())
+    }
+}
+
+struct TocChecksum {
+    style: String,
+    offset: u64,
+    size: u64,
+}
+
+struct Toc {
+    entries: Vec<Entry>,
+    checksum: Option<TocChecksum>,
+}
+
+/// A `<file>` element whose children are still being read.
+#[derive(Default)]
+struct PendingFile {
+    name: Option<String>,
+    kind: Option<String>,
+    link_target: Option<String>,
+    has_data: bool,
+    offset: Option<u64>,
+    length: Option<u64>,
+    size: Option<u64>,
+    encoding: Option<String>,
+    checksum_style: Option<String>,
+    checksum: Option<String>,
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.as_ref() == name)
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+fn parse_number(text: &str, what: &str) -> Result<u64> {
+    text.trim()
+        .parse()
+        .map_err(|_| malformed(format!("invalid {what} '{}'", text.trim())))
+}
+
+fn parse_toc(xml: &[u8]) -> Result<Toc> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    // Element names from the root down to the current element.
+    let mut elements: Vec<Vec<u8>> = Vec::new();
+    let mut files: Vec<PendingFile> = Vec::new();
+    let mut entries = Vec::new();
+    let mut checksum_style = None;
+    let mut checksum_offset = None;
+    let mut checksum_size = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| malformed(format!("table of contents XML: {e}")))?;
+        match event {
+            Event::Start(ref element) | Event::Empty(ref element) => {
+                let name = element.name().as_ref().to_vec();
+                let parent = elements.last().map(Vec::as_slice);
+                match (name.as_slice(), parent) {
+                    (b"file", _) => files.push(PendingFile::default()),
+                    (b"checksum", Some(b"toc")) => {
+                        checksum_style = attribute(element, b"style");
+                    }
+                    (b"data", Some(b"file")) => {
+                        if let Some(file) = files.last_mut() {
+                            file.has_data = true;
+                        }
+                    }
+                    (b"encoding", Some(b"data")) => {
+                        if let Some(file) = files.last_mut() {
+                            file.encoding = attribute(element, b"style");
+                        }
+                    }
+                    (b"archived-checksum", Some(b"data")) => {
+                        if let Some(file) = files.last_mut() {
+                            file.checksum_style = attribute(element, b"style");
+                        }
+                    }
+                    _ => {}
+                }
+                if matches!(event, Event::Start(_)) {
+                    elements.push(name);
+                } else if name == b"file" {
+                    let file = files.pop().unwrap_or_default();
+                    finish_file(file, &files, &mut entries)?;
+                }
+            }
+            Event::Text(ref text) => {
+                let text = text
+                    .unescape()
+                    .map_err(|e| malformed(format!("table of contents XML: {e}")))?;
+                let depth = elements.len();
+                let current = elements.last().map(Vec::as_slice);
+                let parent = depth.checked_sub(2).map(|i| elements[i].as_slice());
+                let grandparent = depth.checked_sub(3).map(|i| elements[i].as_slice());
+                match (current, parent, grandparent) {
+                    (Some(b"offset"), Some(b"checksum"), Some(b"toc")) => {
+                        checksum_offset = Some(parse_number(&text, "checksum offset")?);
+                    }
+                    (Some(b"size"), Some(b"checksum"), Some(b"toc")) => {
+                        checksum_size = Some(parse_number(&text, "checksum size")?);
+                    }
+                    (Some(field), Some(b"file"), _) => {
+                        if let Some(file) = files.last_mut() {
+                            match field {
+                                b"name" => file.name = Some(text.into_owned()),
+                                b"type" => file.kind = Some(text.trim().to_string()),
+                                b"link" => file.link_target = Some(text.into_owned()),
+                                _ => {}
+                            }
+                        }
+                    }
+                    (Some(field), Some(b"data"), Some(b"file")) => {
+                        if let Some(file) = files.last_mut() {
+                            match field {
+                                b"offset" => file.offset = Some(parse_number(&text, "offset")?),
+                                b"length" => file.length = Some(parse_number(&text, "length")?),
+                                b"size" => file.size = Some(parse_number(&text, "size")?),
+                                b"archived-checksum" => {
+                                    file.checksum = Some(text.trim().to_string())
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(_) => {
+                let closed = elements.pop();
+                if closed.as_deref() == Some(b"file".as_slice()) {
+                    let file = files
+                        .pop()
+                        .ok_or_else(|| malformed("unbalanced <file> elements"))?;
+                    finish_file(file, &files, &mut entries)?;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let checksum = match (checksum_offset, checksum_size) {
+        (Some(offset), Some(size)) => Some(TocChecksum {
+            style: checksum_style.unwrap_or_else(|| "sha1".to_string()),
+            offset,
+            size,
+        }),
+        _ => None,
+    };
+    Ok(Toc { entries, checksum })
+}
+
+/// Turns a completed `<file>` into an entry; `ancestors` are the enclosing directories.
+fn finish_file(
+    file: PendingFile,
+    ancestors: &[PendingFile],
+    entries: &mut Vec<Entry>,
+) -> Result<()> {
+    let mut path = String::new();
+    for part in ancestors
+        .iter()
+        .map(|a| a.name.as_deref())
+        .chain([file.name.as_deref()])
+    {
+        let part = part.ok_or_else(|| malformed("file without a name"))?;
+        if part.is_empty() || part == "." || part == ".." || part.contains(['/', '\0']) {
+            return Err(malformed(format!("unsafe entry name {part:?}")));
+        }
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(part);
+    }
+    let kind = match file.kind.as_deref() {
+        Some("file") | None => EntryKind::File,
+        Some("directory") => EntryKind::Directory,
+        Some("symlink") => EntryKind::Symlink,
+        Some(_) => EntryKind::Other,
+    };
+    let data = if file.has_data {
+        let encoding = match file.encoding.as_deref() {
+            None | Some("application/octet-stream") => Encoding::Raw,
+            Some("application/x-gzip") => Encoding::Gzip,
+            Some("application/x-bzip2") => Encoding::Bzip2,
+            Some("application/x-xz") => Encoding::Xz,
+            Some("application/x-lzma") => Encoding::Lzma,
+            Some(other) => {
+                return Err(malformed(format!(
+                    "'{path}' uses unsupported encoding {other}"
+                )))
+            }
+        };
+        Some(EntryData {
+            offset: file
+                .offset
+                .ok_or_else(|| malformed(format!("'{path}' has no data offset")))?,
+            length: file
+                .length
+                .ok_or_else(|| malformed(format!("'{path}' has no data length")))?,
+            size: file
+                .size
+                .ok_or_else(|| malformed(format!("'{path}' has no data size")))?,
+            encoding,
+            archived_checksum: file.checksum.map(|hex| {
+                let style = file.checksum_style.as_deref().unwrap_or("sha1");
+                (ChecksumKind::from_style(style), hex)
+            }),
+        })
+    } else {
+        None
+    };
+    entries.push(Entry {
+        path,
+        kind,
+        link_target: file.link_target,
+        data,
+    });
+    Ok(())
+}
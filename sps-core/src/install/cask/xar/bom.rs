@@ -0,0 +1,202 @@
+//! Bill of materials (`Bom`) files: a block store whose `Paths` B-tree lists every path a
+//! component installs, relative to its install location.
+
+use std::collections::HashMap;
+
+use sps_common::error::{Result, SpsError};
+
+const BOM_MAGIC: &[u8] = b"BOMStore";
+const TREE_MAGIC: &[u8] = b"tree";
+const PATHS_VAR: &str = "Paths";
+const PATH_NODE_HEADER_SIZE: usize = 12;
+
+const TYPE_FILE: u8 = 1;
+const TYPE_DIRECTORY: u8 = 2;
+const TYPE_LINK: u8 = 3;
+
+fn malformed(detail: impl std::fmt::Display) -> SpsError {
+    SpsError::ParseError("Bom", detail.to_string())
+}
+
+fn be_u16(data: &[u8], at: usize) -> Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| malformed("truncated structure"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BomKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct BomEntry {
+    /// Path relative to the install location, without the leading `./`.
+    pub path: String,
+    pub kind: BomKind,
+    pub mode: u32,
+    pub size: u64,
+    pub link_target: Option<String>,
+}
+
+struct BomStore<'a> {
+    data: &'a [u8],
+    blocks: Vec<(usize, usize)>,
+    vars: HashMap<String, u32>,
+}
+
+impl<'a> BomStore<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if !data.starts_with(BOM_MAGIC) {
+            return Err(malformed("missing BOMStore header"));
+        }
+        let index_offset = be_u32(data, 16)? as usize;
+        let vars_offset = be_u32(data, 24)? as usize;
+
+        let block_count = be_u32(data, index_offset)? as usize;
+        if block_count > data.len() / 8 {
+            return Err(malformed("block table larger than the file"));
+        }
+        let blocks = (0..block_count)
+            .map(|i| {
+                let at = index_offset + 4 + i * 8;
+                Ok((be_u32(data, at)? as usize, be_u32(data, at + 4)? as usize))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let var_count = be_u32(data, vars_offset)? as usize;
+        let mut vars = HashMap::new();
+        let mut at = vars_offset + 4;
+        for _ in 0..var_count.min(64) {
+            let block = be_u32(data, at)?;
+            let len = usize::from(
+                *data
+                    .get(at + 4)
+                    .ok_or_else(|| malformed("truncated vars"))?,
+            );
+            let name = data
+                .get(at + 5..at + 5 + len)
+                .ok_or_else(|| malformed("truncated vars"))?;
+            vars.insert(String::from_utf8_lossy(name).into_owned(), block);
+            at += 5 + len;
+        }
+        Ok(Self { data, blocks, vars })
+    }
+
+    fn block(&self, index: u32) -> Result<&'a [u8]> {
+        let (offset, len) = *self
+            .blocks
+            .get(index as usize)
+            .ok_or_else(|| malformed(format!("block {index} out of range")))?;
+        self.data
+            .get(offset..offset + len)
+            .ok_or_else(|| malformed(format!("block {index} extends past the file")))
+    }
+}
+
+/// Lists every path in the bill of materials, in tree order.
+pub(super) fn read_paths(data: &[u8]) -> Result<Vec<BomEntry>> {
+    let store = BomStore::parse(data)?;
+    let tree_block = *store
+        .vars
+        .get(PATHS_VAR)
+        .ok_or_else(|| malformed("no Paths tree"))?;
+    let tree = store.block(tree_block)?;
+    if !tree.starts_with(TREE_MAGIC) {
+        return Err(malformed("Paths is not a tree"));
+    }
+
+    // Descend along the leftmost children to the first leaf, then follow the leaf chain.
+    let mut node_index = be_u32(tree, 8)?;
+    let mut node = store.block(node_index)?;
+    let mut depth = 0;
+    while be_u16(node, 0)? == 0 {
+        depth += 1;
+        if depth > 32 {
+            return Err(malformed("Paths tree is too deep"));
+        }
+        node_index = be_u32(node, PATH_NODE_HEADER_SIZE)?;
+        node = store.block(node_index)?;
+    }
+
+    // Path IDs map to (parent ID, name); parents always precede their children.
+    let mut names: HashMap<u32, (u32, String)> = HashMap::new();
+    let mut entries = Vec::new();
+    let mut visited = 0;
+    loop {
+        visited += 1;
+        if visited > store.blocks.len() {
+            return Err(malformed("Paths leaf chain loops"));
+        }
+        let count = usize::from(be_u16(node, 2)?);
+        for i in 0..count {
+            let at = PATH_NODE_HEADER_SIZE + i * 8;
+            let info = store.block(be_u32(node, at)?)?;
+            let file = store.block(be_u32(node, at + 4)?)?;
+            let id = be_u32(info, 0)?;
+            let parent = be_u32(file, 0)?;
+            let name = file.get(4..).unwrap_or_default();
+            let name = name.split(|&b| b == 0).next().unwrap_or_default();
+            let name = String::from_utf8_lossy(name).into_owned();
+
+            let mut path = name.clone();
+            let mut ancestor = parent;
+            let mut hops = 0;
+            while let Some((next, part)) = names.get(&ancestor) {
+                hops += 1;
+                if hops > 256 {
+                    return Err(malformed("path parents loop"));
+                }
+                path = format!("{part}/{path}");
+                ancestor = *next;
+            }
+            names.insert(id, (parent, name));
+            entries.push(path_entry(&store, path, be_u32(info, 4)?)?);
+        }
+        let forward = be_u32(node, 4)?;
+        if forward == 0 {
+            break;
+        }
+        node = store.block(forward)?;
+    }
+    Ok(entries)
+}
+
+fn path_entry(store: &BomStore, path: String, info_block: u32) -> Result<BomEntry> {
+    let info = store.block(info_block)?;
+    let kind = match *info.first().ok_or_else(|| malformed("empty path info"))? {
+        TYPE_FILE => BomKind::File,
+        TYPE_DIRECTORY => BomKind::Directory,
+        TYPE_LINK => BomKind::Symlink,
+        _ => BomKind::Other,
+    };
+    let link_target = if kind == BomKind::Symlink {
+        let len = be_u32(info, 27)? as usize;
+        info.get(31..31 + len).map(|target| {
+            let target = target.strip_suffix(b"\0").unwrap_or(target);
+            String::from_utf8_lossy(target).into_owned()
+        })
+    } else {
+        None
+    };
+    let path = path
+        .strip_prefix("./")
+        .map(str::to_string)
+        .unwrap_or_else(|| if path == "." { String::new() } else { path });
+    Ok(BomEntry {
+        path,
+        kind,
+        mode: u32::from(be_u16(info, 4)?),
+        size: u64::from(be_u32(info, 18)?),
+        link_target,
+    })
+}
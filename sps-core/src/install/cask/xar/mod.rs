@@ -0,0 +1,435 @@
+//! Reads flat `.pkg` installers (xar archives) without running `installer`: the component
+//! packages they contain, where those install, their receipt IDs and the files they drop.
+
+mod archive;
+mod bom;
+mod payload;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, warn};
+
+use self::archive::{EntryKind, XarArchive};
+use self::bom::BomKind;
+use crate::install::extract::{ExtractGuard, ExtractPolicy};
+
+const PACKAGE_INFO: &str = "PackageInfo";
+const DISTRIBUTION: &str = "Distribution";
+const BOM: &str = "Bom";
+const PAYLOAD: &str = "Payload";
+const SCRIPTS: &str = "Scripts";
+
+/// What a flat package would install.
+#[derive(Debug, Clone)]
+pub struct PkgContents {
+    /// The product title from `Distribution`, for product archives.
+    pub title: Option<String>,
+    pub components: Vec<PkgComponent>,
+}
+
+/// One component package; each leaves a receipt under its identifier.
+#[derive(Debug, Clone)]
+pub struct PkgComponent {
+    /// Receipt ID, as used by `pkgutil --forget` and the `uninstall pkgutil:` stanza.
+    pub identifier: String,
+    pub version: Option<String>,
+    pub install_location: PathBuf,
+    /// Whether the component runs preinstall/postinstall scripts.
+    pub has_scripts: bool,
+    pub files: Vec<PkgFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PkgFileKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// A path a component installs, already joined with its install location.
+#[derive(Debug, Clone)]
+pub struct PkgFile {
+    pub path: PathBuf,
+    pub kind: PkgFileKind,
+    pub mode: u32,
+    pub size: u64,
+    pub link_target: Option<String>,
+}
+
+impl PkgComponent {
+    /// Total size of the regular files the component installs.
+    pub fn installed_size(&self) -> u64 {
+        self.files
+            .iter()
+            .filter(|file| file.kind == PkgFileKind::File)
+            .map(|file| file.size)
+            .sum()
+    }
+}
+
+/// Lists the components of a flat package and the files each installs. File lists come from
+/// the bill of materials, or from the payload itself when a component has no `Bom`.
+pub fn inspect_pkg(pkg_path: &Path) -> Result<PkgContents> {
+    debug!("Inspecting pkg: {}", pkg_path.display());
+    let mut archive = XarArchive::open(pkg_path)?;
+    let distribution = match archive.entry(DISTRIBUTION).cloned() {
+        Some(entry) => Some(parse_distribution(&archive.read_entry(&entry)?)?),
+        None => None,
+    };
+
+    let mut components = Vec::new();
+    for prefix in component_prefixes(&archive, distribution.as_ref()) {
+        let info_entry = archive
+            .entry(&format!("{prefix}{PACKAGE_INFO}"))
+            .cloned()
+            .ok_or_else(|| archive::malformed(format!("'{prefix}' has no PackageInfo")))?;
+        let info = parse_package_info(&archive.read_entry(&info_entry)?)?;
+        let install_location = PathBuf::from(info.install_location.as_deref().unwrap_or("/"));
+        let files = component_files(&mut archive, &prefix)?
+            .into_iter()
+            .map(|mut file| {
+                file.path = install_location.join(&file.path);
+                file
+            })
+            .collect();
+        components.push(PkgComponent {
+            identifier: info.identifier,
+            version: info.version,
+            install_location,
+            has_scripts: archive.entry(&format!("{prefix}{SCRIPTS}")).is_some(),
+            files,
+        });
+    }
+    if components.is_empty() {
+        return Err(archive::malformed(format!(
+            "{} contains no component packages",
+            pkg_path.display()
+        )));
+    }
+    Ok(PkgContents {
+        title: distribution.and_then(|d| d.title),
+        components,
+    })
+}
+
+/// Expands a flat package into `dest` the way `pkgutil --expand-full` does: metadata files are
+/// copied as-is and each `Payload` and `Scripts` archive is unpacked into a directory. Everything
+/// written is held to `policy`, as for any other archive.
+pub fn expand_pkg(pkg_path: &Path, dest: &Path, policy: &ExtractPolicy) -> Result<()> {
+    debug!(
+        "Expanding pkg {} into {}",
+        pkg_path.display(),
+        dest.display()
+    );
+    let mut archive = XarArchive::open(pkg_path)?;
+    fs::create_dir_all(dest)?;
+    let mut guard = ExtractGuard::new(policy, dest, pkg_path)?;
+    for entry in archive.entries().to_vec() {
+        let Some(target) = guard.entry_path(dest, Path::new(&entry.path), 0)? else {
+            continue;
+        };
+        guard.count_entry()?;
+        match entry.kind {
+            EntryKind::Directory => fs::create_dir_all(&target)?,
+            EntryKind::Symlink => {
+                let Some(link) = &entry.link_target else {
+                    continue;
+                };
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                guard.check_symlink(&target, Path::new(link))?;
+                std::os::unix::fs::symlink(link, &target)?;
+            }
+            EntryKind::Other => debug!("Skipping special xar entry '{}'", entry.path),
+            EntryKind::File => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let name = entry.path.rsplit('/').next().unwrap_or_default();
+                if name == PAYLOAD || name == SCRIPTS {
+                    let mut decoded = payload::decode(archive.open_entry(&entry)?)?;
+                    fs::create_dir_all(&target)?;
+                    extract_cpio(&mut decoded, &target, &mut guard)?;
+                } else {
+                    let mut out = File::create(&target)?;
+                    guard
+                        .copy(&mut archive.open_entry(&entry)?, &mut out, &target)
+                        .map_err(|e| match e {
+                            SpsError::Io(e) => {
+                                archive::malformed(format!("'{}': {}", entry.path, e))
+                            }
+                            other => other,
+                        })?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Path prefixes (`""` or `"Foo.pkg/"`) of the component packages, in `Distribution` order.
+fn component_prefixes(archive: &XarArchive, distribution: Option<&Distribution>) -> Vec<String> {
+    let mut prefixes: Vec<String> = archive
+        .entries()
+        .iter()
+        .filter_map(|entry| {
+            let prefix = entry.path.strip_suffix(PACKAGE_INFO)?;
+            (prefix.is_empty()
+                || (prefix.ends_with('/') && !prefix[..prefix.len() - 1].contains('/')))
+            .then(|| prefix.to_string())
+        })
+        .collect();
+    if let Some(distribution) = distribution {
+        let position = |prefix: &String| {
+            distribution
+                .package_refs
+                .iter()
+                .position(|name| format!("{name}/") == *prefix)
+                .unwrap_or(usize::MAX)
+        };
+        prefixes.sort_by_key(position);
+    }
+    prefixes
+}
+
+fn component_files(archive: &mut XarArchive, prefix: &str) -> Result<Vec<PkgFile>> {
+    if let Some(bom_entry) = archive.entry(&format!("{prefix}{BOM}")).cloned() {
+        let entries = bom::read_paths(&archive.read_entry(&bom_entry)?)?;
+        return Ok(entries
+            .into_iter()
+            .filter(|entry| !entry.path.is_empty())
+            .map(|entry| PkgFile {
+                path: PathBuf::from(entry.path),
+                kind: match entry.kind {
+                    BomKind::File => PkgFileKind::File,
+                    BomKind::Directory => PkgFileKind::Directory,
+                    BomKind::Symlink => PkgFileKind::Symlink,
+                    BomKind::Other => PkgFileKind::Other,
+                },
+                mode: entry.mode,
+                size: entry.size,
+                link_target: entry.link_target,
+            })
+            .collect());
+    }
+    let Some(payload_entry) = archive.entry(&format!("{prefix}{PAYLOAD}")).cloned() else {
+        debug!("Component '{}' has no Bom or Payload", prefix);
+        return Ok(Vec::new());
+    };
+    debug!(
+        "Component '{}' has no Bom, listing its payload instead",
+        prefix
+    );
+    let mut decoded = payload::decode(archive.open_entry(&payload_entry)?)?;
+    let mut files = Vec::new();
+    payload::for_each_entry(&mut decoded, |entry, data| {
+        let Some(path) = payload_relative_path(&entry.path) else {
+            return Ok(());
+        };
+        if path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        let kind = match entry.mode & payload::S_IFMT {
+            payload::S_IFREG => PkgFileKind::File,
+            payload::S_IFDIR => PkgFileKind::Directory,
+            payload::S_IFLNK => PkgFileKind::Symlink,
+            _ => PkgFileKind::Other,
+        };
+        let link_target = if kind == PkgFileKind::Symlink {
+            let mut target = Vec::new();
+            data.read_to_end(&mut target)?;
+            Some(String::from_utf8_lossy(&target).into_owned())
+        } else {
+            None
+        };
+        files.push(PkgFile {
+            path,
+            kind,
+            mode: entry.mode & 0o7777,
+            size: entry.size,
+            link_target,
+        });
+        Ok(())
+    })?;
+    Ok(files)
+}
+
+/// Normalises a payload path (`./Applications/Foo.app`) to a relative path, rejecting any
+/// that would leave the install location.
+fn payload_relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => relative.push(part),
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir => {
+                warn!("Skipping payload entry with unsafe path {:?}", path);
+                return None;
+            }
+        }
+    }
+    Some(relative)
+}
+
+/// Unpacks a cpio stream into `dest`, checking every member against `guard`.
+fn extract_cpio(reader: &mut dyn Read, dest: &Path, guard: &mut ExtractGuard) -> Result<()> {
+    // Inode -> first path extracted for it, so later hard links to it can be recreated.
+    let mut hard_links: HashMap<u64, PathBuf> = HashMap::new();
+    let mut directories = Vec::new();
+    payload::for_each_entry(reader, |entry, data| {
+        let name = Path::new(&entry.path);
+        let Some(target) = guard.entry_path(dest, name, 0)? else {
+            return Ok(());
+        };
+        guard.count_entry()?;
+        guard.check_mode(name, entry.mode)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let permissions = fs::Permissions::from_mode(entry.mode & 0o777);
+        match entry.mode & payload::S_IFMT {
+            payload::S_IFDIR => {
+                fs::create_dir_all(&target)?;
+                directories.push((target, permissions));
+            }
+            payload::S_IFLNK => {
+                let mut link = Vec::new();
+                data.read_to_end(&mut link)?;
+                let link = String::from_utf8_lossy(&link);
+                let link = Path::new(link.trim_end_matches('\0'));
+                guard.check_symlink(&target, link)?;
+                std::os::unix::fs::symlink(link, &target)?;
+            }
+            payload::S_IFREG => {
+                if entry.nlink > 1 {
+                    if let Some(existing) = hard_links.get(&entry.inode) {
+                        if entry.size == 0 {
+                            if let Some(existing) = guard.check_hardlink(&target, existing)? {
+                                fs::hard_link(existing, &target)?;
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+                guard.reserve(entry.size)?;
+                let mut out = File::create(&target)?;
+                io::copy(data, &mut out)?;
+                out.set_permissions(permissions)?;
+                out.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(entry.mtime))?;
+                if entry.nlink > 1 {
+                    hard_links.entry(entry.inode).or_insert(target);
+                }
+            }
+            _ => debug!("Skipping special payload entry '{}'", entry.path),
+        }
+        Ok(())
+    })?;
+    // Read-only directories can only be locked down once everything inside them exists.
+    for (directory, permissions) in directories.into_iter().rev() {
+        fs::set_permissions(&directory, permissions)?;
+    }
+    Ok(())
+}
+
+struct PackageInfo {
+    identifier: String,
+    version: Option<String>,
+    install_location: Option<String>,
+}
+
+struct Distribution {
+    title: Option<String>,
+    /// Component package names (`Foo.pkg`) in the order `pkg-ref` elements point at them.
+    package_refs: Vec<String>,
+}
+
+fn xml_error(file: &'static str, e: impl std::fmt::Display) -> SpsError {
+    SpsError::ParseError(file, e.to_string())
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.as_ref() == name)
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+fn parse_package_info(xml: &[u8]) -> Result<PackageInfo> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    loop {
+        match reader
+            .read_event_into(&mut buf)
+            .map_err(|e| xml_error("PackageInfo", e))?
+        {
+            Event::Start(ref element) | Event::Empty(ref element)
+                if element.name().as_ref() == b"pkg-info" =>
+            {
+                let identifier = attribute(element, b"identifier")
+                    .ok_or_else(|| xml_error("PackageInfo", "pkg-info has no identifier"))?;
+                return Ok(PackageInfo {
+                    identifier,
+                    version: attribute(element, b"version"),
+                    install_location: attribute(element, b"install-location"),
+                });
+            }
+            Event::Eof => return Err(xml_error("PackageInfo", "no pkg-info element")),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+fn parse_distribution(xml: &[u8]) -> Result<Distribution> {
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut distribution = Distribution {
+        title: None,
+        package_refs: Vec::new(),
+    };
+    let mut current: Option<Vec<u8>> = None;
+    loop {
+        match reader
+            .read_event_into(&mut buf)
+            .map_err(|e| xml_error("Distribution", e))?
+        {
+            Event::Start(ref element) => current = Some(element.name().as_ref().to_vec()),
+            Event::End(_) => current = None,
+            Event::Text(ref text) => {
+                let text = text.unescape().map_err(|e| xml_error("Distribution", e))?;
+                let text = text.trim();
+                match current.as_deref() {
+                    Some(b"title") if distribution.title.is_none() && !text.is_empty() => {
+                        distribution.title = Some(text.to_string());
+                    }
+                    // `pkg-ref` elements with content point at a component: `#Foo.pkg`.
+                    Some(b"pkg-ref") => {
+                        if let Some(name) = text.strip_prefix('#') {
+                            let name = name.replace("%20", " ");
+                            if !distribution.package_refs.contains(&name) {
+                                distribution.package_refs.push(name);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(distribution)
+}
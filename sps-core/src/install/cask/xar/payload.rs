@@ -0,0 +1,235 @@
+//! `Payload` and `Scripts` streams of a component package: a cpio archive that is gzip-,
+//! bzip2- or xz-compressed, or split into `pbzx` chunks.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use bzip2::read::BzDecoder;
+use flate2::read::MultiGzDecoder;
+use lzma_rust2::XzReader;
+use sps_common::error::{Result, SpsError};
+
+const PBZX_MAGIC: &[u8] = b"pbzx";
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\x00";
+const ODC_MAGIC: &[u8] = b"070707";
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_CRC_MAGIC: &[u8] = b"070702";
+const ODC_HEADER_SIZE: usize = 76;
+const NEWC_HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+/// pbzx chunks are 16 MiB in practice; anything much larger is not a real payload.
+const MAX_PBZX_CHUNK: u64 = 256 << 20;
+const MAX_NAME_SIZE: u64 = 4096;
+
+pub(super) fn malformed(detail: impl std::fmt::Display) -> SpsError {
+    SpsError::ParseError("pkg payload", detail.to_string())
+}
+
+/// Wraps the raw payload stream in whatever decoder its leading bytes call for.
+pub(super) fn decode<'a>(raw: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(raw);
+    let head = reader.fill_buf()?;
+    let decoded: Box<dyn Read + 'a> = if head.starts_with(&[0x1f, 0x8b]) {
+        Box::new(MultiGzDecoder::new(reader))
+    } else if head.starts_with(b"BZh") {
+        Box::new(BzDecoder::new(reader))
+    } else if head.starts_with(XZ_MAGIC) {
+        Box::new(XzReader::new(reader, true))
+    } else if head.starts_with(PBZX_MAGIC) {
+        Box::new(PbzxReader::new(reader)?)
+    } else if head.starts_with(b"0707") {
+        Box::new(reader)
+    } else {
+        return Err(malformed("unrecognised payload compression"));
+    };
+    Ok(decoded)
+}
+
+/// Apple's chunked format: a header, then (uncompressed size, stored size, data) triples where
+/// each chunk is an xz stream unless it was stored as-is.
+struct PbzxReader<R> {
+    inner: R,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> PbzxReader<R> {
+    fn new(mut inner: R) -> Result<Self> {
+        let mut header = [0u8; 12];
+        inner.read_exact(&mut header)?;
+        Ok(Self {
+            inner,
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Loads the next chunk; returns false at the end of the stream.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        let mut sizes = [0u8; 16];
+        match self.inner.read_exact(&mut sizes[..8]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        self.inner.read_exact(&mut sizes[8..])?;
+        let size = u64::from_be_bytes(sizes[..8].try_into().unwrap());
+        let stored = u64::from_be_bytes(sizes[8..].try_into().unwrap());
+        if size > MAX_PBZX_CHUNK || stored > MAX_PBZX_CHUNK {
+            return Err(invalid("pbzx chunk is too large"));
+        }
+        // Read what is actually there rather than trusting the header with the allocation.
+        let mut data = Vec::new();
+        (&mut self.inner).take(stored).read_to_end(&mut data)?;
+        if data.len() as u64 != stored {
+            return Err(invalid("truncated pbzx chunk"));
+        }
+        self.chunk.clear();
+        if data.starts_with(XZ_MAGIC) {
+            XzReader::new(data.as_slice(), true)
+                .take(size + 1)
+                .read_to_end(&mut self.chunk)?;
+        } else {
+            self.chunk = data;
+        }
+        if self.chunk.len() as u64 != size {
+            return Err(invalid("pbzx chunk has the wrong size"));
+        }
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for PbzxReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn invalid(detail: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, detail.to_string())
+}
+
+/// One cpio member header.
+#[derive(Debug, Clone)]
+pub(super) struct CpioEntry {
+    /// Path as stored, usually starting with `./`.
+    pub path: String,
+    pub mode: u32,
+    pub inode: u64,
+    pub nlink: u32,
+    pub mtime: u64,
+    pub size: u64,
+}
+
+pub(super) const S_IFMT: u32 = 0o170000;
+pub(super) const S_IFDIR: u32 = 0o040000;
+pub(super) const S_IFREG: u32 = 0o100000;
+pub(super) const S_IFLNK: u32 = 0o120000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CpioFormat {
+    Odc,
+    Newc,
+}
+
+impl CpioFormat {
+    fn padding(self, len: u64) -> u64 {
+        match self {
+            Self::Odc => 0,
+            Self::Newc => (4 - len % 4) % 4,
+        }
+    }
+}
+
+/// Calls `f` with every member of the cpio stream and a reader over its data. Data `f` leaves
+/// unread is skipped.
+pub(super) fn for_each_entry(
+    reader: &mut dyn Read,
+    mut f: impl FnMut(&CpioEntry, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    loop {
+        let mut magic = [0u8; 6];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|e| malformed(format!("truncated cpio header: {e}")))?;
+        let (format, header_size) = match &magic[..] {
+            ODC_MAGIC => (CpioFormat::Odc, ODC_HEADER_SIZE),
+            NEWC_MAGIC | NEWC_CRC_MAGIC => (CpioFormat::Newc, NEWC_HEADER_SIZE),
+            _ => return Err(malformed("unsupported cpio format")),
+        };
+        let mut header = vec![0u8; header_size];
+        header[..6].copy_from_slice(&magic);
+        reader
+            .read_exact(&mut header[6..])
+            .map_err(|e| malformed(format!("truncated cpio header: {e}")))?;
+        let (mut entry, name_size) = match format {
+            CpioFormat::Odc => parse_odc(&header)?,
+            CpioFormat::Newc => parse_newc(&header)?,
+        };
+        if name_size == 0 || name_size > MAX_NAME_SIZE {
+            return Err(malformed("invalid cpio name size"));
+        }
+        let mut name = vec![0u8; name_size as usize];
+        reader.read_exact(&mut name)?;
+        skip(reader, format.padding(header_size as u64 + name_size))?;
+        let name = name.strip_suffix(b"\0").unwrap_or(&name);
+        entry.path = String::from_utf8_lossy(name).into_owned();
+        if entry.path == TRAILER {
+            return Ok(());
+        }
+
+        let mut data = (&mut *reader).take(entry.size);
+        f(&entry, &mut data)?;
+        io::copy(&mut data, &mut io::sink())?;
+        if data.limit() > 0 {
+            return Err(malformed(format!("truncated data for '{}'", entry.path)));
+        }
+        skip(reader, format.padding(entry.size))?;
+    }
+}
+
+fn skip(reader: &mut dyn Read, len: u64) -> Result<()> {
+    if len > 0 {
+        io::copy(&mut reader.take(len), &mut io::sink())?;
+    }
+    Ok(())
+}
+
+fn field(header: &[u8], start: usize, len: usize, radix: u32) -> Result<u64> {
+    let text = std::str::from_utf8(&header[start..start + len])
+        .map_err(|_| malformed("non-ASCII cpio header"))?;
+    u64::from_str_radix(text, radix).map_err(|_| malformed("invalid cpio header field"))
+}
+
+fn parse_odc(header: &[u8]) -> Result<(CpioEntry, u64)> {
+    let entry = CpioEntry {
+        path: String::new(),
+        inode: (field(header, 6, 6, 8)? << 18) | field(header, 12, 6, 8)?,
+        mode: field(header, 18, 6, 8)? as u32,
+        nlink: field(header, 36, 6, 8)? as u32,
+        mtime: field(header, 48, 11, 8)?,
+        size: field(header, 65, 11, 8)?,
+    };
+    Ok((entry, field(header, 59, 6, 8)?))
+}
+
+fn parse_newc(header: &[u8]) -> Result<(CpioEntry, u64)> {
+    let hex = |index: usize| field(header, 6 + index * 8, 8, 16);
+    let entry = CpioEntry {
+        path: String::new(),
+        inode: (hex(7)? << 48) | ((hex(8)? & 0xffff) << 32) | hex(0)?,
+        mode: hex(1)? as u32,
+        nlink: hex(4)? as u32,
+        mtime: hex(5)?,
+        size: hex(6)?,
+    };
+    Ok((entry, hex(11)?))
+}
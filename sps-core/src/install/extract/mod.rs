@@ -1,17 +1,24 @@
-// Path: sps-core/src/install/extract.rs
+// Path: sps-core/src/install/extract/mod.rs
+mod policy;
+
 use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{self, Read, Seek};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek};
 use std::path::{Component, Path, PathBuf};
-use std::process::Command;
 
-use bzip2::read::BzDecoder;
-use flate2::read::GzDecoder;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use lzma_rust2::{LzipReader, XzReader};
+use ruzstd::frame::ReadFrameHeaderError;
+use ruzstd::frame_decoder::{BlockDecodingStrategy, FrameDecoder, FrameDecoderError};
+use sevenz_rust::{Archive as SevenZArchive, Password, SevenZArchiveEntry, SevenZReader};
 use sps_common::error::{Result, SpsError};
 use tar::{Archive, EntryType};
 use tracing::{debug, error, warn};
 use zip::ZipArchive;
 
+pub(crate) use self::policy::ExtractGuard;
+pub use self::policy::ExtractPolicy;
 #[cfg(target_os = "macos")]
 use crate::utils::xattr;
 
@@ -32,19 +39,17 @@ pub(crate) fn infer_archive_root_dir(
 
     match archive_type {
         "zip" => infer_zip_root(file, archive_path),
-        "gz" | "tgz" => {
-            let decompressed = GzDecoder::new(file);
-            infer_tar_root(decompressed, archive_path)
-        }
-        "bz2" | "tbz" | "tbz2" => {
-            let decompressed = BzDecoder::new(file);
-            infer_tar_root(decompressed, archive_path)
-        }
-        "xz" | "txz" => {
-            // Use external xz command to decompress, then read as tar
-            infer_xz_tar_root(archive_path)
-        }
+        "7z" => infer_7z_root(archive_path),
         "tar" => infer_tar_root(file, archive_path),
+        _ if is_compressed_type(archive_type) => {
+            let (is_tar, decompressed) = sniff_tar(decompressor(file, archive_type)?)?;
+            if is_tar {
+                infer_tar_root(decompressed, archive_path)
+            } else {
+                // A single compressed file has no root directory to strip.
+                Ok(None)
+            }
+        }
         _ => Err(SpsError::Generic(format!(
             "Cannot infer root dir for unsupported archive type '{}' in {}",
             archive_type,
@@ -139,35 +144,150 @@ fn infer_tar_root<R: Read>(reader: R, archive_path_for_log: &Path) -> Result<Opt
     }
 }
 
-fn infer_xz_tar_root(archive_path: &Path) -> Result<Option<PathBuf>> {
-    // Create a temporary file for decompressed content
-    let temp_file =
-        tempfile::NamedTempFile::new().map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
+fn is_compressed_type(archive_type: &str) -> bool {
+    matches!(
+        archive_type,
+        "gz" | "tgz" | "bz2" | "tbz" | "tbz2" | "xz" | "txz" | "zst" | "tzst" | "lz" | "tlz"
+    )
+}
 
-    // Use external xz command to decompress
-    let output = Command::new("xz")
-        .args(["-dc", archive_path.to_str().unwrap()])
-        .output()
-        .map_err(|e| {
-            SpsError::Generic(format!(
-                "Failed to run xz command for decompression: {e}. Make sure xz is installed."
-            ))
-        })?;
+/// Streaming decoder for a compressed archive type. Concatenated streams (multi-member gzip,
+/// xz and lzip files, several zstd frames) are read as one, like the command line tools do.
+fn decompressor(file: File, archive_type: &str) -> Result<Box<dyn Read>> {
+    let reader = BufReader::new(file);
+    Ok(match archive_type {
+        "gz" | "tgz" => Box::new(MultiGzDecoder::new(reader)),
+        "bz2" | "tbz" | "tbz2" => Box::new(MultiBzDecoder::new(reader)),
+        "xz" | "txz" => Box::new(XzReader::new(reader, true)),
+        "zst" | "tzst" => Box::new(ZstdDecoder::new(reader)),
+        "lz" | "tlz" => Box::new(LzipReader::new(reader)),
+        _ => {
+            return Err(SpsError::Generic(format!(
+                "Unsupported compression type '{archive_type}'"
+            )))
+        }
+    })
+}
 
-    if !output.status.success() {
-        return Err(SpsError::Generic(format!(
-            "xz decompression failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )));
+/// A stream whose first block was read ahead and put back in front.
+type Sniffed<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+/// Reads the first block of a decompressed stream to tell a tarball from a single compressed
+/// file. Returns the stream with that block put back in front.
+fn sniff_tar<R: Read>(mut reader: R) -> Result<(bool, Sniffed<R>)> {
+    let mut block = Vec::with_capacity(512);
+    (&mut reader).take(512).read_to_end(&mut block)?;
+    let is_tar = is_tar_header(&block);
+    Ok((is_tar, Cursor::new(block).chain(reader)))
+}
+
+/// A ustar/GNU header, or a v7 header whose checksum adds up.
+fn is_tar_header(block: &[u8]) -> bool {
+    if block.len() < 512 {
+        return false;
+    }
+    if &block[257..262] == b"ustar" {
+        return true;
     }
+    let stored = std::str::from_utf8(&block[148..156])
+        .ok()
+        .map(|field| field.trim_matches(|c| c == ' ' || c == '\0'))
+        .and_then(|field| u32::from_str_radix(field, 8).ok());
+    let sum: u32 = block[..512]
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u32::from(b)
+            }
+        })
+        .sum();
+    stored == Some(sum)
+}
 
-    // Write decompressed data to temp file
-    std::fs::write(temp_file.path(), &output.stdout)
-        .map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
+/// Decodes every frame of a zstd file; skippable frames are passed over.
+struct ZstdDecoder<R: BufRead> {
+    source: R,
+    frame: FrameDecoder,
+    in_frame: bool,
+}
 
-    // Read as tar
-    let file = File::open(temp_file.path()).map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
-    infer_tar_root(file, archive_path)
+impl<R: BufRead> ZstdDecoder<R> {
+    fn new(source: R) -> Self {
+        Self {
+            source,
+            frame: FrameDecoder::new(),
+            in_frame: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.in_frame {
+                while self.frame.can_collect() < buf.len() && !self.frame.is_finished() {
+                    let wanted = buf.len() - self.frame.can_collect();
+                    self.frame
+                        .decode_blocks(&mut self.source, BlockDecodingStrategy::UptoBytes(wanted))
+                        .map_err(io::Error::other)?;
+                }
+                let read = self.frame.read(buf)?;
+                if read > 0 {
+                    return Ok(read);
+                }
+                self.in_frame = false;
+            }
+            if self.source.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+            match self.frame.init(&mut self.source) {
+                Ok(()) => self.in_frame = true,
+                Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                    length,
+                    ..
+                })) => {
+                    io::copy(
+                        &mut (&mut self.source).take(u64::from(length)),
+                        &mut io::sink(),
+                    )?;
+                }
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+    }
+}
+
+fn infer_7z_root(archive_path: &Path) -> Result<Option<PathBuf>> {
+    let archive = SevenZArchive::open(archive_path).map_err(|e| {
+        SpsError::Generic(format!(
+            "Failed to open 7z archive {}: {}",
+            archive_path.display(),
+            e
+        ))
+    })?;
+    let roots: HashSet<PathBuf> = archive
+        .files
+        .iter()
+        .filter_map(|entry| match Path::new(entry.name()).components().next() {
+            Some(Component::Normal(name)) => Some(PathBuf::from(name)),
+            _ => None,
+        })
+        .collect();
+    let has_nested_entries = archive
+        .files
+        .iter()
+        .any(|entry| Path::new(entry.name()).components().count() > 1);
+    if roots.len() == 1 && has_nested_entries {
+        Ok(roots.into_iter().next())
+    } else {
+        Ok(None)
+    }
 }
 
 fn infer_zip_root<R: Read + Seek>(reader: R, archive_path: &Path) -> Result<Option<PathBuf>> {
@@ -237,6 +357,25 @@ pub fn extract_archive(
     target_dir: &Path,
     strip_components: usize,
     archive_type: &str,
+) -> Result<()> {
+    extract_archive_with_policy(
+        archive_path,
+        target_dir,
+        strip_components,
+        archive_type,
+        &ExtractPolicy::default(),
+    )
+}
+
+/// Like [`extract_archive`], with explicit limits. Entries that break the policy (links out of
+/// `target_dir`, device nodes, setuid bits, too many entries or bytes) abort the extraction
+/// with [`SpsError::UnsafeArchive`].
+pub fn extract_archive_with_policy(
+    archive_path: &Path,
+    target_dir: &Path,
+    strip_components: usize,
+    archive_type: &str,
+    policy: &ExtractPolicy,
 ) -> Result<()> {
     debug!(
         "Extracting archive '{}' (type: {}) to '{}' (strip_components={}) using native Rust crates.",
@@ -264,18 +403,22 @@ pub fn extract_archive(
         )))
     })?;
 
+    let mut guard = ExtractGuard::new(policy, target_dir, archive_path)?;
     let result = match archive_type {
-        "zip" => extract_zip_archive(file, target_dir, strip_components, archive_path),
-        "gz" | "tgz" => {
-            let tar = GzDecoder::new(file);
-            extract_tar_archive(tar, target_dir, strip_components, archive_path)
-        }
-        "bz2" | "tbz" | "tbz2" => {
-            let tar = BzDecoder::new(file);
-            extract_tar_archive(tar, target_dir, strip_components, archive_path)
+        "zip" => extract_zip_archive(file, target_dir, strip_components, &mut guard),
+        "7z" => extract_7z_archive(target_dir, strip_components, &mut guard),
+        "tar" => extract_tar_archive(file, target_dir, strip_components, &mut guard),
+        _ if is_compressed_type(archive_type) => {
+            match sniff_tar(decompressor(file, archive_type)?) {
+                Ok((true, tar)) => {
+                    extract_tar_archive(tar, target_dir, strip_components, &mut guard)
+                }
+                Ok((false, content)) => {
+                    extract_single_file(content, target_dir, archive_path, &mut guard)
+                }
+                Err(e) => Err(e),
+            }
         }
-        "xz" | "txz" => extract_xz_tar_archive(archive_path, target_dir, strip_components),
-        "tar" => extract_tar_archive(file, target_dir, strip_components, archive_path),
         _ => Err(SpsError::Generic(format!(
             "Unsupported archive type provided for extraction: '{}' for file {}",
             archive_type,
@@ -298,50 +441,149 @@ pub fn extract_archive(
     result
 }
 
-/// Represents a hardlink operation that was deferred.
-fn extract_xz_tar_archive(
-    archive_path: &Path,
+/// Writes a decompressed single file into `target_dir`, named after the archive without its
+/// compression extension (`tool.gz` -> `tool`).
+fn extract_single_file<R: Read>(
+    mut reader: R,
     target_dir: &Path,
-    strip_components: usize,
+    archive_path: &Path,
+    guard: &mut ExtractGuard,
 ) -> Result<()> {
+    let file_name = archive_path.file_stem().ok_or_else(|| {
+        SpsError::Generic(format!(
+            "Cannot name the file decompressed from {}",
+            archive_path.display()
+        ))
+    })?;
+    let target_path = target_dir.join(file_name);
     debug!(
-        "Extracting XZ+TAR archive using external xz command: {}",
-        archive_path.display()
+        "Decompressing single file {} to {}",
+        archive_path.display(),
+        target_path.display()
     );
+    let mut outfile = File::create(&target_path).map_err(|e| {
+        SpsError::Io(std::sync::Arc::new(io::Error::new(
+            e.kind(),
+            format!("Failed to create file {}: {}", target_path.display(), e),
+        )))
+    })?;
+    guard.count_entry()?;
+    guard.copy(&mut reader, &mut outfile, &target_path)?;
+    Ok(())
+}
 
-    // Create a temporary file for decompressed content
-    let temp_file =
-        tempfile::NamedTempFile::new().map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
+/// Extracts a 7z archive. Unix modes recorded by p7zip are applied, and entries stored as
+/// symlinks are recreated as symlinks.
+fn extract_7z_archive(
+    target_dir: &Path,
+    strip_components: usize,
+    guard: &mut ExtractGuard,
+) -> Result<()> {
+    let archive_path = guard.archive_path();
+    debug!("Starting 7z extraction for {}", archive_path.display());
+    let mut reader = SevenZReader::open(archive_path, Password::empty()).map_err(|e| {
+        SpsError::Generic(format!(
+            "Failed to open 7z archive {}: {}",
+            archive_path.display(),
+            e
+        ))
+    })?;
+    // Keep the entry's own error so policy violations surface as themselves.
+    let mut failure = None;
+    let result = reader.for_each_entries(|entry, content| {
+        match extract_7z_entry(entry, content, target_dir, strip_components, guard) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                let message = e.to_string();
+                failure = Some(e);
+                Err(sevenz_rust::Error::io(io::Error::other(message)))
+            }
+        }
+    });
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    result.map_err(|e| {
+        SpsError::Generic(format!(
+            "Failed to extract 7z archive {}: {}",
+            archive_path.display(),
+            e
+        ))
+    })?;
+    debug!("Finished 7z extraction for {}", archive_path.display());
+    Ok(())
+}
 
-    // Use external xz command to decompress
-    let output = Command::new("xz")
-        .args(["-dc", archive_path.to_str().unwrap()])
-        .output()
-        .map_err(|e| {
-            SpsError::Generic(format!(
-                "Failed to run xz command for extraction: {e}. Make sure xz is installed."
-            ))
-        })?;
+fn extract_7z_entry(
+    entry: &SevenZArchiveEntry,
+    content: &mut dyn Read,
+    target_dir: &Path,
+    strip_components: usize,
+    guard: &mut ExtractGuard,
+) -> Result<()> {
+    const UNIX_EXTENSION: u32 = 0x8000;
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+
+    guard.count_entry()?;
+    let path_in_archive = Path::new(entry.name());
+    let Some(target_path) = guard.entry_path(target_dir, path_in_archive, strip_components)? else {
+        return Ok(());
+    };
+    if entry.is_anti_item() {
+        return Ok(());
+    }
+    let attributes = entry.windows_attributes();
+    let unix_mode = (entry.has_windows_attributes && attributes & UNIX_EXTENSION != 0)
+        .then_some(attributes >> 16);
+    if let Some(mode) = unix_mode {
+        guard.check_mode(path_in_archive, mode)?;
+    }
+    if entry.is_directory() {
+        fs::create_dir_all(&target_path)?;
+        return Ok(());
+    }
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if target_path.symlink_metadata().is_ok() {
+        fs::remove_file(&target_path)?;
+    }
 
-    if !output.status.success() {
-        return Err(SpsError::Generic(format!(
-            "xz decompression failed during extraction: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )));
+    #[cfg(unix)]
+    if unix_mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+        let link_target = read_link_target(content)?;
+        guard.check_symlink(&target_path, &link_target)?;
+        std::os::unix::fs::symlink(&link_target, &target_path)?;
+        return Ok(());
     }
 
-    // Write decompressed data to temp file
-    std::fs::write(temp_file.path(), &output.stdout)
-        .map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
+    // The decoder stops at the entry's recorded size, so it can be counted before anything is
+    // written, as for tar.
+    guard.reserve(entry.size())?;
+    let mut outfile = File::create(&target_path)?;
+    io::copy(&mut content.take(entry.size()), &mut outfile)?;
+    #[cfg(unix)]
+    if let Some(mode) = unix_mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&target_path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(())
+}
 
-    // Extract as tar
-    let file = File::open(temp_file.path()).map_err(|e| SpsError::Io(std::sync::Arc::new(e)))?;
-    extract_tar_archive(file, target_dir, strip_components, archive_path)
+/// Reads the content of a symlink entry stored as data (zip, 7z). Link targets longer than
+/// `PATH_MAX` could not be created anyway.
+#[cfg(unix)]
+fn read_link_target(content: &mut dyn Read) -> Result<PathBuf> {
+    let mut link_target = String::new();
+    content.take(4096).read_to_string(&mut link_target)?;
+    Ok(PathBuf::from(link_target))
 }
 
+/// Represents a hardlink operation that was deferred.
 #[cfg(unix)]
 struct DeferredHardLink {
-    link_path_in_archive: PathBuf,
+    link_path_on_disk: PathBuf,
     target_name_in_archive: PathBuf,
 }
 
@@ -349,8 +591,9 @@ fn extract_tar_archive<R: Read>(
     reader: R,
     target_dir: &Path,
     strip_components: usize,
-    archive_path_for_log: &Path,
+    guard: &mut ExtractGuard,
 ) -> Result<()> {
+    let archive_path_for_log = guard.archive_path();
     let mut archive = Archive::new(reader);
     archive.set_preserve_permissions(true);
     archive.set_unpack_xattrs(true);
@@ -373,6 +616,7 @@ fn extract_tar_archive<R: Read>(
                 e
             ))
         })?;
+        guard.count_entry()?;
 
         let original_path_in_archive: PathBuf = entry
             .path()
@@ -385,58 +629,36 @@ fn extract_tar_archive<R: Read>(
             })?
             .into_owned();
 
-        let stripped_components_iter: Vec<Component<'_>> = original_path_in_archive
-            .components()
-            .skip(strip_components)
-            .collect();
-
-        if stripped_components_iter.is_empty() {
+        let Some(final_target_path_on_disk) =
+            guard.entry_path(target_dir, &original_path_in_archive, strip_components)?
+        else {
             debug!(
                 "Skipping entry due to strip_components: {:?}",
                 original_path_in_archive
             );
             continue;
-        }
+        };
 
-        let mut final_target_path_on_disk = target_dir.to_path_buf();
-        for comp in stripped_components_iter {
-            match comp {
-                Component::Normal(p) => final_target_path_on_disk.push(p),
-                Component::CurDir => {}
-                Component::ParentDir => {
-                    let msg = format!(
-                        "Unsafe '..' in TAR path {} after stripping in {}",
-                        original_path_in_archive.display(),
-                        archive_path_for_log.display()
-                    );
-                    error!("{}", msg);
-                    errors.push(msg);
-                    continue;
-                }
-                Component::Prefix(_) | Component::RootDir => {
-                    let msg = format!(
-                        "Disallowed component {:?} in TAR path {}",
-                        comp,
-                        original_path_in_archive.display()
-                    );
-                    error!("{}", msg);
-                    errors.push(msg);
-                    continue;
-                }
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            EntryType::Char | EntryType::Block => {
+                return Err(guard.violation(format!(
+                    "device node {}",
+                    original_path_in_archive.display()
+                )))
+            }
+            EntryType::Fifo => {
+                return Err(guard.violation(format!(
+                    "special file {}",
+                    original_path_in_archive.display()
+                )))
             }
+            _ => {}
         }
-
-        if !final_target_path_on_disk.starts_with(target_dir) {
-            let msg = format!(
-                "Path traversal {} -> {} detected in {}",
-                original_path_in_archive.display(),
-                final_target_path_on_disk.display(),
-                archive_path_for_log.display()
-            );
-            error!("{}", msg);
-            errors.push(msg);
-            continue;
+        if let Ok(mode) = entry.header().mode() {
+            guard.check_mode(&original_path_in_archive, mode)?;
         }
+        guard.reserve(entry.size())?;
 
         if let Some(parent) = final_target_path_on_disk.parent() {
             if !parent.exists() {
@@ -449,11 +671,17 @@ fn extract_tar_archive<R: Read>(
             }
         }
 
+        if entry_type == EntryType::Symlink {
+            if let Ok(Some(link_target)) = entry.link_name() {
+                guard.check_symlink(&final_target_path_on_disk, &link_target)?;
+            }
+        }
+
         #[cfg(unix)]
-        if entry.header().entry_type() == EntryType::Link {
+        if entry_type == EntryType::Link {
             if let Ok(Some(link_name_in_archive)) = entry.link_name() {
                 let deferred_link = DeferredHardLink {
-                    link_path_in_archive: original_path_in_archive.clone(),
+                    link_path_on_disk: final_target_path_on_disk.clone(),
                     target_name_in_archive: link_name_in_archive.into_owned(),
                 };
                 debug!(
@@ -500,37 +728,24 @@ fn extract_tar_archive<R: Read>(
 
     #[cfg(unix)]
     for deferred in deferred_hardlinks {
-        let mut disk_link_path = target_dir.to_path_buf();
-        for comp in deferred
-            .link_path_in_archive
-            .components()
-            .skip(strip_components)
-        {
-            if let Component::Normal(p) = comp {
-                disk_link_path.push(p);
-            }
-            // Other components should have been caught by safety checks above
-        }
-
-        let mut disk_target_path = target_dir.to_path_buf();
-        // The link_name_in_archive is relative to the archive root *before* stripping.
-        // We need to apply stripping to it as well to find its final disk location.
-        for comp in deferred
-            .target_name_in_archive
-            .components()
-            .skip(strip_components)
-        {
-            if let Component::Normal(p) = comp {
-                disk_target_path.push(p);
-            }
-        }
-
-        if !disk_target_path.starts_with(target_dir) || !disk_link_path.starts_with(target_dir) {
-            let msg = format!("Skipping deferred hardlink due to path traversal attempt: link '{}' -> target '{}'", disk_link_path.display(), disk_target_path.display());
+        let disk_link_path = deferred.link_path_on_disk;
+        // The link target is named relative to the archive root *before* stripping, so it goes
+        // through the same mapping as entry names to find its place on disk.
+        let Some(disk_target_path) = guard.entry_path(
+            target_dir,
+            &deferred.target_name_in_archive,
+            strip_components,
+        )?
+        else {
+            let msg = format!(
+                "Target '{}' for deferred hardlink '{}' is outside the extracted components.",
+                deferred.target_name_in_archive.display(),
+                disk_link_path.display()
+            );
             error!("{}", msg);
             errors.push(msg);
             continue;
-        }
+        };
 
         debug!(
             "Attempting deferred hardlink: disk link path '{}' -> disk target path '{}'",
@@ -538,7 +753,7 @@ fn extract_tar_archive<R: Read>(
             disk_target_path.display()
         );
 
-        if disk_target_path.exists() {
+        if let Some(resolved_target) = guard.check_hardlink(&disk_link_path, &disk_target_path)? {
             if let Some(parent) = disk_link_path.parent() {
                 if !parent.exists() {
                     if let Err(e) = fs::create_dir_all(parent) {
@@ -562,7 +777,7 @@ fn extract_tar_archive<R: Read>(
                 }
             }
 
-            if let Err(e) = fs::hard_link(&disk_target_path, &disk_link_path) {
+            if let Err(e) = fs::hard_link(&resolved_target, &disk_link_path) {
                 let msg = format!(
                     "Failed to create deferred hardlink '{}' -> '{}': {}. Target exists: {}",
                     disk_link_path.display(),
@@ -620,8 +835,9 @@ fn extract_zip_archive<R: Read + Seek>(
     reader: R,
     target_dir: &Path,
     strip_components: usize,
-    archive_path_for_log: &Path,
+    guard: &mut ExtractGuard,
 ) -> Result<()> {
+    let archive_path_for_log = guard.archive_path();
     let mut archive = ZipArchive::new(reader).map_err(|e| {
         SpsError::Generic(format!(
             "Failed to open ZIP {}: {}",
@@ -640,63 +856,22 @@ fn extract_zip_archive<R: Read + Seek>(
             .by_index(i)
             .map_err(|e| SpsError::Generic(format!("Failed to read ZIP entry {i}: {e}")))?;
 
+        guard.count_entry()?;
+        // `enclosed_name` is `None` for absolute names and ones that climb out with `..`.
         let Some(original_path_in_archive) = file.enclosed_name() else {
-            debug!("Skipping unsafe ZIP entry (no enclosed name)");
-            continue;
+            return Err(guard.violation(format!("unsafe entry name {}", file.name())));
         };
-
-        let stripped_components_iter: Vec<Component<'_>> = original_path_in_archive
-            .components()
-            .skip(strip_components)
-            .collect();
-
-        if stripped_components_iter.is_empty() {
+        let Some(final_target_path_on_disk) =
+            guard.entry_path(target_dir, &original_path_in_archive, strip_components)?
+        else {
             debug!(
                 "Skipping ZIP entry {} due to strip_components",
                 original_path_in_archive.display()
             );
             continue;
-        }
-
-        let mut final_target_path_on_disk = target_dir.to_path_buf();
-        for comp in stripped_components_iter {
-            match comp {
-                Component::Normal(p) => final_target_path_on_disk.push(p),
-                Component::CurDir => {}
-                Component::ParentDir => {
-                    error!(
-                        "Unsafe '..' in ZIP path {} after strip_components",
-                        original_path_in_archive.display()
-                    );
-                    return Err(SpsError::Generic(format!(
-                        "Unsafe '..' component in ZIP path {}",
-                        original_path_in_archive.display()
-                    )));
-                }
-                Component::Prefix(_) | Component::RootDir => {
-                    error!(
-                        "Disallowed component {:?} in ZIP path {}",
-                        comp,
-                        original_path_in_archive.display()
-                    );
-                    return Err(SpsError::Generic(format!(
-                        "Disallowed component in ZIP path {}",
-                        original_path_in_archive.display()
-                    )));
-                }
-            }
-        }
-
-        if !final_target_path_on_disk.starts_with(target_dir) {
-            error!(
-                "ZIP path traversal detected: {} -> {}",
-                original_path_in_archive.display(),
-                final_target_path_on_disk.display()
-            );
-            return Err(SpsError::Generic(format!(
-                "ZIP path traversal detected in {}",
-                archive_path_for_log.display()
-            )));
+        };
+        if let Some(mode) = file.unix_mode() {
+            guard.check_mode(&original_path_in_archive, mode)?;
         }
 
         if let Some(parent) = final_target_path_on_disk.parent() {
@@ -730,8 +905,8 @@ fn extract_zip_archive<R: Read + Seek>(
                 )))
             })?;
         } else {
-            // Regular file
-            if final_target_path_on_disk.exists() {
+            // Replace whatever is there, without following it if it is a symlink.
+            if final_target_path_on_disk.symlink_metadata().is_ok() {
                 match fs::remove_file(&final_target_path_on_disk) {
                     Ok(_) => {}
                     Err(e) if e.kind() == io::ErrorKind::NotFound => {}
@@ -739,6 +914,19 @@ fn extract_zip_archive<R: Read + Seek>(
                 }
             }
 
+            #[cfg(unix)]
+            if file.is_symlink() {
+                let link_target = read_link_target(&mut file)?;
+                guard.check_symlink(&final_target_path_on_disk, &link_target)?;
+                debug!(
+                    "Creating symlink: {} -> {}",
+                    final_target_path_on_disk.display(),
+                    link_target.display()
+                );
+                std::os::unix::fs::symlink(&link_target, &final_target_path_on_disk)?;
+                continue;
+            }
+
             debug!("Extracting file: {}", final_target_path_on_disk.display());
             let mut outfile = File::create(&final_target_path_on_disk).map_err(|e| {
                 SpsError::Io(std::sync::Arc::new(io::Error::new(
@@ -751,16 +939,7 @@ fn extract_zip_archive<R: Read + Seek>(
                 )))
             })?;
 
-            std::io::copy(&mut file, &mut outfile).map_err(|e| {
-                SpsError::Io(std::sync::Arc::new(io::Error::new(
-                    e.kind(),
-                    format!(
-                        "Failed to write file {}: {}",
-                        final_target_path_on_disk.display(),
-                        e
-                    ),
-                )))
-            })?;
+            guard.copy(&mut file, &mut outfile, &final_target_path_on_disk)?;
 
             // Set permissions on Unix systems
             #[cfg(unix)]
@@ -0,0 +1,225 @@
+//! What an archive is allowed to put on disk. Every extractor runs its entries past an
+//! [`ExtractGuard`] before writing them, and anything outside the policy aborts the extraction
+//! with [`SpsError::UnsafeArchive`].
+//!
+//! Symlinks must be relative, may only climb with leading `..` components, and must land inside
+//! the target directory when they are created. That keeps every link inside the tree no matter
+//! which order later entries arrive in, so nothing can be written through a link to the outside.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use sps_common::error::{Result, SpsError};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFSOCK: u32 = 0o140000;
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+
+/// Limits on how much an archive may unpack. The defaults are far above anything a bottle,
+/// source tarball or cask ships, and only stop decompression bombs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractPolicy {
+    /// Most entries (files, directories and links) one archive may contain.
+    pub max_entries: u64,
+    /// Most bytes of file content one archive may unpack.
+    pub max_total_size: u64,
+}
+
+impl Default for ExtractPolicy {
+    fn default() -> Self {
+        Self {
+            max_entries: 1 << 20,
+            max_total_size: 32 << 30,
+        }
+    }
+}
+
+/// Tracks one extraction against an [`ExtractPolicy`].
+pub(crate) struct ExtractGuard<'a> {
+    policy: ExtractPolicy,
+    archive_path: &'a Path,
+    /// `target_dir` with symlinks resolved, to compare resolved link targets against.
+    root: PathBuf,
+    entries: u64,
+    total_size: u64,
+}
+
+impl<'a> ExtractGuard<'a> {
+    pub(crate) fn new(
+        policy: &ExtractPolicy,
+        target_dir: &Path,
+        archive_path: &'a Path,
+    ) -> Result<Self> {
+        let root = fs::canonicalize(target_dir).map_err(|e| {
+            SpsError::Io(std::sync::Arc::new(io::Error::new(
+                e.kind(),
+                format!("Failed to resolve {}: {}", target_dir.display(), e),
+            )))
+        })?;
+        Ok(Self {
+            policy: *policy,
+            archive_path,
+            root,
+            entries: 0,
+            total_size: 0,
+        })
+    }
+
+    pub(crate) fn archive_path(&self) -> &'a Path {
+        self.archive_path
+    }
+
+    pub(crate) fn violation(&self, detail: impl std::fmt::Display) -> SpsError {
+        SpsError::UnsafeArchive(format!("{}: {}", self.archive_path.display(), detail))
+    }
+
+    /// Counts one more entry against `max_entries`.
+    pub(crate) fn count_entry(&mut self) -> Result<()> {
+        self.entries += 1;
+        if self.entries > self.policy.max_entries {
+            return Err(self.violation(format!("more than {} entries", self.policy.max_entries)));
+        }
+        Ok(())
+    }
+
+    /// Counts `size` bytes against `max_total_size` before they are written, for formats whose
+    /// headers give the exact size of what follows.
+    pub(crate) fn reserve(&mut self, size: u64) -> Result<()> {
+        self.total_size = self.total_size.saturating_add(size);
+        if self.total_size > self.policy.max_total_size {
+            return Err(self.violation(format!(
+                "unpacks to more than {} bytes",
+                self.policy.max_total_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copies an entry's content to `writer`, counting what is actually decompressed rather
+    /// than what the archive claims.
+    pub(crate) fn copy<R: Read + ?Sized, W: Write + ?Sized>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+        dest: &Path,
+    ) -> Result<u64> {
+        let budget = self.policy.max_total_size.saturating_sub(self.total_size);
+        let written =
+            io::copy(&mut reader.take(budget.saturating_add(1)), writer).map_err(|e| {
+                SpsError::Io(std::sync::Arc::new(io::Error::new(
+                    e.kind(),
+                    format!("Failed to write file {}: {}", dest.display(), e),
+                )))
+            })?;
+        self.reserve(written)?;
+        Ok(written)
+    }
+
+    /// Rejects setuid/setgid bits and anything that isn't a file, directory or symlink.
+    pub(crate) fn check_mode(&self, name: &Path, mode: u32) -> Result<()> {
+        match mode & S_IFMT {
+            S_IFCHR | S_IFBLK => {
+                return Err(self.violation(format!("device node {}", name.display())))
+            }
+            S_IFIFO | S_IFSOCK => {
+                return Err(self.violation(format!("special file {}", name.display())))
+            }
+            _ => {}
+        }
+        if mode & (S_ISUID | S_ISGID) != 0 {
+            return Err(self.violation(format!(
+                "setuid/setgid bits ({:o}) on {}",
+                mode & 0o7777,
+                name.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Maps an entry name onto `target_dir`, dropping `strip_components` leading components.
+    /// Returns `None` when stripping leaves nothing. Absolute names and `..` are rejected.
+    pub(crate) fn entry_path(
+        &self,
+        target_dir: &Path,
+        name: &Path,
+        strip_components: usize,
+    ) -> Result<Option<PathBuf>> {
+        let mut path = target_dir.to_path_buf();
+        let mut kept = 0;
+        for (i, comp) in name.components().enumerate() {
+            match comp {
+                Component::Normal(part) if i >= strip_components => {
+                    path.push(part);
+                    kept += 1;
+                }
+                Component::Normal(_) | Component::CurDir => {}
+                Component::ParentDir => {
+                    return Err(self.violation(format!("'..' in entry {}", name.display())))
+                }
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(self.violation(format!("absolute entry {}", name.display())))
+                }
+            }
+        }
+        Ok((kept > 0).then_some(path))
+    }
+
+    /// Checks a symlink about to be created at `link` (whose parent directory exists).
+    pub(crate) fn check_symlink(&self, link: &Path, target: &Path) -> Result<()> {
+        let escape = || {
+            self.violation(format!(
+                "symlink {} -> {} points outside the target directory",
+                link.display(),
+                target.display()
+            ))
+        };
+        let parent = link.parent().ok_or_else(escape)?;
+        let mut resolved = fs::canonicalize(parent).map_err(|_| escape())?;
+        let mut climbing = true;
+        for comp in target.components() {
+            match comp {
+                Component::ParentDir if climbing => {
+                    resolved.pop();
+                }
+                Component::CurDir => {}
+                Component::Normal(_) => climbing = false,
+                Component::ParentDir => {
+                    return Err(self.violation(format!(
+                        "symlink {} -> {} has '..' after a path component",
+                        link.display(),
+                        target.display()
+                    )))
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(escape()),
+            }
+        }
+        if !resolved.starts_with(&self.root) {
+            return Err(escape());
+        }
+        Ok(())
+    }
+
+    /// Resolves the target of a hardlink about to be created at `link`, or `None` if nothing
+    /// has been extracted there. An existing target must be a regular file inside the target
+    /// directory.
+    pub(crate) fn check_hardlink(&self, link: &Path, target: &Path) -> Result<Option<PathBuf>> {
+        if target.symlink_metadata().is_err() {
+            return Ok(None);
+        }
+        match fs::canonicalize(target) {
+            Ok(resolved) if resolved.starts_with(&self.root) && resolved.is_file() => {
+                Ok(Some(resolved))
+            }
+            _ => Err(self.violation(format!(
+                "hardlink {} -> {} does not point at a file inside the target directory",
+                link.display(),
+                target.display()
+            ))),
+        }
+    }
+}
@@ -3,6 +3,7 @@
 // Declare the top-level modules within the library crate
 pub mod build;
 pub mod check;
+pub mod cleanup;
 pub mod install;
 pub mod pipeline;
 pub mod uninstall;
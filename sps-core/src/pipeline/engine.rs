@@ -21,7 +21,9 @@ pub fn start_worker_pool_manager(
     success_count: Arc<AtomicUsize>,
     fail_count: Arc<AtomicUsize>,
 ) -> SpsResult<()> {
-    let num_workers = std::cmp::max(1, num_cpus::get_physical().saturating_sub(1)).min(6);
+    let num_workers = config
+        .workers
+        .unwrap_or_else(|| std::cmp::max(1, num_cpus::get_physical().saturating_sub(1)).min(6));
     let pool = ThreadPool::new(num_workers);
     debug!(
         "Core worker pool manager started with {} workers.",
@@ -0,0 +1,385 @@
+// sps-core/src/pipeline/transaction.rs
+//! Install transactions for formula jobs.
+//!
+//! A job sets the keg it replaces aside instead of deleting it, builds the new keg in a
+//! staging directory next to its final path and moves it into place with a rename. Every step
+//! is recorded in a journal under `Config::state_dir()/transactions` *before* it is performed,
+//! so a failed or interrupted job can undo exactly what it did, in reverse order. A journal left
+//! behind by a process that died is repaired by [`recover_unfinished`] on the next run.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use tracing::{debug, error, warn};
+
+use crate::install::bottle::link;
+use crate::uninstall::common::remove_filesystem_artifact;
+
+const JOURNAL_DIR_NAME: &str = "transactions";
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks running jobs to stop at their next checkpoint and roll back. Called on Ctrl-C.
+pub fn request_interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// One recorded step. Steps are written before they are carried out, so undoing a step must
+/// cope with it having only partly happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum JournalStep {
+    /// The installed keg `original` was unlinked and moved to `backup`.
+    SetAside {
+        name: String,
+        original: PathBuf,
+        backup: PathBuf,
+    },
+    /// A staging directory for the new keg was created.
+    Staged { path: PathBuf },
+    /// The new keg was created at its final path.
+    KegCreated { path: PathBuf },
+    /// The new keg was linked into the prefix.
+    Linked { path: PathBuf },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    target: String,
+    pid: u32,
+    steps: Vec<JournalStep>,
+}
+
+/// The journal of a single job. Nothing is written to disk until the first step is recorded,
+/// so jobs that never touch the Cellar (casks) leave no trace.
+#[derive(Debug)]
+pub struct InstallTransaction {
+    config: Config,
+    journal_path: PathBuf,
+    journal: Journal,
+}
+
+impl InstallTransaction {
+    pub fn begin(config: &Config, target: &str) -> Self {
+        let file_name = format!("{}.json", target.replace(['/', '\\'], "_"));
+        Self {
+            config: config.clone(),
+            journal_path: journal_dir(config).join(file_name),
+            journal: Journal {
+                target: target.to_string(),
+                pid: std::process::id(),
+                steps: Vec::new(),
+            },
+        }
+    }
+
+    /// Fails with [`SpsError::Interrupted`] once an interrupt was requested.
+    pub fn checkpoint(&self) -> Result<()> {
+        if is_interrupted() {
+            return Err(SpsError::Interrupted(format!(
+                "{} was interrupted",
+                self.journal.target
+            )));
+        }
+        Ok(())
+    }
+
+    /// Unlinks the installed keg and moves it out of the way. It is restored and relinked on
+    /// rollback, and deleted by [`finish`](Self::finish).
+    pub fn set_aside(&mut self, name: &str, keg: &Path) -> Result<()> {
+        self.checkpoint()?;
+        if keg.symlink_metadata().is_err() {
+            debug!("Nothing to set aside at {}", keg.display());
+            return Ok(());
+        }
+        let backup = sibling_path(keg, "sps-backup")?;
+        if backup.symlink_metadata().is_ok() && !remove_filesystem_artifact(&backup, true) {
+            return Err(SpsError::InstallError(format!(
+                "Failed to remove stale backup {}",
+                backup.display()
+            )));
+        }
+        self.record(JournalStep::SetAside {
+            name: name.to_string(),
+            original: keg.to_path_buf(),
+            backup: backup.clone(),
+        })?;
+        link::unlink_keg(keg, &self.config)?;
+        debug!("Setting aside {} as {}", keg.display(), backup.display());
+        fs::rename(keg, &backup).map_err(|e| {
+            SpsError::InstallError(format!(
+                "Failed to move existing keg {} aside: {}",
+                keg.display(),
+                e
+            ))
+        })
+    }
+
+    /// Creates an empty staging directory on the same filesystem as `keg`.
+    pub fn stage(&mut self, keg: &Path) -> Result<PathBuf> {
+        self.checkpoint()?;
+        let staging = sibling_path(keg, "sps-staging")?;
+        self.record(JournalStep::Staged {
+            path: staging.clone(),
+        })?;
+        if staging.symlink_metadata().is_ok() {
+            debug!("Removing leftover staging directory {}", staging.display());
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging).map_err(|e| {
+            SpsError::Io(Arc::new(std::io::Error::new(
+                e.kind(),
+                format!("Failed to create staging dir {}: {}", staging.display(), e),
+            )))
+        })?;
+        Ok(staging)
+    }
+
+    /// Moves a fully prepared staging directory to the keg's final path, which must be vacant.
+    pub fn commit_staged(&mut self, staging: &Path, keg: &Path) -> Result<()> {
+        self.checkpoint()?;
+        self.claim_keg_path(keg)?;
+        debug!(
+            "Moving {} into place at {}",
+            staging.display(),
+            keg.display()
+        );
+        fs::rename(staging, keg).map_err(|e| {
+            SpsError::InstallError(format!(
+                "Failed to move staged keg {} to {}: {}",
+                staging.display(),
+                keg.display(),
+                e
+            ))
+        })
+    }
+
+    /// Records that a keg is about to be created in place (source builds, whose install
+    /// prefix is baked in at configure time and can't be staged elsewhere). The path must be
+    /// vacant.
+    pub fn create_in_place(&mut self, keg: &Path) -> Result<()> {
+        self.checkpoint()?;
+        self.claim_keg_path(keg)
+    }
+
+    /// Records that the keg is about to be linked.
+    pub fn link(&mut self, keg: &Path) -> Result<()> {
+        self.checkpoint()?;
+        self.record(JournalStep::Linked {
+            path: keg.to_path_buf(),
+        })
+    }
+
+    /// Completes the job: deletes the kegs that were set aside and the journal.
+    pub fn finish(self) {
+        for step in &self.journal.steps {
+            match step {
+                JournalStep::SetAside { backup: path, .. } | JournalStep::Staged { path } => {
+                    if path.symlink_metadata().is_ok() && !remove_filesystem_artifact(path, true) {
+                        warn!("Failed to remove {}", path.display());
+                    }
+                }
+                JournalStep::KegCreated { .. } | JournalStep::Linked { .. } => {}
+            }
+        }
+        self.remove_journal();
+    }
+
+    /// Undoes every recorded step, newest first, and deletes the journal.
+    pub fn rollback(self) {
+        if self.journal.steps.is_empty() {
+            return;
+        }
+        warn!("Rolling back changes made for {}", self.journal.target);
+        undo_steps(&self.journal.steps, &self.config);
+        self.remove_journal();
+    }
+
+    /// Records [`JournalStep::KegCreated`] for a path nothing occupies yet. Rollback deletes
+    /// that path, so it must never be recorded for something this job didn't create.
+    fn claim_keg_path(&mut self, keg: &Path) -> Result<()> {
+        if keg.symlink_metadata().is_ok() {
+            return Err(SpsError::InstallError(format!(
+                "Cannot create keg {}: the path is already occupied",
+                keg.display()
+            )));
+        }
+        self.record(JournalStep::KegCreated {
+            path: keg.to_path_buf(),
+        })
+    }
+
+    fn record(&mut self, step: JournalStep) -> Result<()> {
+        self.journal.steps.push(step);
+        write_journal(&self.journal_path, &self.journal)
+    }
+
+    fn remove_journal(&self) {
+        if let Err(e) = fs::remove_file(&self.journal_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(
+                    "Failed to remove transaction journal {}: {}",
+                    self.journal_path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Lists the targets of journals left behind by interrupted runs.
+pub fn unfinished_transactions(config: &Config) -> Vec<String> {
+    read_journals(config)
+        .into_iter()
+        .map(|(path, journal)| match journal {
+            Some(journal) => journal.target,
+            None => path.display().to_string(),
+        })
+        .collect()
+}
+
+/// Rolls back the journals left behind by interrupted runs and returns their targets.
+/// Only call this while holding the exclusive prefix lock, otherwise the journal may belong
+/// to a job that is still running.
+pub fn recover_unfinished(config: &Config) -> Vec<String> {
+    let mut recovered = Vec::new();
+    for (path, journal) in read_journals(config) {
+        let Some(journal) = journal else {
+            warn!(
+                "Ignoring unreadable transaction journal {}. Remove it once the Cellar has been checked.",
+                path.display()
+            );
+            continue;
+        };
+        warn!(
+            "Rolling back unfinished operation on {} (PID {})",
+            journal.target, journal.pid
+        );
+        undo_steps(&journal.steps, config);
+        if let Err(e) = fs::remove_file(&path) {
+            error!(
+                "Failed to remove transaction journal {}: {}",
+                path.display(),
+                e
+            );
+        }
+        recovered.push(journal.target);
+    }
+    recovered
+}
+
+fn undo_steps(steps: &[JournalStep], config: &Config) {
+    for step in steps.iter().rev() {
+        debug!("Undoing {:?}", step);
+        match step {
+            JournalStep::Linked { path } => {
+                if let Err(e) = link::unlink_keg(path, config) {
+                    warn!("Failed to unlink {}: {}", path.display(), e);
+                }
+            }
+            JournalStep::KegCreated { path } | JournalStep::Staged { path } => {
+                if path.symlink_metadata().is_ok() && !remove_filesystem_artifact(path, true) {
+                    error!("Failed to remove {}", path.display());
+                }
+            }
+            JournalStep::SetAside {
+                name,
+                original,
+                backup,
+            } => restore_set_aside(name, original, backup, config),
+        }
+    }
+}
+
+fn restore_set_aside(name: &str, original: &Path, backup: &Path, config: &Config) {
+    if original.symlink_metadata().is_err() && backup.symlink_metadata().is_ok() {
+        if let Err(e) = fs::rename(backup, original) {
+            error!(
+                "Failed to restore {} from {}: {}",
+                original.display(),
+                backup.display(),
+                e
+            );
+            return;
+        }
+    }
+    // A keg without a manifest was never linked by sps; leave it unlinked.
+    let manifest = match link::read_install_manifest(original) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Could not relink {}: {}", original.display(), e);
+            return;
+        }
+    };
+    match link::relink_keg(
+        name,
+        original,
+        config,
+        manifest.link_override,
+        &link::LinkOptions::default(),
+    ) {
+        Ok(_) => debug!("Restored {} and its links", original.display()),
+        Err(e) => warn!(
+            "Restored {} but relinking failed: {}",
+            original.display(),
+            e
+        ),
+    }
+}
+
+/// `Cellar/<name>/<version>` -> `Cellar/<name>/.<version>.<suffix>`. Dot-directories are
+/// skipped by the keg registry.
+fn sibling_path(keg: &Path, suffix: &str) -> Result<PathBuf> {
+    match (keg.parent(), keg.file_name()) {
+        (Some(parent), Some(name)) => {
+            Ok(parent.join(format!(".{}.{}", name.to_string_lossy(), suffix)))
+        }
+        _ => Err(SpsError::InstallError(format!(
+            "Invalid keg path: {}",
+            keg.display()
+        ))),
+    }
+}
+
+fn journal_dir(config: &Config) -> PathBuf {
+    config.state_dir().join(JOURNAL_DIR_NAME)
+}
+
+fn write_journal(path: &Path, journal: &Journal) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let data = serde_json::to_vec_pretty(journal).map_err(|e| SpsError::Json(Arc::new(e)))?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn read_journals(config: &Config) -> Vec<(PathBuf, Option<Journal>)> {
+    let Ok(entries) = fs::read_dir(journal_dir(config)) else {
+        return Vec::new();
+    };
+    let mut journals: Vec<(PathBuf, Option<Journal>)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let journal = fs::read(&path)
+                .ok()
+                .and_then(|data| serde_json::from_slice(&data).ok());
+            (path, journal)
+        })
+        .collect();
+    journals.sort_by(|a, b| a.0.cmp(&b.0));
+    journals
+}
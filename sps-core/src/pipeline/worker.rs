@@ -16,6 +16,7 @@ use tokio::sync::broadcast;
 use tracing::{debug, error, instrument, warn};
 
 use crate::check::installed::{InstalledPackageInfo, PackageType as CorePackageType};
+use crate::pipeline::transaction::InstallTransaction;
 use crate::{build, install, uninstall, upgrade};
 
 pub(super) fn execute_sync_job(
@@ -26,7 +27,12 @@ pub(super) fn execute_sync_job(
 ) -> std::result::Result<(JobAction, PipelinePackageType), Box<(JobAction, SpsError)>> {
     let action = worker_job.request.action.clone();
 
-    let result = do_execute_sync_steps(worker_job, config, cache, event_tx);
+    let mut transaction = InstallTransaction::begin(config, &worker_job.request.target_id);
+    let result = do_execute_sync_steps(worker_job, config, cache, event_tx, &mut transaction);
+    match result {
+        Ok(_) => transaction.finish(),
+        Err(_) => transaction.rollback(),
+    }
 
     result
         .map_err(|e| Box::new((action.clone(), e)))
@@ -39,6 +45,7 @@ fn do_execute_sync_steps(
     config: &Config,
     _cache: Arc<Cache>, // Marked as unused if cache is not directly used in this function body
     event_tx: broadcast::Sender<PipelineEvent>,
+    transaction: &mut InstallTransaction,
 ) -> SpsResult<PipelinePackageType> {
     let job_request = worker_job.request;
     let download_path = worker_job.download_path;
@@ -51,6 +58,9 @@ fn do_execute_sync_steps(
         InstallTargetIdentifier::Cask(_) => (CorePackageType::Cask, PipelinePackageType::Cask),
     };
 
+    // Jobs still queued when Ctrl-C was pressed don't start.
+    transaction.checkpoint()?;
+
     // Check dependencies before proceeding with formula install/upgrade
     if let InstallTargetIdentifier::Formula(formula_arc) = &job_request.target_definition {
         if matches!(job_request.action, JobAction::Install)
@@ -111,6 +121,23 @@ fn do_execute_sync_steps(
 
     let mut formula_installed_path: Option<PathBuf> = None;
 
+    // A `sps link --force` / `sps unlink` choice on the keg being replaced carries over.
+    let previous_link_override = match &job_request.action {
+        JobAction::Upgrade {
+            old_install_path, ..
+        }
+        | JobAction::Reinstall {
+            current_install_path: old_install_path,
+            ..
+        } if core_pkg_type == CorePackageType::Formula => {
+            install::bottle::link::read_install_manifest(old_install_path)
+                .ok()
+                .flatten()
+                .and_then(|manifest| manifest.link_override)
+        }
+        _ => None,
+    };
+
     match &job_request.action {
         JobAction::Upgrade {
             from_version,
@@ -141,6 +168,8 @@ fn do_execute_sync_steps(
                             &old_info,
                             config,
                             &all_dep_paths,
+                            transaction,
+                            job_request.installed_on_request,
                         ))?
                     } else {
                         block_on(upgrade::bottle::upgrade_bottle_formula(
@@ -149,6 +178,8 @@ fn do_execute_sync_steps(
                             &old_info,
                             config,
                             http_client_for_bottle_upgrade,
+                            transaction,
+                            job_request.installed_on_request,
                         ))?
                     };
                     formula_installed_path = Some(installed_path);
@@ -184,14 +215,12 @@ fn do_execute_sync_steps(
                     pkg_type: core_pkg_type.clone(),
                     path: old_install_path.clone(),
                 };
-                let uninstall_opts = uninstall::UninstallOptions { skip_zap: true };
 
                 match core_pkg_type {
-                    CorePackageType::Formula => uninstall::uninstall_formula_artifacts(
-                        &old_info_for_reinstall,
-                        config,
-                        &uninstall_opts,
-                    )?,
+                    // Kept until the new keg is linked, so a failed reinstall can restore it.
+                    CorePackageType::Formula => {
+                        transaction.set_aside(&old_info_for_reinstall.name, old_install_path)?
+                    }
                     CorePackageType::Cask => {
                         uninstall::uninstall_cask_artifacts(&old_info_for_reinstall, config)?
                     }
@@ -226,18 +255,26 @@ fn do_execute_sync_steps(
                         });
                         let build_dep_paths: Vec<PathBuf> = vec![]; // TODO: Populate this from ResolvedGraph
 
+                        transaction.set_aside(formula.name(), &install_dir_base)?;
+                        transaction.create_in_place(&install_dir_base)?;
                         let build_future = build::compile::build_from_source(
                             &download_path,
                             formula,
                             config,
                             &build_dep_paths,
+                            job_request.installed_on_request,
                         );
                         let installed_dir = block_on(build_future)?;
                         formula_installed_path = Some(installed_dir);
                     } else {
                         debug!("[{}] Installing bottle...", job_request.target_id);
-                        let installed_dir =
-                            install::bottle::exec::install_bottle(&download_path, formula, config)?;
+                        let installed_dir = install::bottle::exec::install_bottle(
+                            &download_path,
+                            formula,
+                            config,
+                            transaction,
+                            job_request.installed_on_request,
+                        )?;
                         formula_installed_path = Some(installed_dir);
                     }
                 }
@@ -377,12 +414,36 @@ fn do_execute_sync_steps(
             target_id: job_request.target_id.clone(),
             pkg_type: pipeline_pkg_type,
         });
-        install::bottle::link::link_formula_artifacts(formula, keg_path_for_linking, config)?;
+        transaction.link(keg_path_for_linking)?;
+        match install::bottle::link::link_formula_artifacts(
+            formula,
+            keg_path_for_linking,
+            config,
+            previous_link_override,
+            &install::bottle::link::LinkOptions::default(),
+        ) {
+            Ok(_) => {}
+            // The keg itself is installed and reachable through opt/; report and carry on.
+            Err(SpsError::LinkConflict(report)) => {
+                warn!("[{}] {}", job_request.target_id, report);
+                let _ = event_tx.send(PipelineEvent::LogWarn {
+                    message: format!("Warning: {report}"),
+                });
+            }
+            Err(e) => return Err(e),
+        }
         debug!(
             "[{}] Linking complete for formula {}.",
             job_request.target_id,
             (**formula).name()
         );
+        if let Some(caveats) =
+            install::bottle::link::formula_caveats(formula, config, previous_link_override)
+        {
+            let _ = event_tx.send(PipelineEvent::LogInfo {
+                message: format!("==> Caveats for {}\n{}", formula.name(), caveats),
+            });
+        }
     }
 
     Ok(pipeline_pkg_type)
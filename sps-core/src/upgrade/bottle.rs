@@ -9,12 +9,13 @@ use sps_common::model::formula::Formula;
 use tracing::{debug, error};
 
 use crate::check::installed::InstalledPackageInfo;
-use crate::{install, uninstall};
+use crate::install;
+use crate::pipeline::transaction::InstallTransaction;
 
 /// Upgrades a formula that is installed from a bottle.
 ///
 /// This involves:
-/// 1. Uninstalling the old version of the formula.
+/// 1. Setting the old version aside (unlinked, restored if the upgrade fails).
 /// 2. Installing the new bottle.
 /// 3. Linking the new version.
 pub async fn upgrade_bottle_formula(
@@ -24,6 +25,8 @@ pub async fn upgrade_bottle_formula(
     config: &Config,
     http_client: Arc<reqwest::Client>, /* Added for download_bottle if needed, though path is
                                         * pre-downloaded */
+    transaction: &mut InstallTransaction,
+    installed_on_request: bool,
 ) -> SpsResult<PathBuf> {
     debug!(
         "Upgrading bottle formula {} from {} to {}",
@@ -32,27 +35,27 @@ pub async fn upgrade_bottle_formula(
         formula.version_str_full()
     );
 
-    // 1. Uninstall the old version
+    // 1. Set the old version aside
     debug!(
-        "Uninstalling old bottle version: {} at {}",
+        "Setting old bottle version aside: {} at {}",
         old_install_info.version,
         old_install_info.path.display()
     );
-    let uninstall_opts = uninstall::UninstallOptions { skip_zap: true }; // Zap is not relevant for formula upgrades
-    uninstall::formula::uninstall_formula_artifacts(old_install_info, config, &uninstall_opts)
+    transaction
+        .set_aside(&old_install_info.name, &old_install_info.path)
         .map_err(|e| {
             error!(
-                "Failed to uninstall old version {} of formula {}: {}",
+                "Failed to set aside old version {} of formula {}: {}",
                 old_install_info.version,
                 formula.name(),
                 e
             );
             SpsError::InstallError(format!(
-                "Failed to uninstall old version during upgrade of {}: {e}",
+                "Failed to set aside old version during upgrade of {}: {e}",
                 formula.name()
             ))
         })?;
-    debug!("Successfully uninstalled old version of {}", formula.name());
+    debug!("Old version of {} set aside", formula.name());
 
     // 2. Install the new bottle
     // The new_bottle_download_path is already provided, so we call install_bottle directly.
@@ -64,20 +67,24 @@ pub async fn upgrade_bottle_formula(
         formula.name(),
         new_bottle_download_path.display()
     );
-    let installed_keg_path =
-        install::bottle::exec::install_bottle(new_bottle_download_path, formula, config).map_err(
-            |e| {
-                error!(
-                    "Failed to install new bottle for formula {}: {}",
-                    formula.name(),
-                    e
-                );
-                SpsError::InstallError(format!(
-                    "Failed to install new bottle during upgrade of {}: {e}",
-                    formula.name()
-                ))
-            },
-        )?;
+    let installed_keg_path = install::bottle::exec::install_bottle(
+        new_bottle_download_path,
+        formula,
+        config,
+        transaction,
+        installed_on_request,
+    )
+    .map_err(|e| {
+        error!(
+            "Failed to install new bottle for formula {}: {}",
+            formula.name(),
+            e
+        );
+        SpsError::InstallError(format!(
+            "Failed to install new bottle during upgrade of {}: {e}",
+            formula.name()
+        ))
+    })?;
     debug!(
         "Successfully installed new bottle for {} to {}",
         formula.name(),
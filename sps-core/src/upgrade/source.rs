@@ -4,16 +4,17 @@ use std::path::{Path, PathBuf};
 
 use sps_common::config::Config;
 use sps_common::error::{Result as SpsResult, SpsError};
-use sps_common::model::formula::Formula;
+use sps_common::model::formula::{Formula, FormulaDependencies};
 use tracing::{debug, error};
 
+use crate::build;
 use crate::check::installed::InstalledPackageInfo;
-use crate::{build, uninstall};
+use crate::pipeline::transaction::InstallTransaction;
 
 /// Upgrades a formula that was/will be installed from source.
 ///
 /// This involves:
-/// 1. Uninstalling the old version of the formula.
+/// 1. Setting the old version aside (unlinked, restored if the upgrade fails).
 /// 2. Building and installing the new version from source.
 /// 3. Linking the new version.
 pub async fn upgrade_source_formula(
@@ -22,6 +23,8 @@ pub async fn upgrade_source_formula(
     old_install_info: &InstalledPackageInfo,
     config: &Config,
     all_installed_dependency_paths: &[PathBuf], // For build environment
+    transaction: &mut InstallTransaction,
+    installed_on_request: bool,
 ) -> SpsResult<PathBuf> {
     debug!(
         "Upgrading source-built formula {} from {} to {}",
@@ -30,30 +33,27 @@ pub async fn upgrade_source_formula(
         formula.version_str_full()
     );
 
-    // 1. Uninstall the old version
+    // 1. Set the old version aside
     debug!(
-        "Uninstalling old source-built version: {} at {}",
+        "Setting old source-built version aside: {} at {}",
         old_install_info.version,
         old_install_info.path.display()
     );
-    let uninstall_opts = uninstall::UninstallOptions { skip_zap: true };
-    uninstall::formula::uninstall_formula_artifacts(old_install_info, config, &uninstall_opts)
+    transaction
+        .set_aside(&old_install_info.name, &old_install_info.path)
         .map_err(|e| {
             error!(
-                "Failed to uninstall old version {} of formula {}: {}",
+                "Failed to set aside old version {} of formula {}: {}",
                 old_install_info.version,
                 formula.name(),
                 e
             );
             SpsError::InstallError(format!(
-                "Failed to uninstall old version during source upgrade of {}: {e}",
+                "Failed to set aside old version during source upgrade of {}: {e}",
                 formula.name()
             ))
         })?;
-    debug!(
-        "Successfully uninstalled old source-built version of {}",
-        formula.name()
-    );
+    debug!("Old source-built version of {} set aside", formula.name());
 
     // 2. Build and install the new version from source
     debug!(
@@ -61,11 +61,16 @@ pub async fn upgrade_source_formula(
         formula.name(),
         new_source_download_path.display()
     );
+    // The build installs straight into the keg path; anything already there is set aside first.
+    let new_keg_path = formula.install_prefix(config.cellar_dir().as_path())?;
+    transaction.set_aside(formula.name(), &new_keg_path)?;
+    transaction.create_in_place(&new_keg_path)?;
     let installed_keg_path = build::compile::build_from_source(
         new_source_download_path,
         formula,
         config,
         all_installed_dependency_paths,
+        installed_on_request,
     )
     .await
     .map_err(|e| {
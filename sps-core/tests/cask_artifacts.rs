@@ -0,0 +1,174 @@
+//! How `install_cask` dispatches a cask's artifact stanzas, using a tarball download and a
+//! throwaway prefix. Only stanzas that stay inside the prefix are exercised.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+use sps_common::error::SpsError;
+use sps_common::model::Cask;
+use sps_common::pipeline::JobAction;
+use sps_core::install::cask::install_cask;
+
+use crate::common::Prefix;
+
+impl Prefix {
+    /// A `.tar.gz` download holding `tool.app/`, `tool`, `bin/other` and `man/tool.1`.
+    fn download(&self) -> PathBuf {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (name, mode, data) in [
+            ("tool.app/Contents/MacOS/tool", 0o755, &b"app"[..]),
+            ("tool", 0o755, b"#!/bin/sh\n"),
+            ("bin/other", 0o755, b"#!/bin/sh\n"),
+            ("man/tool.1", 0o644, b".TH TOOL 1\n"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(mode);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+        let path = self.path().join("tool-1.0.tar.gz");
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn install(&self, artifacts: serde_json::Value) -> Result<(), SpsError> {
+        let cask: Cask = serde_json::from_value(json!({
+            "token": "tool",
+            "version": "1.0",
+            "artifacts": artifacts,
+        }))
+        .unwrap();
+        install_cask(&cask, &self.download(), &self.config, &JobAction::Install)
+    }
+
+    fn version_dir(&self) -> PathBuf {
+        self.config.cask_room_version_path("tool", "1.0")
+    }
+
+    /// How many artifacts of `kind` the install manifest records.
+    fn manifest_entries(&self, kind: &str) -> usize {
+        let manifest = fs::read(self.version_dir().join("CASK_INSTALL_MANIFEST.json")).unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&manifest).unwrap();
+        manifest["artifacts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|artifact| artifact["type"] == kind)
+            .count()
+    }
+
+    fn staged(&self) -> PathBuf {
+        self.version_dir().join(".staged")
+    }
+}
+
+fn assert_links_into(link: &Path, staged: &Path) {
+    let target = fs::read_link(link).unwrap();
+    assert!(
+        target.starts_with(staged),
+        "{} -> {}",
+        link.display(),
+        target.display()
+    );
+    assert!(fs::metadata(link).is_ok(), "{} is dangling", link.display());
+}
+
+#[test]
+fn binary_and_manpage_keep_only_their_sources() {
+    let prefix = Prefix::new();
+    prefix
+        .install(json!([
+            { "manpage": ["man/tool.1"] },
+            { "binary": ["tool"] },
+            { "zap": [{ "trash": ["~/Library/Preferences/tool.plist"] }] },
+            { "postflight": null },
+        ]))
+        .unwrap();
+
+    let staged = prefix.staged();
+    assert!(staged.join("tool").is_file());
+    assert!(staged.join("man/tool.1").is_file());
+    // Nothing the stanzas don't name is kept, in particular not the app bundle.
+    assert!(!staged.join("tool.app").exists());
+    assert!(!staged.join("bin/other").exists());
+    assert_links_into(&prefix.config.bin_dir().join("tool"), &staged);
+    assert_links_into(&prefix.config.man_base_dir().join("man1/tool.1"), &staged);
+    assert_eq!(prefix.manifest_entries("binary_link"), 1);
+    assert_eq!(prefix.manifest_entries("manpage_link"), 1);
+}
+
+#[test]
+fn binary_object_form_uses_its_source() {
+    let prefix = Prefix::new();
+    prefix
+        .install(json!([
+            { "binary": [{ "source": "bin/other", "target": "renamed" }] },
+        ]))
+        .unwrap();
+
+    let staged = prefix.staged();
+    assert!(staged.join("bin/other").is_file());
+    assert!(!staged.join("tool").exists());
+    assert_links_into(&prefix.config.bin_dir().join("renamed"), &staged);
+}
+
+#[test]
+fn repeated_stanza_runs_its_handler_once() {
+    let prefix = Prefix::new();
+    // The handler walks every `binary` stanza itself, so a second dispatch would record the
+    // link twice.
+    prefix
+        .install(json!([
+            { "binary": ["tool"] },
+            { "binary": ["tool"] },
+        ]))
+        .unwrap();
+    assert_links_into(&prefix.config.bin_dir().join("tool"), &prefix.staged());
+    assert_eq!(prefix.manifest_entries("binary_link"), 1);
+}
+
+#[test]
+fn sources_outside_the_stage_are_not_copied() {
+    let prefix = Prefix::new();
+    fs::write(prefix.path().join("secret"), b"secret").unwrap();
+    prefix
+        .install(json!([
+            { "binary": ["tool", "../../../secret", "/etc/hostname"] },
+        ]))
+        .unwrap();
+
+    let staged = prefix.staged();
+    assert!(staged.join("tool").is_file());
+    let kept: Vec<_> = fs::read_dir(&staged)
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(kept, ["tool"]);
+}
+
+#[test]
+fn unsupported_stanza_installs_nothing() {
+    let prefix = Prefix::new();
+    match prefix.install(json!([
+        { "binary": ["tool"] },
+        { "artifact": ["tool.app", { "target": "/opt/tool.app" }] },
+    ])) {
+        Err(SpsError::InstallError(msg)) => assert!(msg.contains("artifact"), "{msg}"),
+        other => panic!("expected InstallError, got {other:?}"),
+    }
+    assert!(!prefix.version_dir().exists());
+    assert!(prefix
+        .config
+        .bin_dir()
+        .join("tool")
+        .symlink_metadata()
+        .is_err());
+}
@@ -0,0 +1,195 @@
+//! What `sps cleanup` selects, in a throwaway prefix with a one-formula index.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use sps_core::cleanup::{find_cleanup_items, CleanupKind, CleanupOptions};
+
+use crate::common::Prefix;
+
+const TAG: &str = "arm64_sonoma";
+
+impl Prefix {
+    /// A prefix whose formula index knows `foo` at version 1.10.
+    fn with_foo_index() -> Self {
+        let prefix = Prefix::new();
+        prefix
+            .cache()
+            .store_raw(
+                "formula.json",
+                r#"[{"name": "foo", "full_name": "foo", "versions": {"stable": "1.10"}}]"#,
+            )
+            .unwrap();
+        prefix
+    }
+
+    fn keg(&self, name: &str, version: &str) -> PathBuf {
+        let path = self.config.cellar_dir().join(name).join(version);
+        fs::create_dir_all(path.join("bin")).unwrap();
+        fs::write(path.join("bin").join(name), b"#!/bin/sh\n").unwrap();
+        path
+    }
+
+    /// A cached bottle, last modified `age_days` ago.
+    fn bottle(&self, name: &str, version: &str, age_days: u64) -> PathBuf {
+        let dir = self.cache().get_dir().join("bottles");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}-{version}.{TAG}.bottle.tar.gz"));
+        fs::write(&path, b"bottle").unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age_days * 24 * 60 * 60);
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        path
+    }
+
+    fn select(&self, options: &CleanupOptions, kind: CleanupKind) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = find_cleanup_items(&self.cache(), options)
+            .unwrap()
+            .into_iter()
+            .filter(|item| item.kind == kind)
+            .map(|item| item.path)
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+fn sorted(paths: &[&Path]) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = paths.iter().map(|p| p.to_path_buf()).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn keeps_the_latest_keg() {
+    let prefix = Prefix::with_foo_index();
+    let oldest = prefix.keg("foo", "1.2");
+    let previous = prefix.keg("foo", "1.9_1");
+    prefix.keg("foo", "1.10");
+    prefix.keg("bar", "2.0");
+    // Interrupted installs leave hidden directories that aren't kegs.
+    fs::create_dir_all(prefix.config.cellar_dir().join("foo/.1.11.staging")).unwrap();
+
+    let options = CleanupOptions::default();
+    // 1.10 is newer than 1.9_1 and 1.2 even though it sorts before them as a string.
+    assert_eq!(
+        prefix.select(&options, CleanupKind::Keg),
+        sorted(&[&oldest, &previous])
+    );
+
+    let only_bar = CleanupOptions {
+        names: vec!["bar".to_string()],
+        ..Default::default()
+    };
+    assert!(prefix.select(&only_bar, CleanupKind::Keg).is_empty());
+}
+
+#[test]
+fn keeps_the_linked_keg_after_a_switch() {
+    let prefix = Prefix::with_foo_index();
+    let linked = prefix.keg("foo", "1.2");
+    let latest = prefix.keg("foo", "1.10");
+    let opt_dir = prefix.config.opt_dir();
+    fs::create_dir_all(&opt_dir).unwrap();
+    std::os::unix::fs::symlink(&linked, opt_dir.join("foo")).unwrap();
+
+    assert_eq!(
+        prefix.select(&CleanupOptions::default(), CleanupKind::Keg),
+        [latest]
+    );
+}
+
+#[test]
+fn downloads_of_current_versions_are_kept() {
+    let prefix = Prefix::with_foo_index();
+    let outdated = prefix.bottle("foo", "1.9", 0);
+    prefix.bottle("foo", "1.10", 0);
+    // Nothing in the index owns this one, so it is left alone.
+    prefix.bottle("unknown", "1.0", 0);
+
+    let options = CleanupOptions::default();
+    assert_eq!(prefix.select(&options, CleanupKind::Download), [outdated]);
+}
+
+#[test]
+fn prune_age_expires_current_downloads() {
+    let prefix = Prefix::with_foo_index();
+    let outdated = prefix.bottle("foo", "1.9", 0);
+    let current = prefix.bottle("foo", "1.10", 40);
+    let unknown = prefix.bottle("unknown", "1.0", 10);
+
+    let thirty_days = CleanupOptions {
+        prune_days: Some(30),
+        ..Default::default()
+    };
+    assert_eq!(
+        prefix.select(&thirty_days, CleanupKind::Download),
+        sorted(&[&outdated, &current])
+    );
+
+    // `--prune=all`
+    let all = CleanupOptions {
+        prune_days: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        prefix.select(&all, CleanupKind::Download),
+        sorted(&[&outdated, &current, &unknown])
+    );
+
+    // Further back than the clock goes: nothing is that old.
+    for days in [u64::MAX, u64::MAX / (24 * 60 * 60), 1 << 40] {
+        let ancient = CleanupOptions {
+            prune_days: Some(days),
+            ..Default::default()
+        };
+        assert_eq!(
+            prefix.select(&ancient, CleanupKind::Download),
+            sorted(&[&outdated]),
+            "{days} days"
+        );
+    }
+}
+
+#[test]
+fn scrub_and_partial_downloads() {
+    let prefix = Prefix::with_foo_index();
+    let outdated = prefix.bottle("foo", "1.9", 0);
+    let current = prefix.bottle("foo", "1.10", 0);
+    let partial = prefix
+        .cache()
+        .get_dir()
+        .join("bottles/.foo-1.11.tar.gz.download");
+    fs::write(&partial, b"half").unwrap();
+
+    let scrub = CleanupOptions {
+        scrub: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        prefix.select(&scrub, CleanupKind::Download),
+        sorted(&[&outdated, &current])
+    );
+    assert_eq!(
+        prefix.select(&scrub, CleanupKind::PartialDownload),
+        [partial]
+    );
+
+    // Partial downloads can't be attributed to a package, so naming one leaves them alone.
+    let only_foo = CleanupOptions {
+        names: vec!["foo".to_string()],
+        ..Default::default()
+    };
+    assert!(prefix
+        .select(&only_foo, CleanupKind::PartialDownload)
+        .is_empty());
+    assert_eq!(prefix.select(&only_foo, CleanupKind::Download), [outdated]);
+}
@@ -0,0 +1,70 @@
+//! Setup shared by the integration tests: a throwaway directory to unpack archives into, and a
+//! throwaway prefix. Each test file adds the builders for what it exercises.
+// Every test binary compiles this module; none uses all of it.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use tempfile::TempDir;
+
+/// A temporary directory holding downloads and the `stage` they are unpacked into.
+pub struct Sandbox {
+    dir: TempDir,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Self {
+            dir: TempDir::new().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Where archives are unpacked; not created.
+    pub fn stage(&self) -> PathBuf {
+        self.path().join("stage")
+    }
+
+    /// Writes `bytes` to `name` in the sandbox, outside the stage.
+    pub fn write(&self, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = self.path().join(name);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+}
+
+/// An empty prefix at `prefix/` in a temporary directory, so tests can also put files next to
+/// it.
+pub struct Prefix {
+    dir: TempDir,
+    pub config: Config,
+}
+
+impl Prefix {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config::defaults();
+        config.sps_root = dir.path().join("prefix");
+        fs::create_dir_all(config.opt_dir()).unwrap();
+        Self { dir, config }
+    }
+
+    /// The temporary directory the prefix lives in.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn root(&self) -> &Path {
+        self.config.sps_root()
+    }
+
+    pub fn cache(&self) -> Cache {
+        Cache::new(&self.config).unwrap()
+    }
+}
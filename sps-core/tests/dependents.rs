@@ -0,0 +1,179 @@
+//! The dependency graph `leaves`, `autoremove` and `uninstall` work from, loaded from kegs and
+//! a cask index in a throwaway prefix.
+#![cfg(unix)]
+
+mod common;
+
+use std::collections::BTreeSet;
+use std::fs;
+
+use serde_json::json;
+use sps_core::check::InstalledDependencies;
+
+use crate::common::Prefix;
+
+impl Prefix {
+    /// Installs `name` with a receipt recording its runtime dependencies.
+    fn keg(&self, name: &str, deps: &[&str], on_request: bool) -> &Self {
+        let keg = self.config.formula_keg_path(name, "1.0");
+        fs::create_dir_all(&keg).unwrap();
+        let runtime_dependencies: Vec<_> = deps
+            .iter()
+            .map(|dep| json!({ "full_name": dep, "version": "1.0" }))
+            .collect();
+        let receipt = json!({
+            "installed_on_request": on_request,
+            "runtime_dependencies": runtime_dependencies,
+        });
+        fs::write(keg.join("INSTALL_RECEIPT.json"), receipt.to_string()).unwrap();
+        self
+    }
+
+    /// Installs `casks` and caches a cask index that knows their `depends_on` formulae.
+    fn casks(&self, casks: &[(&str, &[&str])]) -> &Self {
+        let index: Vec<_> = casks
+            .iter()
+            .map(|(token, formulae)| {
+                fs::create_dir_all(self.config.cask_room_dir().join(token)).unwrap();
+                json!({ "token": token, "depends_on": { "formula": formulae } })
+            })
+            .collect();
+        self.cache()
+            .store_raw("cask.json", &serde_json::Value::from(index).to_string())
+            .unwrap();
+        self
+    }
+
+    fn load(&self) -> InstalledDependencies {
+        InstalledDependencies::load(&self.cache()).unwrap()
+    }
+}
+
+/// `app` and `tool` are asked for; `app` needs `lib`, and both `lib` and `tool` need `base`,
+/// which was asked for too. `helper` is only there for the `gui` cask, and nothing needs
+/// `orphan`.
+fn graph() -> Prefix {
+    let prefix = Prefix::new();
+    prefix
+        .keg("app", &["lib"], true)
+        .keg("lib", &["base"], false)
+        .keg("tool", &["base"], true)
+        .keg("base", &[], true)
+        .keg("helper", &[], false)
+        .keg("orphan", &[], false)
+        .casks(&[("gui", &["helper"]), ("plain", &[])]);
+    prefix
+}
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn set(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn edges_come_from_receipts_and_the_cask_index() {
+    let deps = graph().load();
+
+    assert_eq!(deps.dependencies_of("lib"), ["base"]);
+    assert_eq!(deps.dependents_of("base"), ["lib", "tool"]);
+    assert_eq!(deps.cask_dependents_of("helper"), ["gui"]);
+    assert!(deps.cask_dependents_of("base").is_empty());
+    assert!(deps.installed_on_request("base"));
+    assert!(!deps.installed_on_request("lib"));
+}
+
+#[test]
+fn leaves_are_requested_formulae_nothing_depends_on() {
+    assert_eq!(graph().load().leaves(), ["app", "tool"]);
+}
+
+#[test]
+fn unneeded_keeps_what_requested_formulae_and_casks_need() {
+    assert_eq!(graph().load().unneeded(), set(&["orphan"]));
+}
+
+#[test]
+fn with_dependents_follows_dependents_transitively() {
+    let deps = graph().load();
+
+    assert_eq!(
+        deps.with_dependents(&names(&["base"])),
+        set(&["app", "base", "lib", "tool"])
+    );
+    assert_eq!(deps.with_dependents(&names(&["app"])), set(&["app"]));
+}
+
+#[test]
+fn removal_order_puts_dependents_first() {
+    let deps = graph().load();
+
+    let order = deps.removal_order(&set(&["app", "base", "lib", "tool"]));
+    let position = |name: &str| order.iter().position(|n| n == name).unwrap();
+    assert_eq!(order.len(), 4);
+    assert!(position("app") < position("lib"));
+    assert!(position("lib") < position("base"));
+    assert!(position("tool") < position("base"));
+}
+
+#[test]
+fn removable_keeps_formulae_other_formulae_need() {
+    let deps = graph().load();
+
+    let (removable, kept) = deps.removable(&names(&["lib", "orphan"]), &[]);
+    assert_eq!(removable, ["orphan"]);
+    assert_eq!(kept, [("lib".to_string(), names(&["app"]))]);
+
+    // Removing the dependent along with it frees the dependency; anything needing `base` is
+    // still installed.
+    let (removable, kept) = deps.removable(&names(&["app", "lib", "base"]), &[]);
+    assert_eq!(removable, ["app", "lib"]);
+    assert_eq!(kept, [("base".to_string(), names(&["tool"]))]);
+}
+
+#[test]
+fn removable_keeps_formulae_installed_casks_need() {
+    let deps = graph().load();
+
+    let (removable, kept) = deps.removable(&names(&["helper"]), &[]);
+    assert!(removable.is_empty());
+    assert_eq!(kept, [("helper".to_string(), names(&["gui (cask)"]))]);
+
+    // Unless the cask is being uninstalled too.
+    let (removable, kept) = deps.removable(&names(&["helper"]), &names(&["gui"]));
+    assert_eq!(removable, ["helper"]);
+    assert!(kept.is_empty());
+}
+
+#[test]
+fn cycles_are_removed_together_and_ordered_by_name() {
+    let prefix = Prefix::new();
+    prefix
+        .keg("top", &["b"], true)
+        .keg("b", &["a"], false)
+        .keg("a", &["b"], false)
+        .keg("lone", &["a"], false);
+    let deps = prefix.load();
+
+    assert_eq!(
+        deps.with_dependents(&names(&["a"])),
+        set(&["a", "b", "lone", "top"])
+    );
+    let order = deps.removal_order(&set(&["a", "b", "lone", "top"]));
+    assert_eq!(order, ["lone", "top", "a", "b"]);
+
+    // Half a cycle is never removable, and the whole cycle only once nothing outside needs it.
+    let (removable, kept) = deps.removable(&names(&["a"]), &[]);
+    assert!(removable.is_empty());
+    assert_eq!(kept, [("a".to_string(), names(&["b", "lone"]))]);
+    let (removable, _) = deps.removable(&names(&["a", "b"]), &[]);
+    assert!(removable.is_empty());
+    let (removable, kept) = deps.removable(&names(&["a", "b", "lone", "top"]), &[]);
+    assert_eq!(removable, ["a", "b", "lone", "top"]);
+    assert!(kept.is_empty());
+
+    // The cycle is needed by `top`, so nothing in it is unneeded.
+    assert_eq!(deps.unneeded(), set(&["lone"]));
+}
@@ -0,0 +1,559 @@
+//! Reading disk images in-process. Small HFS+ and APFS volumes are built in code and wrapped in
+//! UDIF images whose chunks use each compression `hdiutil` writes.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use flate2::write::ZlibEncoder;
+use lzma_rust2::{XzOptions, XzWriter};
+use plist::{Dictionary, Value};
+use sps_common::error::SpsError;
+use sps_core::install::cask::dmg::extract_dmg_to_stage;
+
+use crate::common::Sandbox;
+
+const SECTOR: usize = 512;
+/// Sectors per UDIF chunk; small enough that every volume spans several chunks.
+const CHUNK_SECTORS: usize = 8;
+
+const CHUNK_ZERO_FILL: u32 = 0x0000_0000;
+const CHUNK_RAW: u32 = 0x0000_0001;
+const CHUNK_ADC: u32 = 0x8000_0004;
+const CHUNK_ZLIB: u32 = 0x8000_0005;
+const CHUNK_BZIP2: u32 = 0x8000_0006;
+const CHUNK_LZFSE: u32 = 0x8000_0007;
+const CHUNK_LZMA: u32 = 0x8000_0008;
+const CHUNK_TERMINATOR: u32 = 0xffff_ffff;
+
+const TOOL: &[u8] = b"#!/bin/sh\necho tool\n";
+const README: &[u8] = &[b'r'; 3000];
+/// Modification time of `tool`, in seconds since the Unix epoch.
+const MODIFIED: u64 = 1_700_000_000;
+
+type Compress = fn(&[u8]) -> Vec<u8>;
+
+/// A named way of damaging a UDIF chunk table.
+type Corruption = (&'static str, fn(&mut [u8]));
+
+impl Sandbox {
+    fn extract(&self, image: &[u8]) -> Result<(), SpsError> {
+        let dmg = self.write("disk.dmg", image);
+        extract_dmg_to_stage(&dmg, &self.stage())
+    }
+
+    /// Checks the tree every test volume holds.
+    fn assert_tree(&self) {
+        let stage = self.stage();
+        let mode = |path: &Path| fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        let tool = stage.join("tool");
+        assert_eq!(fs::read(&tool).unwrap(), TOOL);
+        // The volume marks it setuid; only the permission bits are copied.
+        assert_eq!(mode(&tool), 0o755);
+        assert_eq!(
+            fs::metadata(&tool).unwrap().modified().unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(MODIFIED)
+        );
+
+        assert_eq!(fs::read(stage.join("docs/README")).unwrap(), README);
+        assert_eq!(mode(&stage.join("docs")), 0o750);
+        assert_eq!(
+            fs::read_link(stage.join("link")).unwrap(),
+            Path::new("tool")
+        );
+        assert_eq!(fs::read_dir(&stage).unwrap().count(), 3);
+    }
+}
+
+fn put(buf: &mut [u8], at: usize, bytes: &[u8]) {
+    buf[at..at + bytes.len()].copy_from_slice(bytes);
+}
+
+const HFS_BLOCK: usize = 4096;
+const HFS_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+/// An HFS+ volume holding a setuid `tool`, `docs/README` and a `link` symlink to `tool`. The
+/// catalog B-tree is a header node and one leaf node.
+fn hfsplus_volume() -> Vec<u8> {
+    let mut disk = vec![0u8; 6 * HFS_BLOCK];
+    let header = 1024;
+    put(&mut disk, header, b"H+");
+    put(&mut disk, header + 2, &4u16.to_be_bytes());
+    put(&mut disk, header + 40, &(HFS_BLOCK as u32).to_be_bytes());
+    put_hfs_fork(&mut disk, header + 272, 2 * HFS_BLOCK, 1, 2);
+
+    let catalog = HFS_BLOCK;
+    put(&mut disk, catalog + 14 + 10, &1u32.to_be_bytes());
+    put(
+        &mut disk,
+        catalog + 14 + 18,
+        &(HFS_BLOCK as u16).to_be_bytes(),
+    );
+    put(&mut disk, catalog + 14 + 22, &2u32.to_be_bytes());
+
+    let records = [
+        hfs_file(2, "tool", 16, 0o104755, TOOL.len(), 3),
+        hfs_folder(2, "docs", 17, 0o040750),
+        hfs_file(17, "README", 18, 0o100644, README.len(), 4),
+        hfs_file(2, "link", 19, 0o120755, 4, 5),
+    ];
+    let leaf = &mut disk[2 * HFS_BLOCK..3 * HFS_BLOCK];
+    leaf[8] = 0xff;
+    leaf[9] = 1;
+    put(leaf, 10, &(records.len() as u16).to_be_bytes());
+    let mut offset = 14;
+    for (i, record) in records.iter().enumerate() {
+        put(leaf, offset, record);
+        put(
+            leaf,
+            HFS_BLOCK - 2 * (i + 1),
+            &(offset as u16).to_be_bytes(),
+        );
+        offset += record.len();
+    }
+    put(
+        leaf,
+        HFS_BLOCK - 2 * (records.len() + 1),
+        &(offset as u16).to_be_bytes(),
+    );
+
+    put(&mut disk, 3 * HFS_BLOCK, TOOL);
+    put(&mut disk, 4 * HFS_BLOCK, README);
+    put(&mut disk, 5 * HFS_BLOCK, b"tool");
+    disk
+}
+
+/// Fork data with a single extent.
+fn put_hfs_fork(buf: &mut [u8], at: usize, size: usize, start_block: u32, blocks: u32) {
+    put(buf, at, &(size as u64).to_be_bytes());
+    put(buf, at + 12, &blocks.to_be_bytes());
+    put(buf, at + 16, &start_block.to_be_bytes());
+    put(buf, at + 20, &blocks.to_be_bytes());
+}
+
+fn hfs_key(parent: u32, name: &str) -> Vec<u8> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let mut key = ((6 + 2 * units.len()) as u16).to_be_bytes().to_vec();
+    key.extend_from_slice(&parent.to_be_bytes());
+    key.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    key.extend(units.iter().flat_map(|unit| unit.to_be_bytes()));
+    key
+}
+
+fn hfs_folder(parent: u32, name: &str, id: u32, mode: u16) -> Vec<u8> {
+    let mut data = [0u8; 88];
+    put(&mut data, 0, &1u16.to_be_bytes());
+    put(&mut data, 8, &id.to_be_bytes());
+    put(&mut data, 42, &mode.to_be_bytes());
+    [hfs_key(parent, name), data.to_vec()].concat()
+}
+
+fn hfs_file(parent: u32, name: &str, id: u32, mode: u16, size: usize, block: u32) -> Vec<u8> {
+    let mut data = [0u8; 248];
+    put(&mut data, 0, &2u16.to_be_bytes());
+    put(&mut data, 8, &id.to_be_bytes());
+    put(
+        &mut data,
+        16,
+        &((MODIFIED + HFS_EPOCH_OFFSET) as u32).to_be_bytes(),
+    );
+    put(&mut data, 42, &mode.to_be_bytes());
+    put_hfs_fork(&mut data, 88, size, block, 1);
+    [hfs_key(parent, name), data.to_vec()].concat()
+}
+
+const APFS_BLOCK: usize = 4096;
+const BTNODE_ROOT: u16 = 0x1;
+const BTNODE_LEAF: u16 = 0x2;
+const BTNODE_FIXED_KV_SIZE: u16 = 0x4;
+const APFS_TYPE_INODE: u64 = 3;
+const APFS_TYPE_XATTR: u64 = 4;
+const APFS_TYPE_FILE_EXTENT: u64 = 8;
+const APFS_TYPE_DIR_REC: u64 = 9;
+const DT_DIR: u16 = 4;
+const DT_REG: u16 = 8;
+const DT_LNK: u16 = 10;
+
+/// An APFS container with one unencrypted volume holding the same tree as [`hfsplus_volume`].
+/// Both object maps share one single-node tree, as does the filesystem tree.
+fn apfs_volume() -> Vec<u8> {
+    let mut disk = vec![0u8; 8 * APFS_BLOCK];
+    let block = |n: usize| n * APFS_BLOCK;
+
+    put(&mut disk, 16, &1u64.to_le_bytes());
+    put(&mut disk, 32, b"NXSB");
+    put(&mut disk, 36, &(APFS_BLOCK as u32).to_le_bytes());
+    put(&mut disk, 160, &1u64.to_le_bytes());
+    put(&mut disk, 180, &1u32.to_le_bytes());
+    put(&mut disk, 184, &1026u64.to_le_bytes());
+    put(&mut disk, block(1) + 48, &2u64.to_le_bytes());
+    put(&mut disk, block(4) + 48, &2u64.to_le_bytes());
+
+    let omap_entry = |oid: u64, paddr: u64| {
+        let key = [oid.to_le_bytes(), 1u64.to_le_bytes()].concat();
+        let value = [0u64.to_le_bytes(), paddr.to_le_bytes()].concat();
+        (key, value)
+    };
+    put_apfs_node(
+        &mut disk[block(2)..block(3)],
+        BTNODE_ROOT | BTNODE_LEAF | BTNODE_FIXED_KV_SIZE,
+        &[omap_entry(1026, 3), omap_entry(1027, 5)],
+    );
+
+    let volume = block(3);
+    put(&mut disk, volume + 32, b"APSB");
+    put(&mut disk, volume + 128, &4u64.to_le_bytes());
+    put(&mut disk, volume + 136, &1027u64.to_le_bytes());
+    put(&mut disk, volume + 264, &1u64.to_le_bytes());
+
+    let records = [
+        apfs_inode(2, 0o040755, None),
+        apfs_dir_rec(2, "tool", 16, DT_REG),
+        apfs_inode(16, 0o104755, Some(TOOL.len())),
+        apfs_extent(16, 6),
+        apfs_dir_rec(2, "docs", 17, DT_DIR),
+        apfs_inode(17, 0o040750, None),
+        apfs_dir_rec(17, "README", 18, DT_REG),
+        apfs_inode(18, 0o100644, Some(README.len())),
+        apfs_extent(18, 7),
+        apfs_dir_rec(2, "link", 19, DT_LNK),
+        apfs_inode(19, 0o120755, None),
+        apfs_xattr(19, "com.apple.fs.symlink", b"tool\0"),
+    ];
+    put_apfs_node(
+        &mut disk[block(5)..block(6)],
+        BTNODE_ROOT | BTNODE_LEAF,
+        &records,
+    );
+
+    put(&mut disk, block(6), TOOL);
+    put(&mut disk, block(7), README);
+    disk
+}
+
+/// Lays out a root B-tree node: table of contents, keys growing up, values growing down from
+/// the trailing tree info.
+fn put_apfs_node(node: &mut [u8], flags: u16, entries: &[(Vec<u8>, Vec<u8>)]) {
+    let fixed = flags & BTNODE_FIXED_KV_SIZE != 0;
+    let toc_len = entries.len() * if fixed { 4 } else { 8 };
+    put(node, 32, &flags.to_le_bytes());
+    put(node, 36, &(entries.len() as u32).to_le_bytes());
+    put(node, 42, &(toc_len as u16).to_le_bytes());
+    let keys_start = 56 + toc_len;
+    let values_end = node.len() - 40;
+    let (mut key_offset, mut value_offset) = (0, 0);
+    for (i, (key, value)) in entries.iter().enumerate() {
+        value_offset += value.len();
+        let toc: Vec<u16> = if fixed {
+            vec![key_offset as u16, value_offset as u16]
+        } else {
+            vec![
+                key_offset as u16,
+                key.len() as u16,
+                value_offset as u16,
+                value.len() as u16,
+            ]
+        };
+        let at = 56 + i * toc.len() * 2;
+        put(
+            node,
+            at,
+            &toc.iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<_>>(),
+        );
+        put(node, keys_start + key_offset, key);
+        put(node, values_end - value_offset, value);
+        key_offset += key.len();
+    }
+}
+
+fn apfs_key_header(id: u64, kind: u64) -> Vec<u8> {
+    (kind << 60 | id).to_le_bytes().to_vec()
+}
+
+/// An inode record; files get a data stream extended field with their size.
+fn apfs_inode(id: u64, mode: u16, size: Option<usize>) -> (Vec<u8>, Vec<u8>) {
+    let mut value = vec![0u8; if size.is_some() { 140 } else { 92 }];
+    put(&mut value, 8, &id.to_le_bytes());
+    put(&mut value, 24, &(MODIFIED * 1_000_000_000).to_le_bytes());
+    put(&mut value, 56, &1u32.to_le_bytes());
+    put(&mut value, 80, &mode.to_le_bytes());
+    if let Some(size) = size {
+        put(&mut value, 92, &1u16.to_le_bytes());
+        put(&mut value, 94, &44u16.to_le_bytes());
+        value[96] = 8;
+        put(&mut value, 98, &40u16.to_le_bytes());
+        put(&mut value, 100, &(size as u64).to_le_bytes());
+    }
+    (apfs_key_header(id, APFS_TYPE_INODE), value)
+}
+
+fn apfs_dir_rec(parent: u64, name: &str, id: u64, kind: u16) -> (Vec<u8>, Vec<u8>) {
+    let mut key = apfs_key_header(parent, APFS_TYPE_DIR_REC);
+    key.extend_from_slice(&(name.len() as u16 + 1).to_le_bytes());
+    key.extend_from_slice(name.as_bytes());
+    key.push(0);
+    let mut value = vec![0u8; 18];
+    put(&mut value, 0, &id.to_le_bytes());
+    put(&mut value, 16, &kind.to_le_bytes());
+    (key, value)
+}
+
+/// One block of data stream `id`, starting at logical offset 0.
+fn apfs_extent(id: u64, block: u64) -> (Vec<u8>, Vec<u8>) {
+    let mut key = apfs_key_header(id, APFS_TYPE_FILE_EXTENT);
+    key.extend_from_slice(&0u64.to_le_bytes());
+    let value = [
+        (APFS_BLOCK as u64).to_le_bytes(),
+        block.to_le_bytes(),
+        0u64.to_le_bytes(),
+    ]
+    .concat();
+    (key, value)
+}
+
+/// An extended attribute stored inline.
+fn apfs_xattr(id: u64, name: &str, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut key = apfs_key_header(id, APFS_TYPE_XATTR);
+    key.extend_from_slice(&(name.len() as u16 + 1).to_le_bytes());
+    key.extend_from_slice(name.as_bytes());
+    key.push(0);
+    let mut value = 2u16.to_le_bytes().to_vec();
+    value.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    value.extend_from_slice(data);
+    (key, value)
+}
+
+/// Splits `disk` into chunks stored as `kind`, returning the data fork and its `mish` table.
+/// All-zero chunks become zero-fill chunks, as `hdiutil` writes them.
+fn chunk_table(disk: &[u8], kind: u32, compress: Compress) -> (Vec<u8>, Vec<u8>) {
+    assert_eq!(disk.len() % SECTOR, 0);
+    let mut data_fork = Vec::new();
+    let mut mish = vec![0u8; 204];
+    let push_entry = |mish: &mut Vec<u8>,
+                      kind: u32,
+                      sector: usize,
+                      sectors: usize,
+                      offset: usize,
+                      len: usize| {
+        let mut entry = [0u8; 40];
+        put(&mut entry, 0, &kind.to_be_bytes());
+        put(&mut entry, 8, &(sector as u64).to_be_bytes());
+        put(&mut entry, 16, &(sectors as u64).to_be_bytes());
+        put(&mut entry, 24, &(offset as u64).to_be_bytes());
+        put(&mut entry, 32, &(len as u64).to_be_bytes());
+        mish.extend_from_slice(&entry);
+    };
+    for (i, chunk) in disk.chunks(CHUNK_SECTORS * SECTOR).enumerate() {
+        let (kind, stored) = if chunk.iter().all(|&b| b == 0) {
+            (CHUNK_ZERO_FILL, Vec::new())
+        } else {
+            (kind, compress(chunk))
+        };
+        push_entry(
+            &mut mish,
+            kind,
+            i * CHUNK_SECTORS,
+            chunk.len() / SECTOR,
+            data_fork.len(),
+            stored.len(),
+        );
+        data_fork.extend(stored);
+    }
+    let sectors = disk.len() / SECTOR;
+    push_entry(&mut mish, CHUNK_TERMINATOR, sectors, 0, data_fork.len(), 0);
+    let count = (mish.len() - 204) / 40;
+    put(&mut mish, 0, b"mish");
+    put(&mut mish, 4, &1u32.to_be_bytes());
+    put(&mut mish, 16, &(sectors as u64).to_be_bytes());
+    put(&mut mish, 200, &(count as u32).to_be_bytes());
+    (data_fork, mish)
+}
+
+/// A UDIF image: the data fork, then the property list holding `mish`, then the `koly` trailer.
+fn udif_image(mut data_fork: Vec<u8>, mish: Vec<u8>, sectors: usize) -> Vec<u8> {
+    let mut table = Dictionary::new();
+    table.insert("Name".into(), Value::String("disk image".into()));
+    table.insert("Data".into(), Value::Data(mish));
+    let mut resources = Dictionary::new();
+    resources.insert("blkx".into(), Value::Array(vec![Value::Dictionary(table)]));
+    let mut root = Dictionary::new();
+    root.insert("resource-fork".into(), Value::Dictionary(resources));
+    let mut xml = Vec::new();
+    Value::Dictionary(root).to_writer_xml(&mut xml).unwrap();
+
+    let mut koly = [0u8; 512];
+    put(&mut koly, 0, b"koly");
+    put(&mut koly, 4, &4u32.to_be_bytes());
+    put(&mut koly, 8, &512u32.to_be_bytes());
+    put(&mut koly, 32, &(data_fork.len() as u64).to_be_bytes());
+    put(&mut koly, 216, &(data_fork.len() as u64).to_be_bytes());
+    put(&mut koly, 224, &(xml.len() as u64).to_be_bytes());
+    put(&mut koly, 492, &(sectors as u64).to_be_bytes());
+    data_fork.extend(xml);
+    data_fork.extend_from_slice(&koly);
+    data_fork
+}
+
+fn udif(disk: &[u8], kind: u32, compress: Compress) -> Vec<u8> {
+    let (data_fork, mish) = chunk_table(disk, kind, compress);
+    udif_image(data_fork, mish, disk.len() / SECTOR)
+}
+
+fn zlib(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn bzip2(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn xz(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = XzWriter::new(Vec::new(), XzOptions::with_preset(1)).unwrap();
+    writer.write_all(bytes).unwrap();
+    writer.finish().unwrap()
+}
+
+/// An LZFSE stream of one uncompressed block.
+fn lzfse(bytes: &[u8]) -> Vec<u8> {
+    let mut stream = b"bvx-".to_vec();
+    stream.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    stream.extend_from_slice(bytes);
+    stream.extend_from_slice(b"bvx$");
+    stream
+}
+
+/// ADC with runs of a repeated byte as distance-1 matches and everything else as literals.
+fn adc(bytes: &[u8]) -> Vec<u8> {
+    fn flush(out: &mut Vec<u8>, literals: &mut Vec<u8>) {
+        if !literals.is_empty() {
+            out.push(0x80 | (literals.len() - 1) as u8);
+            out.append(literals);
+        }
+    }
+    let mut out = Vec::new();
+    let mut literals = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let run = match pos.checked_sub(1) {
+            Some(prev) => bytes[pos..]
+                .iter()
+                .take(67)
+                .take_while(|&&b| b == bytes[prev])
+                .count(),
+            None => 0,
+        };
+        if run >= 4 {
+            flush(&mut out, &mut literals);
+            out.push(0x40 | (run - 4) as u8);
+            out.extend_from_slice(&0u16.to_be_bytes());
+            pos += run;
+        } else {
+            literals.push(bytes[pos]);
+            pos += 1;
+            if literals.len() == 128 {
+                flush(&mut out, &mut literals);
+            }
+        }
+    }
+    flush(&mut out, &mut literals);
+    out
+}
+
+#[test]
+fn hfsplus_in_every_chunk_compression() {
+    let disk = hfsplus_volume();
+    let codecs: [(&str, u32, Compress); 6] = [
+        ("raw", CHUNK_RAW, <[u8]>::to_vec),
+        ("zlib", CHUNK_ZLIB, zlib),
+        ("bzip2", CHUNK_BZIP2, bzip2),
+        ("lzfse", CHUNK_LZFSE, lzfse),
+        ("adc", CHUNK_ADC, adc),
+        ("lzma", CHUNK_LZMA, xz),
+    ];
+    for (name, kind, compress) in codecs {
+        let sandbox = Sandbox::new();
+        let image = udif(&disk, kind, compress);
+        sandbox
+            .extract(&image)
+            .unwrap_or_else(|e| panic!("{name}: {e}"));
+        sandbox.assert_tree();
+    }
+}
+
+#[test]
+fn apfs_volume_in_zlib_chunks() {
+    let sandbox = Sandbox::new();
+    sandbox
+        .extract(&udif(&apfs_volume(), CHUNK_ZLIB, zlib))
+        .unwrap();
+    sandbox.assert_tree();
+}
+
+#[test]
+fn raw_disk_without_udif_trailer() {
+    let sandbox = Sandbox::new();
+    sandbox.extract(&hfsplus_volume()).unwrap();
+    sandbox.assert_tree();
+}
+
+fn assert_malformed(what: &str, image: &[u8]) {
+    match Sandbox::new().extract(image) {
+        Err(SpsError::Generic(msg)) if msg.starts_with("Malformed disk image") => {}
+        other => panic!("{what}: expected a malformed image error, got {other:?}"),
+    }
+}
+
+#[test]
+fn out_of_range_chunk_tables_are_rejected() {
+    let disk = hfsplus_volume();
+    // The first entry is the chunk holding the volume header.
+    const ENTRY: usize = 204;
+    let corruptions: [Corruption; 4] = [
+        ("chunk data past the end of the image", |mish| {
+            put(mish, ENTRY + 24, &(1u64 << 40).to_be_bytes())
+        }),
+        ("chunk data offset overflows", |mish| {
+            put(mish, ENTRY + 24, &u64::MAX.to_be_bytes())
+        }),
+        ("chunk covering a terabyte", |mish| {
+            put(mish, ENTRY + 16, &(1u64 << 31).to_be_bytes())
+        }),
+        ("chunk start overflows", |mish| {
+            put(mish, ENTRY + 8, &(u64::MAX / 2).to_be_bytes())
+        }),
+    ];
+    for (what, corrupt) in corruptions {
+        let (data_fork, mut mish) = chunk_table(&disk, CHUNK_ZLIB, zlib);
+        corrupt(&mut mish);
+        assert_malformed(what, &udif_image(data_fork, mish, disk.len() / SECTOR));
+    }
+
+    let mut image = udif(&disk, CHUNK_ZLIB, zlib);
+    let koly = image.len() - 512;
+    put(&mut image, koly + 224, &(1u64 << 40).to_be_bytes());
+    assert_malformed("property list past the end of the image", &image);
+}
+
+#[test]
+fn symlinks_out_of_the_volume_are_rejected() {
+    for target in [b"/etc", b"../x"] {
+        let mut disk = hfsplus_volume();
+        put(&mut disk, 5 * HFS_BLOCK, target);
+        let sandbox = Sandbox::new();
+        match sandbox.extract(&disk) {
+            Err(SpsError::UnsafeArchive(_)) => {}
+            other => panic!("expected UnsafeArchive, got {other:?}"),
+        }
+        assert!(sandbox.stage().join("link").symlink_metadata().is_err());
+    }
+}
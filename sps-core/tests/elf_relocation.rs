@@ -0,0 +1,421 @@
+//! ELF relocation of Linux bottles, on small hand-built 64-bit little-endian images with an
+//! interpreter, a dynamic section (DT_NEEDED plus DT_RUNPATH or DT_RPATH) and section headers.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use sps_core::install::bottle::elf::patch_elf_file;
+use tempfile::TempDir;
+
+const PT_NULL: u32 = 0;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_NOTE: u32 = 4;
+const PT_PHDR: u32 = 6;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_STRSZ: u64 = 10;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+const BASE: u64 = 0x40_0000;
+const PLACEHOLDER: &str = "@@HOMEBREW_PREFIX@@";
+const REPOSITORY_PLACEHOLDER: &str = "@@HOMEBREW_REPOSITORY@@";
+const INTERP: &str = "@@HOMEBREW_PREFIX@@/lib/ld.so";
+const SEARCH_PATH: &str = "@@HOMEBREW_PREFIX@@/lib";
+const EMBEDDED: &str = "etc=@@HOMEBREW_REPOSITORY@@/etc";
+const SHORT_PREFIX: &str = "/opt/x";
+const LONG_PREFIX: &str = "/home/linuxbrew/.linuxbrew/a/much/longer/prefix";
+
+/// Section header indices in the fixture.
+const SHDR_INTERP: usize = 1;
+const SHDR_DYNSTR: usize = 2;
+
+fn put(buf: &mut [u8], off: usize, bytes: &[u8]) {
+    buf[off..off + bytes.len()].copy_from_slice(bytes);
+}
+
+fn align8(buf: &mut Vec<u8>) -> usize {
+    buf.resize(buf.len().next_multiple_of(8), 0);
+    buf.len()
+}
+
+fn u16_at(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(buf[off..off + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+fn c_str(buf: &[u8], off: usize) -> &str {
+    let len = buf[off..].iter().position(|&b| b == 0).unwrap();
+    std::str::from_utf8(&buf[off..off + len]).unwrap()
+}
+
+/// Builds a shared object whose strings all carry a placeholder. `spare` adds one more program
+/// header of that type for the relocator to take over; PT_PHDR is added first and describes the
+/// table instead.
+fn fixture(spare: Option<u32>, search_path_tag: u64) -> Vec<u8> {
+    let phnum = 3 + usize::from(spare.is_some());
+    let mut buf = vec![0u8; 64 + phnum * 56];
+
+    let interp_off = align8(&mut buf);
+    buf.extend_from_slice(INTERP.as_bytes());
+    buf.push(0);
+
+    let dynstr_off = align8(&mut buf);
+    let needed_idx = 1u64;
+    let search_path_idx = needed_idx + "libfoo.so".len() as u64 + 1;
+    buf.push(0);
+    buf.extend_from_slice(b"libfoo.so\0");
+    buf.extend_from_slice(SEARCH_PATH.as_bytes());
+    buf.push(0);
+    let dynstr_size = buf.len() - dynstr_off;
+
+    let dynamic_off = align8(&mut buf);
+    for (tag, val) in [
+        (DT_NEEDED, needed_idx),
+        (search_path_tag, search_path_idx),
+        (DT_STRTAB, BASE + dynstr_off as u64),
+        (DT_STRSZ, dynstr_size as u64),
+        (DT_NULL, 0),
+    ] {
+        buf.extend_from_slice(&tag.to_le_bytes());
+        buf.extend_from_slice(&val.to_le_bytes());
+    }
+    let dynamic_size = buf.len() - dynamic_off;
+
+    let rodata_off = align8(&mut buf);
+    buf.extend_from_slice(EMBEDDED.as_bytes());
+    buf.push(0);
+
+    let shoff = align8(&mut buf);
+    buf.resize(shoff + 3 * 64, 0);
+    for (index, sh_type, off, size) in [
+        (SHDR_INTERP, 1u32, interp_off, INTERP.len() + 1),
+        (SHDR_DYNSTR, 3u32, dynstr_off, dynstr_size),
+    ] {
+        let o = shoff + index * 64;
+        put(&mut buf, o + 4, &sh_type.to_le_bytes());
+        put(&mut buf, o + 16, &(BASE + off as u64).to_le_bytes());
+        put(&mut buf, o + 24, &(off as u64).to_le_bytes());
+        put(&mut buf, o + 32, &(size as u64).to_le_bytes());
+    }
+
+    let file_len = buf.len() as u64;
+    let mut phdrs = vec![
+        (PT_LOAD, 5u32, 0u64, file_len, 0x1000u64),
+        (PT_INTERP, 4, interp_off as u64, INTERP.len() as u64 + 1, 1),
+        (PT_DYNAMIC, 6, dynamic_off as u64, dynamic_size as u64, 8),
+    ];
+    match spare {
+        Some(PT_NOTE) => phdrs.push((PT_NOTE, 4, rodata_off as u64, 0, 4)),
+        Some(PT_PHDR) => phdrs.insert(0, (PT_PHDR, 4, 64, (phnum * 56) as u64, 8)),
+        Some(other) => phdrs.push((other, 0, 0, 0, 0)),
+        None => {}
+    }
+    for (i, (p_type, flags, off, size, align)) in phdrs.into_iter().enumerate() {
+        let o = 64 + i * 56;
+        let vaddr = if p_type == PT_NULL { 0 } else { BASE + off };
+        put(&mut buf, o, &p_type.to_le_bytes());
+        put(&mut buf, o + 4, &flags.to_le_bytes());
+        put(&mut buf, o + 8, &off.to_le_bytes());
+        put(&mut buf, o + 16, &vaddr.to_le_bytes());
+        put(&mut buf, o + 24, &vaddr.to_le_bytes());
+        put(&mut buf, o + 32, &size.to_le_bytes());
+        put(&mut buf, o + 40, &size.to_le_bytes());
+        put(&mut buf, o + 48, &align.to_le_bytes());
+    }
+
+    put(&mut buf, 0, b"\x7fELF\x02\x01\x01");
+    put(&mut buf, 16, &3u16.to_le_bytes()); // ET_DYN
+    put(&mut buf, 18, &62u16.to_le_bytes()); // EM_X86_64
+    put(&mut buf, 20, &1u32.to_le_bytes());
+    put(&mut buf, 32, &64u64.to_le_bytes());
+    put(&mut buf, 40, &(shoff as u64).to_le_bytes());
+    put(&mut buf, 52, &64u16.to_le_bytes());
+    put(&mut buf, 54, &56u16.to_le_bytes());
+    put(&mut buf, 56, &(phnum as u16).to_le_bytes());
+    put(&mut buf, 58, &64u16.to_le_bytes());
+    put(&mut buf, 60, &3u16.to_le_bytes());
+    buf
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Phdr {
+    p_type: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+}
+
+/// Reads back the parts of a patched image the relocator is expected to keep consistent.
+struct Image(Vec<u8>);
+
+impl Image {
+    fn phdrs(&self) -> Vec<Phdr> {
+        let b = &self.0;
+        let phoff = u64_at(b, 32) as usize;
+        (0..u16_at(b, 56) as usize)
+            .map(|i| {
+                let o = phoff + i * 56;
+                Phdr {
+                    p_type: u32_at(b, o),
+                    offset: u64_at(b, o + 8),
+                    vaddr: u64_at(b, o + 16),
+                    filesz: u64_at(b, o + 32),
+                    memsz: u64_at(b, o + 40),
+                }
+            })
+            .collect()
+    }
+
+    fn phdr(&self, p_type: u32) -> Phdr {
+        *self.phdrs().iter().find(|ph| ph.p_type == p_type).unwrap()
+    }
+
+    /// File offset of `vaddr`, which must be mapped by a PT_LOAD segment.
+    fn offset_of(&self, vaddr: u64) -> usize {
+        let load = self
+            .phdrs()
+            .into_iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .find(|ph| vaddr >= ph.vaddr && vaddr < ph.vaddr + ph.filesz)
+            .unwrap_or_else(|| panic!("{vaddr:#x} is not mapped"));
+        (vaddr - load.vaddr + load.offset) as usize
+    }
+
+    fn interp(&self) -> &str {
+        let interp = self.phdr(PT_INTERP);
+        assert_eq!(self.offset_of(interp.vaddr), interp.offset as usize);
+        let value = c_str(&self.0, interp.offset as usize);
+        assert!(interp.filesz > value.len() as u64);
+        value
+    }
+
+    fn dynamic(&self, tag: u64) -> u64 {
+        let dynamic = self.phdr(PT_DYNAMIC);
+        (0..dynamic.filesz as usize / 16)
+            .map(|i| dynamic.offset as usize + i * 16)
+            .map(|o| (u64_at(&self.0, o), u64_at(&self.0, o + 8)))
+            .find(|&(t, _)| t == tag)
+            .unwrap()
+            .1
+    }
+
+    /// The string a dynamic entry refers to, looked up the way the loader does.
+    fn dyn_str(&self, tag: u64) -> &str {
+        let strtab = self.offset_of(self.dynamic(DT_STRTAB));
+        let index = self.dynamic(tag);
+        assert!(index < self.dynamic(DT_STRSZ));
+        c_str(&self.0, strtab + index as usize)
+    }
+
+    /// `(sh_addr, sh_offset, sh_size)` of section `index`.
+    fn section(&self, index: usize) -> (u64, u64, u64) {
+        let o = u64_at(&self.0, 40) as usize + index * 64;
+        (
+            u64_at(&self.0, o + 16),
+            u64_at(&self.0, o + 24),
+            u64_at(&self.0, o + 32),
+        )
+    }
+
+    fn contains(&self, s: &str) -> bool {
+        self.0.windows(s.len()).any(|w| w == s.as_bytes())
+    }
+}
+
+struct Patched {
+    _dir: TempDir,
+    path: PathBuf,
+}
+
+impl Patched {
+    fn write(bytes: &[u8]) -> Self {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("libbar.so");
+        fs::write(&path, bytes).unwrap();
+        Self { _dir: dir, path }
+    }
+
+    fn patch(&self, prefix: &str) -> bool {
+        self.patch_with(prefix, prefix)
+    }
+
+    fn patch_with(&self, prefix: &str, repository: &str) -> bool {
+        let replacements = HashMap::from([
+            (PLACEHOLDER.to_string(), prefix.to_string()),
+            (REPOSITORY_PLACEHOLDER.to_string(), repository.to_string()),
+        ]);
+        patch_elf_file(&self.path, &replacements).unwrap()
+    }
+
+    fn image(&self) -> Image {
+        Image(fs::read(&self.path).unwrap())
+    }
+}
+
+#[test]
+fn shorter_strings_are_rewritten_in_place() {
+    let original = fixture(Some(PT_NULL), DT_RUNPATH);
+    let file = Patched::write(&original);
+    assert!(file.patch(SHORT_PREFIX));
+
+    let image = file.image();
+    assert_eq!(image.0.len(), original.len());
+    assert_eq!(image.interp(), "/opt/x/lib/ld.so");
+    assert_eq!(image.dyn_str(DT_RUNPATH), "/opt/x/lib");
+    assert_eq!(image.dyn_str(DT_NEEDED), "libfoo.so");
+    assert!(image.contains("etc=/opt/x/etc\0"));
+    assert!(!image.contains("@@"));
+    // The spare header is left alone when nothing has to grow.
+    assert_eq!(image.phdrs().len(), 4);
+    assert_eq!(image.phdrs()[3].p_type, PT_NULL);
+}
+
+#[test]
+fn shorter_rpath_is_rewritten_in_place() {
+    let file = Patched::write(&fixture(Some(PT_NULL), DT_RPATH));
+    assert!(file.patch(SHORT_PREFIX));
+    assert_eq!(file.image().dyn_str(DT_RPATH), "/opt/x/lib");
+}
+
+#[test]
+fn longer_interpreter_moves_to_a_new_segment() {
+    let original = fixture(Some(PT_NULL), DT_RUNPATH);
+    let file = Patched::write(&original);
+    assert!(file.patch(LONG_PREFIX));
+
+    let image = file.image();
+    let expected = format!("{LONG_PREFIX}/lib/ld.so");
+    assert_eq!(image.interp(), expected);
+    let interp = image.phdr(PT_INTERP);
+    assert!(interp.offset >= original.len() as u64);
+    let (addr, offset, size) = image.section(SHDR_INTERP);
+    assert_eq!(
+        (addr, offset, size),
+        (interp.vaddr, interp.offset, expected.len() as u64 + 1)
+    );
+}
+
+#[test]
+fn longer_runpath_relocates_dynstr() {
+    let original = fixture(Some(PT_NULL), DT_RUNPATH);
+    let old_strtab = Image(original.clone()).dynamic(DT_STRTAB);
+    let file = Patched::write(&original);
+    assert!(file.patch(LONG_PREFIX));
+
+    let image = file.image();
+    let strtab = image.dynamic(DT_STRTAB);
+    assert_ne!(strtab, old_strtab);
+    assert_eq!(image.dyn_str(DT_RUNPATH), format!("{LONG_PREFIX}/lib"));
+    // Entries that did not change still resolve through the moved table.
+    assert_eq!(image.dyn_str(DT_NEEDED), "libfoo.so");
+    let (addr, offset, size) = image.section(SHDR_DYNSTR);
+    assert_eq!(addr, strtab);
+    assert_eq!(offset as usize, image.offset_of(strtab));
+    assert_eq!(size, image.dynamic(DT_STRSZ));
+
+    // The new segment took the spare header's place and keeps PT_LOADs sorted by address.
+    let phdrs = image.phdrs();
+    assert!(phdrs.iter().all(|ph| ph.p_type != PT_NULL));
+    let loads: Vec<Phdr> = phdrs
+        .into_iter()
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .collect();
+    assert_eq!(loads.len(), 2);
+    assert!(loads[0].vaddr < loads[1].vaddr);
+    assert_eq!(loads[1].offset % 0x1000, loads[1].vaddr % 0x1000);
+    assert_eq!(loads[1].filesz, loads[1].memsz);
+    assert_eq!(loads[1].offset + loads[1].filesz, image.0.len() as u64);
+}
+
+#[test]
+fn note_header_is_repurposed_when_there_is_no_null_one() {
+    let file = Patched::write(&fixture(Some(PT_NOTE), DT_RUNPATH));
+    assert!(file.patch(LONG_PREFIX));
+
+    let image = file.image();
+    assert!(image.phdrs().iter().all(|ph| ph.p_type != PT_NOTE));
+    assert_eq!(image.dyn_str(DT_RUNPATH), format!("{LONG_PREFIX}/lib"));
+    assert_eq!(image.interp(), format!("{LONG_PREFIX}/lib/ld.so"));
+}
+
+#[test]
+fn without_a_spare_header_the_table_moves_and_grows() {
+    for spare in [None, Some(PT_PHDR)] {
+        let original = fixture(spare, DT_RUNPATH);
+        let phnum = Image(original.clone()).phdrs().len();
+        let file = Patched::write(&original);
+        assert!(file.patch_with(LONG_PREFIX, SHORT_PREFIX));
+
+        let image = file.image();
+        assert_eq!(image.interp(), format!("{LONG_PREFIX}/lib/ld.so"));
+        assert_eq!(image.dyn_str(DT_RUNPATH), format!("{LONG_PREFIX}/lib"));
+        assert_eq!(image.dyn_str(DT_NEEDED), "libfoo.so");
+        assert!(image.contains("etc=/opt/x/etc\0"));
+
+        // The table now lives in the new segment, which keeps the first segment's
+        // offset-to-address mapping so the table's address follows from e_phoff.
+        let phdrs = image.phdrs();
+        assert_eq!(phdrs.len(), phnum + 1);
+        let phoff = u64_at(&image.0, 32);
+        assert!(phoff >= original.len() as u64);
+        let loads: Vec<Phdr> = phdrs
+            .iter()
+            .copied()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .collect();
+        assert_eq!(loads.len(), 2);
+        assert_eq!(loads[1].vaddr - loads[1].offset, BASE);
+        assert!(loads[1].vaddr >= loads[0].vaddr + loads[0].memsz);
+        assert_eq!(image.offset_of(phoff + BASE), phoff as usize);
+        assert_eq!(loads[1].offset + loads[1].filesz, image.0.len() as u64);
+        if spare.is_some() {
+            let phdr = image.phdr(PT_PHDR);
+            assert_eq!(phdr.offset, phoff);
+            assert_eq!(phdr.vaddr, phoff + BASE);
+            assert_eq!(phdr.filesz, (phdrs.len() * 56) as u64);
+            assert_eq!(phdrs[0].p_type, PT_PHDR);
+        }
+    }
+}
+
+#[test]
+fn corrupt_header_offsets_are_errors() {
+    let corruptions: [(usize, u64); 3] = [
+        // e_phoff near the end of the address space.
+        (32, u64::MAX - 8),
+        // PT_DYNAMIC's p_offset, so the dynamic entries wrap around.
+        (64 + 2 * 56 + 8, u64::MAX - 8),
+        // PT_LOAD's p_memsz, so the end of its memory image wraps around.
+        (64 + 40, u64::MAX - 8),
+    ];
+    for (at, value) in corruptions {
+        let mut bytes = fixture(None, DT_RUNPATH);
+        put(&mut bytes, at, &value.to_le_bytes());
+        let file = Patched::write(&bytes);
+        let replacements = HashMap::from([(PLACEHOLDER.to_string(), LONG_PREFIX.to_string())]);
+        assert!(
+            patch_elf_file(&file.path, &replacements).is_err(),
+            "corrupt field at {at:#x} was accepted"
+        );
+    }
+}
+
+#[test]
+fn non_elf_files_are_skipped() {
+    let file = Patched::write(b"#!/bin/sh\necho @@HOMEBREW_PREFIX@@\n");
+    assert!(!file.patch(LONG_PREFIX));
+}
@@ -0,0 +1,245 @@
+//! Every compression format the extractor reads, including concatenated streams and single
+//! compressed files. Archives are built in code; zstd frames are written with raw blocks since
+//! the decoder crate can't encode.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{Cursor, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use lzma_rust2::{LzipOptions, LzipWriter};
+use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+use sps_common::error::SpsError;
+use sps_core::install::extract::extract_archive;
+
+use crate::common::Sandbox;
+
+const README: &[u8] = &[b'r'; 1500];
+const TOOL: &[u8] = b"#!/bin/sh\necho tool\n";
+
+type Compress = fn(&[u8]) -> Vec<u8>;
+
+impl Sandbox {
+    fn extract(&self, name: &str, bytes: &[u8], strip_components: usize) -> Result<(), SpsError> {
+        let archive = self.write(name, bytes);
+        let archive_type = name.rsplit('.').next().unwrap();
+        extract_archive(&archive, &self.stage(), strip_components, archive_type)
+    }
+
+    /// Checks the tree [`tarball`] holds, extracted with one component stripped.
+    fn assert_tree(&self) {
+        let stage = self.stage();
+        assert_eq!(fs::read(stage.join("README")).unwrap(), README);
+        let tool = stage.join("bin/tool");
+        assert_eq!(fs::read(&tool).unwrap(), TOOL);
+        assert_eq!(
+            fs::metadata(&tool).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+}
+
+/// `pkg/README` and `pkg/bin/tool`, several tar blocks long.
+fn tarball() -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, mode, data) in [("pkg/README", 0o644, README), ("pkg/bin/tool", 0o755, TOOL)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(mode);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Compresses each part as its own stream and concatenates them.
+fn members(parts: &[&[u8]], compress: Compress) -> Vec<u8> {
+    parts.iter().flat_map(|part| compress(part)).collect()
+}
+
+fn halves(bytes: &[u8]) -> [&[u8]; 2] {
+    let (a, b) = bytes.split_at(bytes.len() / 2);
+    [a, b]
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn bzip2(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn lzip(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = LzipWriter::new(Vec::new(), LzipOptions::with_preset(1));
+    writer.write_all(bytes).unwrap();
+    writer.finish().unwrap()
+}
+
+/// One zstd frame of raw (stored) blocks, with the content size in the header.
+fn zstd(bytes: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 128 << 10;
+    let mut frame = 0xFD2F_B528u32.to_le_bytes().to_vec();
+    // Single segment, 8-byte content size, no checksum.
+    frame.push(0xE0);
+    frame.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    let chunks: Vec<&[u8]> = if bytes.is_empty() {
+        vec![&[]]
+    } else {
+        bytes.chunks(MAX_BLOCK).collect()
+    };
+    for (i, chunk) in chunks.iter().enumerate() {
+        let last = u32::from(i + 1 == chunks.len());
+        let header = (chunk.len() as u32) << 3 | last;
+        frame.extend_from_slice(&header.to_le_bytes()[..3]);
+        frame.extend_from_slice(chunk);
+    }
+    frame
+}
+
+fn zstd_skippable(payload: &[u8]) -> Vec<u8> {
+    let mut frame = 0x184D_2A50u32.to_le_bytes().to_vec();
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn multi_member_tar_gz() {
+    let sandbox = Sandbox::new();
+    let archive = members(&halves(&tarball()), gzip);
+    sandbox.extract("pkg.tar.gz", &archive, 1).unwrap();
+    sandbox.assert_tree();
+}
+
+#[test]
+fn multi_member_tar_bz2() {
+    let sandbox = Sandbox::new();
+    let archive = members(&halves(&tarball()), bzip2);
+    sandbox.extract("pkg.tbz2", &archive, 1).unwrap();
+    sandbox.assert_tree();
+}
+
+#[test]
+fn multi_member_tar_lz() {
+    let sandbox = Sandbox::new();
+    let archive = members(&halves(&tarball()), lzip);
+    sandbox.extract("pkg.tar.lz", &archive, 1).unwrap();
+    sandbox.assert_tree();
+}
+
+#[test]
+fn zstd_frames_and_skippable_frames() {
+    let sandbox = Sandbox::new();
+    let tar = tarball();
+    let [a, b] = halves(&tar);
+    let mut archive = zstd_skippable(b"metadata");
+    archive.extend(zstd(a));
+    archive.extend(zstd_skippable(b""));
+    archive.extend(zstd(b));
+    archive.extend(zstd(b""));
+    sandbox.extract("pkg.tar.zst", &archive, 1).unwrap();
+    sandbox.assert_tree();
+}
+
+#[test]
+fn corrupt_zstd_is_an_error() {
+    let sandbox = Sandbox::new();
+    let mut archive = zstd(&tarball());
+    // Claim a compressed block type with garbage behind it.
+    archive[13] |= 0b110;
+    assert!(sandbox.extract("pkg.tar.zst", &archive, 1).is_err());
+    assert!(sandbox
+        .extract("junk.tar.zst", b"not zstd at all", 1)
+        .is_err());
+}
+
+#[test]
+fn single_compressed_files_are_named_after_the_archive() {
+    let compressors: [(&str, Compress); 4] = [
+        ("tool.gz", gzip),
+        ("tool.bz2", bzip2),
+        ("tool.lz", lzip),
+        ("tool.zst", zstd),
+    ];
+    for (name, compress) in compressors {
+        let sandbox = Sandbox::new();
+        sandbox.extract(name, &compress(TOOL), 0).unwrap();
+        assert_eq!(
+            fs::read(sandbox.stage().join("tool")).unwrap(),
+            TOOL,
+            "{name}"
+        );
+        assert_eq!(fs::read_dir(sandbox.stage()).unwrap().count(), 1, "{name}");
+    }
+}
+
+#[test]
+fn single_file_larger_than_a_tar_block_is_not_a_tarball() {
+    let sandbox = Sandbox::new();
+    let content = vec![b'x'; 4096];
+    sandbox.extract("data.gz", &gzip(&content), 0).unwrap();
+    assert_eq!(fs::read(sandbox.stage().join("data")).unwrap(), content);
+}
+
+#[test]
+fn v7_tarball_without_ustar_magic_is_a_tarball() {
+    let sandbox = Sandbox::new();
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_old();
+    header.set_mode(0o755);
+    header.set_size(TOOL.len() as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "pkg/bin/tool", TOOL)
+        .unwrap();
+    let tar = builder.into_inner().unwrap();
+    assert_ne!(&tar[257..262], b"ustar");
+
+    sandbox.extract("pkg.tgz", &gzip(&tar), 1).unwrap();
+    assert_eq!(fs::read(sandbox.stage().join("bin/tool")).unwrap(), TOOL);
+}
+
+/// A 7z entry with p7zip's unix mode in the high half of its attributes.
+fn sevenz_entry(name: &str, mode: u32) -> SevenZArchiveEntry {
+    let mut entry = SevenZArchiveEntry::new();
+    entry.name = name.to_string();
+    entry.is_directory = mode & 0o170000 == 0o040000;
+    entry.has_windows_attributes = true;
+    entry.windows_attributes = 0x8000 | (mode << 16);
+    entry
+}
+
+fn sevenz(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    let mut writer = SevenZWriter::new(Cursor::new(Vec::new())).unwrap();
+    for &(name, mode, data) in entries {
+        let entry = sevenz_entry(name, mode);
+        let content = (!entry.is_directory).then_some(data);
+        writer.push_archive_entry(entry, content).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn sevenz_with_modes_and_internal_symlink() {
+    let sandbox = Sandbox::new();
+    let archive = sevenz(&[
+        ("pkg", 0o040755, b""),
+        ("pkg/README", 0o100644, README),
+        ("pkg/bin/tool", 0o100755, TOOL),
+        ("pkg/tool", 0o120777, b"bin/tool"),
+    ]);
+    sandbox.extract("pkg.7z", &archive, 1).unwrap();
+    sandbox.assert_tree();
+    let link = sandbox.stage().join("tool");
+    assert_eq!(fs::read_link(&link).unwrap(), Path::new("bin/tool"));
+    assert_eq!(fs::read(&link).unwrap(), TOOL);
+}
@@ -0,0 +1,436 @@
+//! Malicious archives the extractors must refuse, and well-formed ones they must still unpack.
+//! The archives are built in code: the tar and zip writers refuse to produce most of them, so
+//! headers are filled in by hand where needed.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+use sps_common::error::SpsError;
+use sps_core::install::extract::{extract_archive_with_policy, ExtractPolicy};
+use tar::{EntryType, Header};
+
+use crate::common::Sandbox;
+
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFCHR: u32 = 0o020000;
+
+impl Sandbox {
+    /// A sandbox with the stage and a sibling `outside` directory no entry may reach.
+    fn with_outside() -> Self {
+        let sandbox = Sandbox::new();
+        fs::create_dir(sandbox.stage()).unwrap();
+        fs::create_dir(sandbox.outside()).unwrap();
+        sandbox
+    }
+
+    fn outside(&self) -> PathBuf {
+        self.path().join("outside")
+    }
+
+    fn extract(&self, name: &str, bytes: &[u8], policy: &ExtractPolicy) -> Result<(), SpsError> {
+        let archive = self.write(name, bytes);
+        let archive_type = name.rsplit('.').next().unwrap();
+        extract_archive_with_policy(&archive, &self.stage(), 0, archive_type, policy)
+    }
+
+    fn assert_rejected(&self, name: &str, bytes: &[u8], policy: &ExtractPolicy) {
+        match self.extract(name, bytes, policy) {
+            Err(SpsError::UnsafeArchive(_)) => {}
+            other => panic!("expected UnsafeArchive for {name}, got {other:?}"),
+        }
+        assert_eq!(fs::read_dir(self.outside()).unwrap().count(), 0);
+    }
+}
+
+struct TarEntry<'a> {
+    name: &'a str,
+    kind: EntryType,
+    mode: u32,
+    link: &'a str,
+    data: &'a [u8],
+}
+
+fn file<'a>(name: &'a str, data: &'a [u8]) -> TarEntry<'a> {
+    TarEntry {
+        name,
+        kind: EntryType::Regular,
+        mode: 0o644,
+        link: "",
+        data,
+    }
+}
+
+fn symlink<'a>(name: &'a str, link: &'a str) -> TarEntry<'a> {
+    TarEntry {
+        name,
+        kind: EntryType::Symlink,
+        mode: 0o777,
+        link,
+        data: b"",
+    }
+}
+
+fn hardlink<'a>(name: &'a str, link: &'a str) -> TarEntry<'a> {
+    TarEntry {
+        name,
+        kind: EntryType::Link,
+        mode: 0o644,
+        link,
+        data: b"",
+    }
+}
+
+/// Writes names and link targets straight into the header, bypassing the builder's checks.
+fn tar(entries: &[TarEntry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = Header::new_old();
+        header.as_old_mut().name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        header.as_old_mut().linkname[..entry.link.len()].copy_from_slice(entry.link.as_bytes());
+        header.set_entry_type(entry.kind);
+        header.set_mode(entry.mode);
+        header.set_size(entry.data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, entry.data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// A stored (uncompressed) zip whose entries carry arbitrary names and unix modes.
+fn zip(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, mode, data) in entries {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let offset = out.len() as u32;
+        let sizes = [crc.sum(), data.len() as u32, data.len() as u32];
+
+        out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        out.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        sizes
+            .iter()
+            .for_each(|v| out.extend_from_slice(&v.to_le_bytes()));
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&[20, 3, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        sizes
+            .iter()
+            .for_each(|v| central.extend_from_slice(&v.to_le_bytes()));
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 8]);
+        central.extend_from_slice(&(mode << 16).to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out
+}
+
+/// A 7z archive whose entries carry arbitrary names and p7zip unix modes.
+fn sevenz(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    let mut writer = SevenZWriter::new(Cursor::new(Vec::new())).unwrap();
+    for &(name, mode, data) in entries {
+        let mut entry = SevenZArchiveEntry::new();
+        entry.name = name.to_string();
+        entry.is_directory = mode & 0o170000 == S_IFDIR;
+        entry.has_windows_attributes = true;
+        entry.windows_attributes = 0x8000 | (mode << 16);
+        let content = (!entry.is_directory).then_some(data);
+        writer.push_archive_entry(entry, content).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn small_policy() -> ExtractPolicy {
+    ExtractPolicy {
+        max_entries: 4,
+        max_total_size: 1 << 20,
+    }
+}
+
+fn assert_link(path: &Path, target: &str) {
+    assert_eq!(fs::read_link(path).unwrap(), Path::new(target));
+}
+
+#[test]
+fn tar_with_internal_links_extracts() {
+    let sandbox = Sandbox::with_outside();
+    let archive = tar(&[
+        file("pkg/lib/libfoo.1.dylib", b"lib"),
+        symlink("pkg/lib/libfoo.dylib", "libfoo.1.dylib"),
+        symlink("pkg/bin/foo", "../lib/libfoo.dylib"),
+        hardlink("pkg/lib/libfoo.copy", "pkg/lib/libfoo.1.dylib"),
+    ]);
+    sandbox
+        .extract("ok.tar", &archive, &ExtractPolicy::default())
+        .unwrap();
+    let stage = sandbox.stage();
+    assert_link(&stage.join("pkg/bin/foo"), "../lib/libfoo.dylib");
+    assert_eq!(fs::read(stage.join("pkg/bin/foo")).unwrap(), b"lib");
+    assert_eq!(fs::read(stage.join("pkg/lib/libfoo.copy")).unwrap(), b"lib");
+}
+
+#[test]
+fn tar_parent_dir_entry_is_rejected() {
+    let archive = tar(&[file("../outside/evil", b"x")]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_symlink_out_of_target_is_rejected() {
+    let archive = tar(&[symlink("escape", "../outside"), file("escape/evil", b"x")]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_absolute_symlink_is_rejected() {
+    let sandbox = Sandbox::with_outside();
+    let outside = sandbox.outside();
+    let archive = tar(&[
+        symlink("escape", outside.to_str().unwrap()),
+        file("escape/evil", b"x"),
+    ]);
+    sandbox.assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_symlink_chain_out_of_target_is_rejected() {
+    // `here -> .` is harmless alone, but `here/..` then resolves above the target directory.
+    let archive = tar(&[
+        symlink("here", "."),
+        symlink("escape", "here/../outside"),
+        file("escape/evil", b"x"),
+    ]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_nested_symlink_climbing_out_is_rejected() {
+    let archive = tar(&[
+        symlink("here", "."),
+        symlink("here/escape", "../outside"),
+        file("here/escape/evil", b"x"),
+    ]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_hardlink_out_of_target_is_rejected() {
+    let archive = tar(&[hardlink("passwd", "../outside/secret")]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_hardlink_to_directory_is_rejected() {
+    let archive = tar(&[
+        TarEntry {
+            name: "dir/",
+            kind: EntryType::Directory,
+            mode: 0o755,
+            link: "",
+            data: b"",
+        },
+        hardlink("link", "dir"),
+    ]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_device_node_is_rejected() {
+    let archive = tar(&[TarEntry {
+        name: "dev/null",
+        kind: EntryType::Char,
+        mode: 0o666,
+        link: "",
+        data: b"",
+    }]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_fifo_is_rejected() {
+    let archive = tar(&[TarEntry {
+        name: "pipe",
+        kind: EntryType::Fifo,
+        mode: 0o644,
+        link: "",
+        data: b"",
+    }]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_setuid_file_is_rejected() {
+    let archive = tar(&[TarEntry {
+        mode: 0o4755,
+        ..file("bin/su", b"#!/bin/sh\n")
+    }]);
+    Sandbox::with_outside().assert_rejected("a.tar", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn tar_with_too_many_entries_is_rejected() {
+    let names: Vec<String> = (0..5).map(|i| format!("f{i}")).collect();
+    let entries: Vec<TarEntry> = names.iter().map(|name| file(name, b"x")).collect();
+    Sandbox::with_outside().assert_rejected("a.tar", &tar(&entries), &small_policy());
+}
+
+#[test]
+fn tar_gz_bomb_is_rejected() {
+    let zeros = vec![0u8; 2 << 20];
+    let archive = gzip(&tar(&[file("zeros", &zeros)]));
+    let sandbox = Sandbox::with_outside();
+    sandbox.assert_rejected("a.tgz", &archive, &small_policy());
+    assert!(!sandbox.stage().join("zeros").exists());
+}
+
+#[test]
+fn single_file_gz_bomb_is_rejected() {
+    let archive = gzip(&vec![0u8; 2 << 20]);
+    Sandbox::with_outside().assert_rejected("zeros.gz", &archive, &small_policy());
+}
+
+#[test]
+fn zip_with_internal_symlink_extracts() {
+    let archive = zip(&[
+        ("App.app/", S_IFDIR | 0o755, b""),
+        ("App.app/Versions/A/Foo", S_IFREG | 0o755, b"foo"),
+        ("App.app/Versions/Current", S_IFLNK | 0o777, b"A"),
+    ]);
+    let sandbox = Sandbox::with_outside();
+    sandbox
+        .extract("ok.zip", &archive, &ExtractPolicy::default())
+        .unwrap();
+    let current = sandbox.stage().join("App.app/Versions/Current");
+    assert_link(&current, "A");
+    assert_eq!(fs::read(current.join("Foo")).unwrap(), b"foo");
+}
+
+#[test]
+fn zip_parent_dir_entry_is_rejected() {
+    let archive = zip(&[("../outside/evil", S_IFREG | 0o644, b"x")]);
+    Sandbox::with_outside().assert_rejected("a.zip", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn zip_symlink_out_of_target_is_rejected() {
+    let archive = zip(&[
+        ("escape", S_IFLNK | 0o777, b"../outside"),
+        ("escape/evil", S_IFREG | 0o644, b"x"),
+    ]);
+    Sandbox::with_outside().assert_rejected("a.zip", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn zip_device_node_is_rejected() {
+    let archive = zip(&[("dev/null", S_IFCHR | 0o666, b"")]);
+    Sandbox::with_outside().assert_rejected("a.zip", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn zip_setuid_file_is_rejected() {
+    let archive = zip(&[("bin/su", S_IFREG | 0o4755, b"#!/bin/sh\n")]);
+    Sandbox::with_outside().assert_rejected("a.zip", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn zip_with_too_many_entries_is_rejected() {
+    let names: Vec<String> = (0..5).map(|i| format!("f{i}")).collect();
+    let entries: Vec<(&str, u32, &[u8])> = names
+        .iter()
+        .map(|name| (name.as_str(), S_IFREG | 0o644, &b"x"[..]))
+        .collect();
+    Sandbox::with_outside().assert_rejected("a.zip", &zip(&entries), &small_policy());
+}
+
+#[test]
+fn zip_deflate_bomb_is_rejected() {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    writer
+        .start_file("zeros", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    writer.write_all(&vec![0u8; 2 << 20]).unwrap();
+    let archive = writer.finish().unwrap().into_inner();
+    Sandbox::with_outside().assert_rejected("a.zip", &archive, &small_policy());
+}
+
+#[test]
+fn sevenz_with_internal_symlink_extracts() {
+    let archive = sevenz(&[
+        ("App.app", S_IFDIR | 0o755, b""),
+        ("App.app/Versions/A/Foo", S_IFREG | 0o755, b"foo"),
+        ("App.app/Versions/Current", S_IFLNK | 0o777, b"A"),
+    ]);
+    let sandbox = Sandbox::with_outside();
+    sandbox
+        .extract("ok.7z", &archive, &ExtractPolicy::default())
+        .unwrap();
+    let current = sandbox.stage().join("App.app/Versions/Current");
+    assert_link(&current, "A");
+    assert_eq!(fs::read(current.join("Foo")).unwrap(), b"foo");
+}
+
+#[test]
+fn sevenz_parent_dir_entry_is_rejected() {
+    let archive = sevenz(&[("../outside/evil", S_IFREG | 0o644, b"x")]);
+    Sandbox::with_outside().assert_rejected("a.7z", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn sevenz_symlink_out_of_target_is_rejected() {
+    let archive = sevenz(&[
+        ("escape", S_IFLNK | 0o777, b"../outside"),
+        ("escape/evil", S_IFREG | 0o644, b"x"),
+    ]);
+    Sandbox::with_outside().assert_rejected("a.7z", &archive, &ExtractPolicy::default());
+}
+
+#[test]
+fn sevenz_absolute_symlink_is_rejected() {
+    let sandbox = Sandbox::with_outside();
+    let outside = sandbox.outside();
+    let archive = sevenz(&[(
+        "escape",
+        S_IFLNK | 0o777,
+        outside.to_str().unwrap().as_bytes(),
+    )]);
+    sandbox.assert_rejected("a.7z", &archive, &ExtractPolicy::default());
+    assert!(sandbox.stage().join("escape").symlink_metadata().is_err());
+}
+
+#[test]
+fn sevenz_lzma_bomb_is_rejected() {
+    let archive = sevenz(&[("zeros", S_IFREG | 0o644, &vec![0u8; 2 << 20])]);
+    assert!(archive.len() < 64 << 10);
+    let sandbox = Sandbox::with_outside();
+    sandbox.assert_rejected("a.7z", &archive, &small_policy());
+    assert!(!sandbox.stage().join("zeros").exists());
+}
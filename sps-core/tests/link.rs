@@ -0,0 +1,400 @@
+//! Linking kegs into a throwaway prefix: directory splitting, owner manifests, keg-only and
+//! override handling, conflicts and `link_overwrite` globs. Kegs are described by their install
+//! manifests, so no formula definitions are needed.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use sps_common::error::SpsError;
+use sps_core::install::bottle::link::{
+    read_install_manifest, relink_keg, switch_keg, write_install_manifest, FormulaInstallManifest,
+    LinkOptions, LinkOutcome, LinkOverride,
+};
+
+use crate::common::Prefix;
+
+impl Prefix {
+    /// Creates `Cellar/<name>/<version>` holding `files` (paths ending in `/` are directories;
+    /// files under `bin/` are executable) and an install manifest.
+    fn keg(
+        &self,
+        name: &str,
+        version: &str,
+        files: &[&str],
+        manifest: FormulaInstallManifest,
+    ) -> PathBuf {
+        let keg = self.config.formula_keg_path(name, version);
+        for file in files {
+            let path = keg.join(file);
+            if file.ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+                continue;
+            }
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, name).unwrap();
+            let mode = if file.starts_with("bin/") {
+                0o755
+            } else {
+                0o644
+            };
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        write_install_manifest(&keg, &manifest).unwrap();
+        keg
+    }
+
+    fn relink(
+        &self,
+        name: &str,
+        keg: &Path,
+        link_override: Option<LinkOverride>,
+        options: LinkOptions,
+    ) -> Result<LinkOutcome, SpsError> {
+        relink_keg(name, keg, &self.config, link_override, &options)
+    }
+
+    /// Writes a file sps doesn't manage into the prefix.
+    fn unmanaged(&self, relative: &str) {
+        let path = self.root().join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "unmanaged").unwrap();
+    }
+}
+
+fn manifest_links(keg: &Path) -> Vec<String> {
+    read_install_manifest(keg).unwrap().unwrap().links
+}
+
+fn assert_link(link: &Path, target: &Path) {
+    assert_eq!(fs::read_link(link).unwrap(), target, "{}", link.display());
+}
+
+fn assert_conflict(result: Result<LinkOutcome, SpsError>, target: &str) {
+    match result {
+        Err(SpsError::LinkConflict(report)) => {
+            assert!(report.contains(target), "{report}");
+        }
+        other => panic!("expected a link conflict on {target}, got {other:?}"),
+    }
+}
+
+#[test]
+fn links_directories_files_and_wraps_executables() {
+    let prefix = Prefix::new();
+    let keg = prefix.keg(
+        "foo",
+        "1.0",
+        &["bin/foo", "lib/libfoo.dylib", "share/foo/data"],
+        FormulaInstallManifest::default(),
+    );
+    let outcome = prefix
+        .relink("foo", &keg, None, LinkOptions::default())
+        .unwrap();
+
+    let root = prefix.root();
+    assert_link(&prefix.config.formula_opt_path("foo"), &keg);
+    assert_link(
+        &root.join("lib/libfoo.dylib"),
+        &keg.join("lib/libfoo.dylib"),
+    );
+    assert_link(&root.join("share/foo"), &keg.join("share/foo"));
+    let wrapper = fs::read_to_string(root.join("bin/foo")).unwrap();
+    assert!(wrapper.contains(&format!(
+        "exec \"{}\" \"$@\"",
+        keg.join("bin/foo").display()
+    )));
+
+    let mut recorded = manifest_links(&keg);
+    let mut linked = outcome.links;
+    recorded.sort();
+    linked.sort();
+    assert_eq!(recorded, linked);
+    assert_eq!(recorded.len(), 4);
+}
+
+#[test]
+fn shared_directory_is_split_and_owner_manifest_rewritten() {
+    let prefix = Prefix::new();
+    let foo = prefix.keg(
+        "foo",
+        "1.0",
+        &["share/doc/foo.txt"],
+        FormulaInstallManifest::default(),
+    );
+    prefix
+        .relink("foo", &foo, None, LinkOptions::default())
+        .unwrap();
+    let doc = prefix.root().join("share/doc");
+    assert_link(&doc, &foo.join("share/doc"));
+
+    let bar = prefix.keg(
+        "bar",
+        "2.0",
+        &["share/doc/bar.txt"],
+        FormulaInstallManifest::default(),
+    );
+    prefix
+        .relink("bar", &bar, None, LinkOptions::default())
+        .unwrap();
+
+    assert!(!doc.symlink_metadata().unwrap().file_type().is_symlink());
+    assert_link(&doc.join("foo.txt"), &foo.join("share/doc/foo.txt"));
+    assert_link(&doc.join("bar.txt"), &bar.join("share/doc/bar.txt"));
+    let foo_links = manifest_links(&foo);
+    assert!(!foo_links.contains(&doc.to_string_lossy().to_string()));
+    assert!(foo_links.contains(&doc.join("foo.txt").to_string_lossy().to_string()));
+}
+
+#[test]
+fn keg_only_links_opt_unless_forced() {
+    let prefix = Prefix::new();
+    let keg = prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libfoo.dylib"],
+        FormulaInstallManifest {
+            keg_only: true,
+            ..Default::default()
+        },
+    );
+    let lib = prefix.root().join("lib/libfoo.dylib");
+
+    prefix
+        .relink("foo", &keg, None, LinkOptions::default())
+        .unwrap();
+    assert_link(&prefix.config.formula_opt_path("foo"), &keg);
+    assert!(lib.symlink_metadata().is_err());
+
+    prefix
+        .relink(
+            "foo",
+            &keg,
+            Some(LinkOverride::Forced),
+            LinkOptions::default(),
+        )
+        .unwrap();
+    assert_link(&lib, &keg.join("lib/libfoo.dylib"));
+    let manifest = read_install_manifest(&keg).unwrap().unwrap();
+    assert_eq!(manifest.link_override, Some(LinkOverride::Forced));
+    assert!(manifest.keg_only);
+
+    prefix
+        .relink("foo", &keg, None, LinkOptions::default())
+        .unwrap();
+    assert!(lib.symlink_metadata().is_err());
+}
+
+#[test]
+fn unlinked_override_keeps_only_the_opt_link() {
+    let prefix = Prefix::new();
+    let keg = prefix.keg(
+        "foo",
+        "1.0",
+        &["bin/foo", "lib/libfoo.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    prefix
+        .relink("foo", &keg, None, LinkOptions::default())
+        .unwrap();
+    prefix
+        .relink(
+            "foo",
+            &keg,
+            Some(LinkOverride::Unlinked),
+            LinkOptions::default(),
+        )
+        .unwrap();
+
+    let opt = prefix.config.formula_opt_path("foo");
+    assert_link(&opt, &keg);
+    assert!(prefix.root().join("bin/foo").symlink_metadata().is_err());
+    assert!(prefix
+        .root()
+        .join("lib/libfoo.dylib")
+        .symlink_metadata()
+        .is_err());
+    assert_eq!(manifest_links(&keg), [opt.to_string_lossy().to_string()]);
+}
+
+#[test]
+fn conflicting_relink_keeps_the_current_links() {
+    let prefix = Prefix::new();
+    let keg = prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libfoo.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    prefix
+        .relink("foo", &keg, None, LinkOptions::default())
+        .unwrap();
+    let before = manifest_links(&keg);
+
+    // A new file in the keg now collides with one sps doesn't manage.
+    prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libextra.dylib"],
+        read_install_manifest(&keg).unwrap().unwrap(),
+    );
+    prefix.unmanaged("lib/libextra.dylib");
+    assert_conflict(
+        prefix.relink("foo", &keg, None, LinkOptions::default()),
+        "libextra.dylib",
+    );
+
+    assert_link(
+        &prefix.root().join("lib/libfoo.dylib"),
+        &keg.join("lib/libfoo.dylib"),
+    );
+    assert_eq!(
+        fs::read_to_string(prefix.root().join("lib/libextra.dylib")).unwrap(),
+        "unmanaged"
+    );
+    assert_eq!(manifest_links(&keg), before);
+}
+
+#[test]
+fn other_kegs_links_conflict_until_overwritten() {
+    let prefix = Prefix::new();
+    let bar = prefix.keg(
+        "bar",
+        "1.0",
+        &["lib/libshared.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    prefix
+        .relink("bar", &bar, None, LinkOptions::default())
+        .unwrap();
+    let foo = prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libshared.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    let shared = prefix.root().join("lib/libshared.dylib");
+
+    assert_conflict(
+        prefix.relink("foo", &foo, None, LinkOptions::default()),
+        "owned by bar",
+    );
+    let preview = prefix
+        .relink(
+            "foo",
+            &foo,
+            None,
+            LinkOptions {
+                dry_run: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(preview.conflicts.len(), 1);
+    assert_eq!(preview.conflicts[0].owner.as_deref(), Some("bar"));
+    assert_link(&shared, &bar.join("lib/libshared.dylib"));
+
+    let outcome = prefix
+        .relink(
+            "foo",
+            &foo,
+            None,
+            LinkOptions {
+                overwrite: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(outcome.overwritten.len(), 1);
+    assert_link(&shared, &foo.join("lib/libshared.dylib"));
+    assert!(!manifest_links(&bar).contains(&shared.to_string_lossy().to_string()));
+}
+
+#[test]
+fn link_overwrite_globs_replace_only_matching_targets() {
+    let prefix = Prefix::new();
+    let keg = prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libfoo.dylib", "include/foo.h"],
+        FormulaInstallManifest {
+            link_overwrite: vec!["lib/libfoo*".to_string()],
+            ..Default::default()
+        },
+    );
+    prefix.unmanaged("lib/libfoo.dylib");
+    prefix.unmanaged("include/foo.h");
+    assert_conflict(
+        prefix.relink("foo", &keg, None, LinkOptions::default()),
+        "foo.h",
+    );
+
+    fs::remove_file(prefix.root().join("include/foo.h")).unwrap();
+    let outcome = prefix
+        .relink("foo", &keg, None, LinkOptions::default())
+        .unwrap();
+    assert_eq!(outcome.overwritten.len(), 1);
+    assert_eq!(outcome.overwritten[0].owner, None);
+    assert_link(
+        &prefix.root().join("lib/libfoo.dylib"),
+        &keg.join("lib/libfoo.dylib"),
+    );
+}
+
+#[test]
+fn real_directories_are_never_overwritten() {
+    let prefix = Prefix::new();
+    let keg = prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libfoo.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    fs::create_dir_all(prefix.root().join("lib/libfoo.dylib")).unwrap();
+    let options = LinkOptions {
+        overwrite: true,
+        ..Default::default()
+    };
+    assert_conflict(
+        prefix.relink("foo", &keg, None, options),
+        "existing directory",
+    );
+    assert!(prefix.root().join("lib/libfoo.dylib").is_dir());
+}
+
+#[test]
+fn switch_moves_links_to_the_other_keg() {
+    let prefix = Prefix::new();
+    let old = prefix.keg(
+        "foo",
+        "1.0",
+        &["lib/libfoo.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    let new = prefix.keg(
+        "foo",
+        "2.0",
+        &["lib/libfoo.dylib"],
+        FormulaInstallManifest::default(),
+    );
+    prefix
+        .relink("foo", &old, None, LinkOptions::default())
+        .unwrap();
+    switch_keg(
+        "foo",
+        Some(&old),
+        &new,
+        &prefix.config,
+        &LinkOptions::default(),
+    )
+    .unwrap();
+
+    assert_link(&prefix.config.formula_opt_path("foo"), &new);
+    assert_link(
+        &prefix.root().join("lib/libfoo.dylib"),
+        &new.join("lib/libfoo.dylib"),
+    );
+}
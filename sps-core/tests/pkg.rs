@@ -0,0 +1,339 @@
+//! Reading and expanding flat `.pkg` installers. The fixture is a product archive built in code:
+//! an LZMA-compressed `Distribution` and one component whose payload is a gzipped cpio archive.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use lzma_rust2::{LzmaOptions, LzmaWriter};
+use sha1::{Digest, Sha1};
+use sps_common::error::SpsError;
+use sps_common::model::cask::Cask;
+use sps_core::install::cask::inspect_cask_pkgs;
+use sps_core::install::cask::xar::{expand_pkg, inspect_pkg, PkgFileKind};
+use sps_core::install::extract::ExtractPolicy;
+
+use crate::common::Sandbox;
+
+const TOOL: &[u8] = b"#!/bin/sh\necho tool\n";
+const INFO_PLIST: &[u8] = b"<plist version=\"1.0\"><dict/></plist>\n";
+const DISTRIBUTION: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<installer-gui-script minSpecVersion="1">
+    <title>Tool</title>
+    <pkg-ref id="com.example.tool">#Tool.pkg</pkg-ref>
+</installer-gui-script>
+"##;
+const PACKAGE_INFO: &str =
+    r#"<pkg-info identifier="com.example.tool" version="1.0" install-location="/Applications"/>"#;
+
+/// A cpio member: path, mode and content (the target, for symlinks).
+type Member = (&'static str, u32, &'static [u8]);
+
+const APP: &[Member] = &[
+    (".", 0o040755, b""),
+    ("./Tool.app", 0o040755, b""),
+    ("./Tool.app/Contents/Info.plist", 0o100644, INFO_PLIST),
+    ("./Tool.app/Contents/MacOS/tool", 0o100755, TOOL),
+    (
+        "./Tool.app/Contents/Resources/tool",
+        0o120755,
+        b"../MacOS/tool",
+    ),
+];
+
+/// An odc cpio archive, as `mkbom`/`pkgbuild` payloads use.
+fn cpio(members: &[Member]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let trailer: Member = ("TRAILER!!!", 0, b"");
+    for (ino, (name, mode, data)) in members.iter().chain([&trailer]).enumerate() {
+        let header = format!(
+            "070707{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:011o}{:06o}{:011o}",
+            0,
+            ino + 1,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            1_700_000_000u64,
+            name.len() + 1,
+            data.len()
+        );
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn zlib(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// An `.lzma` (LZMA-alone) stream, as older xar writers store `application/x-lzma` data.
+fn lzma_alone(bytes: &[u8]) -> Vec<u8> {
+    let options = LzmaOptions::with_preset(1);
+    let mut writer =
+        LzmaWriter::new_use_header(Vec::new(), &options, Some(bytes.len() as u64)).unwrap();
+    writer.write_all(bytes).unwrap();
+    writer.finish().unwrap()
+}
+
+/// Builds a xar whose heap holds a SHA-1 of the table of contents followed by each file.
+struct Xar {
+    heap: Vec<u8>,
+    toc: String,
+    next_id: usize,
+}
+
+impl Xar {
+    fn new() -> Self {
+        Self {
+            heap: vec![0; 20],
+            toc: String::new(),
+            next_id: 1,
+        }
+    }
+
+    fn file(&mut self, name: &str, content: &[u8], style: &str, stored: &[u8]) {
+        let checksum = hex::encode(Sha1::digest(stored));
+        self.toc.push_str(&format!(
+            "<file id=\"{}\"><name>{}</name><type>file</type><data>\
+             <offset>{}</offset><length>{}</length><size>{}</size>\
+             <encoding style=\"{}\"/>\
+             <archived-checksum style=\"sha1\">{}</archived-checksum>\
+             </data></file>",
+            self.next_id,
+            name,
+            self.heap.len(),
+            stored.len(),
+            content.len(),
+            style,
+            checksum
+        ));
+        self.next_id += 1;
+        self.heap.extend_from_slice(stored);
+    }
+
+    fn open_dir(&mut self, name: &str) {
+        self.toc.push_str(&format!(
+            "<file id=\"{}\"><name>{}</name><type>directory</type>",
+            self.next_id, name
+        ));
+        self.next_id += 1;
+    }
+
+    fn close_dir(&mut self) {
+        self.toc.push_str("</file>");
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let toc = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xar><toc>\
+             <checksum style=\"sha1\"><offset>0</offset><size>20</size></checksum>{}</toc></xar>",
+            self.toc
+        );
+        let compressed = zlib(toc.as_bytes());
+        self.heap[..20].copy_from_slice(&Sha1::digest(&compressed));
+        let mut out = b"xar!".to_vec();
+        out.extend_from_slice(&28u16.to_be_bytes());
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&(compressed.len() as u64).to_be_bytes());
+        out.extend_from_slice(&(toc.len() as u64).to_be_bytes());
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend(compressed);
+        out.extend(self.heap);
+        out
+    }
+}
+
+/// A product archive with one component, `Tool.pkg`, whose payload holds `members`.
+fn flat_pkg(members: &[Member]) -> Vec<u8> {
+    let payload = gzip(&cpio(members));
+    let mut xar = Xar::new();
+    xar.file(
+        "Distribution",
+        DISTRIBUTION.as_bytes(),
+        "application/x-lzma",
+        &lzma_alone(DISTRIBUTION.as_bytes()),
+    );
+    xar.open_dir("Tool.pkg");
+    xar.file(
+        "PackageInfo",
+        PACKAGE_INFO.as_bytes(),
+        "application/x-gzip",
+        &zlib(PACKAGE_INFO.as_bytes()),
+    );
+    xar.file("Payload", &payload, "application/octet-stream", &payload);
+    xar.close_dir();
+    xar.finish()
+}
+
+impl Sandbox {
+    fn dest(&self) -> PathBuf {
+        self.path().join("expanded")
+    }
+
+    fn expand(&self, members: &[Member], policy: &ExtractPolicy) -> Result<(), SpsError> {
+        let pkg = self.write("Tool.pkg", &flat_pkg(members));
+        expand_pkg(&pkg, &self.dest(), policy)
+    }
+}
+
+#[test]
+fn inspect_lists_components_and_payload_files() {
+    let sandbox = Sandbox::new();
+    let pkg = sandbox.write("Tool.pkg", &flat_pkg(APP));
+    let contents = inspect_pkg(&pkg).unwrap();
+    assert_eq!(contents.title.as_deref(), Some("Tool"));
+    let [component] = &contents.components[..] else {
+        panic!("expected one component, got {:?}", contents.components);
+    };
+    assert_eq!(component.identifier, "com.example.tool");
+    assert_eq!(component.version.as_deref(), Some("1.0"));
+    assert_eq!(component.install_location, Path::new("/Applications"));
+    assert!(!component.has_scripts);
+    assert_eq!(
+        component.installed_size(),
+        (TOOL.len() + INFO_PLIST.len()) as u64
+    );
+
+    let file = |path: &str| {
+        component
+            .files
+            .iter()
+            .find(|file| file.path == Path::new(path))
+            .unwrap_or_else(|| panic!("{path} not listed"))
+    };
+    let tool = file("/Applications/Tool.app/Contents/MacOS/tool");
+    assert_eq!((tool.kind, tool.mode), (PkgFileKind::File, 0o755));
+    let link = file("/Applications/Tool.app/Contents/Resources/tool");
+    assert_eq!(link.kind, PkgFileKind::Symlink);
+    assert_eq!(link.link_target.as_deref(), Some("../MacOS/tool"));
+    assert_eq!(file("/Applications/Tool.app").kind, PkgFileKind::Directory);
+}
+
+#[test]
+fn expand_unpacks_metadata_and_payload() {
+    let sandbox = Sandbox::new();
+    sandbox.expand(APP, &ExtractPolicy::default()).unwrap();
+    let dest = sandbox.dest();
+    assert_eq!(
+        fs::read_to_string(dest.join("Distribution")).unwrap(),
+        DISTRIBUTION
+    );
+    assert_eq!(
+        fs::read_to_string(dest.join("Tool.pkg/PackageInfo")).unwrap(),
+        PACKAGE_INFO
+    );
+    let contents = dest.join("Tool.pkg/Payload/Tool.app/Contents");
+    let tool = contents.join("MacOS/tool");
+    assert_eq!(fs::read(&tool).unwrap(), TOOL);
+    assert_eq!(
+        fs::metadata(&tool).unwrap().permissions().mode() & 0o7777,
+        0o755
+    );
+    assert_eq!(
+        fs::read_link(contents.join("Resources/tool")).unwrap(),
+        Path::new("../MacOS/tool")
+    );
+    assert_eq!(fs::read(contents.join("Resources/tool")).unwrap(), TOOL);
+}
+
+#[test]
+fn expand_rejects_unsafe_payloads() {
+    let cases: [(&str, &[Member]); 5] = [
+        ("absolute symlink", &[("./evil", 0o120777, b"/etc/passwd")]),
+        (
+            "symlink climbing out",
+            &[("./evil", 0o120777, b"../../../../outside")],
+        ),
+        ("'..' entry", &[("../outside", 0o100644, b"escaped")]),
+        ("setuid file", &[("./tool", 0o104755, TOOL)]),
+        ("device node", &[("./disk", 0o060644, b"")]),
+    ];
+    for (what, members) in cases {
+        let sandbox = Sandbox::new();
+        match sandbox.expand(members, &ExtractPolicy::default()) {
+            Err(SpsError::UnsafeArchive(_)) => {}
+            other => panic!("{what}: expected UnsafeArchive, got {other:?}"),
+        }
+        assert!(!sandbox.dest().join("Tool.pkg/outside").exists(), "{what}");
+    }
+}
+
+#[test]
+fn expand_enforces_size_and_entry_limits() {
+    let small = ExtractPolicy {
+        max_total_size: 4096,
+        ..Default::default()
+    };
+    let big: &'static [u8] = &[b'x'; 8192];
+    match Sandbox::new().expand(&[("./big", 0o100644, big)], &small) {
+        Err(SpsError::UnsafeArchive(_)) => {}
+        other => panic!("expected UnsafeArchive, got {other:?}"),
+    }
+
+    let few = ExtractPolicy {
+        max_entries: 4,
+        ..Default::default()
+    };
+    match Sandbox::new().expand(APP, &few) {
+        Err(SpsError::UnsafeArchive(_)) => {}
+        other => panic!("expected UnsafeArchive, got {other:?}"),
+    }
+}
+
+fn cask_with_pkg(name: &str) -> Cask {
+    serde_json::from_value(serde_json::json!({
+        "token": "tool",
+        "artifacts": [{"pkg": [name]}],
+    }))
+    .unwrap()
+}
+
+#[test]
+fn cask_pkg_names_stay_inside_the_staged_download() {
+    let sandbox = Sandbox::new();
+    let pkg = flat_pkg(APP);
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(pkg.len() as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "Tool.pkg", pkg.as_slice())
+        .unwrap();
+    let download = sandbox.write("tool-1.0.tar", &builder.into_inner().unwrap());
+
+    let pkgs = inspect_cask_pkgs(&cask_with_pkg("Tool.pkg"), &download).unwrap();
+    assert_eq!(pkgs.len(), 1);
+    assert_eq!(pkgs[0].0, "Tool.pkg");
+    assert_eq!(pkgs[0].1.components[0].identifier, "com.example.tool");
+
+    for name in [
+        "../Tool.pkg",
+        "/etc/Tool.pkg",
+        "Contents/../../Tool.pkg",
+        "",
+    ] {
+        match inspect_cask_pkgs(&cask_with_pkg(name), &download) {
+            Err(SpsError::Generic(msg)) if msg.contains("outside its staged download") => {}
+            other => panic!("{name:?}: expected a rejection, got {other:?}"),
+        }
+    }
+}
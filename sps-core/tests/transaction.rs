@@ -0,0 +1,188 @@
+//! Install transactions in a throwaway prefix: a job that fails after any of its steps, or a
+//! process that dies and leaves its journal behind, must put the Cellar and the prefix links
+//! back the way they were.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+use sps_common::error::SpsError;
+use sps_core::install::bottle::link::{
+    relink_keg, write_install_manifest, FormulaInstallManifest, LinkOptions,
+};
+use sps_core::pipeline::transaction::{
+    recover_unfinished, unfinished_transactions, InstallTransaction,
+};
+
+use crate::common::Prefix;
+
+/// The steps of an upgrade from 1.0 to 2.0, in the order the bottle installer takes them.
+const STEPS: usize = 4;
+
+impl Prefix {
+    /// A prefix with `foo` 1.0 installed and linked.
+    fn with_foo_linked() -> Self {
+        let prefix = Prefix::new();
+        let keg = prefix.keg("1.0");
+        fs::create_dir_all(keg.join("lib")).unwrap();
+        fs::write(keg.join("lib/libfoo.so"), "1.0").unwrap();
+        write_install_manifest(&keg, &FormulaInstallManifest::default()).unwrap();
+        prefix.link(&keg);
+        prefix
+    }
+
+    fn keg(&self, version: &str) -> PathBuf {
+        self.config.formula_keg_path("foo", version)
+    }
+
+    fn link(&self, keg: &Path) {
+        relink_keg("foo", keg, &self.config, None, &LinkOptions::default()).unwrap();
+    }
+
+    /// Runs the first `steps` steps of upgrading to 2.0 and returns the unfinished job.
+    fn upgrade(&self, steps: usize) -> InstallTransaction {
+        let old = self.keg("1.0");
+        let new = self.keg("2.0");
+        let mut transaction = InstallTransaction::begin(&self.config, "foo");
+        if steps > 0 {
+            transaction.set_aside("foo", &old).unwrap();
+        }
+        if steps > 1 {
+            let staging = transaction.stage(&new).unwrap();
+            fs::create_dir_all(staging.join("lib")).unwrap();
+            fs::write(staging.join("lib/libfoo.so"), "2.0").unwrap();
+            write_install_manifest(&staging, &FormulaInstallManifest::default()).unwrap();
+            if steps > 2 {
+                transaction.commit_staged(&staging, &new).unwrap();
+            }
+        }
+        if steps > 3 {
+            transaction.link(&new).unwrap();
+            self.link(&new);
+        }
+        transaction
+    }
+
+    /// Everything under the formula's Cellar directory, dot-directories included.
+    fn cellar_entries(&self) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(self.config.formula_cellar_dir("foo"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn journals(&self) -> usize {
+        fs::read_dir(self.config.state_dir().join("transactions"))
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    }
+
+    /// 1.0 is back in place and linked, and nothing of 2.0 or the job is left.
+    fn assert_restored(&self, what: &str) {
+        assert_eq!(self.cellar_entries(), ["1.0"], "{what}");
+        let lib = self.config.sps_root().join("lib/libfoo.so");
+        assert_eq!(
+            fs::read_link(&lib).unwrap(),
+            self.keg("1.0").join("lib/libfoo.so"),
+            "{what}"
+        );
+        assert_eq!(fs::read_to_string(&lib).unwrap(), "1.0", "{what}");
+        assert_eq!(
+            fs::read_link(self.config.formula_opt_path("foo")).unwrap(),
+            self.keg("1.0"),
+            "{what}"
+        );
+        assert_eq!(self.journals(), 0, "{what}");
+    }
+}
+
+#[test]
+fn finished_upgrade_removes_the_old_keg_and_journal() {
+    let prefix = Prefix::with_foo_linked();
+    prefix.upgrade(STEPS).finish();
+
+    assert_eq!(prefix.cellar_entries(), ["2.0"]);
+    let lib = prefix.config.sps_root().join("lib/libfoo.so");
+    assert_eq!(fs::read_to_string(lib).unwrap(), "2.0");
+    assert_eq!(prefix.journals(), 0);
+}
+
+#[test]
+fn rollback_after_each_step_restores_the_cellar() {
+    for steps in 0..=STEPS {
+        let prefix = Prefix::with_foo_linked();
+        prefix.upgrade(steps).rollback();
+        prefix.assert_restored(&format!("rollback after {steps} step(s)"));
+    }
+}
+
+#[test]
+fn journal_left_by_a_dead_process_is_recovered() {
+    for steps in 1..=STEPS {
+        let prefix = Prefix::with_foo_linked();
+        // The process dies: the transaction is neither finished nor rolled back.
+        drop(prefix.upgrade(steps));
+        assert_eq!(unfinished_transactions(&prefix.config), ["foo"]);
+
+        assert_eq!(recover_unfinished(&prefix.config), ["foo"]);
+        prefix.assert_restored(&format!("recovery after {steps} step(s)"));
+        assert!(unfinished_transactions(&prefix.config).is_empty());
+    }
+}
+
+#[test]
+fn recovery_copes_with_steps_that_never_happened() {
+    let prefix = Prefix::with_foo_linked();
+    // Steps are journaled before they are carried out; here the process died before any of
+    // them touched the Cellar.
+    let cellar = prefix.config.formula_cellar_dir("foo");
+    let journal = json!({
+        "target": "foo",
+        "pid": 1,
+        "steps": [
+            { "step": "set_aside", "name": "foo", "original": cellar.join("1.0"), "backup": cellar.join(".1.0.sps-backup") },
+            { "step": "staged", "path": cellar.join(".2.0.sps-staging") },
+            { "step": "keg_created", "path": cellar.join("2.0") },
+            { "step": "linked", "path": cellar.join("2.0") },
+        ],
+    });
+    let journals = prefix.config.state_dir().join("transactions");
+    fs::create_dir_all(&journals).unwrap();
+    fs::write(journals.join("foo.json"), journal.to_string()).unwrap();
+
+    assert_eq!(recover_unfinished(&prefix.config), ["foo"]);
+    prefix.assert_restored("recovery of unperformed steps");
+}
+
+#[test]
+fn unreadable_journal_is_left_alone() {
+    let prefix = Prefix::with_foo_linked();
+    let journals = prefix.config.state_dir().join("transactions");
+    fs::create_dir_all(&journals).unwrap();
+    fs::write(journals.join("foo.json"), "{ not json").unwrap();
+
+    assert!(recover_unfinished(&prefix.config).is_empty());
+    assert_eq!(prefix.journals(), 1);
+    assert_eq!(prefix.cellar_entries(), ["1.0"]);
+}
+
+#[test]
+fn committing_onto_an_occupied_keg_keeps_it_on_rollback() {
+    let prefix = Prefix::with_foo_linked();
+    let keg = prefix.keg("1.0");
+    let mut transaction = InstallTransaction::begin(&prefix.config, "foo");
+    let staging = transaction.stage(&keg).unwrap();
+    fs::write(staging.join("new"), "").unwrap();
+
+    match transaction.commit_staged(&staging, &keg) {
+        Err(SpsError::InstallError(_)) => {}
+        other => panic!("expected an install error, got {other:?}"),
+    }
+    transaction.rollback();
+    prefix.assert_restored("rollback of a refused commit");
+}
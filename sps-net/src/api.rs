@@ -1,15 +1,22 @@
 use std::sync::Arc;
 
-use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
-use reqwest::Client;
+use reqwest::header::{
+    ACCEPT, AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT,
+};
+use reqwest::{Client, StatusCode};
 use serde_json::Value;
+use sps_common::cache::{Cache, CacheValidators};
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
+use sps_common::index::{IndexKind, MetadataIndex};
 use sps_common::model::cask::{Cask, CaskList};
 use sps_common::model::formula::Formula;
 use tracing::{debug, error};
 
-const FORMULAE_API_BASE_URL: &str = "https://formulae.brew.sh/api";
+use crate::jws;
+use crate::offline::ensure_online;
+use crate::validation::{validate_url_with_policy, UrlPolicy};
+
 const GITHUB_API_BASE_URL: &str = "https://api.github.com";
 const USER_AGENT_STRING: &str = "sps Package Manager (Rust; +https://github.com/your/sp)";
 
@@ -33,8 +40,17 @@ fn build_api_client(config: &Config) -> Result<Client> {
     Ok(Client::builder().default_headers(headers).build()?)
 }
 
-pub async fn fetch_raw_formulae_json(endpoint: &str) -> Result<String> {
-    let url = format!("{FORMULAE_API_BASE_URL}/{endpoint}");
+/// `<api_base_url>/<endpoint>`, checked against the URL policy so that a local stand-in
+/// server is only accepted when `allow_insecure_localhost` is set.
+fn api_url(config: &Config, endpoint: &str) -> Result<String> {
+    let url = format!("{}/{endpoint}", config.api_base_url.trim_end_matches('/'));
+    validate_url_with_policy(&url, &UrlPolicy::from_config(config))?;
+    Ok(url)
+}
+
+pub async fn fetch_raw_formulae_json(endpoint: &str, config: &Config) -> Result<String> {
+    ensure_online(config, endpoint)?;
+    let url = api_url(config, endpoint)?;
     debug!("Fetching data from Homebrew Formulae API: {}", url);
     let client = reqwest::Client::builder()
         .user_agent(USER_AGENT_STRING)
@@ -66,16 +82,157 @@ pub async fn fetch_raw_formulae_json(endpoint: &str) -> Result<String> {
     Ok(body)
 }
 
-pub async fn fetch_all_formulas() -> Result<String> {
-    fetch_raw_formulae_json("formula.json").await
+pub async fn fetch_all_formulas(config: &Config) -> Result<String> {
+    fetch_full_index("formula", config).await
+}
+
+pub async fn fetch_all_casks(config: &Config) -> Result<String> {
+    fetch_full_index("cask", config).await
+}
+
+async fn fetch_full_index(kind: &str, config: &Config) -> Result<String> {
+    match fetch_index(kind, config, None).await? {
+        IndexFetch::Modified { body, .. } => Ok(body),
+        IndexFetch::NotModified => Err(SpsError::Api(format!(
+            "Unexpected 304 response for unconditional {kind} index request"
+        ))),
+    }
+}
+
+/// Result of a possibly conditional index request.
+pub enum IndexFetch {
+    NotModified,
+    Modified {
+        body: String,
+        validators: Option<CacheValidators>,
+    },
+}
+
+/// Fetches the full `formula` or `cask` index. With `api_public_key` configured the signed
+/// `<kind>.jws.json` is fetched and only its verified payload is returned. `cached` validators
+/// of the same URL turn the request into a conditional one.
+pub async fn fetch_index(
+    kind: &str,
+    config: &Config,
+    cached: Option<&CacheValidators>,
+) -> Result<IndexFetch> {
+    let pinned_key = config
+        .api_public_key
+        .as_deref()
+        .map(jws::PinnedKey::load)
+        .transpose()?;
+    let endpoint = if pinned_key.is_some() {
+        format!("{kind}.jws.json")
+    } else {
+        format!("{kind}.json")
+    };
+    ensure_online(config, &format!("{kind}.json"))?;
+    let url = api_url(config, &endpoint)?;
+    debug!("Fetching {} index from {}", kind, url);
+
+    let client = reqwest::Client::builder()
+        .user_agent(USER_AGENT_STRING)
+        .build()?;
+    let mut request = client.get(&url);
+    if let Some(cached) = cached.filter(|c| c.url == url) {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = request.send().await.map_err(|e| {
+        debug!("HTTP request failed for {}: {}", url, e);
+        SpsError::Http(Arc::new(e))
+    })?;
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        debug!("{} is unchanged (304)", url);
+        return Ok(IndexFetch::NotModified);
+    }
+    if !status.is_success() {
+        return Err(SpsError::Api(format!("HTTP status {status} from {url}")));
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let body = response.text().await?;
+    if body.trim().is_empty() {
+        return Err(SpsError::Api(format!(
+            "Empty response body received from {url}"
+        )));
+    }
+    let body = match &pinned_key {
+        Some(key) => jws::verify_payload(&body, key).map_err(|e| match e {
+            SpsError::SignatureError(msg) => SpsError::SignatureError(format!("{url}: {msg}")),
+            other => other,
+        })?,
+        None => body,
+    };
+    let validators = (etag.is_some() || last_modified.is_some()).then(|| CacheValidators {
+        url: url.clone(),
+        etag,
+        last_modified,
+    });
+    Ok(IndexFetch::Modified { body, validators })
+}
+
+/// Refreshes `<kind>.json` in the cache with a conditional request. Returns `false` if the
+/// server reported it unchanged. The cached file is only replaced after the new one has been
+/// fetched completely and, if signed, verified.
+pub async fn refresh_cached_index(kind: &str, cache: &Cache) -> Result<bool> {
+    let filename = format!("{kind}.json");
+    let cached = cache.load_validators(&filename);
+    match fetch_index(kind, cache.config(), cached.as_ref()).await? {
+        IndexFetch::NotModified => {
+            cache.touch(&filename)?;
+            Ok(false)
+        }
+        IndexFetch::Modified { body, validators } => {
+            cache.store_raw(&filename, &body)?;
+            cache.store_validators(&filename, validators.as_ref())?;
+            Ok(true)
+        }
+    }
 }
 
-pub async fn fetch_all_casks() -> Result<String> {
-    fetch_raw_formulae_json("cask.json").await
+/// Opens the metadata index of `kind`, first fetching and caching the JSON it is built from if
+/// it isn't cached yet.
+pub async fn load_or_fetch_index(kind: IndexKind, cache: &Cache) -> Result<MetadataIndex> {
+    if let Some(index) = MetadataIndex::open(cache, kind)? {
+        return Ok(index);
+    }
+    debug!("{} is not cached, fetching from API", kind.source_file());
+    let data = match kind {
+        IndexKind::Formula => fetch_all_formulas(cache.config()).await?,
+        IndexKind::Cask => fetch_all_casks(cache.config()).await?,
+    };
+    cache.store_raw(kind.source_file(), &data)?;
+    MetadataIndex::open(cache, kind)?.ok_or_else(|| {
+        SpsError::Cache(format!(
+            "{} is missing after fetching it",
+            kind.source_file()
+        ))
+    })
 }
 
-pub async fn fetch_formula(name: &str) -> Result<serde_json::Value> {
-    let direct_fetch_result = fetch_raw_formulae_json(&format!("formula/{name}.json")).await;
+pub async fn fetch_formula(name: &str, config: &Config) -> Result<serde_json::Value> {
+    ensure_online(config, &format!("Formula '{name}'"))?;
+    // The per-package endpoints aren't signed, so with a pinned key only the verified index is
+    // trusted.
+    if config.api_public_key.is_some() {
+        debug!("Looking up formula '{}' in the signed index.", name);
+        return find_formula_in_index(name, config).await;
+    }
+    let direct_fetch_result =
+        fetch_raw_formulae_json(&format!("formula/{name}.json"), config).await;
     if let Ok(body) = direct_fetch_result {
         let formula: serde_json::Value = serde_json::from_str(&body)?;
         Ok(formula)
@@ -85,24 +242,33 @@ pub async fn fetch_formula(name: &str) -> Result<serde_json::Value> {
             name,
             direct_fetch_result.err()
         );
-        let all_formulas_body = fetch_all_formulas().await?;
-        let formulas: Vec<serde_json::Value> = serde_json::from_str(&all_formulas_body)?;
-        for formula in formulas {
-            if formula.get("name").and_then(Value::as_str) == Some(name) {
-                return Ok(formula);
-            }
-            if formula.get("full_name").and_then(Value::as_str) == Some(name) {
-                return Ok(formula);
-            }
+        find_formula_in_index(name, config).await
+    }
+}
+
+async fn find_formula_in_index(name: &str, config: &Config) -> Result<serde_json::Value> {
+    let all_formulas_body = fetch_all_formulas(config).await?;
+    let formulas: Vec<serde_json::Value> = serde_json::from_str(&all_formulas_body)?;
+    for formula in formulas {
+        if formula.get("name").and_then(Value::as_str) == Some(name) {
+            return Ok(formula);
+        }
+        if formula.get("full_name").and_then(Value::as_str) == Some(name) {
+            return Ok(formula);
         }
-        Err(SpsError::NotFound(format!(
-            "Formula '{name}' not found in API list"
-        )))
     }
+    Err(SpsError::NotFound(format!(
+        "Formula '{name}' not found in API list"
+    )))
 }
 
-pub async fn fetch_cask(token: &str) -> Result<serde_json::Value> {
-    let direct_fetch_result = fetch_raw_formulae_json(&format!("cask/{token}.json")).await;
+pub async fn fetch_cask(token: &str, config: &Config) -> Result<serde_json::Value> {
+    ensure_online(config, &format!("Cask '{token}'"))?;
+    if config.api_public_key.is_some() {
+        debug!("Looking up cask '{}' in the signed index.", token);
+        return find_cask_in_index(token, config).await;
+    }
+    let direct_fetch_result = fetch_raw_formulae_json(&format!("cask/{token}.json"), config).await;
     if let Ok(body) = direct_fetch_result {
         let cask: serde_json::Value = serde_json::from_str(&body)?;
         Ok(cask)
@@ -112,20 +278,25 @@ pub async fn fetch_cask(token: &str) -> Result<serde_json::Value> {
             token,
             direct_fetch_result.err()
         );
-        let all_casks_body = fetch_all_casks().await?;
-        let casks: Vec<serde_json::Value> = serde_json::from_str(&all_casks_body)?;
-        for cask in casks {
-            if cask.get("token").and_then(Value::as_str) == Some(token) {
-                return Ok(cask);
-            }
+        find_cask_in_index(token, config).await
+    }
+}
+
+async fn find_cask_in_index(token: &str, config: &Config) -> Result<serde_json::Value> {
+    let all_casks_body = fetch_all_casks(config).await?;
+    let casks: Vec<serde_json::Value> = serde_json::from_str(&all_casks_body)?;
+    for cask in casks {
+        if cask.get("token").and_then(Value::as_str) == Some(token) {
+            return Ok(cask);
         }
-        Err(SpsError::NotFound(format!(
-            "Cask '{token}' not found in API list"
-        )))
     }
+    Err(SpsError::NotFound(format!(
+        "Cask '{token}' not found in API list"
+    )))
 }
 
 async fn fetch_github_api_json(endpoint: &str, config: &Config) -> Result<Value> {
+    ensure_online(config, endpoint)?;
     let url = format!("{GITHUB_API_BASE_URL}{endpoint}");
     debug!("Fetching data from GitHub API: {}", url);
     let client = build_api_client(config)?;
@@ -162,8 +333,16 @@ async fn fetch_github_repo_info(owner: &str, repo: &str, config: &Config) -> Res
     fetch_github_api_json(&endpoint, config).await
 }
 
-pub async fn get_formula(name: &str) -> Result<Formula> {
-    let url = format!("{FORMULAE_API_BASE_URL}/formula/{name}.json");
+pub async fn get_formula(name: &str, config: &Config) -> Result<Formula> {
+    ensure_online(config, &format!("Formula '{name}'"))?;
+    if config.api_public_key.is_some() {
+        let raw_json = find_formula_in_index(name, config).await?;
+        return serde_json::from_value::<Formula>(raw_json).map_err(|e| {
+            error!("Failed to parse formula {} JSON: {}", name, e);
+            SpsError::Json(Arc::new(e))
+        });
+    }
+    let url = api_url(config, &format!("formula/{name}.json"))?;
     debug!(
         "Fetching and parsing formula data for '{}' from {}",
         name, url
@@ -217,16 +396,16 @@ pub async fn get_formula(name: &str) -> Result<Formula> {
     }
 }
 
-pub async fn get_all_formulas() -> Result<Vec<Formula>> {
-    let raw_data = fetch_all_formulas().await?;
+pub async fn get_all_formulas(config: &Config) -> Result<Vec<Formula>> {
+    let raw_data = fetch_all_formulas(config).await?;
     serde_json::from_str(&raw_data).map_err(|e| {
         error!("Failed to parse all_formulas response: {}", e);
         SpsError::Json(Arc::new(e))
     })
 }
 
-pub async fn get_cask(name: &str) -> Result<Cask> {
-    let raw_json_result = fetch_cask(name).await;
+pub async fn get_cask(name: &str, config: &Config) -> Result<Cask> {
+    let raw_json_result = fetch_cask(name, config).await;
     let raw_json = match raw_json_result {
         Ok(json_val) => json_val,
         Err(e) => {
@@ -256,8 +435,8 @@ pub async fn get_cask(name: &str) -> Result<Cask> {
     }
 }
 
-pub async fn get_all_casks() -> Result<CaskList> {
-    let raw_data = fetch_all_casks().await?;
+pub async fn get_all_casks(config: &Config) -> Result<CaskList> {
+    let raw_data = fetch_all_casks(config).await?;
     let casks: Vec<Cask> = serde_json::from_str(&raw_data).map_err(|e| {
         error!("Failed to parse all_casks response: {}", e);
         SpsError::Json(Arc::new(e))
@@ -1,24 +1,30 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use futures::StreamExt;
-use reqwest::header::{HeaderMap, ACCEPT, USER_AGENT};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use reqwest::header::{
+    HeaderMap, HeaderName, ACCEPT, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+    RETRY_AFTER, USER_AGENT,
+};
 use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
 use sps_common::model::formula::ResourceSpec;
-use tokio::fs::File as TokioFile;
+use tokio::fs::{File as TokioFile, OpenOptions};
 use tokio::io::AsyncWriteExt;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
-use crate::validation::{validate_url, verify_checksum};
+use crate::offline;
+use crate::rewrite::resolve_download_url;
+use crate::validation::verify_checksum;
 
 pub type ProgressCallback = Arc<dyn Fn(u64, Option<u64>) + Send + Sync>;
 
-const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
-const CONNECT_TIMEOUT_SECS: u64 = 30;
 const USER_AGENT_STRING: &str = "sps package manager (Rust; +https://github.com/alexykn/sp)";
 
 pub async fn fetch_formula_source_or_bottle(
@@ -104,22 +110,23 @@ pub async fn fetch_formula_source_or_bottle_with_progress(
         ))
     })?;
     // Validate primary URL
-    validate_url(url)?;
+    resolve_download_url(config, url)?;
 
-    let client = build_http_client()?;
+    let client = build_http_client(config)?;
 
     let urls_to_try = std::iter::once(url).chain(mirrors.iter().map(|s| s.as_str()));
     let mut last_error: Option<SpsError> = None;
 
     for current_url in urls_to_try {
         // Validate mirror URL
-        validate_url(current_url)?;
+        let current_url = resolve_download_url(config, current_url)?;
         tracing::debug!("Attempting download from: {}", current_url);
         match download_and_verify(
             &client,
-            current_url,
+            &current_url,
             &cache_path,
             sha256_expected,
+            config,
             progress_callback.clone(),
         )
         .await
@@ -167,7 +174,7 @@ pub async fn fetch_resource_with_progress(
         ))
     })?;
     // Validate resource URL
-    validate_url(&resource.url)?;
+    let resource_url = resolve_download_url(config, &resource.url)?;
 
     let url_filename = resource
         .url
@@ -213,12 +220,13 @@ pub async fn fetch_resource_with_progress(
         tracing::debug!("Resource not found in cache.");
     }
 
-    let client = build_http_client()?;
+    let client = build_http_client(config)?;
     match download_and_verify(
         &client,
-        &resource.url,
+        &resource_url,
         &cache_path,
         &resource.sha256,
+        config,
         progress_callback,
     )
     .await
@@ -231,7 +239,7 @@ pub async fn fetch_resource_with_progress(
             Ok(path)
         }
         Err(e) => {
-            error!("Resource download failed from {}: {}", resource.url, e);
+            error!("Resource download failed from {}: {}", resource_url, e);
             let _ = fs::remove_file(&cache_path);
             Err(SpsError::DownloadError(
                 resource.name.clone(),
@@ -242,139 +250,530 @@ pub async fn fetch_resource_with_progress(
     }
 }
 
-fn build_http_client() -> Result<Client> {
+/// Builds the client used for bottle, source and cask downloads. Timeouts come from the
+/// config; the read timeout applies between chunks, not to the whole transfer.
+pub fn build_http_client(config: &Config) -> Result<Client> {
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, USER_AGENT_STRING.parse().unwrap());
     headers.insert(ACCEPT, "*/*".parse().unwrap());
     Client::builder()
-        .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT_SECS))
-        .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+        .read_timeout(Duration::from_secs(config.download_timeout_secs))
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
         .default_headers(headers)
         .redirect(reqwest::redirect::Policy::limited(10))
         .build()
         .map_err(|e| SpsError::HttpError(format!("Failed to build HTTP client: {e}")))
 }
 
+/// Downloads `url` to `final_path` without checksum verification (the caller verifies).
+/// Retries and resumes like every other download.
+pub async fn download_file_with_progress(
+    url: &str,
+    final_path: &Path,
+    config: &Config,
+    progress_callback: Option<ProgressCallback>,
+) -> Result<PathBuf> {
+    let url = resolve_download_url(config, url)?;
+    if let Some(parent) = final_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let client = build_http_client(config)?;
+    download_and_verify(&client, &url, final_path, "", config, progress_callback).await
+}
+
 async fn download_and_verify(
     client: &Client,
     url: &str,
     final_path: &Path,
     sha256_expected: &str,
+    config: &Config,
     progress_callback: Option<ProgressCallback>,
 ) -> Result<PathBuf> {
-    let temp_filename = format!(
+    let request = DownloadRequest {
+        client,
+        url,
+        headers: HeaderMap::new(),
+        content_addressed: false,
+        config,
+        progress_callback,
+    };
+    download_verified(&request, final_path, sha256_expected).await?;
+    Ok(final_path.to_path_buf())
+}
+
+/// Parameters shared by every attempt of one download.
+pub(crate) struct DownloadRequest<'a> {
+    pub client: &'a Client,
+    pub url: &'a str,
+    /// Extra request headers, e.g. registry authorization.
+    pub headers: HeaderMap,
+    /// The URL names the content itself (an OCI blob digest), so a partial file can be
+    /// resumed even when the server sends no `ETag`/`Last-Modified`.
+    pub content_addressed: bool,
+    pub config: &'a Config,
+    pub progress_callback: Option<ProgressCallback>,
+}
+
+/// What is known about a partial download, stored next to it as `<temp>.meta`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+enum AttemptError {
+    Retry {
+        error: SpsError,
+        retry_after: Option<Duration>,
+    },
+    Fatal(SpsError),
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+const BASE_BACKOFF_MILLIS: u64 = 500;
+
+/// Downloads into `.<name>.download` next to `final_path`, verifies the checksum and moves the
+/// file into place. A partial file left by an earlier attempt or run is resumed; if a resumed
+/// file fails verification it is downloaded once more from scratch.
+pub(crate) async fn download_verified(
+    request: &DownloadRequest<'_>,
+    final_path: &Path,
+    sha256_expected: &str,
+) -> Result<()> {
+    let temp_path = final_path.with_file_name(format!(
         ".{}.download",
         final_path.file_name().unwrap_or_default().to_string_lossy()
-    );
-    let temp_path = final_path.with_file_name(temp_filename);
+    ));
+    if let Some(source) = local_source(request, final_path)? {
+        return copy_local(request, &source, &temp_path, final_path, sha256_expected);
+    }
     tracing::debug!("Downloading to temporary path: {}", temp_path.display());
-    if temp_path.exists() {
-        if let Err(e) = fs::remove_file(&temp_path) {
-            tracing::warn!(
-                "Could not remove existing temporary file {}: {}",
-                temp_path.display(),
-                e
+
+    let resumed = download_with_retries(request, &temp_path).await?;
+    if !sha256_expected.is_empty() {
+        if let Err(e) = verify_checksum(&temp_path, sha256_expected) {
+            discard_partial(&temp_path);
+            if !resumed {
+                return Err(e);
+            }
+            warn!(
+                "Resumed download of {} failed verification ({}). Downloading it again from the start.",
+                request.url, e
             );
+            download_with_retries(request, &temp_path).await?;
+            verify_checksum(&temp_path, sha256_expected).inspect_err(|_| {
+                discard_partial(&temp_path);
+            })?;
+        }
+        tracing::debug!(
+            "Checksum verified for temporary file: {}",
+            temp_path.display()
+        );
+    } else {
+        tracing::warn!(
+            "Skipping checksum verification for {} - none provided.",
+            temp_path.display()
+        );
+    }
+
+    fs::rename(&temp_path, final_path).map_err(|e| {
+        SpsError::IoError(format!(
+            "Failed to move temp file {} to {}: {}",
+            temp_path.display(),
+            final_path.display(),
+            e
+        ))
+    })?;
+    let _ = fs::remove_file(meta_path(&temp_path));
+    tracing::debug!(
+        "Moved verified file to final location: {}",
+        final_path.display()
+    );
+    Ok(())
+}
+
+/// The local file to copy instead of downloading: the target of a `file://` URL (a rewrite
+/// to a local mirror), or the offline mirror's copy of the URL.
+fn local_source(request: &DownloadRequest<'_>, final_path: &Path) -> Result<Option<PathBuf>> {
+    if request.url.starts_with("file:") {
+        let path = url::Url::parse(request.url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| {
+                SpsError::ValidationError(format!("Invalid file URL: {}", request.url))
+            })?;
+        return Ok(Some(path));
+    }
+    if request.config.offline {
+        let file_name = final_path.file_name().unwrap_or_default().to_string_lossy();
+        return offline::find_in_mirror(request.config, request.url, &file_name).map(Some);
+    }
+    Ok(None)
+}
+
+/// Copies a local file into place instead of downloading it.
+fn copy_local(
+    request: &DownloadRequest<'_>,
+    source: &Path,
+    temp_path: &Path,
+    final_path: &Path,
+    sha256_expected: &str,
+) -> Result<()> {
+    debug!("Copying {} from {}", request.url, source.display());
+    let copied = fs::copy(source, temp_path).map_err(|e| {
+        SpsError::IoError(format!(
+            "Failed to copy {} from the mirror: {}",
+            source.display(),
+            e
+        ))
+    })?;
+    if let Some(callback) = &request.progress_callback {
+        callback(copied, Some(copied));
+    }
+    if !sha256_expected.is_empty() {
+        verify_checksum(temp_path, sha256_expected).inspect_err(|_| {
+            discard_partial(temp_path);
+        })?;
+    }
+    fs::rename(temp_path, final_path)?;
+    Ok(())
+}
+
+/// Runs download attempts until one completes, backing off exponentially (with jitter, or as
+/// long as `Retry-After` asks) after connection errors, timeouts, 408, 429 and 5xx responses.
+/// Returns whether any attempt resumed a partial file.
+async fn download_with_retries(request: &DownloadRequest<'_>, temp_path: &Path) -> Result<bool> {
+    let max_retries = request.config.download_retries;
+    let mut rng = SmallRng::from_os_rng();
+    let mut resumed = false;
+    let mut attempt = 0;
+    loop {
+        match download_attempt(request, temp_path, &mut resumed).await {
+            Ok(()) => return Ok(resumed),
+            Err(AttemptError::Fatal(e)) => return Err(e),
+            Err(AttemptError::Retry { error, retry_after }) => {
+                if attempt >= max_retries {
+                    error!(
+                        "Giving up on {} after {} attempts: {}",
+                        request.url,
+                        attempt + 1,
+                        error
+                    );
+                    return Err(error);
+                }
+                let delay = retry_delay(attempt, retry_after, &mut rng);
+                attempt += 1;
+                warn!(
+                    "Download from {} failed ({}). Retrying in {:.1}s (attempt {}/{}).",
+                    request.url,
+                    error,
+                    delay.as_secs_f64(),
+                    attempt + 1,
+                    max_retries + 1
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// How long to wait before retry number `attempt + 1`: what `Retry-After` asked for, up to
+/// `MAX_RETRY_AFTER`, or an exponential backoff with up to 50% jitter.
+fn retry_delay(attempt: u32, retry_after: Option<Duration>, rng: &mut impl Rng) -> Duration {
+    match retry_after {
+        Some(wait) => wait.min(MAX_RETRY_AFTER),
+        None => {
+            let backoff =
+                Duration::from_millis(BASE_BACKOFF_MILLIS.saturating_mul(1 << attempt.min(16)))
+                    .min(MAX_BACKOFF);
+            let jitter = rng.random_range(0..=backoff.as_millis() as u64 / 2);
+            backoff + Duration::from_millis(jitter)
         }
     }
+}
 
-    let response = client.get(url).send().await.map_err(|e| {
+async fn download_attempt(
+    request: &DownloadRequest<'_>,
+    temp_path: &Path,
+    resumed: &mut bool,
+) -> std::result::Result<(), AttemptError> {
+    let url = request.url;
+    let meta_path = meta_path(temp_path);
+    let existing_len = fs::metadata(temp_path).map(|m| m.len()).unwrap_or(0);
+    let saved = read_partial_meta(&meta_path).filter(|meta| meta.url == url);
+    let validator = saved
+        .as_ref()
+        .and_then(|meta| meta.etag.clone().or_else(|| meta.last_modified.clone()));
+    let can_resume =
+        existing_len > 0 && (validator.is_some() || (request.content_addressed && saved.is_some()));
+
+    let mut req = request.client.get(url).headers(request.headers.clone());
+    if can_resume {
+        debug!(
+            "Resuming {} at byte {} (validator: {:?})",
+            url, existing_len, validator
+        );
+        req = req.header(RANGE, format!("bytes={existing_len}-"));
+        if let Some(validator) = &validator {
+            req = req.header(IF_RANGE, validator.as_str());
+        }
+    } else if existing_len > 0 {
+        debug!(
+            "Discarding partial download {} (no way to validate it)",
+            temp_path.display()
+        );
+    }
+
+    let response = req.send().await.map_err(|e| {
         debug!("HTTP request failed for {url}: {e}");
-        SpsError::HttpError(format!("HTTP request failed for {url}: {e}"))
+        classify_reqwest_error(url, e)
     })?;
     let status = response.status();
     tracing::debug!("Received HTTP status: {} for {}", status, url);
 
-    if !status.is_success() {
-        let body_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Failed to read response body".to_string());
-        tracing::error!("HTTP error {} for URL {}: {}", status, url, body_text);
-        return match status {
-            StatusCode::NOT_FOUND => Err(SpsError::DownloadError(
-                final_path
-                    .file_name()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_default(),
-                url.to_string(),
-                "Resource not found (404)".to_string(),
-            )),
-            StatusCode::FORBIDDEN => Err(SpsError::DownloadError(
-                final_path
-                    .file_name()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_default(),
-                url.to_string(),
-                "Access forbidden (403)".to_string(),
-            )),
-            _ => Err(SpsError::HttpError(format!(
-                "HTTP error {status} for URL {url}: {body_text}"
-            ))),
+    let offset = match status {
+        StatusCode::PARTIAL_CONTENT if can_resume => {
+            match content_range_start(response.headers()) {
+                Some(start) if start == existing_len => {
+                    *resumed = true;
+                    existing_len
+                }
+                other => {
+                    discard_partial(temp_path);
+                    return Err(AttemptError::Retry {
+                        error: SpsError::HttpError(format!(
+                            "Unexpected Content-Range start {other:?} for {url}, expected {existing_len}"
+                        )),
+                        retry_after: Some(Duration::ZERO),
+                    });
+                }
+            }
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE if can_resume => {
+            if content_range_total(response.headers()) == Some(existing_len) {
+                debug!(
+                    "Partial download {} is already complete",
+                    temp_path.display()
+                );
+                return Ok(());
+            }
+            discard_partial(temp_path);
+            return Err(AttemptError::Retry {
+                error: SpsError::HttpError(format!("Range not satisfiable for {url}")),
+                retry_after: Some(Duration::ZERO),
+            });
+        }
+        s if s.is_success() => 0,
+        _ => return Err(status_error(url, response).await),
+    };
+
+    if offset == 0 {
+        let meta = PartialDownload {
+            url: url.to_string(),
+            etag: header_string(response.headers(), ETAG),
+            last_modified: header_string(response.headers(), LAST_MODIFIED),
         };
+        if let Ok(data) = serde_json::to_vec(&meta) {
+            let _ = fs::write(&meta_path, data);
+        }
     }
 
-    // Get total size from Content-Length header if available
-    let total_size = response.content_length();
-
-    let mut temp_file = TokioFile::create(&temp_path).await.map_err(|e| {
-        SpsError::IoError(format!(
-            "Failed to create temp file {}: {}",
+    let total_size = response.content_length().map(|len| len + offset);
+    let mut file = if offset > 0 {
+        OpenOptions::new().append(true).open(temp_path).await
+    } else {
+        TokioFile::create(temp_path).await
+    }
+    .map_err(|e| {
+        AttemptError::Fatal(SpsError::IoError(format!(
+            "Failed to open temp file {}: {}",
             temp_path.display(),
             e
-        ))
+        )))
     })?;
 
     // Use bytes_stream() for chunked download with progress reporting
     let mut stream = response.bytes_stream();
-    let mut bytes_downloaded = 0u64;
+    let mut bytes_downloaded = offset;
+    if let Some(ref callback) = request.progress_callback {
+        callback(bytes_downloaded, total_size);
+    }
 
     while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| SpsError::HttpError(format!("Failed to read chunk: {e}")))?;
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Whatever arrived so far stays on disk for the next attempt to resume from.
+                let _ = file.flush().await;
+                return Err(classify_reqwest_error(url, e));
+            }
+        };
 
-        temp_file.write_all(&chunk).await.map_err(|e| {
-            SpsError::IoError(format!(
+        file.write_all(&chunk).await.map_err(|e| {
+            AttemptError::Fatal(SpsError::IoError(format!(
                 "Failed to write chunk to {}: {}",
                 temp_path.display(),
                 e
-            ))
+            )))
         })?;
 
         bytes_downloaded += chunk.len() as u64;
 
         // Call progress callback if provided
-        if let Some(ref callback) = progress_callback {
+        if let Some(ref callback) = request.progress_callback {
             callback(bytes_downloaded, total_size);
         }
     }
-
-    drop(temp_file);
+    file.flush().await.map_err(|e| {
+        AttemptError::Fatal(SpsError::IoError(format!(
+            "Failed to flush {}: {}",
+            temp_path.display(),
+            e
+        )))
+    })?;
+    drop(file);
+
+    if let Some(total) = total_size {
+        if bytes_downloaded < total {
+            return Err(AttemptError::Retry {
+                error: SpsError::HttpError(format!(
+                    "Connection closed after {bytes_downloaded} of {total} bytes from {url}"
+                )),
+                retry_after: None,
+            });
+        }
+    }
     tracing::debug!("Finished writing download stream to temp file.");
+    Ok(())
+}
 
-    if !sha256_expected.is_empty() {
-        verify_checksum(&temp_path, sha256_expected)?;
-        tracing::debug!(
-            "Checksum verified for temporary file: {}",
-            temp_path.display()
-        );
+fn classify_reqwest_error(url: &str, e: reqwest::Error) -> AttemptError {
+    let error = SpsError::HttpError(format!("HTTP request failed for {url}: {e}"));
+    if e.is_builder() || e.is_redirect() {
+        AttemptError::Fatal(error)
     } else {
-        tracing::warn!(
-            "Skipping checksum verification for {} - none provided.",
-            temp_path.display()
+        AttemptError::Retry {
+            error,
+            retry_after: None,
+        }
+    }
+}
+
+async fn status_error(url: &str, response: reqwest::Response) -> AttemptError {
+    let status = response.status();
+    let retry_after = retry_after(response.headers());
+    let file_name = url.rsplit('/').next().unwrap_or_default().to_string();
+    let body_text = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Failed to read response body".to_string());
+    tracing::error!("HTTP error {} for URL {}: {}", status, url, body_text);
+    match status {
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => AttemptError::Retry {
+            error: SpsError::HttpError(format!("HTTP error {status} for URL {url}")),
+            retry_after,
+        },
+        s if s.is_server_error() => AttemptError::Retry {
+            error: SpsError::HttpError(format!("HTTP error {status} for URL {url}")),
+            retry_after,
+        },
+        StatusCode::NOT_FOUND => AttemptError::Fatal(SpsError::DownloadError(
+            file_name,
+            url.to_string(),
+            "Resource not found (404)".to_string(),
+        )),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            AttemptError::Fatal(SpsError::DownloadError(
+                file_name,
+                url.to_string(),
+                format!("Access forbidden ({})", status.as_u16()),
+            ))
+        }
+        _ => AttemptError::Fatal(SpsError::HttpError(format!(
+            "HTTP error {status} for URL {url}: {body_text}"
+        ))),
+    }
+}
+
+/// `Retry-After` as either delay seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = header_string(headers, RETRY_AFTER)?;
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let when = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(
+        when.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// `Content-Range: bytes <start>-<end>/<total>` -> `start`.
+fn content_range_start(headers: &HeaderMap) -> Option<u64> {
+    let value = header_string(headers, CONTENT_RANGE)?;
+    let range = value.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+/// `Content-Range: bytes */<total>` (or a full range) -> `total`.
+fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let value = header_string(headers, CONTENT_RANGE)?;
+    value.rsplit('/').next()?.trim().parse().ok()
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+fn meta_path(temp_path: &Path) -> PathBuf {
+    let mut name = temp_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".meta");
+    temp_path.with_file_name(name)
+}
+
+fn read_partial_meta(meta_path: &Path) -> Option<PartialDownload> {
+    let data = fs::read(meta_path).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn discard_partial(temp_path: &Path) {
+    let _ = fs::remove_file(temp_path);
+    let _ = fs::remove_file(meta_path(temp_path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_is_capped() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(retry_delay(0, Some(day), &mut rng), MAX_RETRY_AFTER);
+        let wait = Duration::from_secs(7);
+        assert_eq!(retry_delay(5, Some(wait), &mut rng), wait);
+        assert_eq!(
+            retry_delay(0, Some(Duration::ZERO), &mut rng),
+            Duration::ZERO
         );
     }
 
-    fs::rename(&temp_path, final_path).map_err(|e| {
-        SpsError::IoError(format!(
-            "Failed to move temp file {} to {}: {}",
-            temp_path.display(),
-            final_path.display(),
-            e
-        ))
-    })?;
-    tracing::debug!(
-        "Moved verified file to final location: {}",
-        final_path.display()
-    );
-    Ok(final_path.to_path_buf())
+    #[test]
+    fn backoff_doubles_up_to_the_cap_with_bounded_jitter() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for attempt in 0..40 {
+            let base =
+                Duration::from_millis(BASE_BACKOFF_MILLIS << attempt.min(16)).min(MAX_BACKOFF);
+            let delay = retry_delay(attempt, None, &mut rng);
+            assert!(
+                delay >= base && delay <= base + base / 2,
+                "attempt {attempt}: {delay:?}"
+            );
+        }
+    }
 }
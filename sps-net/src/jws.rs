@@ -0,0 +1,169 @@
+// sps-net/src/jws.rs
+//! Verification of Homebrew's signed API payloads (`formula.jws.json`, `cask.jws.json`).
+//!
+//! These use the JWS JSON serialization with an unencoded payload (RFC 7797, `"b64": false`):
+//!
+//! ```json
+//! { "payload": "[...]", "signatures": [{ "protected": "<b64url header>", "signature": "..." }] }
+//! ```
+//!
+//! The signing input is `<protected>.<payload>`, signed with RSASSA-PSS/SHA-512 (`PS512`).
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::signature::{UnparsedPublicKey, RSA_PSS_2048_8192_SHA512};
+use serde::Deserialize;
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+
+#[derive(Deserialize)]
+struct JwsDocument {
+    payload: String,
+    signatures: Vec<JwsSignature>,
+}
+
+#[derive(Deserialize)]
+struct JwsSignature {
+    protected: String,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct ProtectedHeader {
+    alg: String,
+    #[serde(default)]
+    b64: Option<bool>,
+}
+
+/// An RSA public key to verify payloads against.
+pub struct PinnedKey {
+    /// PKCS#1 `RSAPublicKey` DER.
+    der: Vec<u8>,
+}
+
+impl PinnedKey {
+    /// Loads a PEM `PUBLIC KEY` (SubjectPublicKeyInfo) or `RSA PUBLIC KEY` (PKCS#1) file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let pem = fs::read_to_string(path).map_err(|e| {
+            SpsError::Config(format!(
+                "Failed to read API public key {}: {e}",
+                path.display()
+            ))
+        })?;
+        Self::from_pem(&pem).map_err(|e| {
+            SpsError::Config(format!("Invalid API public key {}: {e}", path.display()))
+        })
+    }
+
+    pub fn from_pem(pem: &str) -> std::result::Result<Self, String> {
+        let is_spki = pem.contains("-----BEGIN PUBLIC KEY-----");
+        if !is_spki && !pem.contains("-----BEGIN RSA PUBLIC KEY-----") {
+            return Err("expected a PEM encoded RSA public key".to_string());
+        }
+        let body: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .map(str::trim)
+            .collect();
+        let der = STANDARD
+            .decode(body)
+            .map_err(|e| format!("bad base64: {e}"))?;
+        let der = if is_spki { spki_rsa_key(&der)? } else { der };
+        Ok(Self { der })
+    }
+}
+
+/// Verifies a signed payload document and returns the payload it carries.
+pub fn verify_payload(document: &str, key: &PinnedKey) -> Result<String> {
+    let document: JwsDocument = serde_json::from_str(document)
+        .map_err(|e| SpsError::SignatureError(format!("not a JWS document: {e}")))?;
+    let public_key = UnparsedPublicKey::new(&RSA_PSS_2048_8192_SHA512, &key.der);
+
+    let mut last_error = "no signatures".to_string();
+    for signature in &document.signatures {
+        let header: ProtectedHeader = match URL_SAFE_NO_PAD
+            .decode(&signature.protected)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        {
+            Some(header) => header,
+            None => {
+                last_error = "unreadable protected header".to_string();
+                continue;
+            }
+        };
+        if header.alg != "PS512" {
+            last_error = format!("unsupported algorithm {}", header.alg);
+            continue;
+        }
+        let Ok(signature_bytes) = URL_SAFE_NO_PAD.decode(&signature.signature) else {
+            last_error = "signature is not base64url".to_string();
+            continue;
+        };
+        let signing_input = format!("{}.{}", signature.protected, document.payload);
+        if public_key
+            .verify(signing_input.as_bytes(), &signature_bytes)
+            .is_err()
+        {
+            last_error = "signature does not match the pinned key".to_string();
+            continue;
+        }
+        debug!("API payload signature verified");
+        return if header.b64 == Some(false) {
+            Ok(document.payload)
+        } else {
+            let bytes = URL_SAFE_NO_PAD
+                .decode(&document.payload)
+                .map_err(|e| SpsError::SignatureError(format!("bad payload encoding: {e}")))?;
+            String::from_utf8(bytes)
+                .map_err(|e| SpsError::SignatureError(format!("payload is not UTF-8: {e}")))
+        };
+    }
+    Err(SpsError::SignatureError(last_error))
+}
+
+/// Extracts the PKCS#1 key from a DER SubjectPublicKeyInfo:
+/// `SEQUENCE { AlgorithmIdentifier, BIT STRING { RSAPublicKey } }`.
+fn spki_rsa_key(der: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    const RSA_ENCRYPTION_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+    let (spki, _) = der_element(der, 0x30)?;
+    let (algorithm, rest) = der_element(spki, 0x30)?;
+    let (oid, _) = der_element(algorithm, 0x06)?;
+    if oid != RSA_ENCRYPTION_OID {
+        return Err("not an RSA key".to_string());
+    }
+    let (bit_string, _) = der_element(rest, 0x03)?;
+    match bit_string.split_first() {
+        Some((0, key)) => Ok(key.to_vec()),
+        _ => Err("malformed key bit string".to_string()),
+    }
+}
+
+/// Reads one DER element with the expected tag; returns its contents and the bytes after it.
+fn der_element(input: &[u8], tag: u8) -> std::result::Result<(&[u8], &[u8]), String> {
+    let malformed = || "malformed DER".to_string();
+    let (&actual_tag, rest) = input.split_first().ok_or_else(malformed)?;
+    if actual_tag != tag {
+        return Err(malformed());
+    }
+    let (&first, mut rest) = rest.split_first().ok_or_else(malformed)?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 || rest.len() < count {
+            return Err(malformed());
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | usize::from(b));
+        rest = &rest[count..];
+        len
+    };
+    if rest.len() < len {
+        return Err(malformed());
+    }
+    Ok(rest.split_at(len))
+}
@@ -1,7 +1,10 @@
 // spm-fetch/src/lib.rs
 pub mod api;
 pub mod http;
+pub mod jws;
 pub mod oci;
+pub mod offline;
+pub mod rewrite;
 pub mod validation;
 
 // Re-export necessary types from sps-core IF using Option A from Step 3
@@ -26,4 +29,6 @@ pub use sps_common::{
     }, // Need Config, Result, SpsError, Cache
 };
 
-pub use crate::validation::{validate_url, verify_checksum, verify_content_type /* ... */};
+pub use crate::validation::{
+    validate_url, validate_url_with_policy, verify_checksum, verify_content_type, /* ... */
+};
@@ -1,13 +1,11 @@
 use std::collections::HashMap;
-use std::fs::{remove_file, File};
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
-use futures::StreamExt;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
-use reqwest::header::{ACCEPT, AUTHORIZATION};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
 use reqwest::{Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use sps_common::config::Config;
@@ -15,16 +13,15 @@ use sps_common::error::{Result, SpsError};
 use tracing::{debug, error};
 use url::Url;
 
-use crate::http::ProgressCallback;
-use crate::validation::{validate_url, verify_checksum};
+use crate::http::{download_verified, DownloadRequest, ProgressCallback};
+use crate::rewrite::resolve_download_url;
+use crate::validation::{validate_url_with_policy, UrlPolicy};
 
 const OCI_MANIFEST_V1_TYPE: &str = "application/vnd.oci.image.index.v1+json";
 const OCI_LAYER_V1_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
 const DEFAULT_GHCR_TOKEN_ENDPOINT: &str = "https://ghcr.io/token";
 pub const DEFAULT_GHCR_DOMAIN: &str = "ghcr.io";
 
-const CONNECT_TIMEOUT_SECS: u64 = 30;
-const REQUEST_TIMEOUT_SECS: u64 = 300;
 const USER_AGENT_STRING: &str = "sps package manager (Rust; +https://github.com/alexykn/sps)";
 
 #[derive(Deserialize, Debug)]
@@ -78,7 +75,8 @@ async fn fetch_oci_resource<T: serde::de::DeserializeOwned>(
 ) -> Result<T> {
     let url = Url::parse(resource_url)
         .map_err(|e| SpsError::Generic(format!("Invalid URL '{resource_url}': {e}")))?;
-    validate_url(url.as_str())?;
+    validate_url_with_policy(url.as_str(), &UrlPolicy::from_config(config))?;
+    crate::offline::ensure_online(config, resource_url)?;
     let registry_domain = url.host_str().unwrap_or(DEFAULT_GHCR_DOMAIN);
     let repo_path = extract_repo_path_from_url(&url).unwrap_or("");
 
@@ -120,59 +118,43 @@ pub async fn download_oci_blob_with_progress(
     progress_callback: Option<ProgressCallback>,
 ) -> Result<()> {
     debug!("Downloading OCI blob: {}", blob_url);
+    let blob_url = &resolve_download_url(config, blob_url)?;
     let url = Url::parse(blob_url)
         .map_err(|e| SpsError::Generic(format!("Invalid URL '{blob_url}': {e}")))?;
-    validate_url(url.as_str())?;
     let registry_domain = url.host_str().unwrap_or(DEFAULT_GHCR_DOMAIN);
     let repo_path = extract_repo_path_from_url(&url).unwrap_or("");
 
-    let auth = determine_auth(config, client, registry_domain, repo_path).await?;
-    let resp = execute_oci_request(client, blob_url, OCI_LAYER_V1_TYPE, &auth).await?;
-
-    // Get total size from Content-Length header if available
-    let total_size = resp.content_length();
-
-    let tmp = destination_path.with_file_name(format!(
-        ".{}.download",
-        destination_path.file_name().unwrap().to_string_lossy()
-    ));
-    let mut out = File::create(&tmp).map_err(|e| SpsError::Io(Arc::new(e)))?;
-
-    let mut stream = resp.bytes_stream();
-    let mut bytes_downloaded = 0u64;
-
-    while let Some(chunk) = stream.next().await {
-        let b = chunk.map_err(|e| SpsError::Http(Arc::new(e)))?;
-        std::io::Write::write_all(&mut out, &b).map_err(|e| SpsError::Io(Arc::new(e)))?;
-
-        bytes_downloaded += b.len() as u64;
-
-        // Call progress callback if provided
-        if let Some(ref callback) = progress_callback {
-            callback(bytes_downloaded, total_size);
-        }
+    // Offline or rewritten to a local file, the blob comes from disk and needs no token.
+    let auth = if config.offline || url.scheme() == "file" {
+        OciAuth::None
+    } else {
+        determine_auth(config, client, registry_domain, repo_path).await?
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static(OCI_LAYER_V1_TYPE));
+    if let Some(value) = auth_header_value(&auth) {
+        let value = HeaderValue::from_str(&value)
+            .map_err(|e| SpsError::Generic(format!("Invalid registry credentials: {e}")))?;
+        headers.insert(AUTHORIZATION, value);
     }
-    std::fs::rename(&tmp, destination_path).map_err(|e| SpsError::Io(Arc::new(e)))?;
 
-    if !expected_digest.is_empty() {
-        match verify_checksum(destination_path, expected_digest) {
-            Ok(_) => {
-                tracing::debug!("OCI Blob checksum verified: {}", destination_path.display());
-            }
-            Err(e) => {
-                tracing::error!(
-                    "OCI Blob checksum mismatch ({}). Deleting downloaded file.",
-                    e
-                );
-                let _ = remove_file(destination_path);
-                return Err(e);
-            }
-        }
-    } else {
+    // Blob URLs name their digest, so a partial blob can be resumed without validators.
+    let request = DownloadRequest {
+        client,
+        url: blob_url,
+        headers,
+        content_addressed: !expected_digest.is_empty(),
+        config,
+        progress_callback,
+    };
+    download_verified(&request, destination_path, expected_digest).await?;
+    if expected_digest.is_empty() {
         tracing::warn!(
             "Skipping checksum verification for OCI blob {} - no checksum provided.",
             destination_path.display()
         );
+    } else {
+        tracing::debug!("OCI Blob checksum verified: {}", destination_path.display());
     }
 
     debug!("Blob saved to {}", destination_path.display());
@@ -187,11 +169,11 @@ pub async fn fetch_oci_manifest_index(
     fetch_oci_resource(manifest_url, OCI_MANIFEST_V1_TYPE, config, client).await
 }
 
-pub fn build_oci_client() -> Result<Client> {
+pub fn build_oci_client(config: &Config) -> Result<Client> {
     Client::builder()
         .user_agent(USER_AGENT_STRING)
-        .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
-        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+        .read_timeout(Duration::from_secs(config.download_timeout_secs))
         .redirect(reqwest::redirect::Policy::default())
         .build()
         .map_err(|e| SpsError::Http(Arc::new(e)))
@@ -309,16 +291,8 @@ async fn execute_oci_request(
 ) -> Result<Response> {
     debug!("OCI request → {} (Accept: {})", url, accept);
     let mut req = client.get(url).header(ACCEPT, accept);
-    match auth {
-        OciAuth::AnonymousBearer { token } | OciAuth::ExplicitBearer { token }
-            if !token.is_empty() =>
-        {
-            req = req.header(AUTHORIZATION, format!("Bearer {token}"))
-        }
-        OciAuth::Basic { encoded } if !encoded.is_empty() => {
-            req = req.header(AUTHORIZATION, format!("Basic {encoded}"))
-        }
-        _ => {}
+    if let Some(value) = auth_header_value(auth) {
+        req = req.header(AUTHORIZATION, value);
     }
 
     let resp = req.send().await.map_err(|e| SpsError::Http(Arc::new(e)))?;
@@ -337,3 +311,15 @@ async fn execute_oci_request(
         Err(err)
     }
 }
+
+fn auth_header_value(auth: &OciAuth) -> Option<String> {
+    match auth {
+        OciAuth::AnonymousBearer { token } | OciAuth::ExplicitBearer { token }
+            if !token.is_empty() =>
+        {
+            Some(format!("Bearer {token}"))
+        }
+        OciAuth::Basic { encoded } if !encoded.is_empty() => Some(format!("Basic {encoded}")),
+        _ => None,
+    }
+}
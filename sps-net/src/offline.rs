@@ -0,0 +1,108 @@
+// sps-net/src/offline.rs
+//! Offline mode: no network access, downloads are copied from a local mirror.
+//!
+//! The mirror is a directory (or `file://` URL) that is searched for, in order:
+//!
+//! 1. `<mirror>/<host>/<path>`, the layout of a recursive `wget --mirror`
+//! 2. `<mirror>/<name>`, the file name sps stores the download under in its cache
+//! 3. `<mirror>/<last URL path segment>`
+use std::path::{Path, PathBuf};
+
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use tracing::debug;
+use url::Url;
+
+/// Fails with [`SpsError::Offline`] in offline mode. `what` names the resource that would have
+/// been fetched.
+pub fn ensure_online(config: &Config, what: &str) -> Result<()> {
+    if config.offline {
+        return Err(SpsError::Offline(format!(
+            "{what} is not in the local cache"
+        )));
+    }
+    Ok(())
+}
+
+/// Finds the mirror copy of `url`. `file_name` is the name of the download's cache entry.
+pub fn find_in_mirror(config: &Config, url: &str, file_name: &str) -> Result<PathBuf> {
+    let mirror = config.mirror.as_deref().ok_or_else(|| {
+        SpsError::Offline(format!(
+            "{url} can't be downloaded and no mirror is configured (set 'mirror' or SPS_MIRROR)"
+        ))
+    })?;
+    let root = mirror_root(mirror)?;
+
+    let mut candidates = Vec::new();
+    if let Ok(parsed) = Url::parse(url) {
+        if let Some(host) = parsed.host_str() {
+            let relative = parsed.path().trim_start_matches('/');
+            if let Some(path) = safe_join(&root.join(host), relative) {
+                candidates.push(path);
+            }
+        }
+        if let Some(last) = parsed.path_segments().and_then(|mut s| s.next_back()) {
+            candidates.extend(safe_join(&root, file_name));
+            candidates.extend(safe_join(&root, last));
+        }
+    } else {
+        candidates.extend(safe_join(&root, file_name));
+    }
+
+    for candidate in candidates {
+        debug!("Looking for {} at {}", url, candidate.display());
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+    }
+    Err(SpsError::Offline(format!(
+        "{url} is not in the mirror {}",
+        root.display()
+    )))
+}
+
+fn mirror_root(mirror: &str) -> Result<PathBuf> {
+    if mirror.starts_with("file:") {
+        let url = Url::parse(mirror)
+            .map_err(|e| SpsError::Config(format!("Invalid mirror URL '{mirror}': {e}")))?;
+        return url
+            .to_file_path()
+            .map_err(|_| SpsError::Config(format!("Invalid mirror URL '{mirror}'")));
+    }
+    Ok(PathBuf::from(mirror))
+}
+
+/// Joins a URL path below `root`, refusing anything that would escape it.
+fn safe_join(root: &Path, relative: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in relative.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => path.push(segment),
+        }
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_join_stays_below_the_root() {
+        let root = Path::new("/mirror");
+        assert_eq!(
+            safe_join(root, "ghcr.io/./v2//foo"),
+            Some(PathBuf::from("/mirror/ghcr.io/v2/foo"))
+        );
+        assert_eq!(safe_join(root, ""), Some(PathBuf::from("/mirror")));
+        assert_eq!(
+            safe_join(root, "/etc/passwd"),
+            Some(PathBuf::from("/mirror/etc/passwd"))
+        );
+        for escape in ["..", "../etc/passwd", "a/../../b", "a/b/.."] {
+            assert_eq!(safe_join(root, escape), None, "{escape}");
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// sps-net/src/rewrite.rs
+//! Download URL rewriting.
+//!
+//! `url_rewrites` rules replace a URL prefix, e.g. to fetch bottles from an internal mirror.
+//! The longest matching prefix wins. `HOMEBREW_ARTIFACT_DOMAIN` is the implicit rule
+//! `https://ghcr.io/` -> `<artifact_domain>/`, applied when no explicit rule matches.
+use sps_common::config::Config;
+use sps_common::error::Result;
+use tracing::debug;
+
+use crate::oci::DEFAULT_GHCR_DOMAIN;
+use crate::validation::{validate_url, validate_url_with_policy, UrlPolicy};
+
+/// Validates `url`, applies the rewrite rules and validates the result. The original URL must
+/// pass the usual policy; a rewritten one may also be a `file://` URL.
+pub fn resolve_download_url(config: &Config, url: &str) -> Result<String> {
+    let policy = UrlPolicy::from_config(config);
+    validate_url_with_policy(url, &policy)?;
+    let rewritten = rewrite_url(config, url);
+    if rewritten != url {
+        debug!("Rewrote download URL {} -> {}", url, rewritten);
+        validate_url_with_policy(
+            &rewritten,
+            &UrlPolicy {
+                allow_file: true,
+                ..policy
+            },
+        )?;
+    }
+    Ok(rewritten)
+}
+
+/// Applies the rewrite rules to `url`, returning it unchanged if none matches.
+pub fn rewrite_url(config: &Config, url: &str) -> String {
+    let explicit = config
+        .url_rewrites
+        .iter()
+        .filter(|rule| url.starts_with(&rule.prefix))
+        .max_by_key(|rule| rule.prefix.len());
+    if let Some(rule) = explicit {
+        return format!("{}{}", rule.replacement, &url[rule.prefix.len()..]);
+    }
+    if let Some(domain) = artifact_domain(config) {
+        let ghcr = format!("https://{DEFAULT_GHCR_DOMAIN}/");
+        if let Some(rest) = url.strip_prefix(&ghcr) {
+            return format!("{domain}/{rest}");
+        }
+    }
+    url.to_string()
+}
+
+/// `artifact_domain` as a URL prefix without trailing slash; a bare host gets `https://`.
+fn artifact_domain(config: &Config) -> Option<String> {
+    let domain = config.artifact_domain.as_deref()?.trim_end_matches('/');
+    if domain.is_empty() {
+        return None;
+    }
+    if domain.contains("://") {
+        Some(domain.to_string())
+    } else if validate_url(&format!("https://{domain}/")).is_ok() {
+        Some(format!("https://{domain}"))
+    } else {
+        None
+    }
+}
@@ -6,8 +6,9 @@ use std::path::Path;
 
 use infer;
 use sha2::{Digest, Sha256};
+use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
-use url::Url;
+use url::{Host, Url};
 //use tokio::fs::File;
 //use tokio::io::AsyncReadExt;
 //use tracing::debug; // Use tracing
@@ -117,11 +118,40 @@ pub fn verify_content_type(path: &Path, expected_ext: &str) -> Result<()> {
     }
 }
 
+/// Which URLs sps is willing to fetch from.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UrlPolicy {
+    /// Accept `http://` for localhost, 127.0.0.0/8 and ::1 (stand-in servers for tests).
+    pub allow_insecure_localhost: bool,
+    /// Accept `file://` URLs (targets of configured URL rewrites).
+    pub allow_file: bool,
+}
+
+impl UrlPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            allow_insecure_localhost: config.allow_insecure_localhost,
+            allow_file: false,
+        }
+    }
+}
+
 /// Validates a URL, ensuring it uses the HTTPS scheme.
 pub fn validate_url(url_str: &str) -> Result<()> {
+    validate_url_with_policy(url_str, &UrlPolicy::default())
+}
+
+/// Validates a URL against `policy`. HTTPS is always accepted.
+pub fn validate_url_with_policy(url_str: &str, policy: &UrlPolicy) -> Result<()> {
     let url = Url::parse(url_str)
         .map_err(|e| SpsError::Generic(format!("Failed to parse URL '{url_str}': {e}")))?;
-    if url.scheme() == "https" {
+    let allowed = match url.scheme() {
+        "https" => true,
+        "http" => policy.allow_insecure_localhost && is_localhost(&url),
+        "file" => policy.allow_file,
+        _ => false,
+    };
+    if allowed {
         Ok(())
     } else {
         Err(SpsError::ValidationError(format!(
@@ -131,3 +161,12 @@ pub fn validate_url(url_str: &str) -> Result<()> {
         )))
     }
 }
+
+fn is_localhost(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
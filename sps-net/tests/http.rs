@@ -0,0 +1,340 @@
+//! Downloads against a stand-in HTTP server on localhost that answers each connection with the
+//! next scripted response.
+#![cfg(unix)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+use sps_common::config::Config;
+use sps_common::error::SpsError;
+use sps_net::http::download_file_with_progress;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+
+/// A request as the server saw it: header names are lowercased.
+struct Request {
+    headers: HashMap<String, String>,
+    received: Instant,
+}
+
+struct Server {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    /// Serves `responses` in order, one per connection.
+    async fn start(responses: Vec<Vec<u8>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/foo-1.0.tar.gz", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    assert!(n > 0, "connection closed before the request head ended");
+                    head.extend_from_slice(&buf[..n]);
+                }
+                let headers = String::from_utf8_lossy(&head)
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+                    .collect();
+                seen.lock().unwrap().push(Request {
+                    headers,
+                    received: Instant::now(),
+                });
+                stream.write_all(&response).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        Self { url, requests }
+    }
+
+    fn requests(&self) -> std::sync::MutexGuard<'_, Vec<Request>> {
+        self.requests.lock().unwrap()
+    }
+
+    /// The `Range` and `If-Range` headers of each request.
+    fn ranges(&self) -> Vec<(Option<String>, Option<String>)> {
+        self.requests()
+            .iter()
+            .map(|r| {
+                (
+                    r.headers.get("range").cloned(),
+                    r.headers.get("if-range").cloned(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// A response whose `Content-Length` is `length`, or the length of `body`.
+fn response(status: &str, headers: &[(&str, &str)], body: &[u8], length: Option<usize>) -> Vec<u8> {
+    let mut head = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        length.unwrap_or(body.len())
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    let mut response = head.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn ok(headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    response("200 OK", headers, body, None)
+}
+
+/// No `Range` or `If-Range`: the whole file.
+fn whole() -> (Option<String>, Option<String>) {
+    (None, None)
+}
+
+fn range(from: u64, validator: &str) -> (Option<String>, Option<String>) {
+    (Some(format!("bytes={from}-")), Some(validator.to_string()))
+}
+
+struct Target {
+    dir: TempDir,
+    config: Config,
+}
+
+impl Target {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config::defaults();
+        config.sps_root = dir.path().join("prefix");
+        config.allow_insecure_localhost = true;
+        config.download_retries = 3;
+        Self { dir, config }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.path().join("foo-1.0.tar.gz")
+    }
+
+    fn partial(&self) -> PathBuf {
+        self.dir.path().join(".foo-1.0.tar.gz.download")
+    }
+
+    fn meta(&self) -> PathBuf {
+        self.dir.path().join(".foo-1.0.tar.gz.download.meta")
+    }
+
+    /// Leaves a partial download from an earlier run, described by `meta`.
+    fn leave_partial(&self, content: &str, meta: serde_json::Value) {
+        fs::write(self.partial(), content).unwrap();
+        fs::write(self.meta(), meta.to_string()).unwrap();
+    }
+
+    async fn download(&self, server: &Server) -> Result<String, SpsError> {
+        download_file_with_progress(&server.url, &self.path(), &self.config, None).await?;
+        assert!(!self.partial().exists(), "partial download left behind");
+        assert!(
+            !self.meta().exists(),
+            "partial download metadata left behind"
+        );
+        Ok(fs::read_to_string(self.path()).unwrap())
+    }
+}
+
+#[tokio::test]
+async fn interrupted_download_resumes_from_where_it_stopped() {
+    let target = Target::new();
+    let server = Server::start(vec![
+        response("200 OK", &[("ETag", "\"v1\"")], b"hello", Some(10)),
+        response(
+            "206 Partial Content",
+            &[("ETag", "\"v1\""), ("Content-Range", "bytes 5-9/10")],
+            b"world",
+            None,
+        ),
+    ])
+    .await;
+
+    assert_eq!(target.download(&server).await.unwrap(), "helloworld");
+    assert_eq!(server.ranges(), [whole(), range(5, "\"v1\"")]);
+}
+
+#[tokio::test]
+async fn last_modified_validates_a_partial_without_etag() {
+    let target = Target::new();
+    let modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+    let server = Server::start(vec![response(
+        "206 Partial Content",
+        &[("Content-Range", "bytes 5-9/10")],
+        b"world",
+        None,
+    )])
+    .await;
+    target.leave_partial(
+        "hello",
+        json!({ "url": server.url, "etag": null, "last_modified": modified }),
+    );
+
+    assert_eq!(target.download(&server).await.unwrap(), "helloworld");
+    assert_eq!(server.ranges(), [range(5, modified)]);
+}
+
+#[tokio::test]
+async fn full_response_to_a_range_request_replaces_the_partial() {
+    let target = Target::new();
+    let server = Server::start(vec![ok(&[("ETag", "\"v2\"")], b"fresh content")]).await;
+    target.leave_partial("stale", json!({ "url": server.url, "etag": "\"v1\"" }));
+
+    assert_eq!(target.download(&server).await.unwrap(), "fresh content");
+    assert_eq!(server.ranges(), [range(5, "\"v1\"")]);
+}
+
+#[tokio::test]
+async fn partial_without_matching_metadata_is_downloaded_again() {
+    type Meta = fn(&str) -> serde_json::Value;
+    let metas: [Meta; 3] = [
+        // Saved for another URL.
+        |_| json!({ "url": "https://example.com/other.tar.gz", "etag": "\"v1\"" }),
+        // No validator to send in `If-Range`.
+        |url| json!({ "url": url }),
+        |_| json!("not metadata"),
+    ];
+    for meta in metas {
+        let target = Target::new();
+        let server = Server::start(vec![ok(&[], b"helloworld")]).await;
+        let meta = meta(&server.url);
+        target.leave_partial("stale", meta.clone());
+
+        assert_eq!(
+            target.download(&server).await.unwrap(),
+            "helloworld",
+            "{meta}"
+        );
+        assert_eq!(server.ranges(), [whole()], "{meta}");
+    }
+}
+
+#[tokio::test]
+async fn unexpected_content_range_starts_over() {
+    let target = Target::new();
+    let server = Server::start(vec![
+        response(
+            "206 Partial Content",
+            &[("Content-Range", "bytes 0-9/10")],
+            b"helloworld",
+            None,
+        ),
+        ok(&[], b"helloworld"),
+    ])
+    .await;
+    target.leave_partial("hello", json!({ "url": server.url, "etag": "\"v1\"" }));
+
+    assert_eq!(target.download(&server).await.unwrap(), "helloworld");
+    assert_eq!(server.ranges(), [range(5, "\"v1\""), whole()]);
+}
+
+#[tokio::test]
+async fn range_not_satisfiable_for_a_complete_partial_keeps_it() {
+    let target = Target::new();
+    let server = Server::start(vec![response(
+        "416 Range Not Satisfiable",
+        &[("Content-Range", "bytes */10")],
+        b"",
+        None,
+    )])
+    .await;
+    target.leave_partial("helloworld", json!({ "url": server.url, "etag": "\"v1\"" }));
+
+    assert_eq!(target.download(&server).await.unwrap(), "helloworld");
+    assert_eq!(server.ranges(), [range(10, "\"v1\"")]);
+}
+
+#[tokio::test]
+async fn range_not_satisfiable_otherwise_starts_over() {
+    let target = Target::new();
+    let server = Server::start(vec![
+        response(
+            "416 Range Not Satisfiable",
+            &[("Content-Range", "bytes */3")],
+            b"",
+            None,
+        ),
+        ok(&[], b"new"),
+    ])
+    .await;
+    target.leave_partial("hello", json!({ "url": server.url, "etag": "\"v1\"" }));
+
+    assert_eq!(target.download(&server).await.unwrap(), "new");
+    assert_eq!(server.ranges(), [range(5, "\"v1\""), whole()]);
+}
+
+#[tokio::test]
+async fn retry_after_replaces_the_backoff() {
+    let target = Target::new();
+    let unavailable = |wait: &str| {
+        response(
+            "503 Service Unavailable",
+            &[("Retry-After", wait)],
+            b"",
+            None,
+        )
+    };
+    let server = Server::start(vec![
+        unavailable("1"),
+        // A date in the past: retry right away rather than backing off.
+        unavailable("Wed, 21 Oct 2015 07:28:00 GMT"),
+        ok(&[], b"helloworld"),
+    ])
+    .await;
+
+    assert_eq!(target.download(&server).await.unwrap(), "helloworld");
+    let requests = server.requests();
+    let waits: Vec<Duration> = requests
+        .windows(2)
+        .map(|pair| pair[1].received - pair[0].received)
+        .collect();
+    assert_eq!(waits.len(), 2);
+    assert!(
+        (Duration::from_secs(1)..Duration::from_millis(1400)).contains(&waits[0]),
+        "{waits:?}"
+    );
+    assert!(waits[1] < Duration::from_millis(400), "{waits:?}");
+}
+
+#[tokio::test]
+async fn retries_give_up_after_the_configured_attempts() {
+    let mut target = Target::new();
+    target.config.download_retries = 1;
+    let unavailable = || response("503 Service Unavailable", &[], b"", None);
+    let server = Server::start(vec![unavailable(), unavailable(), ok(&[], b"late")]).await;
+
+    match target.download(&server).await {
+        Err(SpsError::HttpError(msg)) => assert!(msg.contains("503"), "{msg}"),
+        other => panic!("expected an HTTP error, got {other:?}"),
+    }
+    assert_eq!(server.requests().len(), 2);
+    assert!(!target.path().exists());
+}
+
+#[tokio::test]
+async fn not_found_is_not_retried() {
+    let target = Target::new();
+    let server = Server::start(vec![response("404 Not Found", &[], b"", None)]).await;
+
+    match target.download(&server).await {
+        Err(SpsError::DownloadError(..)) => {}
+        other => panic!("expected a download error, got {other:?}"),
+    }
+    assert_eq!(server.requests().len(), 1);
+}
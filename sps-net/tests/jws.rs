@@ -0,0 +1,88 @@
+//! Verifying signed API payloads. `formula.jws.json` was signed (PS512, `b64: false`) with the
+//! private half of `key.pub.pem`; `other-key.pub.pem` is an unrelated key.
+
+use serde_json::Value;
+use sps_common::error::SpsError;
+use sps_net::jws::{verify_payload, PinnedKey};
+
+const DOCUMENT: &str = include_str!("fixtures/formula.jws.json");
+const PAYLOAD: &str = r#"[{"name":"hello","versions":{"stable":"2.12.1"}}]"#;
+
+fn key(pem: &str) -> PinnedKey {
+    PinnedKey::from_pem(pem).unwrap()
+}
+
+fn signing_key() -> PinnedKey {
+    key(include_str!("fixtures/key.pub.pem"))
+}
+
+fn assert_rejected(result: Result<String, SpsError>) {
+    match result {
+        Err(SpsError::SignatureError(_)) => {}
+        other => panic!("expected SignatureError, got {other:?}"),
+    }
+}
+
+#[test]
+fn good_signature_returns_the_payload() {
+    assert_eq!(verify_payload(DOCUMENT, &signing_key()).unwrap(), PAYLOAD);
+}
+
+#[test]
+fn pkcs1_key_verifies_too() {
+    let pkcs1 = key(include_str!("fixtures/key.rsa.pem"));
+    assert_eq!(verify_payload(DOCUMENT, &pkcs1).unwrap(), PAYLOAD);
+}
+
+#[test]
+fn tampered_payload_is_rejected() {
+    let tampered = DOCUMENT.replace("2.12.1", "2.12.2");
+    assert_ne!(tampered, DOCUMENT);
+    assert_rejected(verify_payload(&tampered, &signing_key()));
+}
+
+#[test]
+fn tampered_header_is_rejected() {
+    let mut document: Value = serde_json::from_str(DOCUMENT).unwrap();
+    // `{"alg":"PS512"}`: the payload would now be read as base64url.
+    document["signatures"][0]["protected"] = "eyJhbGciOiJQUzUxMiJ9".into();
+    assert_rejected(verify_payload(&document.to_string(), &signing_key()));
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let other = key(include_str!("fixtures/other-key.pub.pem"));
+    assert_rejected(verify_payload(DOCUMENT, &other));
+}
+
+#[test]
+fn unsigned_or_malformed_documents_are_rejected() {
+    let key = signing_key();
+    assert_rejected(verify_payload(PAYLOAD, &key));
+    assert_rejected(verify_payload(
+        &format!(r#"{{"payload":{PAYLOAD:?},"signatures":[]}}"#),
+        &key,
+    ));
+    let mut document: Value = serde_json::from_str(DOCUMENT).unwrap();
+    document["signatures"][0]["signature"] = "not base64!".into();
+    assert_rejected(verify_payload(&document.to_string(), &key));
+}
+
+#[test]
+fn malformed_keys_are_rejected() {
+    let pem =
+        |body: &str| format!("-----BEGIN PUBLIC KEY-----\n{body}\n-----END PUBLIC KEY-----\n");
+    // Not PEM at all.
+    assert!(PinnedKey::from_pem("ssh-rsa AAAAB3NzaC1yc2E").is_err());
+    // Not base64.
+    assert!(PinnedKey::from_pem(&pem("****")).is_err());
+    // A SEQUENCE claiming more bytes than follow.
+    assert!(PinnedKey::from_pem(&pem("MIIBIjAN")).is_err());
+    // A well-formed SubjectPublicKeyInfo for an EC key: SEQUENCE { SEQUENCE { OID
+    // 1.2.840.10045.2.1 }, BIT STRING }.
+    assert!(PinnedKey::from_pem(&pem("MA8wCQYHKoZIzj0CAQMCAAQ=")).is_err());
+    // A truncated copy of the real key.
+    let real = include_str!("fixtures/key.pub.pem");
+    let body: String = real.lines().filter(|l| !l.starts_with("-----")).collect();
+    assert!(PinnedKey::from_pem(&pem(&body[..body.len() / 2])).is_err());
+}
@@ -0,0 +1,115 @@
+//! Where offline downloads are looked up in the mirror.
+#![cfg(unix)]
+
+use std::fs;
+use std::path::PathBuf;
+
+use sps_common::config::Config;
+use sps_common::error::SpsError;
+use sps_net::offline::find_in_mirror;
+use tempfile::TempDir;
+
+const URL: &str = "https://ghcr.io/v2/homebrew/core/foo/blobs/sha256:abc";
+const CACHE_NAME: &str = "foo--1.0.arm64_sonoma.bottle.tar.gz";
+
+struct Mirror {
+    dir: TempDir,
+    config: Config,
+}
+
+impl Mirror {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config::defaults();
+        config.offline = true;
+        config.mirror = Some(dir.path().display().to_string());
+        Self { dir, config }
+    }
+
+    fn add(&self, relative: &str) -> PathBuf {
+        let path = self.dir.path().join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, relative).unwrap();
+        path
+    }
+
+    fn find(&self, url: &str) -> Result<PathBuf, SpsError> {
+        find_in_mirror(&self.config, url, CACHE_NAME)
+    }
+}
+
+fn assert_offline_error(result: Result<PathBuf, SpsError>, needle: &str) {
+    match result {
+        Err(SpsError::Offline(msg)) => assert!(msg.contains(needle), "{msg}"),
+        other => panic!("expected an offline error mentioning {needle}, got {other:?}"),
+    }
+}
+
+#[test]
+fn mirror_layout_wins_then_cache_name_then_last_segment() {
+    let mirror = Mirror::new();
+    let by_path = mirror.add("ghcr.io/v2/homebrew/core/foo/blobs/sha256:abc");
+    let by_cache_name = mirror.add(CACHE_NAME);
+    let by_last_segment = mirror.add("sha256:abc");
+
+    assert_eq!(mirror.find(URL).unwrap(), by_path);
+    fs::remove_file(&by_path).unwrap();
+    assert_eq!(mirror.find(URL).unwrap(), by_cache_name);
+    fs::remove_file(&by_cache_name).unwrap();
+    assert_eq!(mirror.find(URL).unwrap(), by_last_segment);
+    fs::remove_file(&by_last_segment).unwrap();
+    assert_offline_error(mirror.find(URL), "is not in the mirror");
+}
+
+#[test]
+fn directories_are_not_mirror_copies() {
+    let mirror = Mirror::new();
+    fs::create_dir_all(
+        mirror
+            .dir
+            .path()
+            .join("ghcr.io/v2/homebrew/core/foo/blobs/sha256:abc"),
+    )
+    .unwrap();
+    let by_cache_name = mirror.add(CACHE_NAME);
+
+    assert_eq!(mirror.find(URL).unwrap(), by_cache_name);
+}
+
+#[test]
+fn file_url_mirrors_are_accepted() {
+    let mut mirror = Mirror::new();
+    let by_cache_name = mirror.add(CACHE_NAME);
+    mirror.config.mirror = Some(format!("file://{}", mirror.dir.path().display()));
+
+    assert_eq!(mirror.find(URL).unwrap(), by_cache_name);
+}
+
+#[test]
+fn dot_dot_in_urls_never_leaves_the_mirror() {
+    let outer = TempDir::new().unwrap();
+    let mut config = Config::defaults();
+    config.offline = true;
+    let root = outer.path().join("mirror");
+    fs::create_dir_all(&root).unwrap();
+    config.mirror = Some(root.display().to_string());
+    fs::write(outer.path().join("secret"), "outside").unwrap();
+
+    for url in [
+        "https://example.com/../../secret",
+        "https://example.com/%2e%2e/%2E%2E/secret",
+        "https://example.com/a/..%2F..%2Fsecret",
+    ] {
+        if let Ok(found) = find_in_mirror(&config, url, "download") {
+            assert!(found.starts_with(&root), "{url} -> {}", found.display());
+        }
+    }
+}
+
+#[test]
+fn missing_mirror_is_an_offline_error() {
+    let mut mirror = Mirror::new();
+    mirror.config.mirror = None;
+
+    assert_offline_error(mirror.find(URL), "no mirror is configured");
+}
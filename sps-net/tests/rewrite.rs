@@ -0,0 +1,85 @@
+//! How download URLs are rewritten: explicit `url_rewrites` rules, then the artifact domain.
+
+use sps_common::config::{Config, UrlRewrite};
+use sps_common::error::SpsError;
+use sps_net::rewrite::resolve_download_url;
+
+const URL: &str = "https://ghcr.io/v2/homebrew/core/foo/blobs/sha256:abc";
+
+fn config_with_rewrites(rules: &[(&str, &str)]) -> Config {
+    let mut config = Config::defaults();
+    config.url_rewrites = rules
+        .iter()
+        .map(|(prefix, replacement)| UrlRewrite {
+            prefix: prefix.to_string(),
+            replacement: replacement.to_string(),
+        })
+        .collect();
+    config
+}
+
+#[test]
+fn longest_matching_prefix_wins() {
+    let config = config_with_rewrites(&[
+        ("https://ghcr.io/", "https://mirror.example/all/"),
+        (
+            "https://ghcr.io/v2/homebrew/core/",
+            "https://mirror.example/core/",
+        ),
+        ("https://ghcr.io/v2/", "https://mirror.example/v2/"),
+    ]);
+
+    assert_eq!(
+        resolve_download_url(&config, URL).unwrap(),
+        "https://mirror.example/core/foo/blobs/sha256:abc"
+    );
+    assert_eq!(
+        resolve_download_url(&config, "https://ghcr.io/v2/other/bar").unwrap(),
+        "https://mirror.example/v2/other/bar"
+    );
+    assert_eq!(
+        resolve_download_url(&config, "https://ghcr.io/token").unwrap(),
+        "https://mirror.example/all/token"
+    );
+    assert_eq!(
+        resolve_download_url(&config, "https://github.com/foo.tar.gz").unwrap(),
+        "https://github.com/foo.tar.gz"
+    );
+}
+
+#[test]
+fn artifact_domain_applies_only_without_an_explicit_rule() {
+    let mut config = config_with_rewrites(&[("https://ghcr.io/v2/other/", "https://explicit/")]);
+    config.artifact_domain = Some("artifacts.example/".to_string());
+
+    assert_eq!(
+        resolve_download_url(&config, URL).unwrap(),
+        "https://artifacts.example/v2/homebrew/core/foo/blobs/sha256:abc"
+    );
+    assert_eq!(
+        resolve_download_url(&config, "https://ghcr.io/v2/other/bar").unwrap(),
+        "https://explicit/bar"
+    );
+}
+
+#[test]
+fn rewrites_may_point_at_files_but_not_at_plain_http() {
+    let config = config_with_rewrites(&[
+        ("https://ghcr.io/", "file:///srv/mirror/"),
+        ("https://github.com/", "http://mirror.example/"),
+    ]);
+
+    assert_eq!(
+        resolve_download_url(&config, URL).unwrap(),
+        "file:///srv/mirror/v2/homebrew/core/foo/blobs/sha256:abc"
+    );
+    assert!(matches!(
+        resolve_download_url(&config, "https://github.com/foo.tar.gz"),
+        Err(SpsError::ValidationError(_))
+    ));
+    // The original URL is held to the usual policy.
+    assert!(matches!(
+        resolve_download_url(&config, "file:///etc/passwd"),
+        Err(SpsError::ValidationError(_))
+    ));
+}
@@ -4,30 +4,53 @@ use std::sync::Arc;
 
 use clap::{ArgAction, Parser, Subcommand};
 use sps_common::error::Result;
+use sps_common::lock::LockMode;
 use sps_common::{Cache, Config};
 
 // Module declarations
+pub mod autoremove;
+pub mod cleanup;
+pub mod config;
+pub mod deps;
 pub mod info;
 pub mod init;
 pub mod install;
+pub mod leaves;
+pub mod link;
 pub mod list;
+pub mod pin;
 pub mod reinstall;
 pub mod search;
 pub mod status;
+pub mod switch;
 pub mod uninstall;
+pub mod unlink;
+pub mod unpin;
 pub mod update;
 pub mod upgrade;
+pub mod uses;
 // Re-export InitArgs to make it accessible as cli::InitArgs
 // Import other command Args structs
+use crate::cli::autoremove::Autoremove;
+use crate::cli::cleanup::Cleanup;
+pub use crate::cli::config::ConfigArgs;
+use crate::cli::deps::Deps;
 use crate::cli::info::Info;
 pub use crate::cli::init::InitArgs;
 use crate::cli::install::InstallArgs;
+use crate::cli::leaves::Leaves;
+use crate::cli::link::Link;
 use crate::cli::list::List;
+use crate::cli::pin::Pin;
 use crate::cli::reinstall::ReinstallArgs;
 use crate::cli::search::Search;
+use crate::cli::switch::Switch;
 use crate::cli::uninstall::Uninstall;
+use crate::cli::unlink::Unlink;
+use crate::cli::unpin::Unpin;
 use crate::cli::update::Update;
 use crate::cli::upgrade::UpgradeArgs;
+use crate::cli::uses::Uses;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None, name = "sps", bin_name = "sps")]
@@ -36,6 +59,14 @@ pub struct CliArgs {
     #[arg(short, long, action = ArgAction::Count, global = true)]
     pub verbose: u8,
 
+    /// Fail immediately if another sps process holds the prefix lock, instead of waiting for it
+    #[arg(long, global = true)]
+    pub no_wait: bool,
+
+    /// Never use the network: metadata comes from the cache, downloads from the mirror
+    #[arg(long, global = true)]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -46,26 +77,77 @@ pub enum Command {
     Search(Search),
     List(List),
     Info(Info),
+    Deps(Deps),
+    Uses(Uses),
+    Leaves(Leaves),
+    Autoremove(Autoremove),
+    Cleanup(Cleanup),
+    Pin(Pin),
+    Unpin(Unpin),
+    Switch(Switch),
     Update(Update),
     Install(InstallArgs),
     Uninstall(Uninstall),
     Reinstall(ReinstallArgs),
     Upgrade(UpgradeArgs),
+    Link(Link),
+    Unlink(Unlink),
+    Config(ConfigArgs),
 }
 
 impl Command {
+    /// The prefix lock this command needs: shared for read-only commands, exclusive for
+    /// anything that changes installed packages, links or cached API data.
+    pub fn lock_mode(&self) -> Option<LockMode> {
+        match self {
+            Self::Init(_) | Self::Config(_) => None,
+            Self::Search(_)
+            | Self::List(_)
+            | Self::Info(_)
+            | Self::Deps(_)
+            | Self::Uses(_)
+            | Self::Leaves(_) => Some(LockMode::Shared),
+            Self::Install(command) if command.is_dry_run() => Some(LockMode::Shared),
+            Self::Autoremove(command) if command.is_dry_run() => Some(LockMode::Shared),
+            Self::Cleanup(command) if command.is_dry_run() => Some(LockMode::Shared),
+            Self::Update(_)
+            | Self::Install(_)
+            | Self::Uninstall(_)
+            | Self::Autoremove(_)
+            | Self::Cleanup(_)
+            | Self::Pin(_)
+            | Self::Unpin(_)
+            | Self::Switch(_)
+            | Self::Reinstall(_)
+            | Self::Upgrade(_)
+            | Self::Link(_)
+            | Self::Unlink(_) => Some(LockMode::Exclusive),
+        }
+    }
+
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
         match self {
             Self::Init(command) => command.run(config).await,
             Self::Search(command) => command.run(config, cache).await,
             Self::List(command) => command.run(config, cache).await,
             Self::Info(command) => command.run(config, cache).await,
+            Self::Deps(command) => command.run(config, cache).await,
+            Self::Uses(command) => command.run(config, cache).await,
+            Self::Leaves(command) => command.run(config, cache).await,
+            Self::Autoremove(command) => command.run(config, cache).await,
+            Self::Cleanup(command) => command.run(config, cache).await,
+            Self::Pin(command) => command.run(config, cache).await,
+            Self::Unpin(command) => command.run(config, cache).await,
+            Self::Switch(command) => command.run(config, cache).await,
             Self::Update(command) => command.run(config, cache).await,
             // Commands that use the pipeline
             Self::Install(command) => command.run(config, cache).await,
             Self::Reinstall(command) => command.run(config, cache).await,
             Self::Upgrade(command) => command.run(config, cache).await,
             Self::Uninstall(command) => command.run(config, cache).await,
+            Self::Link(command) => command.run(config, cache).await,
+            Self::Unlink(command) => command.run(config, cache).await,
+            Self::Config(command) => command.run(config).await,
         }
     }
 }
@@ -0,0 +1,61 @@
+//! Contains the logic for the `autoremove` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::Result;
+use sps_core::check::InstalledDependencies;
+
+use crate::cli::uninstall::Uninstall;
+
+#[derive(Args, Debug)]
+pub struct Autoremove {
+    /// List the formulae that would be removed without removing them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Autoremove {
+    /// A dry run only lists what would go, so it doesn't need the exclusive lock.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Uninstalls formulae that were only installed as dependencies and are no longer needed.
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let deps = InstalledDependencies::load(&cache)?;
+        let names = deps.removal_order(&deps.unneeded());
+        if names.is_empty() {
+            println!("No unneeded dependencies to remove");
+            return Ok(());
+        }
+
+        if self.dry_run {
+            println!(
+                "Would uninstall {} unneeded {}:",
+                names.len(),
+                if names.len() == 1 {
+                    "formula"
+                } else {
+                    "formulae"
+                }
+            );
+            for name in &names {
+                println!("{}", name.cyan());
+            }
+            return Ok(());
+        }
+
+        // Nothing outside this set needs any of it, and it is already in removal order.
+        Uninstall {
+            names,
+            zap: false,
+            ignore_dependencies: true,
+            cascade: false,
+        }
+        .run(config, cache)
+        .await
+    }
+}
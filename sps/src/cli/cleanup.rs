@@ -0,0 +1,109 @@
+//! Contains the logic for the `cleanup` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_core::cleanup::{self, CleanupItem, CleanupKind, CleanupOptions};
+use tracing::error;
+
+use crate::cli::uninstall::format_size;
+
+#[derive(Args, Debug)]
+pub struct Cleanup {
+    /// Only clean up after these formulae and casks
+    pub names: Vec<String>,
+
+    /// Also remove cached downloads older than this many days ("all" for every one)
+    #[arg(long, value_name = "DAYS", value_parser = parse_prune_days)]
+    pub prune: Option<u64>,
+
+    /// Show what would be removed without removing anything
+    #[arg(long, short = 'n')]
+    pub dry_run: bool,
+
+    /// Scrub the cache: remove downloads even of current versions
+    #[arg(short = 's')]
+    pub scrub: bool,
+}
+
+fn parse_prune_days(value: &str) -> std::result::Result<u64, String> {
+    if value == "all" {
+        return Ok(0);
+    }
+    value
+        .parse()
+        .map_err(|_| format!("expected a number of days or 'all', got '{value}'"))
+}
+
+impl Cleanup {
+    /// A dry run only lists what would go, so it doesn't need the exclusive lock.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub async fn run(&self, _config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let options = CleanupOptions {
+            names: self.names.clone(),
+            prune_days: self.prune,
+            scrub: self.scrub,
+        };
+        let items = cleanup::find_cleanup_items(&cache, &options)?;
+        if items.is_empty() {
+            println!("Nothing to clean up");
+            return Ok(());
+        }
+
+        let mut reclaimed = 0;
+        let mut failures = 0;
+        for item in &items {
+            let label = format!("{} ({})", item.path.display(), format_size(item.size));
+            if self.dry_run {
+                println!("Would remove {}: {}", kind_label(item), label);
+                reclaimed += item.size;
+                continue;
+            }
+            match cleanup::remove_cleanup_item(item) {
+                Ok(()) => {
+                    println!("Removing {}: {}", kind_label(item), label);
+                    reclaimed += item.size;
+                }
+                Err(e) => {
+                    error!("✖ {}", e);
+                    failures += 1;
+                }
+            }
+        }
+
+        if self.dry_run {
+            println!(
+                "This operation would free approximately {} of disk space.",
+                format_size(reclaimed).bold()
+            );
+        } else {
+            println!(
+                "This operation has freed approximately {} of disk space.",
+                format_size(reclaimed).bold()
+            );
+        }
+        if failures > 0 {
+            return Err(SpsError::Generic(format!(
+                "Failed to remove {failures} of {} items",
+                items.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn kind_label(item: &CleanupItem) -> &'static str {
+    match item.kind {
+        CleanupKind::Keg => "old keg",
+        CleanupKind::CaskVersion => "old cask version",
+        CleanupKind::PrivateStoreCopy => "orphaned app copy",
+        CleanupKind::Download => "cached download",
+        CleanupKind::PartialDownload => "partial download",
+    }
+}
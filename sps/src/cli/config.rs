@@ -0,0 +1,110 @@
+// sps/src/cli/config.rs
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use sps_common::config::{self, Config, CONFIG_KEYS};
+use sps_common::error::{Result, SpsError};
+
+/// Show or change settings in the sps config files
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective value of a setting
+    Get {
+        key: String,
+        /// Also print where the value comes from
+        #[arg(long)]
+        show_origin: bool,
+    },
+    /// Write a setting to the user config file
+    Set {
+        key: String,
+        value: String,
+        /// Write to /etc/sps/config.toml instead
+        #[arg(long)]
+        system: bool,
+    },
+    /// Remove a setting from the user config file
+    Unset {
+        key: String,
+        /// Remove from /etc/sps/config.toml instead
+        #[arg(long)]
+        system: bool,
+    },
+    /// List all settings with their effective values
+    List {
+        /// Also print where each value comes from
+        #[arg(long)]
+        show_origin: bool,
+    },
+}
+
+impl ConfigArgs {
+    pub async fn run(&self, config: &Config) -> Result<()> {
+        match &self.command {
+            ConfigCommand::Get { key, show_origin } => {
+                let value = config.get(key)?.unwrap_or_default();
+                if *show_origin {
+                    println!("{}\t{}", config.origin(key), value);
+                } else {
+                    println!("{value}");
+                }
+            }
+            ConfigCommand::Set { key, value, system } => {
+                let path = target_file(*system)?;
+                config::write_config_value(&path, key, Some(value))?;
+                println!("✓ Set {} in {}", key.cyan(), path.display());
+                warn_if_shadowed(key, &path)?;
+            }
+            ConfigCommand::Unset { key, system } => {
+                let path = target_file(*system)?;
+                config::write_config_value(&path, key, None)?;
+                println!("✓ Removed {} from {}", key.cyan(), path.display());
+            }
+            ConfigCommand::List { show_origin } => {
+                for def in CONFIG_KEYS {
+                    let value = match config.get(def.name)? {
+                        Some(_) if def.secret => "<set>".to_string(),
+                        Some(value) => value,
+                        None => String::new(),
+                    };
+                    if *show_origin {
+                        println!("{}\t{} = {}", config.origin(def.name), def.name, value);
+                    } else {
+                        println!("{} = {}", def.name, value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn target_file(system: bool) -> Result<PathBuf> {
+    if system {
+        return Ok(config::system_config_path());
+    }
+    config::user_config_path()
+        .ok_or_else(|| SpsError::Config("Could not determine the home directory".to_string()))
+}
+
+/// Points out when the value just written is overridden by a later layer.
+fn warn_if_shadowed(key: &str, written: &std::path::Path) -> Result<()> {
+    let reloaded = Config::load()?;
+    let origin = reloaded.origin(key);
+    if origin != config::ConfigOrigin::File(written.to_path_buf()) {
+        eprintln!(
+            "{} {} is overridden by {}",
+            "Warning:".yellow(),
+            key,
+            origin
+        );
+    }
+    Ok(())
+}
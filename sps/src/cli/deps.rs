@@ -0,0 +1,281 @@
+//! Contains the logic for the `deps` command.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use serde::Serialize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::dependency::resolver::{NodeInstallStrategy, PerTargetInstallPreferences};
+use sps_common::dependency::{
+    DependencyResolver, DependencyTag, ResolutionContext, ResolutionStatus, ResolvedDependency,
+};
+use sps_common::error::Result;
+use sps_common::formulary::Formulary;
+use sps_common::index::IndexKind;
+use sps_common::keg::KegRegistry;
+use sps_common::pipeline::JobAction;
+use sps_net::api;
+
+#[derive(Args, Debug)]
+pub struct Deps {
+    /// Name of the formula
+    pub name: String,
+
+    /// Show dependencies as a tree
+    #[arg(long)]
+    pub tree: bool,
+
+    /// Only show dependencies that are installed
+    #[arg(long)]
+    pub installed: bool,
+
+    /// Include build dependencies
+    #[arg(long)]
+    pub include_build: bool,
+
+    /// Include optional dependencies
+    #[arg(long)]
+    pub include_optional: bool,
+
+    /// Print the dependencies as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Serialize)]
+struct DepEntry {
+    name: String,
+    tags: Vec<&'static str>,
+    status: &'static str,
+    installed: bool,
+}
+
+#[derive(Serialize)]
+struct DepNode {
+    name: String,
+    tags: Vec<&'static str>,
+    installed: bool,
+    dependencies: Vec<DepNode>,
+}
+
+impl Deps {
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        // The formulary reads the cached formula index; make sure there is one.
+        api::load_or_fetch_index(IndexKind::Formula, &cache).await?;
+
+        let formulary = Formulary::new(config.clone());
+        let keg_registry = KegRegistry::new(config.clone());
+        let name = formulary.load_formula(&self.name)?.name().to_string();
+
+        let preferences = PerTargetInstallPreferences::default();
+        let actions = HashMap::new();
+        // The resolver takes its context by value; the tree walk needs one of its own.
+        let ctx = listing_context(
+            &formulary,
+            &keg_registry,
+            config,
+            &preferences,
+            &actions,
+            self.include_build,
+            self.include_optional,
+        );
+        let graph = DependencyResolver::new(listing_context(
+            &formulary,
+            &keg_registry,
+            config,
+            &preferences,
+            &actions,
+            self.include_build,
+            self.include_optional,
+        ))
+        .resolve_targets(std::slice::from_ref(&name))?;
+        let details = &graph.resolution_details;
+        let is_installed = |dep: &str| matches!(keg_registry.get_installed_keg(dep), Ok(Some(_)));
+
+        if self.tree {
+            let root = build_tree(
+                &name,
+                DependencyTag::empty(),
+                details,
+                &ctx,
+                &is_installed,
+                self.installed,
+                &mut Vec::new(),
+            );
+            if self.json {
+                println!("{}", serde_json::to_string_pretty(&root)?);
+            } else {
+                println!("{}", root.name.bold());
+                print_children(&root.dependencies, "");
+            }
+            return Ok(());
+        }
+
+        let mut entries: Vec<DepEntry> = details
+            .iter()
+            .filter(|(dep_name, _)| **dep_name != name)
+            .map(|(dep_name, dep)| DepEntry {
+                name: dep_name.clone(),
+                tags: tag_labels(dep.accumulated_tags),
+                status: status_label(dep.status),
+                installed: is_installed(dep_name),
+            })
+            .filter(|entry| !self.installed || entry.installed)
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+            return Ok(());
+        }
+        for entry in &entries {
+            println!(
+                "{}",
+                format_entry(&entry.name, &entry.tags, entry.installed, entry.status)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The resolution settings `deps` and `uses` list dependencies with: every formula is taken to
+/// be poured from a bottle, so pure build dependencies are skipped, unless `include_build`
+/// treats every formula as built from source.
+pub(crate) fn listing_context<'a>(
+    formulary: &'a Formulary,
+    keg_registry: &'a KegRegistry,
+    config: &'a Config,
+    preferences: &'a PerTargetInstallPreferences,
+    actions: &'a HashMap<String, JobAction>,
+    include_build: bool,
+    include_optional: bool,
+) -> ResolutionContext<'a> {
+    ResolutionContext {
+        formulary,
+        keg_registry,
+        sps_prefix: config.sps_root(),
+        include_optional,
+        include_test: false,
+        skip_recommended: false,
+        initial_target_preferences: preferences,
+        build_all_from_source: include_build,
+        cascade_source_preference_to_dependencies: true,
+        has_bottle_for_current_platform: |_| true,
+        initial_target_actions: actions,
+    }
+}
+
+/// The strategy [`listing_context`] resolves every formula with.
+pub(crate) fn listing_strategy(include_build: bool) -> NodeInstallStrategy {
+    if include_build {
+        NodeInstallStrategy::SourceOnly
+    } else {
+        NodeInstallStrategy::BottlePreferred
+    }
+}
+
+pub(crate) fn tag_labels(tags: DependencyTag) -> Vec<&'static str> {
+    [
+        (DependencyTag::RUNTIME, "runtime"),
+        (DependencyTag::BUILD, "build"),
+        (DependencyTag::TEST, "test"),
+        (DependencyTag::OPTIONAL, "optional"),
+        (DependencyTag::RECOMMENDED, "recommended"),
+    ]
+    .into_iter()
+    .filter(|(tag, _)| tags.contains(*tag))
+    .map(|(_, label)| label)
+    .collect()
+}
+
+fn status_label(status: ResolutionStatus) -> &'static str {
+    match status {
+        ResolutionStatus::Installed => "installed",
+        ResolutionStatus::Missing | ResolutionStatus::Requested => "missing",
+        ResolutionStatus::SkippedOptional => "skipped",
+        ResolutionStatus::NotFound => "not found",
+        ResolutionStatus::Failed => "failed",
+    }
+}
+
+/// `name`, followed by its tags unless it is a plain runtime dependency and a marker for
+/// installed or unknown formulae.
+pub(crate) fn format_entry(name: &str, tags: &[&str], installed: bool, status: &str) -> String {
+    let mut line = if installed {
+        format!("{} {}", name, "✓".green())
+    } else {
+        name.to_string()
+    };
+    if tags != ["runtime"] && !tags.is_empty() {
+        line.push_str(&format!(" {}", format!("({})", tags.join(", ")).dimmed()));
+    }
+    if status == "not found" {
+        line.push_str(&format!(" {}", "(not found)".red()));
+    }
+    line
+}
+
+/// Builds the dependency tree below `name` from the resolved graph, following the edges the
+/// resolver followed. A dependency that is its own ancestor is not expanded again.
+fn build_tree(
+    name: &str,
+    tags: DependencyTag,
+    details: &HashMap<String, ResolvedDependency>,
+    ctx: &ResolutionContext<'_>,
+    is_installed: &dyn Fn(&str) -> bool,
+    installed_only: bool,
+    ancestors: &mut Vec<String>,
+) -> DepNode {
+    let mut node = DepNode {
+        name: name.to_string(),
+        tags: tag_labels(tags),
+        installed: is_installed(name),
+        dependencies: Vec::new(),
+    };
+    let Some(resolved) = details.get(name) else {
+        return node;
+    };
+    if ancestors.iter().any(|a| a == name) {
+        return node;
+    }
+    ancestors.push(name.to_string());
+    for dep in resolved.formula.dependencies().unwrap_or_default() {
+        if !details.contains_key(&dep.name)
+            || !ctx.should_process_dependency_edge(
+                &resolved.formula,
+                dep.tags,
+                resolved.determined_install_strategy,
+            )
+            || (installed_only && !is_installed(&dep.name))
+        {
+            continue;
+        }
+        node.dependencies.push(build_tree(
+            &dep.name,
+            dep.tags,
+            details,
+            ctx,
+            is_installed,
+            installed_only,
+            ancestors,
+        ));
+    }
+    ancestors.pop();
+    node
+}
+
+fn print_children(children: &[DepNode], prefix: &str) {
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        println!(
+            "{}{} {}",
+            prefix,
+            if last { "└──" } else { "├──" },
+            format_entry(&child.name, &child.tags, child.installed, "")
+        );
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        print_children(&child.dependencies, &child_prefix);
+    }
+}
@@ -8,8 +8,13 @@ use serde_json::Value;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
+use sps_common::index::{IndexKind, MetadataIndex};
+use sps_common::model::cask::Cask;
+use sps_core::install::cask::xar::{PkgContents, PkgFileKind};
 use sps_net::api;
 
+use crate::cli::uninstall::format_size;
+
 #[derive(Args, Debug)]
 pub struct Info {
     /// Name of the formula or cask
@@ -18,6 +23,10 @@ pub struct Info {
     /// Show information for a cask, not a formula
     #[arg(long)]
     pub cask: bool,
+
+    /// List the files the cask's pkg installers would install (downloads the cask)
+    #[arg(long)]
+    pub files: bool,
 }
 
 impl Info {
@@ -30,7 +39,14 @@ impl Info {
         // Print loading message instead of spinner
         println!("Loading info for {name}");
 
-        if self.cask {
+        if self.files {
+            let info = get_cask_info(Arc::clone(&cache), name).await?;
+            print_cask_info(name, &info);
+            let cask: Cask = serde_json::from_value(info)?;
+            let pkgs = inspect_cask_pkgs(&cask, &cache).await?;
+            print_pkg_contents(&pkgs, true);
+            Ok(())
+        } else if self.cask {
             match get_cask_info(Arc::clone(&cache), name).await {
                 Ok(info) => {
                     print_cask_info(name, &info);
@@ -70,80 +86,120 @@ impl Info {
 
 /// Retrieves formula information from the cache or API as raw JSON
 async fn get_formula_info_raw(cache: Arc<Cache>, name: &str) -> Result<Value> {
-    match cache.load_raw("formula.json") {
-        Ok(formula_data) => {
-            let formulas: Vec<Value> =
-                serde_json::from_str(&formula_data).map_err(SpsError::from)?;
-            for formula in formulas {
-                if let Some(fname) = formula.get("name").and_then(Value::as_str) {
-                    if fname == name {
-                        return Ok(formula);
-                    }
-                }
-                // Also check aliases if needed
-                if let Some(aliases) = formula.get("aliases").and_then(|a| a.as_array()) {
-                    if aliases.iter().any(|a| a.as_str() == Some(name)) {
-                        return Ok(formula);
-                    }
-                }
-            }
-            tracing::debug!("Formula '{}' not found within cached 'formula.json'.", name);
-            // Explicitly return NotFound if not in cache
-            return Err(SpsError::NotFound(format!(
-                "Formula '{name}' not found in cache"
-            )));
-        }
-        Err(e) => tracing::debug!(
-            "Cache file 'formula.json' not found or failed to load ({}). Fetching from API.",
-            e
-        ),
+    if let Some(value) = lookup_cached(&cache, IndexKind::Formula, name)? {
+        return Ok(value);
     }
     tracing::debug!("Fetching formula '{}' directly from API", name);
-    // api::fetch_formula returns Value directly now
-    let value = api::fetch_formula(name).await?;
-    // Store in cache if fetched successfully
-    // Note: This might overwrite the full list cache, consider storing individual files or a map
-    // cache.store_raw(&format!("formula/{}.json", name), &value.to_string())?; // Example of
-    // storing individually
-    Ok(value)
+    api::fetch_formula(name, cache.config()).await
 }
 
 /// Retrieves cask information from the cache or API
 async fn get_cask_info(cache: Arc<Cache>, name: &str) -> Result<Value> {
-    match cache.load_raw("cask.json") {
-        Ok(cask_data) => {
-            let casks: Vec<Value> = serde_json::from_str(&cask_data).map_err(SpsError::from)?;
-            for cask in casks {
-                if let Some(token) = cask.get("token").and_then(Value::as_str) {
-                    if token == name {
-                        return Ok(cask);
-                    }
-                }
-                // Check aliases if needed
-                if let Some(aliases) = cask.get("aliases").and_then(|a| a.as_array()) {
-                    if aliases.iter().any(|a| a.as_str() == Some(name)) {
-                        return Ok(cask);
+    if let Some(value) = lookup_cached(&cache, IndexKind::Cask, name)? {
+        return Ok(value);
+    }
+    tracing::debug!("Fetching cask '{}' directly from API", name);
+    api::fetch_cask(name, cache.config()).await
+}
+
+/// Downloads the cask (or reuses the cached download) and reads its pkg installers in-process.
+pub(crate) async fn inspect_cask_pkgs(
+    cask: &Cask,
+    cache: &Cache,
+) -> Result<Vec<(String, PkgContents)>> {
+    let download_path = sps_core::install::cask::download_cask(cask, cache).await?;
+    sps_core::install::cask::inspect_cask_pkgs(cask, &download_path)
+}
+
+/// Prints what each pkg installer would put on the system: its receipt IDs, versions and
+/// install locations, and with `list_files` every path in its bill of materials.
+pub(crate) fn print_pkg_contents(pkgs: &[(String, PkgContents)], list_files: bool) {
+    if pkgs.is_empty() {
+        println!("\n{}", "No pkg installers".blue().bold());
+        return;
+    }
+    for (name, contents) in pkgs {
+        let title = match &contents.title {
+            Some(title) => format!("Package: {name} ({title})"),
+            None => format!("Package: {name}"),
+        };
+        println!("\n{}", title.blue().bold());
+
+        let mut table = prettytable::Table::new();
+        table.set_format(*prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        table.add_row(prettytable::row![
+            "Receipt ID".yellow(),
+            "Version".yellow(),
+            "Location".yellow(),
+            "Files".yellow(),
+            "Size".yellow(),
+            "Scripts".yellow()
+        ]);
+        for component in &contents.components {
+            let scripts = if component.has_scripts { "yes" } else { "no" };
+            table.add_row(prettytable::row![
+                component.identifier,
+                component.version.as_deref().unwrap_or("-"),
+                component.install_location.display(),
+                component.files.len(),
+                format_size(component.installed_size()),
+                scripts
+            ]);
+        }
+        table.printstd();
+
+        if list_files {
+            for component in &contents.components {
+                println!("\n{}", component.identifier.bold());
+                for file in &component.files {
+                    match (&file.kind, &file.link_target) {
+                        (PkgFileKind::Directory, _) => println!("  {}/", file.path.display()),
+                        (PkgFileKind::Symlink, Some(target)) => {
+                            println!("  {} -> {target}", file.path.display())
+                        }
+                        _ => println!("  {}", file.path.display()),
                     }
                 }
             }
-            tracing::debug!("Cask '{}' not found within cached 'cask.json'.", name);
-            // Explicitly return NotFound if not in cache
-            return Err(SpsError::NotFound(format!(
-                "Cask '{name}' not found in cache"
-            )));
         }
-        Err(e) => tracing::debug!(
-            "Cache file 'cask.json' not found or failed to load ({}). Fetching from API.",
-            e
-        ),
     }
-    tracing::debug!("Fetching cask '{}' directly from API", name);
-    // api::fetch_cask returns Value directly now
-    let value = api::fetch_cask(name).await?;
-    // Store in cache if fetched successfully
-    // cache.store_raw(&format!("cask/{}.json", name), &value.to_string())?; // Example of storing
-    // individually
-    Ok(value)
+}
+
+/// Looks `name` up in the index of the cached API data. `Ok(None)` means there is no cached data
+/// to consult; a name missing from the cached data is `NotFound`.
+fn lookup_cached(cache: &Cache, kind: IndexKind, name: &str) -> Result<Option<Value>> {
+    let index = match MetadataIndex::open(cache, kind) {
+        Ok(Some(index)) => index,
+        Ok(None) => {
+            tracing::debug!("'{}' is not cached. Fetching from API.", kind.source_file());
+            return Ok(None);
+        }
+        Err(e) => {
+            tracing::debug!(
+                "Failed to open the index of '{}' ({}). Fetching from API.",
+                kind.source_file(),
+                e
+            );
+            return Ok(None);
+        }
+    };
+    match index.get_raw(name)? {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => {
+            tracing::debug!(
+                "'{}' not found within cached '{}'.",
+                name,
+                kind.source_file()
+            );
+            let what = match kind {
+                IndexKind::Formula => "Formula",
+                IndexKind::Cask => "Cask",
+            };
+            Err(SpsError::NotFound(format!(
+                "{what} '{name}' not found in cache"
+            )))
+        }
+    }
 }
 
 /// Prints formula information in a formatted table
@@ -3,11 +3,16 @@
 use std::sync::Arc;
 
 use clap::Args;
+use colored::Colorize;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
-use sps_common::error::Result;
+use sps_common::error::{Result, SpsError};
+use sps_common::index::{IndexKind, MetadataIndex};
+use sps_common::model::cask::Cask;
+use sps_common::model::formula::Formula;
 use tracing::instrument;
 
+use crate::cli::info::{inspect_cask_pkgs, print_pkg_contents};
 // Import pipeline components from the new module
 use crate::pipeline::runner::{self, CommandType, PipelineFlags};
 
@@ -33,6 +38,11 @@ pub struct InstallArgs {
         help = "Force building the formula from source, even if a bottle is available"
     )]
     build_from_source: bool,
+    #[arg(
+        long,
+        help = "Show what the casks' pkg installers would install without installing anything (casks only)"
+    )]
+    dry_run: bool,
     // Worker/Queue size flags might belong here or be global CLI flags
     // #[arg(long, value_name = "sps_WORKERS")]
     // max_workers: Option<usize>,
@@ -41,15 +51,23 @@ pub struct InstallArgs {
 }
 
 impl InstallArgs {
+    /// A dry run only downloads and reads installers, so it doesn't need the exclusive lock.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
     #[instrument(skip(self, config, cache), fields(targets = ?self.names))]
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
         // --- Argument Validation (moved from old run) ---
         if self.formula && self.cask {
-            return Err(sps_common::error::SpsError::Generic(
+            return Err(SpsError::Generic(
                 "Cannot use --formula and --cask together.".to_string(),
             ));
         }
         // Add validation for skip_deps if needed
+        if self.dry_run {
+            return self.preview_pkgs(&cache).await;
+        }
 
         // --- Prepare Pipeline Flags ---
         let flags = PipelineFlags {
@@ -73,4 +91,87 @@ impl InstallArgs {
         )
         .await
     }
+
+    /// Reads each cask's pkg installers in-process and prints their receipt IDs and install
+    /// locations. Errors are reported per target so one bad cask doesn't hide the rest.
+    /// Formulae have no installers to read, so a dry run naming one is refused up front.
+    async fn preview_pkgs(&self, cache: &Cache) -> Result<()> {
+        let formulae = if self.cask {
+            None
+        } else {
+            MetadataIndex::open(cache, IndexKind::Formula)
+                .ok()
+                .flatten()
+        };
+        let casks = MetadataIndex::open(cache, IndexKind::Cask).ok().flatten();
+        let mut targets = Vec::with_capacity(self.names.len());
+        let mut formula_names = Vec::new();
+        for name in &self.names {
+            match self
+                .resolve_cask(name, formulae.as_ref(), casks.as_ref(), cache.config())
+                .await
+            {
+                Ok(None) => formula_names.push(name.as_str()),
+                Ok(Some(cask)) => targets.push((name, Ok(cask))),
+                Err(e) => targets.push((name, Err(e))),
+            }
+        }
+        if !formula_names.is_empty() {
+            return Err(SpsError::Generic(format!(
+                "--dry-run only previews the pkg installers of casks, and {} {}. Install formulae without --dry-run, or pass --cask for a cask of the same name.",
+                formula_names.join(", "),
+                if formula_names.len() == 1 { "is a formula" } else { "are formulae" },
+            )));
+        }
+
+        let mut failed = false;
+        for (name, cask) in targets {
+            println!("{}", format!("Cask: {name}").green().bold());
+            let pkgs = match cask {
+                Ok(cask) => inspect_cask_pkgs(&cask, cache).await,
+                Err(e) => Err(e),
+            };
+            match pkgs {
+                Ok(pkgs) => print_pkg_contents(&pkgs, false),
+                Err(e) => {
+                    failed = true;
+                    eprintln!("{} {name}: {e}", "Error:".red().bold());
+                }
+            }
+        }
+        if failed {
+            return Err(SpsError::Generic(
+                "Some casks could not be inspected".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resolves `name` the way the install pipeline does: a formula unless `--cask` is given or
+    /// no formula has that name. `Ok(None)` means it is a formula.
+    async fn resolve_cask(
+        &self,
+        name: &str,
+        formulae: Option<&MetadataIndex>,
+        casks: Option<&MetadataIndex>,
+        config: &Config,
+    ) -> Result<Option<Cask>> {
+        if self.formula {
+            return Ok(None);
+        }
+        if formulae.is_some_and(|index| index.get::<Formula>(name).ok().flatten().is_some()) {
+            return Ok(None);
+        }
+        if let Some(Ok(Some(cask))) = casks.map(|index| index.get::<Cask>(name)) {
+            return Ok(Some(cask));
+        }
+        if !self.cask {
+            match sps_net::api::get_formula(name, config).await {
+                Ok(_) => return Ok(None),
+                Err(SpsError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        sps_net::api::get_cask(name, config).await.map(Some)
+    }
 }
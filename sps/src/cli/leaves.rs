@@ -0,0 +1,22 @@
+//! Contains the logic for the `leaves` command.
+use std::sync::Arc;
+
+use clap::Args;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::Result;
+use sps_core::check::InstalledDependencies;
+
+#[derive(Args, Debug)]
+pub struct Leaves;
+
+impl Leaves {
+    /// Lists formulae installed on request that no other installed formula depends on.
+    pub async fn run(&self, _config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let deps = InstalledDependencies::load(&cache)?;
+        for name in deps.leaves() {
+            println!("{name}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+// sps/src/cli/link.rs
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_core::check::{installed, PackageType};
+use sps_core::install::bottle::link::{self, LinkOptions, LinkOverride};
+use tracing::error;
+
+#[derive(Args, Debug)]
+pub struct Link {
+    /// The installed formulas to link into the prefix
+    #[arg(required = true)]
+    pub names: Vec<String>,
+    /// Link keg-only formulas too. The choice is kept across upgrades and reinstalls.
+    #[arg(long)]
+    pub force: bool,
+    /// Replace conflicting files owned by other formulas or not managed by sps
+    #[arg(long)]
+    pub overwrite: bool,
+    /// Show what would be linked and which files conflict, without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Link {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let mut failed = 0;
+        for name in &self.names {
+            if let Err(e) = self.link_one(name, config).await {
+                error!("✖ Failed to link '{}': {}", name.cyan(), e);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(SpsError::Generic(format!(
+                "Failed to link {failed} formula(s)"
+            )));
+        }
+        Ok(())
+    }
+
+    async fn link_one(&self, name: &str, config: &Config) -> Result<()> {
+        let info = installed::get_installed_package(name, config)
+            .await?
+            .ok_or_else(|| SpsError::NotFound(format!("Formula '{name}' is not installed")))?;
+        if info.pkg_type != PackageType::Formula {
+            return Err(SpsError::Generic(format!(
+                "'{name}' is a cask; only formulas can be linked"
+            )));
+        }
+        let manifest = link::read_install_manifest(&info.path)?.unwrap_or_default();
+        if manifest.keg_only && !self.force {
+            return Err(SpsError::Generic(format!(
+                "'{name}' is keg-only and was not linked into {}. Use `sps link --force {name}` to link it anyway.",
+                config.sps_root().display()
+            )));
+        }
+        let link_override = self.force.then_some(LinkOverride::Forced);
+        let options = LinkOptions {
+            overwrite: self.overwrite,
+            dry_run: self.dry_run,
+        };
+        let outcome = link::relink_keg(name, &info.path, config, link_override, &options)?;
+
+        if !self.dry_run {
+            println!(
+                "✓ Linked {} {} ({} links)",
+                name.green(),
+                info.version,
+                outcome.links.len()
+            );
+            for replaced in &outcome.overwritten {
+                println!("  {} {}", "Overwrote".yellow(), replaced);
+            }
+            return Ok(());
+        }
+
+        println!(
+            "Would link {} {} ({} links):",
+            name.green(),
+            info.version,
+            outcome.links.len()
+        );
+        for link in &outcome.links {
+            println!("  {link}");
+        }
+        if !outcome.overwritten.is_empty() {
+            println!("Would overwrite:");
+            for replaced in &outcome.overwritten {
+                println!("  {replaced}");
+            }
+        }
+        if !outcome.conflicts.is_empty() {
+            println!("{}", "Conflicts (linking would fail):".red());
+            for conflict in &outcome.conflicts {
+                println!("  {conflict}");
+            }
+        }
+        Ok(())
+    }
+}
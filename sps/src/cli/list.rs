@@ -6,8 +6,11 @@ use prettytable::{format, Cell, Row, Table};
 use serde_json::Value;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
-use sps_common::error::Result;
+use sps_common::error::{Result, SpsError};
 use sps_common::formulary::Formulary;
+use sps_common::keg::{InstalledKeg, KegRegistry};
+use sps_common::model::Formula;
+use sps_common::pin::PinnedPackages;
 use sps_core::check::installed::{get_installed_packages, PackageType};
 use sps_core::check::update::check_for_updates;
 use sps_core::check::InstalledPackageInfo;
@@ -23,11 +26,18 @@ pub struct List {
     /// Show only packages with updates available
     #[arg(long = "outdated")]
     pub outdated_only: bool,
+    /// Show every installed version of a formula and which one is active
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["cask_only", "outdated_only"])]
+    pub versions: Option<String>,
 }
 
 impl List {
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        if let Some(name) = &self.versions {
+            return print_versions(name, config);
+        }
         let installed = get_installed_packages(config).await?;
+        let pins = &PinnedPackages::load(config)?;
         // Only show the latest version for each name
         use std::collections::HashMap;
         let mut formula_map: HashMap<&str, &sps_core::check::installed::InstalledPackageInfo> =
@@ -63,27 +73,27 @@ impl List {
         // If user wants to show installed formulas only.
         if self.formula_only {
             if self.outdated_only {
-                self.print_outdated_formulas_table(&formulas, config)
+                self.print_outdated_formulas_table(&formulas, config, pins)
                     .await?;
             } else {
-                self.print_formulas_table(formulas, config);
+                self.print_formulas_table(formulas, config, pins);
             }
             return Ok(());
         }
         // If user wants to show installed casks only.
         if self.cask_only {
             if self.outdated_only {
-                self.print_outdated_casks_table(&casks, cache.clone())
+                self.print_outdated_casks_table(&casks, cache.clone(), pins)
                     .await?;
             } else {
-                self.print_casks_table(casks, cache);
+                self.print_casks_table(casks, cache, pins);
             }
             return Ok(());
         }
 
         // If user wants to show only outdated packages
         if self.outdated_only {
-            self.print_outdated_all_table(&formulas, &casks, config, cache)
+            self.print_outdated_all_table(&formulas, &casks, config, cache, pins)
                 .await?;
             return Ok(());
         }
@@ -105,13 +115,13 @@ impl List {
             let (has_new, _) = match latest {
                 Some(ref f) => {
                     let latest_version = f.version_str_full();
-                    (latest_version != pkg.version, latest_version)
+                    (is_newer(f, pkg), latest_version)
                 }
                 None => (false, "-".to_string()),
             };
             table.add_row(Row::new(vec![
                 Cell::new("Formula").style_spec("Fg"),
-                Cell::new(&pkg.name).style_spec("Fb"),
+                name_cell(&pkg.name, pins),
                 Cell::new(&pkg.version),
                 // TODO: update to display the latest version string.
                 // TODO: Not showing when the using --all flag.
@@ -136,7 +146,7 @@ impl List {
             };
             table.add_row(Row::new(vec![
                 Cell::new("Cask").style_spec("Fy"),
-                Cell::new(&pkg.name).style_spec("Fb"),
+                name_cell(&pkg.name, pins),
                 Cell::new(&pkg.version),
                 Cell::new(if has_new { "✔" } else { "" }),
             ]));
@@ -160,6 +170,7 @@ impl List {
         &self,
         formulas: Vec<&sps_core::check::installed::InstalledPackageInfo>,
         config: &Config,
+        pins: &PinnedPackages,
     ) {
         if formulas.is_empty() {
             println!("No formulas installed.");
@@ -186,12 +197,12 @@ impl List {
             let (has_new, _) = match latest {
                 Some(ref f) => {
                     let latest_version = f.version_str_full();
-                    (latest_version != pkg.version, latest_version)
+                    (is_newer(f, pkg), latest_version)
                 }
                 None => (false, "-".to_string()),
             };
             table.add_row(Row::new(vec![
-                Cell::new(&pkg.name).style_spec("Fb"),
+                name_cell(&pkg.name, pins),
                 Cell::new(&pkg.version),
                 Cell::new(if has_new { "✔" } else { "" }),
             ]));
@@ -205,6 +216,7 @@ impl List {
         &self,
         casks: Vec<&sps_core::check::installed::InstalledPackageInfo>,
         cache: Arc<Cache>,
+        pins: &PinnedPackages,
     ) {
         if casks.is_empty() {
             println!("No casks installed.");
@@ -241,7 +253,7 @@ impl List {
                 None => (false, "-".to_string()),
             };
             table.add_row(Row::new(vec![
-                Cell::new(&pkg.name).style_spec("Fb"),
+                name_cell(&pkg.name, pins),
                 Cell::new(&pkg.version),
                 Cell::new(if has_new { "✔" } else { "" }),
             ]));
@@ -255,6 +267,7 @@ impl List {
         &self,
         formulas: &[&InstalledPackageInfo],
         config: &Config,
+        pins: &PinnedPackages,
     ) -> Result<()> {
         if formulas.is_empty() {
             println!("No formulas installed.");
@@ -289,7 +302,7 @@ impl List {
         let mut count = 0;
         for update in updates {
             table.add_row(Row::new(vec![
-                Cell::new(&update.name).style_spec("Fb"),
+                name_cell(&update.name, pins),
                 Cell::new(&update.installed_version),
                 Cell::new(&update.available_version).style_spec("Fg"),
             ]));
@@ -305,6 +318,7 @@ impl List {
         &self,
         casks: &[&InstalledPackageInfo],
         cache: Arc<Cache>,
+        pins: &PinnedPackages,
     ) -> Result<()> {
         if casks.is_empty() {
             println!("No casks installed.");
@@ -338,7 +352,7 @@ impl List {
         let mut count = 0;
         for update in updates {
             table.add_row(Row::new(vec![
-                Cell::new(&update.name).style_spec("Fb"),
+                name_cell(&update.name, pins),
                 Cell::new(&update.installed_version),
                 Cell::new(&update.available_version).style_spec("Fy"),
             ]));
@@ -356,6 +370,7 @@ impl List {
         casks: &[&InstalledPackageInfo],
         config: &Config,
         cache: Arc<Cache>,
+        pins: &PinnedPackages,
     ) -> Result<()> {
         // Convert to owned for update checking
         let mut all_packages: Vec<InstalledPackageInfo> = Vec::new();
@@ -400,7 +415,7 @@ impl List {
 
             table.add_row(Row::new(vec![
                 Cell::new(type_name).style_spec(type_style),
-                Cell::new(&update.name).style_spec("Fb"),
+                name_cell(&update.name, pins),
                 Cell::new(&update.installed_version),
                 Cell::new(&update.available_version).style_spec("Fg"),
             ]));
@@ -420,3 +435,49 @@ impl List {
         Ok(())
     }
 }
+
+/// Lists the versions of a formula kept in the Cellar, marking the one that is active.
+fn print_versions(name: &str, config: &Config) -> Result<()> {
+    let keg_registry = KegRegistry::new(config.clone());
+    let kegs = keg_registry.list_kegs(name)?;
+    let linked_keg = keg_registry.get_linked_keg(name);
+    let linked = linked_keg.is_some();
+    let Some(active) = linked_keg.or(keg_registry.get_installed_keg(name)?) else {
+        return Err(SpsError::NotFound(format!(
+            "Formula '{name}' is not installed"
+        )));
+    };
+    println!("{}", name.bold());
+    for keg in &kegs {
+        if keg.path != active.path {
+            println!("  {}", keg.version_str);
+        } else if linked {
+            println!("  {} {}", keg.version_str, "(active)".green());
+        } else {
+            println!("  {} {}", keg.version_str, "(active, not linked)".yellow());
+        }
+    }
+    Ok(())
+}
+
+/// The name column; pinned packages are marked.
+fn name_cell(name: &str, pins: &PinnedPackages) -> Cell {
+    if pins.is_pinned(name) {
+        Cell::new(&format!("{name} (pinned)")).style_spec("Fb")
+    } else {
+        Cell::new(name).style_spec("Fb")
+    }
+}
+
+/// Whether the formula definition is newer than the installed keg, using Homebrew ordering.
+fn is_newer(formula: &Formula, pkg: &InstalledPackageInfo) -> bool {
+    let installed = InstalledKeg {
+        name: pkg.name.clone(),
+        version_str: pkg.version.clone(),
+        path: pkg.path.clone(),
+    };
+    match installed.pkg_version() {
+        Some(current) => formula.pkg_version() > current,
+        None => formula.version_str_full() != pkg.version,
+    }
+}
@@ -0,0 +1,47 @@
+//! Contains the logic for the `pin` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::pin::PinnedPackages;
+use sps_core::check::installed;
+use tracing::error;
+
+#[derive(Args, Debug)]
+pub struct Pin {
+    /// The installed formulas or casks to hold at their current version
+    #[arg(required = true)]
+    pub names: Vec<String>,
+}
+
+impl Pin {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let mut pins = PinnedPackages::load(config)?;
+        let mut failed = 0;
+        for name in &self.names {
+            match installed::get_installed_package(name, config).await? {
+                Some(info) => {
+                    if pins.pin(name) {
+                        println!("Pinned {} at {}", name.cyan(), info.version);
+                    } else {
+                        println!("{} is already pinned", name.cyan());
+                    }
+                }
+                None => {
+                    error!("✖ Cannot pin '{}': not installed", name.cyan());
+                    failed += 1;
+                }
+            }
+        }
+        pins.save(config)?;
+        if failed > 0 {
+            return Err(SpsError::NotFound(format!(
+                "{failed} package(s) could not be pinned"
+            )));
+        }
+        Ok(())
+    }
+}
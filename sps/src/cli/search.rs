@@ -6,7 +6,8 @@ use prettytable::{format, Cell, Row, Table};
 use serde_json::Value;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
-use sps_common::error::Result;
+use sps_common::error::{Result, SpsError};
+use sps_common::index::IndexKind;
 use sps_net::api;
 use terminal_size::{terminal_size, Width};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
@@ -86,135 +87,25 @@ pub async fn run_search(
 }
 
 async fn search_formulas(cache: Arc<Cache>, query: &str) -> Result<Vec<Value>> {
-    let query_lower = query.to_lowercase();
-    let mut matches = Vec::new();
-    let mut data_source_name = "cache";
-
-    let formula_data_result = cache.load_raw("formula.json");
-
-    let formulas: Vec<Value> = match formula_data_result {
-        Ok(formula_data) => serde_json::from_str(&formula_data)?,
-        Err(e) => {
-            tracing::debug!("Formula cache load failed ({}), fetching from API", e);
-            data_source_name = "API";
-            let all_formulas = api::fetch_all_formulas().await?;
-
-            if let Err(cache_err) = cache.store_raw("formula.json", &all_formulas) {
-                tracing::warn!("Failed to cache formula data after fetching: {}", cache_err);
-            }
-            serde_json::from_str(&all_formulas)?
-        }
-    };
-
-    for formula in formulas {
-        if is_formula_match(&formula, &query_lower) {
-            matches.push(formula);
-        }
-    }
-
-    tracing::debug!(
-        "Found {} potential formula matches from {}",
-        matches.len(),
-        data_source_name
-    );
-    tracing::debug!(
-        "Filtered down to {} formula matches with available bottles",
-        matches.len()
-    );
-
+    let matches = search_index(&cache, IndexKind::Formula, query).await?;
+    tracing::debug!("Found {} formula matches", matches.len());
     Ok(matches)
 }
 
 async fn search_casks(cache: Arc<Cache>, query: &str) -> Result<Vec<Value>> {
-    let query_lower = query.to_lowercase();
-    let mut matches = Vec::new();
-    let mut data_source_name = "cache";
-
-    let cask_data_result = cache.load_raw("cask.json");
-
-    let casks: Vec<Value> = match cask_data_result {
-        Ok(cask_data) => serde_json::from_str(&cask_data)?,
-        Err(e) => {
-            tracing::debug!("Cask cache load failed ({}), fetching from API", e);
-            data_source_name = "API";
-            let all_casks = api::fetch_all_casks().await?;
-
-            if let Err(cache_err) = cache.store_raw("cask.json", &all_casks) {
-                tracing::warn!("Failed to cache cask data after fetching: {}", cache_err);
-            }
-            serde_json::from_str(&all_casks)?
-        }
-    };
-
-    for cask in casks {
-        if is_cask_match(&cask, &query_lower) {
-            matches.push(cask);
-        }
-    }
-    tracing::debug!(
-        "Found {} cask matches from {}",
-        matches.len(),
-        data_source_name
-    );
+    let matches = search_index(&cache, IndexKind::Cask, query).await?;
+    tracing::debug!("Found {} cask matches", matches.len());
     Ok(matches)
 }
 
-fn is_formula_match(formula: &Value, query: &str) -> bool {
-    if let Some(name) = formula.get("name").and_then(|n| n.as_str()) {
-        if name.to_lowercase().contains(query) {
-            return true;
-        }
-    }
-
-    if let Some(full_name) = formula.get("full_name").and_then(|n| n.as_str()) {
-        if full_name.to_lowercase().contains(query) {
-            return true;
-        }
-    }
-
-    if let Some(desc) = formula.get("desc").and_then(|d| d.as_str()) {
-        if desc.to_lowercase().contains(query) {
-            return true;
-        }
-    }
-
-    if let Some(aliases) = formula.get("aliases").and_then(|a| a.as_array()) {
-        for alias in aliases {
-            if let Some(alias_str) = alias.as_str() {
-                if alias_str.to_lowercase().contains(query) {
-                    return true;
-                }
-            }
-        }
-    }
-
-    false
-}
-
-fn is_cask_match(cask: &Value, query: &str) -> bool {
-    if let Some(token) = cask.get("token").and_then(|t| t.as_str()) {
-        if token.to_lowercase().contains(query) {
-            return true;
-        }
-    }
-
-    if let Some(names) = cask.get("name").and_then(|n| n.as_array()) {
-        for name in names {
-            if let Some(name_str) = name.as_str() {
-                if name_str.to_lowercase().contains(query) {
-                    return true;
-                }
-            }
-        }
-    }
-
-    if let Some(desc) = cask.get("desc").and_then(|d| d.as_str()) {
-        if desc.to_lowercase().contains(query) {
-            return true;
-        }
-    }
-
-    false
+/// Scans the search text of the `kind` index, fetching the API data first if it isn't cached.
+async fn search_index(cache: &Cache, kind: IndexKind, query: &str) -> Result<Vec<Value>> {
+    api::load_or_fetch_index(kind, cache)
+        .await?
+        .search(&query.to_lowercase())?
+        .iter()
+        .map(|json| serde_json::from_str(json).map_err(SpsError::from))
+        .collect()
 }
 
 fn truncate_vis(s: &str, max: usize) -> String {
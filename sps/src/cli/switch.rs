@@ -0,0 +1,102 @@
+// sps/src/cli/switch.rs
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::keg::KegRegistry;
+use sps_core::install::bottle::link::{self, LinkOptions};
+
+#[derive(Args, Debug)]
+pub struct Switch {
+    /// The installed formula to switch
+    pub name: String,
+    /// The installed version to link instead of the current one
+    #[arg(value_name = "VERSION")]
+    pub target_version: String,
+    /// Replace conflicting files owned by other formulas or not managed by sps
+    #[arg(long)]
+    pub overwrite: bool,
+    /// Show what would be linked and which files conflict, without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Switch {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let name = &self.name;
+        let keg_registry = KegRegistry::new(config.clone());
+        let kegs = keg_registry.list_kegs(name)?;
+        if kegs.is_empty() {
+            return Err(SpsError::NotFound(format!(
+                "Formula '{name}' is not installed"
+            )));
+        }
+        let Some(target) = kegs
+            .iter()
+            .find(|keg| keg.version_str == self.target_version)
+        else {
+            let versions: Vec<&str> = kegs.iter().map(|keg| keg.version_str.as_str()).collect();
+            return Err(SpsError::NotFound(format!(
+                "Version {} of '{name}' is not installed. Installed versions: {}",
+                self.target_version,
+                versions.join(", ")
+            )));
+        };
+        let current = keg_registry.get_linked_keg(name);
+        if current.as_ref().is_some_and(|keg| keg.path == target.path) {
+            println!("{} {} is already active", name.green(), target.version_str);
+            return Ok(());
+        }
+
+        let options = LinkOptions {
+            overwrite: self.overwrite,
+            dry_run: self.dry_run,
+        };
+        let outcome = link::switch_keg(
+            name,
+            current.as_ref().map(|keg| keg.path.as_path()),
+            &target.path,
+            config,
+            &options,
+        )?;
+
+        if !self.dry_run {
+            println!(
+                "✓ Switched {} to {} ({} links)",
+                name.green(),
+                target.version_str,
+                outcome.links.len()
+            );
+            for replaced in &outcome.overwritten {
+                println!("  {} {}", "Overwrote".yellow(), replaced);
+            }
+            return Ok(());
+        }
+
+        println!(
+            "Would switch {} to {} ({} links):",
+            name.green(),
+            target.version_str,
+            outcome.links.len()
+        );
+        for link in &outcome.links {
+            println!("  {link}");
+        }
+        if !outcome.overwritten.is_empty() {
+            println!("Would overwrite:");
+            for replaced in &outcome.overwritten {
+                println!("  {replaced}");
+            }
+        }
+        if !outcome.conflicts.is_empty() {
+            println!("{}", "Conflicts (switching would fail):".red());
+            for conflict in &outcome.conflicts {
+                println!("  {conflict}");
+            }
+        }
+        Ok(())
+    }
+}
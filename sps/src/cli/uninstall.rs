@@ -5,13 +5,14 @@ use clap::Args;
 use colored::Colorize;
 use sps_common::config::Config;
 use sps_common::error::{Result, SpsError};
+use sps_common::index::{IndexKind, MetadataIndex};
 use sps_common::model::cask::Cask;
 use sps_common::Cache;
-use sps_core::check::{installed, PackageType};
+use sps_core::check::{installed, InstalledDependencies, PackageType};
 use sps_core::{uninstall as core_uninstall, UninstallOptions};
 use sps_net::api;
 use tracing::{debug, error, warn};
-use {serde_json, walkdir};
+use walkdir;
 
 #[derive(Args, Debug)]
 pub struct Uninstall {
@@ -25,14 +26,85 @@ pub struct Uninstall {
         help = "Perform a deep clean for casks, removing associated user data, caches, and configuration files. Use with caution!"
     )]
     pub zap: bool,
+    /// Uninstall formulae even if installed formulae still depend on them
+    #[arg(long, conflicts_with = "cascade")]
+    pub ignore_dependencies: bool,
+    /// Also uninstall the installed formulae that depend on them
+    #[arg(long)]
+    pub cascade: bool,
 }
 
 impl Uninstall {
+    /// Checks the installed formulae among the requested names against the formulae and casks
+    /// that depend on them. Returns the names to uninstall in order, with the dependency graph
+    /// unless `--ignore-dependencies` is given; formulae that are still needed are reported in
+    /// `errors` instead, unless `--cascade` adds their formula dependents.
+    fn plan_removals(
+        &self,
+        cache: &Cache,
+        errors: &mut Vec<(String, SpsError)>,
+    ) -> Result<(Vec<String>, Option<InstalledDependencies>)> {
+        if self.ignore_dependencies {
+            return Ok((self.names.clone(), None));
+        }
+        let deps = InstalledDependencies::load(cache)?;
+        let is_formula =
+            |name: &String| !name.contains('/') && !name.contains("..") && deps.is_installed(name);
+        let formulae: Vec<String> = self
+            .names
+            .iter()
+            .filter(|n| is_formula(n))
+            .cloned()
+            .collect();
+        let others: Vec<String> = self
+            .names
+            .iter()
+            .filter(|n| !is_formula(n))
+            .cloned()
+            .collect();
+
+        let candidates: Vec<String> = if self.cascade {
+            let closure = deps.with_dependents(&formulae);
+            let extra: Vec<&str> = closure
+                .iter()
+                .filter(|name| !formulae.contains(name))
+                .map(String::as_str)
+                .collect();
+            if !extra.is_empty() {
+                println!("Also uninstalling dependents: {}", extra.join(", "));
+            }
+            closure.into_iter().collect()
+        } else {
+            formulae
+        };
+
+        let (removable, kept) = deps.removable(&candidates, &others);
+        for (name, dependents) in kept {
+            let hint = if self.cascade {
+                "Uninstall the casks among them first, or use --ignore-dependencies to uninstall it anyway"
+            } else {
+                "Use --ignore-dependencies to uninstall it anyway, or --cascade to uninstall them too"
+            };
+            let msg = format!("'{name}' is required by {}. {hint}", dependents.join(", "));
+            error!("✖ {msg}");
+            errors.push((name, SpsError::DependencyError(msg)));
+        }
+        let removable = removable.into_iter().collect();
+        let order = others
+            .into_iter()
+            .chain(deps.removal_order(&removable))
+            .collect();
+        Ok((order, Some(deps)))
+    }
+
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
-        let names = &self.names;
         let mut errors: Vec<(String, SpsError)> = Vec::new();
+        let (names, deps) = self.plan_removals(&cache, &mut errors)?;
+        // Packages that are still installed although they were meant to go. Their dependencies
+        // are kept too, so that they don't break.
+        let mut not_removed: HashSet<&str> = HashSet::new();
 
-        for name in names {
+        for name in &names {
             // Basic name validation to prevent path traversal
             if name.contains('/') || name.contains("..") {
                 let msg = format!("Invalid package name '{name}' contains disallowed characters");
@@ -41,6 +113,24 @@ impl Uninstall {
                 continue;
             }
 
+            if let Some(deps) = &deps {
+                let dependents = deps.dependents_of(name);
+                let cask_dependents = deps.cask_dependents_of(name);
+                if let Some(dependent) = dependents
+                    .iter()
+                    .chain(&cask_dependents)
+                    .find(|dependent| not_removed.contains(**dependent))
+                {
+                    let msg = format!(
+                        "Not uninstalling '{name}': '{dependent}', which depends on it, was not uninstalled"
+                    );
+                    error!("✖ {msg}");
+                    errors.push((name.to_string(), SpsError::DependencyError(msg)));
+                    not_removed.insert(name);
+                    continue;
+                }
+            }
+
             println!("Uninstalling {name}...");
 
             match installed::get_installed_package(name, config).await {
@@ -71,6 +161,7 @@ impl Uninstall {
                     if let Err(e) = uninstall_result {
                         error!("✖ Failed to uninstall '{}': {}", name.cyan(), e);
                         errors.push((name.to_string(), e));
+                        not_removed.insert(name);
                         // Continue to zap anyway for casks, as per plan
                     } else {
                         println!(
@@ -92,18 +183,23 @@ impl Uninstall {
 
                         // Fetch the Cask definition (needed for the zap stanza)
                         let cask_def_result: Result<Cask> = async {
-                            match api::get_cask(name).await {
+                            // Offline the cached definition is the only one there is.
+                            let api_result = if config.offline {
+                                Err(SpsError::Offline(format!("Cask '{name}'")))
+                            } else {
+                                api::get_cask(name, config).await
+                            };
+                            match api_result {
                                 Ok(cask) => Ok(cask),
                                 Err(e) => {
-                                    warn!("Failed API fetch for zap definition for '{}' ({}), trying cache...", name, e);
-                                    match cache.load_raw("cask.json") {
-                                        Ok(raw_json) => {
-                                            let casks: Vec<Cask> = serde_json::from_str(&raw_json)
-                                                .map_err(|cache_e| SpsError::Cache(format!("Failed parse cached cask.json: {cache_e}")))?;
-                                            casks.into_iter()
-                                                .find(|c| c.token == *name)
-                                                .ok_or_else(|| SpsError::NotFound(format!("Cask '{name}' def not in cache either")))
-                                        }
+                                    if !config.offline {
+                                        warn!("Failed API fetch for zap definition for '{}' ({}), trying cache...", name, e);
+                                    }
+                                    match MetadataIndex::open(&cache, IndexKind::Cask) {
+                                        Ok(Some(index)) => index
+                                            .get::<Cask>(name)?
+                                            .ok_or_else(|| SpsError::NotFound(format!("Cask '{name}' def not in cache either"))),
+                                        Ok(None) => Err(SpsError::Cache("Failed load cask cache for zap: cask.json is not cached".to_string())),
                                         Err(cache_e) => Err(SpsError::Cache(format!("Failed load cask cache for zap: {cache_e}"))),
                                     }
                                 }
@@ -148,6 +244,7 @@ impl Uninstall {
                     let msg = format!("Failed check install status for '{name}': {e}");
                     error!("✖ {msg}");
                     errors.push((name.clone(), SpsError::Generic(msg)));
+                    not_removed.insert(name);
                 }
             }
         }
@@ -210,7 +307,7 @@ fn count_files_and_size(path: &std::path::Path) -> Result<(usize, u64)> {
     Ok((file_count, total_size))
 }
 
-fn format_size(size: u64) -> String {
+pub(crate) fn format_size(size: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
     const GB: u64 = MB * 1024;
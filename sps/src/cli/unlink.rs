@@ -0,0 +1,56 @@
+// sps/src/cli/unlink.rs
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_core::check::{installed, PackageType};
+use sps_core::install::bottle::link::{self, LinkOptions, LinkOverride};
+use tracing::error;
+
+#[derive(Args, Debug)]
+pub struct Unlink {
+    /// The installed formulas to remove from the prefix. Their opt/ link is kept.
+    #[arg(required = true)]
+    pub names: Vec<String>,
+}
+
+impl Unlink {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let mut failed = 0;
+        for name in &self.names {
+            if let Err(e) = unlink_one(name, config).await {
+                error!("✖ Failed to unlink '{}': {}", name.cyan(), e);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(SpsError::Generic(format!(
+                "Failed to unlink {failed} formula(s)"
+            )));
+        }
+        Ok(())
+    }
+}
+
+async fn unlink_one(name: &str, config: &Config) -> Result<()> {
+    let info = installed::get_installed_package(name, config)
+        .await?
+        .ok_or_else(|| SpsError::NotFound(format!("Formula '{name}' is not installed")))?;
+    if info.pkg_type != PackageType::Formula {
+        return Err(SpsError::Generic(format!(
+            "'{name}' is a cask; only formulas can be unlinked"
+        )));
+    }
+    link::relink_keg(
+        name,
+        &info.path,
+        config,
+        Some(LinkOverride::Unlinked),
+        &LinkOptions::default(),
+    )?;
+    println!("✓ Unlinked {} {}", name.green(), info.version);
+    Ok(())
+}
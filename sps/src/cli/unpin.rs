@@ -0,0 +1,39 @@
+//! Contains the logic for the `unpin` command.
+use std::sync::Arc;
+
+use clap::Args;
+use colored::Colorize;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::error::{Result, SpsError};
+use sps_common::pin::PinnedPackages;
+use tracing::error;
+
+#[derive(Args, Debug)]
+pub struct Unpin {
+    /// The pinned formulas or casks to allow upgrading again
+    #[arg(required = true)]
+    pub names: Vec<String>,
+}
+
+impl Unpin {
+    pub async fn run(&self, config: &Config, _cache: Arc<Cache>) -> Result<()> {
+        let mut pins = PinnedPackages::load(config)?;
+        let mut failed = 0;
+        for name in &self.names {
+            if pins.unpin(name) {
+                println!("Unpinned {}", name.cyan());
+            } else {
+                error!("✖ '{}' is not pinned", name.cyan());
+                failed += 1;
+            }
+        }
+        pins.save(config)?;
+        if failed > 0 {
+            return Err(SpsError::NotFound(format!(
+                "{failed} package(s) were not pinned"
+            )));
+        }
+        Ok(())
+    }
+}
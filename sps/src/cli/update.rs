@@ -4,7 +4,8 @@ use std::sync::Arc;
 
 use sps_common::cache::Cache;
 use sps_common::config::Config;
-use sps_common::error::Result;
+use sps_common::error::{Result, SpsError};
+use sps_common::index::{self, IndexKind, MetadataIndex};
 use sps_net::api;
 
 #[derive(clap::Args, Debug)]
@@ -13,38 +14,52 @@ pub struct Update;
 impl Update {
     pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
         tracing::debug!("Running manual update..."); // Log clearly it's the manual one
+        if config.offline {
+            return Err(SpsError::Offline(
+                "updating the package lists needs network access".to_string(),
+            ));
+        }
 
         // Use the ui utility function to create the spinner
         println!("Updating package lists"); // <-- CHANGED
 
         tracing::debug!("Using cache directory: {:?}", config.cache_dir());
 
-        // Fetch and store raw formula data
-        match api::fetch_all_formulas().await {
-            Ok(raw_data) => {
-                cache.store_raw("formula.json", &raw_data)?;
-                tracing::debug!("✓ Successfully cached formulas data");
-                println!("Cached formulas data");
-            }
-            Err(e) => {
-                let err_msg = format!("Failed to fetch/store formulas from API: {e}");
-                tracing::error!("{}", err_msg);
-                println!(); // Clear spinner on error
-                return Err(e);
+        // Refresh the cached indexes; unchanged ones cost a 304.
+        for (kind, index_kind, label) in [
+            ("formula", IndexKind::Formula, "Formulas"),
+            ("cask", IndexKind::Cask, "Casks"),
+        ] {
+            match api::refresh_cached_index(kind, &cache).await {
+                Ok(true) => {
+                    tracing::debug!("✓ Successfully cached {} data", label);
+                    println!("Cached {} data", label.to_lowercase());
+                }
+                Ok(false) => {
+                    tracing::debug!("{} data unchanged on the server", label);
+                    println!("{label} data is up to date");
+                    // The JSON was only touched; keep its index from looking stale.
+                    if let Err(e) = index::restamp(&cache, index_kind) {
+                        tracing::debug!("Could not restamp the {} index: {}", kind, e);
+                    }
+                }
+                Err(e @ SpsError::SignatureError(_)) => {
+                    tracing::error!("Rejected {} index: {}", label, e);
+                    eprintln!(
+                        "The downloaded {} data failed signature verification and may have been tampered with. Keeping the previous data.",
+                        label.to_lowercase()
+                    );
+                    return Err(e);
+                }
+                Err(e) => {
+                    let err_msg = format!("Failed to fetch/store {label} from API: {e}");
+                    tracing::error!("{}", err_msg);
+                    return Err(e);
+                }
             }
-        }
-
-        // Fetch and store raw cask data
-        match api::fetch_all_casks().await {
-            Ok(raw_data) => {
-                cache.store_raw("cask.json", &raw_data)?;
-                tracing::debug!("✓ Successfully cached casks data");
-                println!("Cached casks data");
-            }
-            Err(e) => {
-                let err_msg = format!("Failed to fetch/store casks from API: {e}");
-                tracing::error!("{}", err_msg);
-                return Err(e);
+            // Opening rebuilds the index if it is missing or stale.
+            if let Err(e) = MetadataIndex::open(&cache, index_kind) {
+                tracing::warn!("Failed to build the {} index: {}", kind, e);
             }
         }
 
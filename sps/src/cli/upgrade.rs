@@ -4,6 +4,7 @@ use clap::Args;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
 use sps_common::error::Result;
+use sps_common::pin::PinnedPackages;
 use sps_core::check::installed;
 
 use crate::pipeline::runner::{self, CommandType, PipelineFlags};
@@ -25,7 +26,16 @@ impl UpgradeArgs {
         let targets = if self.all {
             // Get all installed package names
             let installed = installed::get_installed_packages(config).await?;
-            installed.into_iter().map(|p| p.name).collect()
+            let pins = PinnedPackages::load(config)?;
+            let (pinned, unpinned): (Vec<_>, Vec<_>) =
+                installed.into_iter().partition(|p| pins.is_pinned(&p.name));
+            if !pinned.is_empty() {
+                println!("Not upgrading {} pinned package(s):", pinned.len());
+                for p in &pinned {
+                    println!("  {} {}", p.name, p.version);
+                }
+            }
+            unpinned.into_iter().map(|p| p.name).collect()
         } else {
             self.names.clone()
         };
@@ -0,0 +1,134 @@
+//! Contains the logic for the `uses` command.
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use clap::Args;
+use sps_common::cache::Cache;
+use sps_common::config::Config;
+use sps_common::dependency::resolver::PerTargetInstallPreferences;
+use sps_common::error::Result;
+use sps_common::formulary::Formulary;
+use sps_common::index::IndexKind;
+use sps_common::keg::KegRegistry;
+use sps_common::model::formula::Formula;
+use sps_core::check::dependents::receipt_runtime_dependencies;
+use sps_net::api;
+use tracing::debug;
+
+use crate::cli::deps::{format_entry, listing_context, listing_strategy};
+
+#[derive(Args, Debug)]
+pub struct Uses {
+    /// Name of the formula
+    pub name: String,
+
+    /// Only show installed formulae
+    #[arg(long)]
+    pub installed: bool,
+
+    /// Also show formulae that depend on it indirectly
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Include formulae that only need it to build
+    #[arg(long)]
+    pub include_build: bool,
+
+    /// Include formulae that depend on it optionally
+    #[arg(long)]
+    pub include_optional: bool,
+}
+
+impl Uses {
+    pub async fn run(&self, config: &Config, cache: Arc<Cache>) -> Result<()> {
+        let index = api::load_or_fetch_index(IndexKind::Formula, &cache).await?;
+        let formulary = Formulary::new(config.clone());
+        let keg_registry = KegRegistry::new(config.clone());
+
+        let installed: HashSet<String> = keg_registry
+            .list_installed_kegs()?
+            .into_iter()
+            .map(|keg| keg.name)
+            .collect();
+        let target = match formulary.load_formula(&self.name) {
+            Ok(formula) => formula.name().to_string(),
+            Err(_) if installed.contains(&self.name) => self.name.clone(),
+            Err(e) => return Err(e),
+        };
+
+        // Installed formulae, or every formula in the cached index plus installed ones that
+        // have since left it.
+        let mut candidates: BTreeSet<String> = installed.iter().cloned().collect();
+        if !self.installed {
+            candidates.extend(index.names().into_iter().map(str::to_string));
+        }
+
+        let preferences = PerTargetInstallPreferences::default();
+        let actions = HashMap::new();
+        let ctx = listing_context(
+            &formulary,
+            &keg_registry,
+            config,
+            &preferences,
+            &actions,
+            self.include_build,
+            self.include_optional,
+        );
+        let strategy = listing_strategy(self.include_build);
+
+        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+        for name in &candidates {
+            let formula: Arc<Formula> = match index.get::<Formula>(name) {
+                Ok(Some(formula)) => Arc::new(formula),
+                Ok(None) => {
+                    // An installed formula that has left the index: its receipt still records
+                    // what it was built against.
+                    let receipt_deps = keg_registry
+                        .get_installed_keg(name)?
+                        .and_then(|keg| receipt_runtime_dependencies(&keg.path));
+                    match receipt_deps {
+                        Some(deps) => {
+                            for dep in deps {
+                                dependents.entry(dep).or_default().push(name.clone());
+                            }
+                        }
+                        None => debug!("'{}' is not in the formula index, skipping", name),
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    debug!("Failed to read '{}' from the formula index: {}", name, e);
+                    continue;
+                }
+            };
+            for dep in formula.dependencies()? {
+                if ctx.should_process_dependency_edge(&formula, dep.tags, strategy) {
+                    dependents.entry(dep.name).or_default().push(name.clone());
+                }
+            }
+        }
+
+        let mut found = BTreeSet::new();
+        let mut queue = VecDeque::from([target.clone()]);
+        while let Some(name) = queue.pop_front() {
+            for parent in dependents.get(&name).into_iter().flatten() {
+                if *parent != target && found.insert(parent.clone()) && self.recursive {
+                    queue.push_back(parent.clone());
+                }
+            }
+        }
+
+        if found.is_empty() {
+            debug!("No formulae depend on '{}'", target);
+            return Ok(());
+        }
+        for name in &found {
+            let is_installed = installed.contains(name);
+            println!(
+                "{}",
+                format_entry(name, &[], is_installed && !self.installed, "")
+            );
+        }
+        Ok(())
+    }
+}
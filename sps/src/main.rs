@@ -1,4 +1,5 @@
 // sps/src/main.rs
+use std::path::{Path, PathBuf};
 use std::process::{self}; // StdCommand is used
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
@@ -7,8 +8,10 @@ use std::{env, fs};
 use clap::Parser;
 use colored::Colorize;
 use sps_common::cache::Cache;
-use sps_common::config::Config;
+use sps_common::config::{Config, ConfigOrigin};
 use sps_common::error::{Result as spResult, SpsError};
+use sps_common::lock::{LockMode, PrefixLock};
+use sps_core::pipeline::transaction;
 use tracing::level_filters::LevelFilter;
 use tracing::{debug, error, warn}; // Import all necessary tracing macros
 use tracing_subscriber::fmt::writer::MakeWriterExt;
@@ -33,24 +36,13 @@ async fn run_init_command(init_args: &InitArgs, verbose_level: u8) -> spResult<(
         .without_time()
         .try_init();
 
-    let initial_config_for_path = Config::load().map_err(|e| {
-        // Handle error if even basic config loading fails for path determination
+    let config = Config::load().map_err(|e| {
         SpsError::Config(format!(
             "Could not determine sps_root for init (config load failed): {e}"
         ))
     })?;
 
-    // Create a minimal Config struct, primarily for sps_root() and derived paths.
-    let temp_config_for_init = Config {
-        sps_root: initial_config_for_path.sps_root().to_path_buf(),
-        api_base_url: "https://formulae.brew.sh/api".to_string(),
-        artifact_domain: None,
-        docker_registry_token: None,
-        docker_registry_basic_auth: None,
-        github_api_token: None,
-    };
-
-    init_args.run(&temp_config_for_init).await
+    init_args.run(&config).await
 }
 
 #[tokio::main]
@@ -69,7 +61,20 @@ async fn main() -> spResult<()> {
         }
     }
 
-    let config = Config::load().map_err(|e| {
+    // Settings can be inspected and changed before the prefix exists.
+    if let Command::Config(ref config_args) = cli_args.command {
+        let result = match load_config(&cli_args) {
+            Ok(config) => config_args.run(&config).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {:#}", "Error".red().bold(), e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let config = load_config(&cli_args).map_err(|e| {
         SpsError::Config(format!(
             "Could not load config (have you run 'sps init'?): {e}"
         ))
@@ -140,12 +145,33 @@ async fn main() -> spResult<()> {
 
     let needs_update_check = matches!(
         cli_args.command,
-        Command::Install(_) | Command::Search { .. } | Command::Info { .. } | Command::Upgrade(_)
+        Command::Install(_)
+            | Command::Search { .. }
+            | Command::Info { .. }
+            | Command::Deps(_)
+            | Command::Uses(_)
+            | Command::Upgrade(_)
     );
 
+    let wait_for_lock = !cli_args.no_wait;
     if needs_update_check {
-        if let Err(e) = check_and_run_auto_update(&config, Arc::clone(&cache)).await {
-            error!("Error during auto-update check: {}", e); // Use `error!` macro
+        // Auto-update rewrites the cached API data, so it needs the lock exclusively even when
+        // the command itself only reads. Only take it once an update is actually due.
+        if auto_update_timestamp_if_due(&config).is_some() {
+            match PrefixLock::acquire(&config, LockMode::Exclusive, wait_for_lock) {
+                Ok(_update_lock) => {
+                    // Another process may have updated while we waited for the lock.
+                    if let Some(timestamp_file) = auto_update_timestamp_if_due(&config) {
+                        run_auto_update(&config, Arc::clone(&cache), &timestamp_file).await;
+                    }
+                }
+                Err(SpsError::LockError(e)) if !wait_for_lock => {
+                    debug!("Skipping auto-update, the prefix is busy: {}", e);
+                }
+                Err(e) => {
+                    error!("Error during auto-update check: {}", e); // Use `error!` macro
+                }
+            }
         }
     } else {
         debug!(
@@ -155,10 +181,45 @@ async fn main() -> spResult<()> {
         );
     }
 
+    let prefix_lock = match cli_args.command.lock_mode() {
+        Some(mode) => match PrefixLock::acquire(&config, mode, wait_for_lock) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("{}: {:#}", "Error".red().bold(), e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(lock) = &prefix_lock {
+        repair_unfinished_transactions(&config, lock.mode());
+    }
+
+    if matches!(
+        cli_args.command,
+        Command::Install(_) | Command::Reinstall(_) | Command::Upgrade(_)
+    ) {
+        // Running jobs roll back what they changed instead of dying half-way. A second Ctrl-C
+        // exits right away; the journal is then repaired on the next run.
+        tokio::spawn(async {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!(
+                    "\n{} Interrupted, rolling back unfinished changes (press Ctrl-C again to exit now)...",
+                    "Warning:".yellow()
+                );
+                transaction::request_interrupt();
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    process::exit(130);
+                }
+            }
+        });
+    }
+
     // Pass config and cache to the command's run method
     let command_execution_result = match &cli_args.command {
-        Command::Init(_) => {
-            /* This case is handled above and main exits */
+        Command::Init(_) | Command::Config(_) => {
+            /* These cases are handled above and main exits */
             unreachable!()
         }
         _ => cli_args.command.run(&config, cache).await,
@@ -166,11 +227,13 @@ async fn main() -> spResult<()> {
 
     if let Err(e) = command_execution_result {
         // For pipeline commands (Install, Reinstall, Upgrade), errors are already
-        // displayed via the status system, so only log in verbose mode
-        let is_pipeline_command = matches!(
-            cli_args.command,
-            Command::Install(_) | Command::Reinstall(_) | Command::Upgrade(_)
-        );
+        // displayed via the status system, so only log in verbose mode. An install dry run
+        // doesn't go through the pipeline.
+        let is_pipeline_command = match &cli_args.command {
+            Command::Install(command) => !command.is_dry_run(),
+            Command::Reinstall(_) | Command::Upgrade(_) => true,
+            _ => false,
+        };
 
         if is_pipeline_command {
             // Only show error details in verbose mode
@@ -183,25 +246,64 @@ async fn main() -> spResult<()> {
             error!("Command failed: {:#}", e);
             eprintln!("{}: {:#}", "Error".red().bold(), e);
         }
+        // process::exit skips destructors; release the lock and its holder record first.
+        drop(prefix_lock);
         process::exit(1);
     }
 
+    drop(prefix_lock);
+    if transaction::is_interrupted() {
+        process::exit(130);
+    }
     debug!("Command completed successfully."); // Use `debug!` macro
     Ok(())
 }
 
-async fn check_and_run_auto_update(config: &Config, cache: Arc<Cache>) -> spResult<()> {
+/// Loads the layered config and applies global command-line overrides.
+fn load_config(cli_args: &CliArgs) -> spResult<Config> {
+    let mut config = Config::load()?;
+    if cli_args.offline {
+        config.set("offline", "true", ConfigOrigin::Flag("--offline"))?;
+    }
+    Ok(config)
+}
+
+/// Rolls back installs a previous run left unfinished. That is only safe while holding the
+/// exclusive lock; read-only commands just point the problem out.
+fn repair_unfinished_transactions(config: &Config, mode: LockMode) {
+    if mode == LockMode::Exclusive {
+        for target in transaction::recover_unfinished(config) {
+            eprintln!(
+                "{} Rolled back an unfinished operation on {}.",
+                "Warning:".yellow(),
+                target
+            );
+        }
+    } else {
+        let unfinished = transaction::unfinished_transactions(config);
+        if !unfinished.is_empty() {
+            eprintln!(
+                "{} Unfinished operations on {} will be rolled back by the next install, upgrade or uninstall.",
+                "Warning:".yellow(),
+                unfinished.join(", ")
+            );
+        }
+    }
+}
+
+/// Returns the auto-update timestamp file when an update is due: auto-update is enabled, sps is
+/// online and the last update is older than the configured interval. Takes no lock.
+fn auto_update_timestamp_if_due(config: &Config) -> Option<PathBuf> {
     if env::var("SPS_NO_AUTO_UPDATE").is_ok_and(|v| v == "1") {
         debug!("Auto-update disabled via SPS_NO_AUTO_UPDATE=1.");
-        return Ok(());
+        return None;
+    }
+    if config.offline {
+        debug!("Skipping auto-update in offline mode.");
+        return None;
     }
 
-    let default_interval_secs: u64 = 86400;
-    let update_interval_secs = env::var("SPS_AUTO_UPDATE_SECS")
-        .ok()
-        .and_then(|s| s.parse::<u64>().ok())
-        .unwrap_or(default_interval_secs);
-    let update_interval = Duration::from_secs(update_interval_secs);
+    let update_interval = Duration::from_secs(config.auto_update_secs);
     debug!("Auto-update interval: {:?}", update_interval);
 
     let timestamp_file = config.state_dir().join(".sps_last_update_check");
@@ -256,39 +358,44 @@ async fn check_and_run_auto_update(config: &Config, cache: Arc<Cache>) -> spResu
     }
 
     if needs_update {
-        println!(
-            "{}{}",
-            "==> ".bold().blue(),
-            "Running auto-update...".bold()
-        );
-        match cli::update::Update.run(config, cache).await {
-            Ok(_) => {
-                println!(
-                    "{}{}",
-                    "==> ".bold().blue(),
-                    "Auto-update successful.".bold()
-                );
-                match fs::File::create(&timestamp_file) {
-                    Ok(_) => {
-                        debug!("Updated timestamp file: {}", timestamp_file.display());
-                    }
-                    Err(e) => {
-                        warn!(
-                            "Failed to create or update timestamp file '{}': {}",
-                            timestamp_file.display(),
-                            e
-                        );
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Auto-update failed: {}", e);
-                eprintln!("{} Auto-update failed: {}", "Warning:".yellow(), e);
-            }
-        }
+        Some(timestamp_file)
     } else {
         debug!("Skipping auto-update.");
+        None
     }
+}
 
-    Ok(())
+/// Runs `sps update` and refreshes the timestamp file. Failures are reported as warnings; the
+/// command itself still runs on the cached data.
+async fn run_auto_update(config: &Config, cache: Arc<Cache>, timestamp_file: &Path) {
+    println!(
+        "{}{}",
+        "==> ".bold().blue(),
+        "Running auto-update...".bold()
+    );
+    match cli::update::Update.run(config, cache).await {
+        Ok(_) => {
+            println!(
+                "{}{}",
+                "==> ".bold().blue(),
+                "Auto-update successful.".bold()
+            );
+            match fs::File::create(timestamp_file) {
+                Ok(_) => {
+                    debug!("Updated timestamp file: {}", timestamp_file.display());
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to create or update timestamp file '{}': {}",
+                        timestamp_file.display(),
+                        e
+                    );
+                }
+            }
+        }
+        Err(e) => {
+            error!("Auto-update failed: {}", e);
+            eprintln!("{} Auto-update failed: {}", "Warning:".yellow(), e);
+        }
+    }
 }
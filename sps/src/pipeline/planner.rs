@@ -11,8 +11,10 @@ use sps_common::dependency::resolver::{
 };
 use sps_common::error::{Result as SpsResult, SpsError};
 use sps_common::formulary::Formulary;
+use sps_common::index::{IndexKind, MetadataIndex};
 use sps_common::keg::KegRegistry;
 use sps_common::model::{Cask, Formula, InstallTargetIdentifier};
+use sps_common::pin::PinnedPackages;
 use sps_common::pipeline::{JobAction, PipelineEvent, PlannedJob, PlannedOperations};
 use sps_core::check::installed::{self, InstalledPackageInfo, PackageType as CorePackageType};
 use sps_core::check::update::{self, UpdateInfo};
@@ -29,6 +31,8 @@ struct IntermediatePlan {
     initial_ops: HashMap<String, (JobAction, Option<InstallTargetIdentifier>)>,
     errors: Vec<(String, SpsError)>,
     already_satisfied: HashSet<String>,
+    /// Kegs of already installed formulae that were named explicitly.
+    requested_kegs: Vec<PathBuf>,
     processed_globally: HashSet<String>,
     private_store_sources: HashMap<String, PathBuf>,
 }
@@ -44,11 +48,12 @@ pub(crate) async fn fetch_target_definitions(
     }
     let mut futures = JoinSet::new();
 
-    let formulae_map_handle = tokio::spawn(load_or_fetch_formulae_map(cache.clone()));
-    let casks_map_handle = tokio::spawn(load_or_fetch_casks_map(cache.clone()));
+    let formulae_index_handle =
+        tokio::spawn(load_or_fetch_index(cache.clone(), IndexKind::Formula));
+    let casks_index_handle = tokio::spawn(load_or_fetch_index(cache.clone(), IndexKind::Cask));
 
-    let formulae_map = match formulae_map_handle.await {
-        Ok(Ok(map)) => Some(map),
+    let formulae_index = match formulae_index_handle.await {
+        Ok(Ok(index)) => Some(index),
         Ok(Err(e)) => {
             debug!("[FetchDefs] Failed to load/fetch full formulae list: {}", e);
             None
@@ -61,8 +66,8 @@ pub(crate) async fn fetch_target_definitions(
             None
         }
     };
-    let casks_map = match casks_map_handle.await {
-        Ok(Ok(map)) => Some(map),
+    let casks_index = match casks_index_handle.await {
+        Ok(Ok(index)) => Some(index),
         Ok(Err(e)) => {
             debug!("[FetchDefs] Failed to load/fetch full casks list: {}", e);
             None
@@ -78,27 +83,32 @@ pub(crate) async fn fetch_target_definitions(
 
     for name_str in names {
         let name_owned = name_str.to_string();
-        let local_formulae_map = formulae_map.clone();
-        let local_casks_map = casks_map.clone();
+        let local_formulae_index = formulae_index.clone();
+        let local_casks_index = casks_index.clone();
+        let config = cache.config().clone();
 
         futures.spawn(async move {
-            if let Some(ref map) = local_formulae_map {
-                if let Some(f_arc) = map.get(&name_owned) {
-                    return (name_owned, Ok(InstallTargetIdentifier::Formula(f_arc.clone())));
+            if let Some(ref index) = local_formulae_index {
+                match index.get::<Formula>(&name_owned) {
+                    Ok(Some(formula)) => return (name_owned, Ok(InstallTargetIdentifier::Formula(Arc::new(formula)))),
+                    Ok(None) => {}
+                    Err(e) => debug!("[FetchDefs] Cached formula '{}' unreadable: {}", name_owned, e),
                 }
             }
-            if let Some(ref map) = local_casks_map {
-                if let Some(c_arc) = map.get(&name_owned) {
-                    return (name_owned, Ok(InstallTargetIdentifier::Cask(c_arc.clone())));
+            if let Some(ref index) = local_casks_index {
+                match index.get::<Cask>(&name_owned) {
+                    Ok(Some(cask)) => return (name_owned, Ok(InstallTargetIdentifier::Cask(Arc::new(cask)))),
+                    Ok(None) => {}
+                    Err(e) => debug!("[FetchDefs] Cached cask '{}' unreadable: {}", name_owned, e),
                 }
             }
             debug!("[FetchDefs] Definition for '{}' not found in cached lists, fetching directly from API...", name_owned);
-            match sps_net::api::get_formula(&name_owned).await {
+            match sps_net::api::get_formula(&name_owned, &config).await {
                 Ok(formula_obj) => return (name_owned, Ok(InstallTargetIdentifier::Formula(Arc::new(formula_obj)))),
                 Err(SpsError::NotFound(_)) => {}
                 Err(e) => return (name_owned, Err(e)),
             }
-            match sps_net::api::get_cask(&name_owned).await {
+            match sps_net::api::get_cask(&name_owned, &config).await {
                 Ok(cask_obj) => (name_owned, Ok(InstallTargetIdentifier::Cask(Arc::new(cask_obj)))),
                 Err(SpsError::NotFound(_)) => (name_owned.clone(), Err(SpsError::NotFound(format!("Formula or Cask '{name_owned}' not found")))),
                 Err(e) => (name_owned, Err(e)),
@@ -129,56 +139,10 @@ pub(crate) async fn fetch_target_definitions(
     results
 }
 
-async fn load_or_fetch_formulae_map(cache: Arc<Cache>) -> SpsResult<HashMap<String, Arc<Formula>>> {
-    match cache.load_raw("formula.json") {
-        Ok(data) => {
-            let formulas: Vec<Formula> = serde_json::from_str(&data)
-                .map_err(|e| SpsError::Cache(format!("Parse cached formula.json failed: {e}")))?;
-            Ok(formulas
-                .into_iter()
-                .map(|f| (f.name.clone(), Arc::new(f)))
-                .collect())
-        }
-        Err(_) => {
-            debug!("[FetchDefs] Cache miss for formula.json, fetching from API...");
-            let raw_data = sps_net::api::fetch_all_formulas().await?;
-            if let Err(e) = cache.store_raw("formula.json", &raw_data) {
-                warn!("Failed to store formula.json in cache: {}", e);
-            }
-            let formulas: Vec<Formula> =
-                serde_json::from_str(&raw_data).map_err(|e| SpsError::Json(Arc::new(e)))?;
-            Ok(formulas
-                .into_iter()
-                .map(|f| (f.name.clone(), Arc::new(f)))
-                .collect())
-        }
-    }
-}
-
-async fn load_or_fetch_casks_map(cache: Arc<Cache>) -> SpsResult<HashMap<String, Arc<Cask>>> {
-    match cache.load_raw("cask.json") {
-        Ok(data) => {
-            let casks: Vec<Cask> = serde_json::from_str(&data)
-                .map_err(|e| SpsError::Cache(format!("Parse cached cask.json failed: {e}")))?;
-            Ok(casks
-                .into_iter()
-                .map(|c| (c.token.clone(), Arc::new(c)))
-                .collect())
-        }
-        Err(_) => {
-            debug!("[FetchDefs] Cache miss for cask.json, fetching from API...");
-            let raw_data = sps_net::api::fetch_all_casks().await?;
-            if let Err(e) = cache.store_raw("cask.json", &raw_data) {
-                warn!("Failed to store cask.json in cache: {}", e);
-            }
-            let casks: Vec<Cask> =
-                serde_json::from_str(&raw_data).map_err(|e| SpsError::Json(Arc::new(e)))?;
-            Ok(casks
-                .into_iter()
-                .map(|c| (c.token.clone(), Arc::new(c)))
-                .collect())
-        }
-    }
+async fn load_or_fetch_index(cache: Arc<Cache>, kind: IndexKind) -> SpsResult<Arc<MetadataIndex>> {
+    sps_net::api::load_or_fetch_index(kind, &cache)
+        .await
+        .map(Arc::new)
 }
 
 pub(crate) struct OperationPlanner<'a> {
@@ -322,6 +286,9 @@ impl<'a> OperationPlanner<'a> {
                         plan.initial_ops
                             .insert(name.clone(), (JobAction::Install, None));
                     } else {
+                        if installed_info.pkg_type == CorePackageType::Formula {
+                            plan.requested_kegs.push(installed_info.path.clone());
+                        }
                         debug!("Target '{}' already installed and manifest indicates it. Marking as satisfied.", name);
                         plan.already_satisfied.insert(name.clone());
                         plan.processed_globally.insert(name.clone());
@@ -403,6 +370,7 @@ impl<'a> OperationPlanner<'a> {
         &self,
         targets: &[String],
         all: bool,
+        pins: &PinnedPackages,
     ) -> PlanResult<IntermediatePlan> {
         let mut plan = IntermediatePlan::default();
         let packages_to_check = if all {
@@ -415,10 +383,29 @@ impl<'a> OperationPlanner<'a> {
                     ));
                     e
                 })?
+                .into_iter()
+                .filter(|info| {
+                    let pinned = pins.is_pinned(&info.name);
+                    if pinned {
+                        debug!("[Planner] Not upgrading pinned package '{}'", info.name);
+                    }
+                    !pinned
+                })
+                .collect()
         } else {
             let mut specific = Vec::new();
             for name in targets {
                 match self.check_installed_status(name).await {
+                    Ok(Some(info)) if pins.is_pinned(&info.name) => {
+                        plan.errors.push((
+                            name.to_string(),
+                            SpsError::Pinned(format!(
+                                "'{name}' is pinned at {}. Run `sps unpin {name}` to allow upgrading it.",
+                                info.version
+                            )),
+                        ));
+                        plan.processed_globally.insert(name.clone());
+                    }
                     Ok(Some(info)) => {
                         if info.pkg_type == CorePackageType::Cask {
                             let manifest_path = info.path.join("CASK_INSTALL_MANIFEST.json");
@@ -533,12 +520,13 @@ impl<'a> OperationPlanner<'a> {
             command_type, initial_targets
         );
 
+        let pins = PinnedPackages::load(self.config)?;
         let mut intermediate_plan = match command_type {
             CommandType::Install => self.plan_for_install(initial_targets).await?,
             CommandType::Reinstall => self.plan_for_reinstall(initial_targets).await?,
             CommandType::Upgrade { all } => {
                 debug!("[Planner] Calling plan_for_upgrade with all={}", all);
-                let plan = self.plan_for_upgrade(initial_targets, all).await?;
+                let plan = self.plan_for_upgrade(initial_targets, all, &pins).await?;
                 debug!("[Planner] plan_for_upgrade returned with {} initial_ops, {} errors, {} already_satisfied",
                     plan.initial_ops.len(), plan.errors.len(), plan.already_satisfied.len());
                 debug!(
@@ -883,6 +871,16 @@ impl<'a> OperationPlanner<'a> {
                             .private_store_sources
                             .get(name)
                             .cloned(),
+                        installed_on_request: match action {
+                            JobAction::Install => true,
+                            JobAction::Upgrade {
+                                old_install_path, ..
+                            }
+                            | JobAction::Reinstall {
+                                current_install_path: old_install_path,
+                                ..
+                            } => sps_core::install::bottle::installed_on_request(old_install_path),
+                        },
                     });
                     names_processed_from_initial_ops.insert(name.clone());
                 }
@@ -946,7 +944,17 @@ impl<'a> OperationPlanner<'a> {
                     continue;
                 }
 
-                if matches!(
+                if pins.is_pinned(dep_name)
+                    && matches!(self.check_installed_status(dep_name).await, Ok(Some(_)))
+                {
+                    debug!(
+                        "[Planner] Keeping pinned dependency '{}' at its installed version",
+                        dep_name
+                    );
+                    intermediate_plan
+                        .already_satisfied
+                        .insert(dep_name.to_string());
+                } else if matches!(
                     dep_detail.status,
                     ResolutionStatus::Missing | ResolutionStatus::Requested
                 ) {
@@ -970,6 +978,7 @@ impl<'a> OperationPlanner<'a> {
                         action: JobAction::Install,
                         is_source_build: is_source_build_for_dep,
                         use_private_store_source: None,
+                        installed_on_request: false,
                     });
                 } else if dep_detail.status == ResolutionStatus::Installed {
                     intermediate_plan
@@ -998,6 +1007,7 @@ impl<'a> OperationPlanner<'a> {
                             .private_store_sources
                             .get(&cask_token)
                             .cloned(),
+                        installed_on_request: false,
                     });
                 }
                 Ok(Some(_installed_info)) => {
@@ -1042,6 +1052,7 @@ impl<'a> OperationPlanner<'a> {
             jobs: final_planned_jobs,
             errors: intermediate_plan.errors,
             already_installed_or_up_to_date: intermediate_plan.already_satisfied,
+            requested_kegs: intermediate_plan.requested_kegs,
             resolved_graph: resolved_formula_graph_opt,
         })
     }
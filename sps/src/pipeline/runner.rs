@@ -6,7 +6,6 @@ use std::time::Instant;
 
 use colored::Colorize;
 use crossbeam_channel::bounded as crossbeam_bounded;
-use reqwest::Client as HttpClient;
 use sps_common::cache::Cache;
 use sps_common::config::Config;
 use sps_common::dependency::resolver::{ResolutionStatus, ResolvedGraph};
@@ -309,7 +308,7 @@ pub async fn run_pipeline(
     if !downloads_to_initiate.is_empty() {
         debug!("Cloning runner_event_tx_clone for download_coordinator_event_tx_clone");
         let download_coordinator_event_tx_clone = runner_event_tx_clone.clone();
-        let http_client = Arc::new(HttpClient::new());
+        let http_client = Arc::new(sps_net::http::build_http_client(config)?);
         let config_for_downloader_owned = config.clone();
 
         let mut download_coordinator = DownloadCoordinator::new(
@@ -530,6 +529,18 @@ pub async fn run_pipeline(
     }
     debug!("Core worker pool joined. core_event_tx_for_worker_manager (broadcast sender) dropped.");
 
+    // Planning only notes that an installed formula was asked for; its receipt is updated here,
+    // after the jobs have run, like the receipts the workers write.
+    for keg_path in &planner_output.requested_kegs {
+        if let Err(e) = sps_core::install::bottle::mark_installed_on_request(keg_path) {
+            debug!(
+                "Could not mark {} as installed on request: {}",
+                keg_path.display(),
+                e
+            );
+        }
+    }
+
     let duration = start_time.elapsed();
     let success_total = final_success_count.load(Ordering::Relaxed);
     let fail_total = final_fail_count.load(Ordering::Relaxed) + initial_fail_count_from_planner;